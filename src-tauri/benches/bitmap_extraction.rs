@@ -0,0 +1,40 @@
+//! `CF_BITMAP` through `GetDIBits` against `CF_DIBV5` on a 1920×1080
+//! capture. Both end in the same PNG encode, so the difference is the GDI
+//! round trip. Windows only.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[cfg(target_os = "windows")]
+fn bitmap_vs_dibv5(c: &mut Criterion) {
+    use std::hint::black_box;
+
+    use app_lib::bench::{encode_dibv5, extract_image_from_bitmap, extract_image_from_dibv5};
+    use image::RgbaImage;
+    use windows::Win32::Graphics::Gdi::{CreateBitmap, DeleteObject, HGDIOBJ};
+
+    let (width, height) = (1920, 1080);
+    let image = RgbaImage::from_fn(width, height, |x, y| image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255]));
+    let dibv5 = encode_dibv5(&image);
+    // CreateBitmap takes top-down BGRA rows.
+    let bgra: Vec<u8> = image.pixels().flat_map(|p| [p[2], p[1], p[0], p[3]]).collect();
+    let bitmap = unsafe { CreateBitmap(width as i32, height as i32, 1, 32, Some(bgra.as_ptr().cast())) };
+    assert!(!bitmap.is_invalid(), "CreateBitmap failed");
+
+    let mut group = c.benchmark_group("1080p_extraction");
+    group.sample_size(20);
+    group.bench_function("cf_bitmap", |b| {
+        b.iter(|| unsafe { extract_image_from_bitmap(black_box(bitmap.0 as isize)) }.unwrap())
+    });
+    group.bench_function("cf_dibv5", |b| b.iter(|| extract_image_from_dibv5(black_box(&dibv5)).unwrap()));
+    group.finish();
+
+    unsafe {
+        let _ = DeleteObject(HGDIOBJ(bitmap.0));
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn bitmap_vs_dibv5(_: &mut Criterion) {}
+
+criterion_group!(benches, bitmap_vs_dibv5);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Hashing a 4K capture once (`save_image_with_hash` with the listener's
+//! hash) against twice (the listener's hash, then `save_image` hashing
+//! again). The capture is already in the library, so both stop at the
+//! duplicate check and the difference is the second hash.
+
+use std::hint::black_box;
+
+use app_lib::bench::{content_hash, CaptureSource, ImageManager};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use image::{ImageFormat, RgbaImage};
+
+fn capture_4k() -> Vec<u8> {
+    // Noise, so the PNG is as large as a busy screenshot rather than a
+    // flat one.
+    let mut state = 0x2545_f491_u32;
+    let image = RgbaImage::from_fn(3840, 2160, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        image::Rgba([r, g, b, 255])
+    });
+    let mut data = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Png).unwrap();
+    data
+}
+
+fn now() -> i64 {
+    0
+}
+
+fn capture_hash(c: &mut Criterion) {
+    let dir = tempfile::TempDir::new().unwrap();
+    let storage_dir = dir.path().join("library");
+    let mut manager = ImageManager::with_root(storage_dir.clone(), now).unwrap();
+    let scan = ImageManager::scan_storage(&storage_dir).unwrap();
+    manager.finish_loading(scan).unwrap();
+    let data = capture_4k();
+    manager.save_image(&data, CaptureSource::Screenshot).unwrap();
+
+    let mut group = c.benchmark_group("4k_capture");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(20);
+    group.bench_function("hashed_twice", |b| {
+        b.iter(|| {
+            black_box(content_hash(&data));
+            manager.save_image(&data, CaptureSource::Screenshot).unwrap()
+        })
+    });
+    group.bench_function("hashed_once", |b| {
+        b.iter(|| manager.save_image_with_hash(&data, content_hash(&data), CaptureSource::Screenshot).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, capture_hash);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! One listener pass over what modern apps copy: a high-DPI bitmap as
+//! CF_DIBV5 plus `PNG` (Edge, Teams) and a copied WebP file. The pass goes
+//! through Win32, or WinRT when built with `--features winrt-clipboard`, so
+//! run the bench both ways and compare `win32/*` with `winrt/*`. Windows
+//! only; it replaces the clipboard contents.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[cfg(target_os = "windows")]
+fn clipboard_read(c: &mut Criterion) {
+    use std::hint::black_box;
+
+    use app_lib::bench::{put_bitmap_on_clipboard, put_file_on_clipboard, read_clipboard_image_len};
+    use image::{ImageFormat, RgbaImage};
+
+    const PATH: &str = if cfg!(feature = "winrt-clipboard") { "winrt" } else { "win32" };
+
+    #[cfg(feature = "winrt-clipboard")]
+    unsafe {
+        use windows::Win32::System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED};
+        RoInitialize(RO_INIT_MULTITHREADED).expect("RoInitialize failed");
+    }
+
+    // A 4K screen at 200% scaling.
+    let image = RgbaImage::from_fn(3840, 2160, |x, y| image::Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, 255]));
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png).unwrap();
+    let dir = tempfile::TempDir::new().unwrap();
+    let webp = dir.path().join("copied.webp");
+    image.save_with_format(&webp, ImageFormat::WebP).unwrap();
+
+    let mut group = c.benchmark_group("clipboard_read");
+    group.sample_size(10);
+
+    put_bitmap_on_clipboard(&image, &png).unwrap();
+    group.bench_function(format!("{}/high_dpi_bitmap", PATH), |b| {
+        b.iter(|| black_box(read_clipboard_image_len()).expect("no image read"))
+    });
+
+    put_file_on_clipboard(&webp.to_string_lossy()).unwrap();
+    group.bench_function(format!("{}/webp_file", PATH), |b| {
+        b.iter(|| black_box(read_clipboard_image_len()).expect("no image read"))
+    });
+    group.finish();
+}
+
+#[cfg(not(target_os = "windows"))]
+fn clipboard_read(_: &mut Criterion) {}
+
+criterion_group!(benches, clipboard_read);
+criterion_main!(benches);
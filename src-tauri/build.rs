@@ -1,3 +1,42 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// Icons decoded to raw RGBA here so startup doesn't decode PNGs: `(const
+/// prefix, source file)`.
+const ICONS: [(&str, &str); 3] = [
+  ("WINDOW_ICON", "icons/256x256.png"),
+  ("TRAY_ICON", "icons/tray-icon.png"),
+  ("TRAY_ICON_DARK", "icons/tray-icon-dark.png"),
+];
+
 fn main() {
+  let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+  let mut constants = String::new();
+  for (name, source) in ICONS {
+    println!("cargo:rerun-if-changed={}", source);
+    let (rgba, width, height) = decode_rgba(Path::new(source));
+    let target = out_dir.join(format!("{}.rgba", name.to_lowercase()));
+    fs::write(&target, rgba).unwrap();
+    writeln!(constants, "pub const {}: &[u8] = include_bytes!({:?});", name, target.display().to_string()).unwrap();
+    writeln!(constants, "pub const {}_SIZE: (u32, u32) = ({}, {});", name, width, height).unwrap();
+  }
+  fs::write(out_dir.join("icons.rs"), constants).unwrap();
+
   tauri_build::build()
 }
+
+fn decode_rgba(path: &Path) -> (Vec<u8>, u32, u32) {
+  let decoder = png::Decoder::new(BufReader::new(File::open(path).unwrap()));
+  let mut reader = decoder.read_info().unwrap();
+  let mut buffer = vec![0; reader.output_buffer_size().unwrap()];
+  let frame = reader.next_frame(&mut buffer).unwrap();
+  assert!(
+    frame.color_type == png::ColorType::Rgba && frame.bit_depth == png::BitDepth::Eight,
+    "{} must be an 8-bit RGBA PNG",
+    path.display()
+  );
+  buffer.truncate(frame.buffer_size());
+  (buffer, frame.width, frame.height)
+}
@@ -0,0 +1,53 @@
+//! Internals measured by the benches under `benches/`. Not a stable API.
+
+pub use crate::image_manager::{content_hash, ImageManager};
+pub use crate::types::CaptureSource;
+#[cfg(target_os = "windows")]
+pub use crate::dib::encode_dibv5;
+
+/// Decodes a `CF_BITMAP` handle through `GetDIBits`, as the listener does.
+///
+/// # Safety
+///
+/// `bitmap_handle` must be a valid `HBITMAP` not selected into any DC.
+#[cfg(target_os = "windows")]
+pub unsafe fn extract_image_from_bitmap(bitmap_handle: isize) -> Option<Vec<u8>> {
+    crate::clipboard::ClipboardListener::extract_image_from_bitmap(bitmap_handle)
+}
+
+/// Decodes `CF_DIBV5` bytes, as the listener does.
+#[cfg(target_os = "windows")]
+pub fn extract_image_from_dibv5(data: &[u8]) -> Option<Vec<u8>> {
+    crate::clipboard::ClipboardListener::extract_image_from_dibv5(data, crate::dib::MAX_DIMENSION)
+}
+
+/// Replaces the clipboard contents with `image` as CF_DIBV5 and `png` as
+/// the registered `PNG` format, the way browsers copy an image.
+#[cfg(target_os = "windows")]
+pub fn put_bitmap_on_clipboard(image: &image::RgbaImage, png: &[u8]) -> Result<(), String> {
+    use windows::Win32::System::DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, RegisterClipboardFormatW};
+
+    const CF_DIBV5: u32 = 17;
+    let dib = encode_dibv5(image);
+    unsafe {
+        OpenClipboard(None).map_err(|_| "Failed to open clipboard".to_string())?;
+        let _ = EmptyClipboard();
+        let result = crate::set_clipboard_bytes(CF_DIBV5, &dib)
+            .and_then(|()| crate::set_clipboard_bytes(RegisterClipboardFormatW(windows::core::w!("PNG")), png));
+        let _ = CloseClipboard();
+        result
+    }
+}
+
+/// Places a single file on the clipboard as CF_HDROP.
+#[cfg(target_os = "windows")]
+pub fn put_file_on_clipboard(path: &str) -> Result<(), String> {
+    crate::put_file_on_clipboard(path)
+}
+
+/// Size of the image one listener pass reads off the clipboard: through
+/// WinRT with the `winrt-clipboard` feature, Win32 otherwise.
+#[cfg(target_os = "windows")]
+pub fn read_clipboard_image_len() -> Option<usize> {
+    crate::clipboard::ClipboardListener::read_clipboard_image_len()
+}
@@ -0,0 +1,63 @@
+//! Append-only audit log of captures, one JSON object per line, enabled with
+//! `enable_capture_log`. Only metadata is written, never image data.
+
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+#[cfg(target_os = "windows")]
+use std::io::Write;
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "windows")]
+use serde::Serialize;
+#[cfg(target_os = "windows")]
+use crate::types::ImageMetadata;
+
+pub struct CaptureLog {
+    path: PathBuf,
+    /// Only written by `record`; captures are only taken on Windows.
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    writer: BufWriter<File>,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(Serialize)]
+struct CaptureLogEntry<'a> {
+    ts: i64,
+    id: &'a str,
+    hash: &'a str,
+    format: Option<&'a str>,
+    width: Option<u32>,
+    height: Option<u32>,
+    source_app: Option<&'a str>,
+}
+
+impl CaptureLog {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { path: path.to_path_buf(), writer: BufWriter::new(file) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends a line for a new capture and flushes it, so the log is
+    /// complete even if the app is killed.
+    #[cfg(target_os = "windows")]
+    pub fn record(&mut self, metadata: &ImageMetadata, source_app: Option<&str>) -> anyhow::Result<()> {
+        let dimensions = image::image_dimensions(&metadata.path).ok();
+        let entry = CaptureLogEntry {
+            ts: metadata.created_at,
+            id: &metadata.id,
+            hash: &metadata.content_hash,
+            format: metadata.format.as_deref(),
+            width: dimensions.map(|(width, _)| width),
+            height: dimensions.map(|(_, height)| height),
+            source_app,
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
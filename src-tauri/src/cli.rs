@@ -0,0 +1,178 @@
+//! Headless subcommands (`snapmag list`, `export`, `import`) that work on the
+//! storage directory directly instead of starting the UI.
+//!
+//! While the GUI is running the library is shared read-only: `list` and
+//! `export` read the stored files through `ImageManager::open_shared`, which
+//! skips the startup cleanup and leaves the GUI's undo journal alone, and
+//! `import` is refused because the running instance wouldn't see files
+//! written behind its back. Requests are not forwarded to the GUI.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use crate::error::SnapMagError;
+use crate::image_manager::{self, ImageManager};
+use crate::types::{CaptureSource, ImageMetadata};
+
+#[derive(Debug, Parser)]
+#[command(name = "snapmag", version, about = "SnapMag clipboard image library")]
+pub struct Cli {
+    /// Runs headless instead of opening the window.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Prints the stored images, newest first.
+    List {
+        /// Print JSON instead of one line per image.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Copies stored images into a folder.
+    Export {
+        /// Only images captured within this long, e.g. `90m`, `24h`, `7d`.
+        #[arg(long, value_parser = parse_age)]
+        since: Option<i64>,
+        #[arg(long)]
+        dest: PathBuf,
+    },
+    /// Adds the image files of a folder to the library.
+    Import {
+        folder: PathBuf,
+    },
+}
+
+/// One file written by `export`.
+#[derive(Debug, Serialize)]
+struct ExportedImage {
+    id: String,
+    created_at: i64,
+    path: String,
+}
+
+/// Runs `command` and returns the process exit code. `gui_running` makes the
+/// library read-only.
+pub fn run(command: Command, gui_running: bool) -> i32 {
+    match execute(command, gui_running) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("snapmag: {}", e);
+            1
+        }
+    }
+}
+
+fn execute(command: Command, gui_running: bool) -> anyhow::Result<()> {
+    let mut image_manager = ImageManager::open_shared()?;
+    image_manager.load_index()?;
+
+    match command {
+        Command::List { json } => {
+            let images = image_manager.get_images();
+            if json {
+                print_json(&images)?;
+            } else {
+                for image in &images {
+                    let created = chrono::DateTime::from_timestamp(image.created_at, 0)
+                        .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    println!("{}\t{}\t{}", image.id, created, image.path);
+                }
+            }
+        }
+        Command::Export { since, dest } => {
+            let exported = export_images(&image_manager.get_images(), since, &dest)?;
+            print_json(&exported)?;
+        }
+        Command::Import { folder } => {
+            if gui_running {
+                return Err(SnapMagError::InvalidArgument(
+                    "SnapMag is running; close it or copy the images in the app to import them".to_string(),
+                ).into());
+            }
+            let imported = import_folder(&mut image_manager, &folder)?;
+            image_manager.save_index()?;
+            print_json(&imported)?;
+        }
+    }
+    Ok(())
+}
+
+fn export_images(images: &[ImageMetadata], since: Option<i64>, dest: &Path) -> anyhow::Result<Vec<ExportedImage>> {
+    let cutoff = match since {
+        Some(age) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64 - age,
+        None => i64::MIN,
+    };
+    fs::create_dir_all(dest)?;
+
+    let mut exported = Vec::new();
+    for image in images.iter().filter(|image| image.created_at >= cutoff) {
+        let source = Path::new(&image.path);
+        let Some(file_name) = source.file_name() else {
+            continue;
+        };
+        // The extension follows the content, in case the stored one is wrong.
+        let target = match (source.file_stem(), image_manager::content_extension(source)) {
+            (Some(stem), Some(ext)) => dest.join(format!("{}.{}", stem.to_string_lossy(), ext)),
+            _ => dest.join(file_name),
+        };
+        fs::copy(source, &target)?;
+        if let Some(raw) = image.raw_path.as_deref().map(Path::new) {
+            if let Some(raw_name) = raw.file_name().filter(|_| raw.exists()) {
+                fs::copy(raw, dest.join(raw_name))?;
+            }
+        }
+        exported.push(ExportedImage {
+            id: image.id.clone(),
+            created_at: image.created_at,
+            path: target.to_string_lossy().to_string(),
+        });
+    }
+    Ok(exported)
+}
+
+/// Saves every image file directly inside `folder`, in file name order.
+/// Files that fail to import are reported on stderr and skipped.
+fn import_folder(image_manager: &mut ImageManager, folder: &Path) -> anyhow::Result<Vec<ImageMetadata>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(folder)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && image::ImageFormat::from_path(path).is_ok())
+        .collect();
+    paths.sort();
+
+    let mut imported = Vec::new();
+    for path in paths {
+        let result = fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| image_manager.save_image(&data, CaptureSource::Import));
+        match result {
+            Ok(saved) => imported.push(saved.metadata),
+            Err(e) => eprintln!("snapmag: skipping {}: {}", path.display(), e),
+        }
+    }
+    Ok(imported)
+}
+
+fn print_json<T: Serialize + ?Sized>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Parses an age such as `45s`, `90m`, `24h` or `7d` into seconds.
+fn parse_age(s: &str) -> Result<i64, String> {
+    let (value, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let value: i64 = value.parse().map_err(|_| format!("invalid age: {}", s))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" | "" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("unknown age unit in {}, expected s, m, h or d", s)),
+    };
+    value.checked_mul(multiplier).ok_or_else(|| format!("age too large: {}", s))
+}
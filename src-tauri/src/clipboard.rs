@@ -1,30 +1,1138 @@
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tauri::{AppHandle, Emitter};
-use crate::types::ClipboardEvent;
-use crate::image_manager::ImageManager;
-use log::{info, error, debug};
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(any(target_os = "windows", test))]
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+#[cfg(target_os = "windows")]
+use tauri::Emitter;
+use crate::types::{CaptureSource, ClipboardError, ClipboardStats, ImageMetadata};
+#[cfg(target_os = "windows")]
+use crate::types::{CaptureSkippedEvent, ClipboardCaptureSkippedEvent, ClipboardEvent, ClipboardNonImageEvent, FolderImportTruncatedEvent, ImagesAddedEvent, ImagesRemovedEvent, QuietModeEvent};
+use crate::image_manager::{ContentHash, ImageManager};
+use crate::snippets::SnippetStore;
+use crate::capture_log::CaptureLog;
+use crate::error::SnapMagError;
+use log::{info, error};
+#[cfg(target_os = "windows")]
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardListenerConfig {
+    /// Store animated GIFs from the registered "GIF" format as-is instead of
+    /// keeping only their first frame as PNG.
+    pub save_animated_gifs_as_gif: bool,
+    /// Import the images inside folders copied in Explorer. Off by default so
+    /// copying a large folder doesn't silently mass-import it.
+    pub capture_dropped_folders: bool,
+    /// Most images imported from copied folders per clipboard change.
+    pub max_dropped_folder_files: usize,
+    pub thread_priority: ThreadPriority,
+    /// Only capture images whose clipboard owner is in `included_apps`.
+    pub inclusion_mode: bool,
+    /// Process names (e.g. `obs64.exe` or `obs64`), matched case-insensitively.
+    pub included_apps: Vec<String>,
+    /// Apps never captured from while inclusion mode is off.
+    pub excluded_apps: Vec<String>,
+    /// Registered clipboard formats (e.g. `Figma Layers`) checked for image
+    /// data before the standard bitmap formats, keyed by format name.
+    pub custom_clipboard_formats: HashMap<String, CustomFormatExtractor>,
+    /// Also emit one `clipboard-update` per capture, for frontends that
+    /// don't handle the batched `images-added` event yet.
+    pub emit_legacy_clipboard_update: bool,
+    /// Extractors tried in order until one yields an image; see
+    /// `EXTRACTOR_NAMES`. `custom` stands for all custom formats.
+    pub extractor_chain: Vec<String>,
+    /// Also store the clipboard DIB each capture was converted from, as
+    /// `<id>.dib` next to the image, so it can be re-converted after a
+    /// conversion bug.
+    pub keep_raw_capture: bool,
+    /// Also keep copied text (CF_UNICODETEXT) as snippets when the clipboard
+    /// holds no image and isn't marked private, see `is_private_content`.
+    /// Off by default.
+    pub capture_text: bool,
+    /// Shorter text isn't kept as a snippet.
+    pub min_snippet_chars: usize,
+    /// Record the foreground window title with each capture. Off by default
+    /// because titles can contain private text.
+    pub capture_window_title: bool,
+    /// Bitmaps with more pixels are skipped instead of decoded on the
+    /// listener thread; see `force_capture_last_skipped`.
+    pub max_decode_pixels: u64,
+    /// Clipboard data larger than this is skipped before being copied.
+    pub max_clipboard_bytes: u64,
+    /// Go quiet while a full-screen app, game or presentation is in the
+    /// foreground: the frontend is told to hold back notifications.
+    pub quiet_when_fullscreen: bool,
+    /// While quiet, also stop capturing. Copies made meanwhile are not
+    /// captured on resume.
+    pub pause_capture_when_fullscreen: bool,
+    /// Emit `clipboard-non-image` when the clipboard changes to something
+    /// that isn't an image. Off by default; the WinRT reader never sends it.
+    pub notify_on_non_image: bool,
+}
+
+/// How to get image bytes out of an app-specific clipboard format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CustomFormatExtractor {
+    /// The format holds a PNG file as-is.
+    RawPng,
+    /// The format holds a JPEG file as-is.
+    RawJpeg,
+    /// The format holds a JSON object whose `image_field` is a base64 image,
+    /// optionally as a `data:` URL. A field starting with `/` is a JSON pointer.
+    Json { image_field: String },
+    /// The format holds text starting with `prefix`, followed by a base64 image.
+    Base64 { prefix: String },
+}
+
+#[cfg(target_os = "windows")]
+impl CustomFormatExtractor {
+    /// Returns the image bytes in `data`, or `None` if it doesn't hold an
+    /// image the way this extractor expects.
+    fn extract(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let image = match self {
+            Self::RawPng | Self::RawJpeg => {
+                let expected = if *self == Self::RawPng { image::ImageFormat::Png } else { image::ImageFormat::Jpeg };
+                return (image::guess_format(data).ok() == Some(expected)).then(|| data.to_vec());
+            }
+            Self::Json { image_field } => {
+                let value: serde_json::Value = serde_json::from_slice(Self::trim_nul(data)).ok()?;
+                let field = if image_field.starts_with('/') { value.pointer(image_field) } else { value.get(image_field) };
+                let text = field?.as_str()?;
+                Self::decode_base64(text.split_once(";base64,").map_or(text, |(_, payload)| payload))?
+            }
+            Self::Base64 { prefix } => {
+                let text = std::str::from_utf8(Self::trim_nul(data)).ok()?;
+                Self::decode_base64(text.trim_start().strip_prefix(prefix.as_str())?)?
+            }
+        };
+        image::guess_format(&image).is_ok().then_some(image)
+    }
+
+    /// Text formats are usually NUL-terminated and padded by `GlobalSize`.
+    fn trim_nul(data: &[u8]) -> &[u8] {
+        let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        &data[..end]
+    }
+
+    fn decode_base64(text: &str) -> Option<Vec<u8>> {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.decode(text.trim()).ok()
+    }
+}
+
+impl ClipboardListenerConfig {
+    /// Whether captures owned by `app` pass the inclusion or exclusion list.
+    /// An unknown owner only passes when inclusion mode is off.
+    #[cfg(target_os = "windows")]
+    fn allows_app(&self, app: Option<&str>) -> bool {
+        let normalize = |name: &str| {
+            let name = name.to_lowercase();
+            name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+        };
+        let listed = |apps: &[String]| {
+            app.map(normalize).is_some_and(|app| apps.iter().any(|listed| normalize(listed) == app))
+        };
+        if self.inclusion_mode {
+            listed(&self.included_apps)
+        } else {
+            !listed(&self.excluded_apps)
+        }
+    }
+}
+
+/// Scheduling priority of the listener thread. Defaults to below normal so
+/// polling the clipboard never competes with the foreground application.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadPriority {
+    AboveNormal,
+    Normal,
+    #[default]
+    BelowNormal,
+    Idle,
+}
+
+impl std::str::FromStr for ThreadPriority {
+    type Err = SnapMagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "above_normal" => Ok(Self::AboveNormal),
+            "normal" => Ok(Self::Normal),
+            "below_normal" => Ok(Self::BelowNormal),
+            "idle" => Ok(Self::Idle),
+            _ => Err(SnapMagError::InvalidArgument(format!("unknown thread priority: {}", s))),
+        }
+    }
+}
+
+impl Default for ClipboardListenerConfig {
+    fn default() -> Self {
+        Self {
+            save_animated_gifs_as_gif: false,
+            capture_dropped_folders: false,
+            max_dropped_folder_files: 50,
+            thread_priority: ThreadPriority::default(),
+            inclusion_mode: false,
+            included_apps: Vec::new(),
+            excluded_apps: Vec::new(),
+            custom_clipboard_formats: HashMap::new(),
+            emit_legacy_clipboard_update: true,
+            extractor_chain: DEFAULT_EXTRACTOR_CHAIN.iter().map(|name| name.to_string()).collect(),
+            keep_raw_capture: false,
+            capture_text: false,
+            min_snippet_chars: 20,
+            capture_window_title: false,
+            max_decode_pixels: 100_000_000,
+            max_clipboard_bytes: 512 * 1024 * 1024,
+            quiet_when_fullscreen: false,
+            pause_capture_when_fullscreen: false,
+            notify_on_non_image: false,
+        }
+    }
+}
+
+/// Extractor names accepted in `ClipboardListenerConfig::extractor_chain`.
+/// `png` (the registered "PNG" format) and `tiff` (CF_TIFF) are stored
+/// without re-encoding but are off by default.
+pub const EXTRACTOR_NAMES: [&str; 8] = ["hdrop", "gif", "custom", "png", "dibv5", "dib", "tiff", "bitmap"];
+const DEFAULT_EXTRACTOR_CHAIN: [&str; 6] = ["hdrop", "gif", "custom", "dibv5", "dib", "bitmap"];
+
+/// Checks that `chain` is non-empty and names each of `EXTRACTOR_NAMES` at
+/// most once.
+pub fn validate_extractor_chain(chain: &[String]) -> Result<(), SnapMagError> {
+    if chain.is_empty() {
+        return Err(SnapMagError::InvalidArgument("extractor chain must not be empty".to_string()));
+    }
+    for (index, name) in chain.iter().enumerate() {
+        if !EXTRACTOR_NAMES.contains(&name.as_str()) {
+            return Err(SnapMagError::InvalidArgument(format!("unknown clipboard extractor: {}", name)));
+        }
+        if chain[..index].contains(name) {
+            return Err(SnapMagError::InvalidArgument(format!("clipboard extractor listed twice: {}", name)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+const CF_TEXT: u32 = 1;
+#[cfg(target_os = "windows")]
+const CF_BITMAP: u32 = 2;
+#[cfg(target_os = "windows")]
+const CF_TIFF: u32 = 6;
+#[cfg(target_os = "windows")]
+const CF_UNICODETEXT: u32 = 13;
+#[cfg(target_os = "windows")]
+const CF_OEMTEXT: u32 = 7;
+#[cfg(target_os = "windows")]
+const CF_DIB: u32 = 8;
+#[cfg(target_os = "windows")]
+const CF_HDROP: u32 = 15;
+#[cfg(target_os = "windows")]
+const CF_DIBV5: u32 = 17;
+#[cfg(target_os = "windows")]
+const CF_GIF_NAME: &str = "GIF";
+#[cfg(target_os = "windows")]
+const CF_PNG_NAME: &str = "PNG";
+/// Formats password managers and the like add to content that clipboard
+/// monitors shouldn't keep; copied text is not stored when one is present.
+#[cfg(target_os = "windows")]
+const PRIVATE_FORMAT_NAMES: [&str; 2] = ["ExcludeClipboardContentFromMonitorProcessing", "Clipboard Viewer Ignore"];
+/// Holds a DWORD; 0 keeps the content out of clipboard history, and so out
+/// of the snippets.
+#[cfg(target_os = "windows")]
+const CAN_INCLUDE_IN_HISTORY_NAME: &str = "CanIncludeInClipboardHistory";
+
+/// NUL-terminated UTF-16 text from a CF_UNICODETEXT buffer.
+#[cfg(target_os = "windows")]
+fn utf16_text(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Image bytes pulled out of one clipboard format.
+#[cfg(target_os = "windows")]
+struct Extracted {
+    data: Vec<u8>,
+    source: CaptureSource,
+    tag: Option<&'static str>,
+    /// The clipboard bytes `data` was converted from, when kept.
+    raw: Option<Vec<u8>>,
+}
+
+#[cfg(target_os = "windows")]
+impl Extracted {
+    fn screenshot(data: Vec<u8>) -> Self {
+        Self { data, source: CaptureSource::Screenshot, tag: None, raw: None }
+    }
+
+    /// A conversion of `bytes`, keeping them when `keep_raw`.
+    fn converted(data: Vec<u8>, bytes: &[u8], keep_raw: bool) -> Self {
+        Self { raw: keep_raw.then(|| bytes.to_vec()), ..Self::screenshot(data) }
+    }
+}
+
+/// One clipboard format the listener can turn into image bytes.
+#[cfg(target_os = "windows")]
+trait ClipboardExtractor {
+    fn format_id(&self) -> u32;
+    /// Format name for logs.
+    fn name(&self) -> &str;
+    /// Reads `handle`, the format's data from `GetClipboardData`, which is
+    /// only valid while the clipboard is open.
+    unsafe fn extract(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<Extracted> {
+        with_global_bytes(handle, |bytes| self.decode(bytes))
+    }
+    /// Turns a copy of the format's global memory into an image.
+    fn decode(&self, _bytes: &[u8]) -> Option<Extracted> {
+        None
+    }
+    /// Width and height the data decodes to, read from its header without
+    /// decoding, for formats that are decoded on capture.
+    unsafe fn dimensions(&self, _handle: windows::Win32::Foundation::HANDLE) -> Option<(u32, u32)> {
+        None
+    }
+    /// Whether the data can hold an image at all. Data that can't, such as
+    /// copied files none of which are images, is passed over without an
+    /// extraction error.
+    unsafe fn applies(&self, _handle: windows::Win32::Foundation::HANDLE) -> bool {
+        true
+    }
+    /// Whether the data is an `HGLOBAL`, so its size can be checked before
+    /// it is copied.
+    fn is_global_memory(&self) -> bool {
+        true
+    }
+}
+
+/// Clipboard data over the listener's size limits.
+#[cfg(target_os = "windows")]
+struct TooLarge {
+    format_id: u32,
+    format: String,
+    bytes: u64,
+    pixels: Option<u64>,
+}
+
+/// A clipboard change skipped as too large. It stays `handled` once forced,
+/// so the same change is neither reported nor captured again.
+#[cfg(target_os = "windows")]
+#[derive(Clone, Copy)]
+struct TooLargeSkip {
+    sequence: u32,
+    format_id: u32,
+    handled: bool,
+}
+
+/// Locks a global memory handle and passes its bytes to `f`.
+#[cfg(target_os = "windows")]
+unsafe fn with_global_bytes<T>(handle: windows::Win32::Foundation::HANDLE, f: impl FnOnce(&[u8]) -> Option<T>) -> Option<T> {
+    use windows::Win32::Foundation::HGLOBAL;
+    use windows::Win32::System::Memory::{GlobalLock, GlobalSize, GlobalUnlock};
+    
+    let hglobal = HGLOBAL(handle.0);
+    let ptr = GlobalLock(hglobal) as *const u8;
+    let size = GlobalSize(hglobal);
+    let result = if ptr.is_null() || size == 0 { None } else { f(std::slice::from_raw_parts(ptr, size)) };
+    let _ = GlobalUnlock(hglobal);
+    result
+}
+
+/// Image files copied in Explorer (CF_HDROP).
+#[cfg(target_os = "windows")]
+struct HDropExtractor;
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for HDropExtractor {
+    fn format_id(&self) -> u32 {
+        CF_HDROP
+    }
+
+    fn name(&self) -> &str {
+        "CF_HDROP"
+    }
+
+    unsafe fn extract(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<Extracted> {
+        let (data, source) = ClipboardListener::extract_image_from_files(windows::Win32::UI::Shell::HDROP(handle.0))?;
+        Some(Extracted { data, source, tag: None, raw: None })
+    }
+
+    unsafe fn applies(&self, handle: windows::Win32::Foundation::HANDLE) -> bool {
+        ClipboardListener::dropped_paths(windows::Win32::UI::Shell::HDROP(handle.0))
+            .iter()
+            .any(|path| ClipboardListener::has_image_extension(path))
+    }
+}
+
+/// The registered "GIF" format. Animated GIFs keep only their first frame
+/// unless `keep_animated`.
+#[cfg(target_os = "windows")]
+struct GifExtractor {
+    format_id: u32,
+    keep_animated: bool,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for GifExtractor {
+    fn format_id(&self) -> u32 {
+        self.format_id
+    }
+
+    fn name(&self) -> &str {
+        CF_GIF_NAME
+    }
+
+    fn decode(&self, gif_bytes: &[u8]) -> Option<Extracted> {
+        let animated = ClipboardListener::gif_is_animated(gif_bytes);
+        if animated && self.keep_animated {
+            debug!("Keeping animated GIF as-is, size: {} bytes", gif_bytes.len());
+            return Some(Extracted::screenshot(gif_bytes.to_vec()));
+        }
+        let data = unsafe { ClipboardListener::extract_image_from_gif(gif_bytes.as_ptr(), gif_bytes.len()) }?;
+        Some(Extracted {
+            tag: animated.then_some("auto:animated-gif-first-frame"),
+            ..Extracted::screenshot(data)
+        })
+    }
+}
+
+/// A user-configured format, see `ClipboardListenerConfig::custom_clipboard_formats`.
+#[cfg(target_os = "windows")]
+struct CustomExtractor {
+    name: String,
+    format_id: u32,
+    extractor: CustomFormatExtractor,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for CustomExtractor {
+    fn format_id(&self) -> u32 {
+        self.format_id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Extracted> {
+        self.extractor.extract(bytes).map(Extracted::screenshot)
+    }
+}
+
+/// The registered "PNG" format browsers and Office put next to the bitmap,
+/// which keeps transparency.
+#[cfg(target_os = "windows")]
+struct RawPngExtractor {
+    format_id: u32,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for RawPngExtractor {
+    fn format_id(&self) -> u32 {
+        self.format_id
+    }
+
+    fn name(&self) -> &str {
+        CF_PNG_NAME
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Extracted> {
+        CustomFormatExtractor::RawPng.extract(bytes).map(Extracted::screenshot)
+    }
+}
+
+/// CF_TIFF, stored as-is.
+#[cfg(target_os = "windows")]
+struct TiffExtractor;
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for TiffExtractor {
+    fn format_id(&self) -> u32 {
+        CF_TIFF
+    }
+
+    fn name(&self) -> &str {
+        "CF_TIFF"
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Extracted> {
+        (image::guess_format(bytes).ok() == Some(image::ImageFormat::Tiff)).then(|| Extracted::screenshot(bytes.to_vec()))
+    }
+}
+
+/// Keeps the DIB itself when `keep_raw`; see
+/// `ClipboardListenerConfig::keep_raw_capture`.
+#[cfg(target_os = "windows")]
+struct DibV5Extractor {
+    keep_raw: bool,
+    max_dimension: u32,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for DibV5Extractor {
+    fn format_id(&self) -> u32 {
+        CF_DIBV5
+    }
+
+    fn name(&self) -> &str {
+        "CF_DIBV5"
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Extracted> {
+        let data = ClipboardListener::extract_image_from_dibv5(bytes, self.max_dimension)?;
+        Some(Extracted::converted(data, bytes, self.keep_raw))
+    }
+
+    unsafe fn dimensions(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<(u32, u32)> {
+        with_global_bytes(handle, |bytes| {
+            crate::dib::DibHeader::parse(bytes, crate::dib::BITMAPV5HEADER_SIZE, crate::dib::FORCED_MAX_DIMENSION)
+                .ok()
+                .map(|header| (header.width, header.height))
+        })
+    }
+}
+
+#[cfg(target_os = "windows")]
+struct DibExtractor {
+    keep_raw: bool,
+    max_dimension: u32,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for DibExtractor {
+    fn format_id(&self) -> u32 {
+        CF_DIB
+    }
+
+    fn name(&self) -> &str {
+        "CF_DIB"
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Option<Extracted> {
+        let data = ClipboardListener::extract_image_from_dib(bytes, self.max_dimension)?;
+        Some(Extracted::converted(data, bytes, self.keep_raw))
+    }
+
+    unsafe fn dimensions(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<(u32, u32)> {
+        with_global_bytes(handle, |bytes| {
+            crate::dib::DibHeader::parse(bytes, crate::dib::BITMAPINFOHEADER_SIZE, crate::dib::FORCED_MAX_DIMENSION)
+                .ok()
+                .map(|header| (header.width, header.height))
+        })
+    }
+}
+
+#[cfg(target_os = "windows")]
+struct BitmapExtractor;
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractor for BitmapExtractor {
+    fn format_id(&self) -> u32 {
+        CF_BITMAP
+    }
+
+    fn name(&self) -> &str {
+        "CF_BITMAP"
+    }
+
+    unsafe fn extract(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<Extracted> {
+        ClipboardListener::extract_image_from_bitmap(handle.0 as isize).map(Extracted::screenshot)
+    }
+
+    unsafe fn dimensions(&self, handle: windows::Win32::Foundation::HANDLE) -> Option<(u32, u32)> {
+        let (width, height) = ClipboardListener::bitmap_dimensions(handle.0 as isize)?;
+        Some((width.unsigned_abs(), height.unsigned_abs()))
+    }
+
+    /// CF_BITMAP is an `HBITMAP`, not global memory.
+    fn is_global_memory(&self) -> bool {
+        false
+    }
+}
+
+/// Extractors in the order they are tried; the first to yield an image wins.
+#[cfg(target_os = "windows")]
+struct ClipboardExtractorChain {
+    extractors: Vec<Box<dyn ClipboardExtractor>>,
+    max_pixels: u64,
+    max_bytes: u64,
+    /// Largest side DIBs are decoded at; anything larger is too large.
+    max_dimension: u32,
+}
+
+#[cfg(target_os = "windows")]
+impl ClipboardExtractorChain {
+    /// Builds the chain named by `config.extractor_chain`. `format_ids` maps
+    /// registered format names to their ids; unregistered ones are left out.
+    fn from_config(config: &ClipboardListenerConfig, format_ids: &HashMap<String, u32>, max_dimension: u32) -> Self {
+        let registered = |name: &str| format_ids.get(name).copied().filter(|&id| id != 0);
+        let mut extractors: Vec<Box<dyn ClipboardExtractor>> = Vec::new();
+        for name in &config.extractor_chain {
+            match name.as_str() {
+                "hdrop" => extractors.push(Box::new(HDropExtractor)),
+                "gif" => {
+                    if let Some(format_id) = registered(CF_GIF_NAME) {
+                        extractors.push(Box::new(GifExtractor { format_id, keep_animated: config.save_animated_gifs_as_gif }));
+                    }
+                }
+                "custom" => {
+                    for (name, extractor) in &config.custom_clipboard_formats {
+                        if let Some(format_id) = registered(name) {
+                            extractors.push(Box::new(CustomExtractor { name: name.clone(), format_id, extractor: extractor.clone() }));
+                        }
+                    }
+                }
+                "png" => {
+                    if let Some(format_id) = registered(CF_PNG_NAME) {
+                        extractors.push(Box::new(RawPngExtractor { format_id }));
+                    }
+                }
+                "dibv5" => extractors.push(Box::new(DibV5Extractor { keep_raw: config.keep_raw_capture, max_dimension })),
+                "dib" => extractors.push(Box::new(DibExtractor { keep_raw: config.keep_raw_capture, max_dimension })),
+                "tiff" => extractors.push(Box::new(TiffExtractor)),
+                "bitmap" => extractors.push(Box::new(BitmapExtractor)),
+                _ => log::warn!("Ignoring unknown clipboard extractor: {}", name),
+            }
+        }
+        Self { extractors, max_pixels: config.max_decode_pixels, max_bytes: config.max_clipboard_bytes, max_dimension }
+    }
+
+    /// Runs the extractors whose format is among `formats`, adding one entry
+    /// to `errors` per format that couldn't be read. The clipboard must be
+    /// open. Stops at the first format over the size limits, which is
+    /// neither copied nor decoded.
+    unsafe fn extract(&self, formats: &[u32], errors: &mut Vec<ClipboardError>) -> Result<Option<Extracted>, TooLarge> {
+        use windows::Win32::Foundation::HGLOBAL;
+        use windows::Win32::System::DataExchange::GetClipboardData;
+        use windows::Win32::System::Memory::GlobalSize;
+        
+        for extractor in self.extractors.iter().filter(|extractor| formats.contains(&extractor.format_id())) {
+            debug!("Found {} format", extractor.name());
+            let handle = match GetClipboardData(extractor.format_id()) {
+                Ok(handle) => handle,
+                Err(e) => {
+                    debug!("Failed to get {} data", extractor.name());
+                    errors.push(ClipboardError {
+                        timestamp: unix_seconds() as i64,
+                        format: extractor.format_id(),
+                        error_message: format!("failed to get {} data: {}", extractor.name(), e),
+                    });
+                    continue;
+                }
+            };
+            if !extractor.applies(handle) {
+                debug!("No image in {} data", extractor.name());
+                continue;
+            }
+            let bytes = if extractor.is_global_memory() { GlobalSize(HGLOBAL(handle.0)) as u64 } else { 0 };
+            let dimensions = extractor.dimensions(handle);
+            let pixels = dimensions.map(|(width, height)| width as u64 * height as u64);
+            let too_wide = dimensions.is_some_and(|(width, height)| width.max(height) > self.max_dimension);
+            if bytes > self.max_bytes || pixels.is_some_and(|pixels| pixels > self.max_pixels) || too_wide {
+                return Err(TooLarge { format_id: extractor.format_id(), format: extractor.name().to_string(), bytes, pixels });
+            }
+            match extractor.extract(handle) {
+                Some(extracted) => {
+                    debug!("Successfully extracted image from {}", extractor.name());
+                    return Ok(Some(extracted));
+                }
+                None => {
+                    debug!("Failed to extract image from {}", extractor.name());
+                    errors.push(ClipboardError {
+                        timestamp: unix_seconds() as i64,
+                        format: extractor.format_id(),
+                        error_message: format!("{} data did not decode as an image", extractor.name()),
+                    });
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Counters updated by the listener thread, read through `get_stats`.
+#[derive(Debug, Default)]
+struct ListenerStats {
+    iterations: AtomicU64,
+    images_captured: AtomicU64,
+    duplicates_skipped: AtomicU64,
+    errors: AtomicU64,
+    files_scanned: AtomicU64,
+    /// Unix seconds of the last new capture, 0 if none yet.
+    last_capture_at: AtomicU64,
+    /// Unix seconds the listener thread was started, 0 if not started.
+    started_at: AtomicU64,
+    /// See `ClipboardStats::quiet`.
+    quiet: AtomicBool,
+}
+
+impl ListenerStats {
+    fn record_capture(&self) {
+        self.images_captured.fetch_add(1, Ordering::Relaxed);
+        self.last_capture_at.store(unix_seconds(), Ordering::Relaxed);
+    }
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Result of one pass over the clipboard formats; `data` is `None` when no
+/// format held a usable image.
+#[cfg(target_os = "windows")]
+struct ClipboardImage {
+    data: Option<Vec<u8>>,
+    source: CaptureSource,
+    tag: Option<&'static str>,
+    raw: Option<Vec<u8>>,
+    dropped_folders: Vec<String>,
+    /// Copied text, only read with `capture_text` when there is no image.
+    text: Option<String>,
+    /// Data left on the clipboard for being over the size limits.
+    too_large: Option<TooLarge>,
+    /// Image formats offered but unreadable, only kept when no image was
+    /// found at all; a failure the next extractor recovers from isn't one.
+    extraction_errors: Vec<ClipboardError>,
+    /// Formats on the clipboard, only kept when no image was found.
+    formats: Vec<u32>,
+}
+
+/// New captures waiting to go out as one `images-added` event, sent once
+/// `IMAGES_ADDED_WINDOW` passes without another capture. The caller passes
+/// the time in, so the batching doesn't depend on the listener.
+#[cfg(any(target_os = "windows", test))]
+#[derive(Default)]
+struct AddedImagesBatch {
+    items: Vec<ImageMetadata>,
+    last_push: Option<Instant>,
+}
+
+#[cfg(any(target_os = "windows", test))]
+impl AddedImagesBatch {
+    fn push(&mut self, metadata: ImageMetadata, now: Instant) {
+        self.items.push(metadata);
+        self.last_push = Some(now);
+    }
+
+    /// Takes the batch, in capture order, once its window has elapsed at
+    /// `now` or unconditionally with `force`.
+    fn take_due(&mut self, now: Instant, force: bool) -> Option<Vec<ImageMetadata>> {
+        let due = force || self.last_push.is_some_and(|pushed| now.duration_since(pushed) >= IMAGES_ADDED_WINDOW);
+        if !due || self.items.is_empty() {
+            return None;
+        }
+        self.last_push = None;
+        Some(std::mem::take(&mut self.items))
+    }
+}
+
+/// A different image that arrived during the capture cooldown, kept in
+/// memory until it is recovered or another skipped capture replaces it.
+struct SkippedCapture {
+    data: Vec<u8>,
+    hash: ContentHash,
+    source: CaptureSource,
+    tag: Option<&'static str>,
+}
 
 pub struct ClipboardListener {
     handle: Arc<Mutex<Option<AppHandle>>>,
     running: Arc<Mutex<bool>>,
     image_manager: Arc<Mutex<ImageManager>>,
     last_hash: Arc<Mutex<Option<String>>>,
+    #[cfg(target_os = "windows")]
     last_detection_time: Arc<Mutex<u64>>,
+    config: Arc<Mutex<ClipboardListenerConfig>>,
+    thread: Option<thread::JoinHandle<()>>,
+    stats: Arc<ListenerStats>,
+    skipped_capture: Arc<Mutex<Option<SkippedCapture>>>,
+    #[cfg(target_os = "windows")]
+    snippets: Arc<Mutex<SnippetStore>>,
+    /// The last capture skipped as too large.
+    #[cfg(target_os = "windows")]
+    too_large_skip: Arc<Mutex<Option<TooLargeSkip>>>,
+    /// Newest last, at most `MAX_RECENT_ERRORS`.
+    recent_errors: Arc<Mutex<VecDeque<ClipboardError>>>,
+    /// Clipboard sequence number of the app's own last clipboard write,
+    /// which is not captured.
+    own_sequence: Arc<Mutex<Option<u32>>>,
+    /// Shared with `AppState`, which opens and closes it.
+    #[cfg(target_os = "windows")]
+    capture_log: Arc<Mutex<Option<CaptureLog>>>,
 }
 
+/// Extraction failures kept for `get_recent_errors`.
+#[cfg(target_os = "windows")]
+const MAX_RECENT_ERRORS: usize = 50;
+#[cfg(target_os = "windows")]
 const CLIPBOARD_COOLDOWN_MS: u64 = 2000;
+/// Quiet period after which batched captures are announced.
+#[cfg(any(target_os = "windows", test))]
+const IMAGES_ADDED_WINDOW: Duration = Duration::from_millis(300);
 
 impl ClipboardListener {
-    pub fn new(image_manager: Arc<Mutex<ImageManager>>) -> Self {
+    /// `snippets` and `capture_log` are only used by the Windows listener.
+    #[cfg_attr(not(target_os = "windows"), allow(unused_variables))]
+    pub fn new(image_manager: Arc<Mutex<ImageManager>>, snippets: Arc<Mutex<SnippetStore>>, capture_log: Arc<Mutex<Option<CaptureLog>>>) -> Self {
         Self {
             handle: Arc::new(Mutex::new(None)),
             running: Arc::new(Mutex::new(false)),
             image_manager,
             last_hash: Arc::new(Mutex::new(None)),
+            #[cfg(target_os = "windows")]
             last_detection_time: Arc::new(Mutex::new(0)),
+            config: Arc::new(Mutex::new(ClipboardListenerConfig::default())),
+            thread: None,
+            stats: Arc::new(ListenerStats::default()),
+            skipped_capture: Arc::new(Mutex::new(None)),
+            #[cfg(target_os = "windows")]
+            snippets,
+            #[cfg(target_os = "windows")]
+            too_large_skip: Arc::new(Mutex::new(None)),
+            recent_errors: Arc::new(Mutex::new(VecDeque::new())),
+            own_sequence: Arc::new(Mutex::new(None)),
+            #[cfg(target_os = "windows")]
+            capture_log,
+        }
+    }
+
+    pub fn set_save_animated_gifs_as_gif(&self, enabled: bool) {
+        self.config.lock().unwrap().save_animated_gifs_as_gif = enabled;
+    }
+
+    pub fn set_capture_dropped_folders(&self, enabled: bool, max_files: usize) {
+        let mut config = self.config.lock().unwrap();
+        config.capture_dropped_folders = enabled;
+        config.max_dropped_folder_files = max_files;
+    }
+
+    /// Changes the listener thread's priority, applying it immediately when
+    /// the thread is running. Has no effect outside Windows.
+    pub fn set_thread_priority(&self, priority: ThreadPriority) {
+        self.config.lock().unwrap().thread_priority = priority;
+        
+        #[cfg(target_os = "windows")]
+        if let Some(thread) = &self.thread {
+            use std::os::windows::io::AsRawHandle;
+            use windows::Win32::Foundation::HANDLE;
+            
+            Self::apply_thread_priority(HANDLE(thread.as_raw_handle()), priority);
+        }
+    }
+
+    pub fn set_inclusion_mode(&self, enabled: bool, apps: Vec<String>) {
+        let mut config = self.config.lock().unwrap();
+        config.inclusion_mode = enabled;
+        config.included_apps = apps;
+    }
+
+    pub fn set_excluded_apps(&self, apps: Vec<String>) {
+        self.config.lock().unwrap().excluded_apps = apps;
+    }
+
+    /// Replaces the extractor order. Every name must be one of
+    /// `EXTRACTOR_NAMES`, at most once.
+    pub fn set_extractor_chain(&self, chain: Vec<String>) -> Result<(), SnapMagError> {
+        validate_extractor_chain(&chain)?;
+        self.config.lock().unwrap().extractor_chain = chain;
+        Ok(())
+    }
+
+    pub fn config(&self) -> ClipboardListenerConfig {
+        self.config.lock().unwrap().clone()
+    }
+
+    /// Replaces the whole configuration, applying the thread priority
+    /// immediately. Custom formats are registered on the next poll.
+    pub fn set_config(&self, config: ClipboardListenerConfig) {
+        let priority = config.thread_priority;
+        *self.config.lock().unwrap() = config;
+        self.set_thread_priority(priority);
+    }
+
+    pub fn set_capture_text(&self, enabled: bool, min_chars: Option<usize>) {
+        let mut config = self.config.lock().unwrap();
+        config.capture_text = enabled;
+        if let Some(min_chars) = min_chars {
+            config.min_snippet_chars = min_chars;
+        }
+    }
+
+    pub fn set_capture_window_title(&self, enabled: bool) {
+        self.config.lock().unwrap().capture_window_title = enabled;
+    }
+
+    pub fn set_fullscreen_quiet(&self, enabled: bool, pause_capture: bool) {
+        let mut config = self.config.lock().unwrap();
+        config.quiet_when_fullscreen = enabled;
+        config.pause_capture_when_fullscreen = pause_capture;
+    }
+
+    pub fn set_capture_size_limits(&self, max_decode_pixels: u64, max_clipboard_bytes: u64) {
+        let mut config = self.config.lock().unwrap();
+        config.max_decode_pixels = max_decode_pixels;
+        config.max_clipboard_bytes = max_clipboard_bytes;
+    }
+
+    pub fn set_keep_raw_capture(&self, enabled: bool) {
+        self.config.lock().unwrap().keep_raw_capture = enabled;
+    }
+
+    pub fn set_emit_legacy_clipboard_update(&self, enabled: bool) {
+        self.config.lock().unwrap().emit_legacy_clipboard_update = enabled;
+    }
+
+    pub fn set_notify_on_non_image(&self, enabled: bool) {
+        self.config.lock().unwrap().notify_on_non_image = enabled;
+    }
+
+    /// Adds or replaces an app-specific clipboard format. It's registered
+    /// with Windows on the listener's next poll.
+    pub fn add_custom_clipboard_format(&self, name: &str, extractor: CustomFormatExtractor) -> Result<(), SnapMagError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(SnapMagError::InvalidArgument("clipboard format name must not be empty".to_string()));
+        }
+        self.config.lock().unwrap().custom_clipboard_formats.insert(name.to_string(), extractor);
+        Ok(())
+    }
+
+    /// Decodes the image currently on the clipboard the same way the
+    /// listener would, without saving it. `None` when there is no image or
+    /// the clipboard stays busy.
+    #[cfg(target_os = "windows")]
+    pub fn read_clipboard_image(&self) -> Option<Vec<u8>> {
+        use windows::Win32::System::DataExchange::{OpenClipboard, CloseClipboard};
+        
+        let config = self.config.lock().unwrap().clone();
+        let mut format_ids = HashMap::new();
+        Self::register_formats(&config, &mut format_ids);
+        
+        unsafe {
+            // The listener thread may have the clipboard open for a moment.
+            let mut opened = false;
+            for _ in 0..5 {
+                if OpenClipboard(None).is_ok() {
+                    opened = true;
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            if !opened {
+                error!("Failed to open clipboard");
+                return None;
+            }
+            let image = Self::extract_clipboard_image(&config, &format_ids);
+            let _ = CloseClipboard();
+            image.data
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    pub fn read_clipboard_image(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn get_stats(&self) -> ClipboardStats {
+        let started_at = self.stats.started_at.load(Ordering::Relaxed);
+        let last_capture_at = self.stats.last_capture_at.load(Ordering::Relaxed);
+        ClipboardStats {
+            iterations: self.stats.iterations.load(Ordering::Relaxed),
+            images_captured: self.stats.images_captured.load(Ordering::Relaxed),
+            duplicates_skipped: self.stats.duplicates_skipped.load(Ordering::Relaxed),
+            errors: self.stats.errors.load(Ordering::Relaxed),
+            files_scanned: self.stats.files_scanned.load(Ordering::Relaxed),
+            last_capture_at: (last_capture_at > 0).then_some(last_capture_at as i64),
+            uptime_seconds: if started_at > 0 { unix_seconds().saturating_sub(started_at) } else { 0 },
+            quiet: self.stats.quiet.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Zeroes the counters. Uptime keeps counting from when the listener started.
+    pub fn reset_stats(&self) {
+        self.stats.iterations.store(0, Ordering::Relaxed);
+        self.stats.images_captured.store(0, Ordering::Relaxed);
+        self.stats.duplicates_skipped.store(0, Ordering::Relaxed);
+        self.stats.errors.store(0, Ordering::Relaxed);
+        self.stats.files_scanned.store(0, Ordering::Relaxed);
+        self.stats.last_capture_at.store(0, Ordering::Relaxed);
+    }
+
+    /// Saves the capture dropped by the cooldown, if one is stashed. It also
+    /// becomes the last seen image so the listener doesn't capture it again.
+    pub fn recover_skipped_capture(&self) -> anyhow::Result<Option<ImageMetadata>> {
+        let Some(skipped) = self.skipped_capture.lock().unwrap().take() else {
+            return Ok(None);
+        };
+        *self.last_hash.lock().unwrap() = Some(skipped.hash.clone());
+        
+        crate::image_manager::validate_image(&skipped.data)?;
+        let saved = self.image_manager.lock().unwrap().save_image_with_hash(&skipped.data, skipped.hash, skipped.source)?;
+        self.stats.files_scanned.fetch_add(saved.scanned_files as u64, Ordering::Relaxed);
+        let mut metadata = saved.metadata;
+        if !saved.is_duplicate {
+            self.stats.record_capture();
+            if let Some(tag) = skipped.tag {
+                metadata = self.image_manager.lock().unwrap().add_tag(&metadata.id, tag)?;
+            }
+            metadata = Self::with_eager_blurhash(&self.image_manager, metadata);
         }
+        info!("Recovered skipped capture: {}", metadata.path);
+        Ok(Some(metadata))
+    }
+
+    /// Captures the clipboard data last skipped as too large, ignoring the
+    /// size limits, if the clipboard still holds it. The decode runs on its
+    /// own thread; the image is announced through `images-added` and a
+    /// failure through `background-error`.
+    #[cfg(target_os = "windows")]
+    pub fn force_capture_last_skipped(&self) -> Result<(), SnapMagError> {
+        let skipped = self.too_large_skip
+            .lock()
+            .unwrap()
+            .ok_or_else(|| SnapMagError::NotFound("no capture was skipped for its size".to_string()))?;
+        if skipped.handled {
+            return Err(SnapMagError::InvalidArgument("the skipped capture was already saved".to_string()));
+        }
+        let config = self.config.lock().unwrap().clone();
+        
+        let handle = self.handle.clone();
+        let image_manager = self.image_manager.clone();
+        let last_hash = self.last_hash.clone();
+        let stats = self.stats.clone();
+        let too_large_skip = self.too_large_skip.clone();
+        thread::spawn(move || {
+            let result = Self::capture_skipped(&config, skipped, &image_manager, &last_hash, &stats);
+            let app = handle.lock().unwrap().clone();
+            match result {
+                Ok(metadata) => {
+                    if let Some(current) = too_large_skip.lock().unwrap().as_mut().filter(|current| current.sequence == skipped.sequence) {
+                        current.handled = true;
+                    }
+                    info!("Force-captured oversized clipboard image: {}", metadata.path);
+                    if let Some(app) = app {
+                        let items = vec![crate::to_list_item(metadata)];
+                        if let Err(e) = app.emit("images-added", ImagesAddedEvent { items }) {
+                            error!("Failed to emit images-added event: {}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to force-capture skipped clipboard image: {}", e);
+                    if let Some(app) = app {
+                        crate::diagnostics::report(&app, "clipboard", "force_capture_failed", e.to_string());
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    pub fn force_capture_last_skipped(&self) -> Result<(), SnapMagError> {
+        Err(SnapMagError::Unsupported("clipboard capture".to_string()))
+    }
+
+    /// Copies the skipped format off the clipboard if it still holds the
+    /// skipped change, then decodes and saves it with the clipboard closed.
+    #[cfg(target_os = "windows")]
+    fn capture_skipped(
+        config: &ClipboardListenerConfig,
+        skipped: TooLargeSkip,
+        image_manager: &Mutex<ImageManager>,
+        last_hash: &Mutex<Option<String>>,
+        stats: &ListenerStats,
+    ) -> anyhow::Result<ImageMetadata> {
+        use windows::Win32::System::DataExchange::{CloseClipboard, GetClipboardData, GetClipboardSequenceNumber, OpenClipboard};
+        
+        // CF_BITMAP is a GDI handle; Windows synthesizes CF_DIB from it.
+        let format_id = if skipped.format_id == CF_BITMAP { CF_DIB } else { skipped.format_id };
+        let copied = unsafe {
+            let mut opened = false;
+            for _ in 0..10 {
+                if OpenClipboard(None).is_ok() {
+                    opened = true;
+                    break;
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            if !opened {
+                return Err(anyhow::anyhow!("Failed to open clipboard"));
+            }
+            let copied = (GetClipboardSequenceNumber() == skipped.sequence).then(|| {
+                GetClipboardData(format_id)
+                    .ok()
+                    .and_then(|handle| with_global_bytes(handle, |bytes| Some(bytes.to_vec())))
+            });
+            let _ = CloseClipboard();
+            copied
+        };
+        let bytes = copied
+            .ok_or_else(|| SnapMagError::InvalidArgument("the clipboard changed since the capture was skipped".to_string()))?
+            .ok_or_else(|| anyhow::anyhow!("Failed to copy clipboard data"))?;
+        
+        let mut format_ids = HashMap::new();
+        Self::register_formats(config, &mut format_ids);
+        let chain = ClipboardExtractorChain::from_config(config, &format_ids, crate::dib::FORCED_MAX_DIMENSION);
+        let extracted = chain.extractors
+            .iter()
+            .find(|extractor| extractor.format_id() == format_id)
+            .and_then(|extractor| extractor.decode(&bytes))
+            .ok_or_else(|| SnapMagError::ImageDecodeError("clipboard data did not decode".to_string()))?;
+        let data = extracted.data;
+        
+        let hash = crate::image_manager::content_hash(&data);
+        *last_hash.lock().unwrap() = Some(hash.clone());
+        crate::image_manager::validate_image(&data)?;
+        let saved = image_manager.lock().unwrap().save_image_with_hash(&data, hash, extracted.source)?;
+        stats.files_scanned.fetch_add(saved.scanned_files as u64, Ordering::Relaxed);
+        if saved.is_duplicate {
+            return Ok(saved.metadata);
+        }
+        stats.record_capture();
+        Ok(Self::with_eager_blurhash(image_manager, saved.metadata))
+    }
+
+    /// Adds the BlurHash of new capture `metadata` when `eager_blurhash` is
+    /// on, decoding the image with the manager unlocked.
+    fn with_eager_blurhash(image_manager: &Mutex<ImageManager>, metadata: ImageMetadata) -> ImageMetadata {
+        if metadata.blurhash.is_some() || !image_manager.lock().unwrap().config().eager_blurhash {
+            return metadata;
+        }
+        let Some(blurhash) = crate::image_manager::compute_blurhash(std::path::Path::new(&metadata.path)) else {
+            return metadata;
+        };
+        let cached = image_manager.lock().unwrap().cache_blurhash(&metadata.id, &metadata.path, blurhash);
+        cached.unwrap_or(metadata)
+    }
+
+    /// Clipboard reads that offered an image but yielded none, newest first.
+    pub fn get_recent_errors(&self) -> Vec<ClipboardError> {
+        self.recent_errors.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    pub fn clear_recent_errors(&self) {
+        self.recent_errors.lock().unwrap().clear();
+    }
+
+    /// Keeps the listener from capturing what the app itself just put on
+    /// the clipboard, identified by its sequence number after the write.
+    pub fn ignore_own_write(&self, sequence: u32) {
+        *self.own_sequence.lock().unwrap() = Some(sequence);
     }
 
     pub fn reset_hash(&self) {
@@ -33,6 +1141,7 @@ impl ClipboardListener {
         info!("Clipboard listener hash reset");
     }
 
+    #[cfg(target_os = "windows")]
     pub fn start(&mut self, app_handle: AppHandle) {
         *self.handle.lock().unwrap() = Some(app_handle.clone());
         *self.running.lock().unwrap() = true;
@@ -44,120 +1153,201 @@ impl ClipboardListener {
         let image_manager = self.image_manager.clone();
         let last_hash = self.last_hash.clone();
         let last_detection_time = self.last_detection_time.clone();
+        let config = self.config.clone();
+        let stats = self.stats.clone();
+        let skipped_capture = self.skipped_capture.clone();
+        let snippets = self.snippets.clone();
+        let too_large_skip = self.too_large_skip.clone();
+        let recent_errors = self.recent_errors.clone();
+        let own_sequence = self.own_sequence.clone();
+        let capture_log = self.capture_log.clone();
+        stats.started_at.store(unix_seconds(), Ordering::Relaxed);
         
-        thread::spawn(move || {
-            Self::listen_loop(handle, running, image_manager, last_hash, last_detection_time);
-        });
+        self.thread = Some(thread::spawn(move || {
+            let priority = config.lock().unwrap().thread_priority;
+            Self::apply_thread_priority(unsafe { windows::Win32::System::Threading::GetCurrentThread() }, priority);
+            Self::listen_loop(handle, running, image_manager, last_hash, last_detection_time, config, stats, skipped_capture, snippets, too_large_skip, recent_errors, own_sequence, capture_log);
+        }));
+    }
+
+    /// Clipboard capture is Windows-only; elsewhere the listener keeps the
+    /// app handle but never runs, so no capture events are sent.
+    #[cfg(not(target_os = "windows"))]
+    pub fn start(&mut self, app_handle: AppHandle) {
+        *self.handle.lock().unwrap() = Some(app_handle);
+        log::warn!("Clipboard capture is not supported on this platform; the listener is not started");
+    }
+
+    /// Whether `start` actually watches the clipboard on this platform.
+    pub fn is_capture_supported() -> bool {
+        cfg!(target_os = "windows")
+    }
+
+    /// Stops the listener thread and waits for it to exit, which flushes
+    /// captures still waiting for their `images-added` event.
+    pub fn stop(&mut self) {
+        *self.running.lock().unwrap() = false;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Clipboard listener thread panicked");
+            }
+        }
+        info!("Clipboard listener stopped");
+    }
+
+    #[cfg(target_os = "windows")]
+    fn apply_thread_priority(thread: windows::Win32::Foundation::HANDLE, priority: ThreadPriority) {
+        use windows::Win32::System::Threading::{
+            SetThreadPriority, THREAD_PRIORITY_ABOVE_NORMAL, THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_IDLE, THREAD_PRIORITY_NORMAL,
+        };
+        
+        let level = match priority {
+            ThreadPriority::AboveNormal => THREAD_PRIORITY_ABOVE_NORMAL,
+            ThreadPriority::Normal => THREAD_PRIORITY_NORMAL,
+            ThreadPriority::BelowNormal => THREAD_PRIORITY_BELOW_NORMAL,
+            ThreadPriority::Idle => THREAD_PRIORITY_IDLE,
+        };
+        match unsafe { SetThreadPriority(thread, level) } {
+            Ok(()) => info!("Clipboard listener thread priority set to {:?}", priority),
+            Err(e) => error!("Failed to set clipboard listener thread priority: {}", e),
+        }
     }
 
     #[cfg(target_os = "windows")]
+    #[allow(clippy::too_many_arguments)]
     fn listen_loop(
         handle: Arc<Mutex<Option<AppHandle>>>,
         running: Arc<Mutex<bool>>,
         image_manager: Arc<Mutex<ImageManager>>,
         last_hash: Arc<Mutex<Option<String>>>,
         last_detection_time: Arc<Mutex<u64>>,
+        config: Arc<Mutex<ClipboardListenerConfig>>,
+        stats: Arc<ListenerStats>,
+        skipped_capture: Arc<Mutex<Option<SkippedCapture>>>,
+        snippets: Arc<Mutex<SnippetStore>>,
+        too_large_skip: Arc<Mutex<Option<TooLargeSkip>>>,
+        recent_errors: Arc<Mutex<VecDeque<ClipboardError>>>,
+        own_sequence: Arc<Mutex<Option<u32>>>,
+        capture_log: Arc<Mutex<Option<CaptureLog>>>,
     ) {
-        use windows::Win32::System::DataExchange::{OpenClipboard, CloseClipboard, GetClipboardData, EnumClipboardFormats, CountClipboardFormats};
-        use windows::Win32::UI::Shell::HDROP;
-        
-        const CF_DIB: u32 = 8;
-        const CF_DIBV5: u32 = 17;
-        const CF_BITMAP: u32 = 2;
-        const CF_HDROP: u32 = 15;
-        
         info!("Clipboard listener loop started");
+        #[cfg(feature = "winrt-clipboard")]
+        if let Err(e) = unsafe { windows::Win32::System::WinRT::RoInitialize(windows::Win32::System::WinRT::RO_INIT_MULTITHREADED) } {
+            error!("Failed to initialize WinRT on the clipboard thread: {}", e);
+        }
+        
+        let mut last_skipped_sequence = 0;
+        let mut last_snippet_sequence = 0;
+        let mut last_error_sequence = 0;
+        let mut last_non_image_sequence = 0;
+        let mut paused_sequence = None;
+        let mut format_ids: HashMap<String, u32> = HashMap::new();
+        let mut added_images = AddedImagesBatch::default();
         
         while *running.lock().unwrap() {
             thread::sleep(Duration::from_millis(200));
+            stats.iterations.fetch_add(1, Ordering::Relaxed);
+            Self::flush_added_images(&handle, &mut added_images, false);
+            
+            let config_snapshot = config.lock().unwrap().clone();
+            Self::register_formats(&config_snapshot, &mut format_ids);
+            
+            // Checked once a second; the shell call is cheap but not free.
+            if stats.iterations.load(Ordering::Relaxed) % 5 == 0 {
+                let quiet = config_snapshot.quiet_when_fullscreen && unsafe { Self::fullscreen_app_active() };
+                if stats.quiet.swap(quiet, Ordering::Relaxed) != quiet {
+                    info!("Quiet mode {}", if quiet { "on: a full-screen app is active" } else { "off" });
+                    if let Some(handle) = handle.lock().unwrap().as_ref() {
+                        let event = QuietModeEvent { quiet, capture_paused: quiet && config_snapshot.pause_capture_when_fullscreen };
+                        if let Err(e) = handle.emit("quiet-mode-changed", event) {
+                            error!("Failed to emit quiet-mode-changed event: {}", e);
+                        }
+                    }
+                }
+            }
+            if stats.quiet.load(Ordering::Relaxed) && config_snapshot.pause_capture_when_fullscreen {
+                paused_sequence = Some(unsafe { windows::Win32::System::DataExchange::GetClipboardSequenceNumber() });
+                continue;
+            }
             
             unsafe {
-                debug!("Attempting to open clipboard");
-                if OpenClipboard(None).is_ok() {
-                    debug!("Clipboard opened successfully");
-                    
-                    let format_count = CountClipboardFormats();
-                    debug!("Clipboard contains {} format(s)", format_count);
-                    
-                    let mut formats = Vec::new();
-                    let mut format = EnumClipboardFormats(0);
-                    while format != 0 {
-                        formats.push(format);
-                        debug!("Found clipboard format: {}", format);
-                        format = EnumClipboardFormats(format);
+                if let Some((image, owner_app, sequence)) = Self::read_clipboard(&config_snapshot, &format_ids) {
+                    // Whatever was copied during the pause stays uncaptured.
+                    if paused_sequence == Some(sequence) {
+                        continue;
+                    }
+                    paused_sequence = None;
+                    if *own_sequence.lock().unwrap() == Some(sequence) {
+                        continue;
                     }
-                    debug!("Available formats: {:?}", formats);
+                    let ClipboardImage { data: image_data, source, tag: capture_tag, raw, dropped_folders, text, too_large, extraction_errors, formats } = image;
                     
-                    let mut image_data = None;
+                    if !extraction_errors.is_empty() && sequence != last_error_sequence {
+                        last_error_sequence = sequence;
+                        Self::record_extraction_errors(&handle, &recent_errors, extraction_errors);
+                    }
                     
-                    if formats.contains(&CF_HDROP) {
-                        debug!("Found CF_HDROP format (file copy)");
-                        if let Ok(hdrop_handle) = GetClipboardData(CF_HDROP) {
-                            debug!("Processing file drop handle: {:?}", hdrop_handle);
-                            image_data = Self::extract_image_from_files(HDROP(hdrop_handle.0));
-                            if image_data.is_some() {
-                                debug!("Successfully extracted image from file drop");
-                            } else {
-                                debug!("No image found in file drop");
+                    if let Some(too_large) = too_large {
+                        let mut skipped = too_large_skip.lock().unwrap();
+                        if skipped.map(|skipped| skipped.sequence) != Some(sequence) {
+                            *skipped = Some(TooLargeSkip { sequence, format_id: too_large.format_id, handled: false });
+                            log::warn!("Skipping {} clipboard data of {} bytes, {:?} pixels: over the size limits", too_large.format, too_large.bytes, too_large.pixels);
+                            if let Some(handle) = handle.lock().unwrap().as_ref() {
+                                let event = CaptureSkippedEvent {
+                                    reason: "too-large".to_string(),
+                                    format: Some(too_large.format),
+                                    bytes: Some(too_large.bytes),
+                                    pixels: too_large.pixels,
+                                };
+                                if let Err(e) = handle.emit("capture-skipped", event) {
+                                    error!("Failed to emit capture-skipped event: {}", e);
+                                }
                             }
-                        } else {
-                            debug!("Failed to get CF_HDROP data");
                         }
+                        continue;
                     }
+                    let context_title = if config_snapshot.capture_window_title && image_data.is_some() {
+                        Self::foreground_window_title()
+                    } else {
+                        None
+                    };
                     
-                    if image_data.is_none() && formats.contains(&CF_DIBV5) {
-                        debug!("Found CF_DIBV5 format (likely screenshot)");
-                        if let Ok(dib_handle) = GetClipboardData(CF_DIBV5) {
-                            let dib_ptr = dib_handle.0 as *const u8;
-                            debug!("CF_DIBV5 data handle: {:?}", dib_handle);
-                            image_data = Self::extract_image_from_dibv5(dib_ptr);
-                            if image_data.is_some() {
-                                debug!("Successfully extracted image from CF_DIBV5");
-                            } else {
-                                debug!("Failed to extract image from CF_DIBV5");
+                    if image_data.is_some() && !config.lock().unwrap().allows_app(owner_app.as_deref()) {
+                        if sequence != last_skipped_sequence {
+                            last_skipped_sequence = sequence;
+                            let app = owner_app.unwrap_or_else(|| "unknown".to_string());
+                            info!("Skipping clipboard image from filtered app: {}", app);
+                            if let Some(handle) = handle.lock().unwrap().as_ref() {
+                                if let Err(e) = handle.emit("clipboard-capture-skipped", ClipboardCaptureSkippedEvent { app }) {
+                                    error!("Failed to emit clipboard-capture-skipped event: {}", e);
+                                }
                             }
-                        } else {
-                            debug!("Failed to get CF_DIBV5 data");
                         }
+                        continue;
                     }
                     
-                    if image_data.is_none() && formats.contains(&CF_DIB) {
-                        debug!("Found CF_DIB format");
-                        if let Ok(dib_handle) = GetClipboardData(CF_DIB) {
-                            let dib_ptr = dib_handle.0 as *const u8;
-                            debug!("CF_DIB data handle: {:?}", dib_handle);
-                            image_data = Self::extract_image_from_dib(dib_ptr);
-                            if image_data.is_some() {
-                                debug!("Successfully extracted image from CF_DIB");
-                            } else {
-                                debug!("Failed to extract image from CF_DIB");
-                            }
-                        } else {
-                            debug!("Failed to get CF_DIB data");
-                        }
+                    if image_data.is_none() && config_snapshot.notify_on_non_image && !formats.is_empty() && sequence != last_non_image_sequence {
+                        last_non_image_sequence = sequence;
+                        Self::announce_non_image(&handle, formats);
                     }
                     
-                    if image_data.is_none() && formats.contains(&CF_BITMAP) {
-                        debug!("Found CF_BITMAP format");
-                        if let Ok(bitmap_handle) = GetClipboardData(CF_BITMAP) {
-                            debug!("CF_BITMAP data handle: {:?}", bitmap_handle);
-                            image_data = Self::extract_image_from_bitmap(bitmap_handle.0 as isize);
-                            if image_data.is_some() {
-                                debug!("Successfully extracted image from CF_BITMAP");
-                            } else {
-                                debug!("Failed to extract image from CF_BITMAP");
-                            }
-                        } else {
-                            debug!("Failed to get CF_BITMAP data");
-                        }
+                    if image_data.is_none() && !dropped_folders.is_empty() {
+                        Self::import_dropped_folders(&dropped_folders, &handle, &image_manager, &last_hash, &stats, &config_snapshot, &capture_log, owner_app.as_deref(), &mut added_images);
+                        continue;
                     }
                     
-                    let _ = CloseClipboard();
+                    if let Some(text) = text.filter(|_| sequence != last_snippet_sequence) {
+                        last_snippet_sequence = sequence;
+                        if config_snapshot.allows_app(owner_app.as_deref()) {
+                            Self::capture_snippet(&handle, &snippets, &config_snapshot, &text, owner_app);
+                        }
+                        continue;
+                    }
                     
                     if let Some(data) = image_data {
                         debug!("Successfully extracted image data, size: {} bytes", data.len());
                         
-                        let hash = Self::calculate_hash(&data);
+                        let hash = crate::image_manager::content_hash(&data);
                         debug!("Calculated image hash: {}", hash);
                         
                         let now = SystemTime::now()
@@ -168,6 +1358,10 @@ impl ClipboardListener {
                         let mut last_detection = last_detection_time.lock().unwrap();
                         if now < *last_detection + CLIPBOARD_COOLDOWN_MS {
                             debug!("Within cooldown window ({}ms), ignoring detection", CLIPBOARD_COOLDOWN_MS);
+                            drop(last_detection);
+                            if last_hash.lock().unwrap().as_deref() != Some(hash.as_str()) {
+                                Self::stash_skipped_capture(&skipped_capture, &handle, SkippedCapture { data, hash, source, tag: capture_tag });
+                            }
                             continue;
                         }
                         
@@ -180,26 +1374,73 @@ impl ClipboardListener {
                             drop(last);
                             drop(last_detection);
                             
-                            match image_manager.lock().unwrap().save_image(&data) {
-                                Ok((metadata, is_duplicate)) => {
-                                    if is_duplicate {
+                            let (save_result, evicted_ids) = match crate::image_manager::validate_image(&data) {
+                                Ok(()) => {
+                                    let mut manager = image_manager.lock().unwrap();
+                                    let result = manager.save_image_with_hash(&data, hash.clone(), source);
+                                    (result, manager.take_evicted_ids())
+                                }
+                                Err(e) => (Err(e.into()), Vec::new()),
+                            };
+                            if !evicted_ids.is_empty() {
+                                if let Some(handle) = handle.lock().unwrap().as_ref() {
+                                    if let Err(e) = handle.emit("images-removed", ImagesRemovedEvent { ids: evicted_ids }) {
+                                        error!("Failed to emit images-removed event: {}", e);
+                                    }
+                                }
+                            }
+                            {
+                                let mut skipped = skipped_capture.lock().unwrap();
+                                if skipped.as_ref().is_some_and(|skipped| skipped.hash == hash) {
+                                    *skipped = None;
+                                }
+                            }
+                            match save_result {
+                                Ok(saved) => {
+                                    stats.files_scanned.fetch_add(saved.scanned_files as u64, Ordering::Relaxed);
+                                    let metadata = saved.metadata;
+                                    if saved.is_duplicate {
+                                        stats.duplicates_skipped.fetch_add(1, Ordering::Relaxed);
                                         debug!("Duplicate image detected (hash: {}), not emitting event", hash);
                                     } else {
-                                        info!("New image saved to: {}", metadata.path);
-                                        let app_handle = handle.lock().unwrap();
-                                        if let Some(handle) = app_handle.as_ref() {
-                                            if let Err(e) = handle.emit("clipboard-update", ClipboardEvent {
-                                                image_path: metadata.path.clone(),
-                                            }) {
-                                                error!("Failed to emit clipboard-update event: {}", e);
-                                            } else {
-                                                debug!("Emitted clipboard-update event for: {}", metadata.path);
+                                        stats.record_capture();
+                                        let metadata = match raw {
+                                            Some(raw) => {
+                                                let result = image_manager.lock().unwrap().attach_raw_capture(&metadata.id, &raw);
+                                                result.unwrap_or_else(|e| {
+                                                    error!("Failed to keep raw capture of {}: {}", metadata.id, e);
+                                                    metadata
+                                                })
+                                            }
+                                            None => metadata,
+                                        };
+                                        let metadata = match context_title {
+                                            Some(title) => {
+                                                let result = image_manager.lock().unwrap().set_context_title(&metadata.id, Some(title));
+                                                result.unwrap_or_else(|e| {
+                                                    error!("Failed to record window title of {}: {}", metadata.id, e);
+                                                    metadata
+                                                })
+                                            }
+                                            None => metadata,
+                                        };
+                                        if let Some(tag) = capture_tag {
+                                            if let Err(e) = image_manager.lock().unwrap().add_tag(&metadata.id, tag) {
+                                                error!("Failed to tag image {}: {}", metadata.id, e);
                                             }
                                         }
+                                        let metadata = Self::with_eager_blurhash(&image_manager, metadata);
+                                        info!("New image saved to: {}", metadata.path);
+                                        Self::announce_capture(&handle, &config_snapshot, &capture_log, owner_app.as_deref(), &mut added_images, metadata);
                                     }
                                 }
+                                Err(e) if matches!(e.downcast_ref(), Some(SnapMagError::ImageDecodeError(_))) => {
+                                    log::warn!("Skipping clipboard data that isn't a complete image: {}", e);
+                                }
                                 Err(e) => {
+                                    stats.errors.fetch_add(1, Ordering::Relaxed);
                                     error!("Failed to save image: {}", e);
+                                    Self::report_save_failure(&handle, &e);
                                 }
                             };
                         } else {
@@ -213,410 +1454,899 @@ impl ClipboardListener {
                 }
             }
         }
-        
-        info!("Clipboard listener loop stopped");
+        
+        Self::flush_added_images(&handle, &mut added_images, true);
+        info!("Clipboard listener loop stopped");
+    }
+
+    /// Keeps `text` as a snippet unless it is too short or already kept, and
+    /// announces it through `snippet-captured`.
+    #[cfg(target_os = "windows")]
+    fn capture_snippet(
+        handle: &Arc<Mutex<Option<AppHandle>>>,
+        snippets: &Mutex<SnippetStore>,
+        config: &ClipboardListenerConfig,
+        text: &str,
+        source_app: Option<String>,
+    ) {
+        let result = snippets.lock().unwrap().add(text, source_app, config.min_snippet_chars, unix_seconds() as i64);
+        match result {
+            Ok(Some(snippet)) => {
+                info!("New text snippet captured ({} chars)", snippet.text.chars().count());
+                if let Some(handle) = handle.lock().unwrap().as_ref() {
+                    if let Err(e) = handle.emit("snippet-captured", snippet) {
+                        error!("Failed to emit snippet-captured event: {}", e);
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to save text snippet: {}", e),
+        }
+    }
+
+    /// Tells the frontend the clipboard changed to `formats`, none of which
+    /// held an image, through `clipboard-non-image`.
+    #[cfg(target_os = "windows")]
+    fn announce_non_image(handle: &Arc<Mutex<Option<AppHandle>>>, formats: Vec<u32>) {
+        debug!("Clipboard changed without an image: formats {:?}", formats);
+        let event = ClipboardNonImageEvent {
+            has_text: formats.iter().any(|format| matches!(*format, CF_UNICODETEXT | CF_TEXT | CF_OEMTEXT)),
+            has_files: formats.contains(&CF_HDROP),
+            formats,
+        };
+        if let Some(handle) = handle.lock().unwrap().as_ref() {
+            if let Err(e) = handle.emit("clipboard-non-image", event) {
+                error!("Failed to emit clipboard-non-image event: {}", e);
+            }
+        }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    fn listen_loop(
-        handle: Arc<Mutex<Option<AppHandle>>>,
-        running: Arc<Mutex<bool>>,
-        _image_manager: Arc<Mutex<ImageManager>>,
-        _last_hash: Arc<Mutex<Option<String>>>,
+    /// Queues a new capture for the next `images-added` event, also sending
+    /// the legacy `clipboard-update` right away when that's enabled, and
+    /// appends it to the capture log when one is open.
+    #[cfg(target_os = "windows")]
+    fn announce_capture(
+        handle: &Arc<Mutex<Option<AppHandle>>>,
+        config: &ClipboardListenerConfig,
+        capture_log: &Mutex<Option<CaptureLog>>,
+        source_app: Option<&str>,
+        added_images: &mut AddedImagesBatch,
+        metadata: ImageMetadata,
     ) {
-        while *running.lock().unwrap() {
-            thread::sleep(Duration::from_secs(1));
-            
-            if let Some(app_handle) = &*handle.lock().unwrap() {
-                let _ = app_handle.emit("clipboard-update", ClipboardEvent {
-                    image_path: String::new(),
-                });
+        if let Some(log) = capture_log.lock().unwrap().as_mut() {
+            if let Err(e) = log.record(&metadata, source_app) {
+                error!("Failed to write capture log {}: {}", log.path().display(), e);
+            }
+        }
+        if config.emit_legacy_clipboard_update {
+            if let Some(handle) = handle.lock().unwrap().as_ref() {
+                if let Err(e) = handle.emit("clipboard-update", ClipboardEvent {
+                    image_path: metadata.path.clone(),
+                }) {
+                    error!("Failed to emit clipboard-update event: {}", e);
+                } else {
+                    debug!("Emitted clipboard-update event for: {}", metadata.path);
+                }
             }
         }
+        added_images.push(metadata, Instant::now());
     }
 
     #[cfg(target_os = "windows")]
-    fn calculate_hash(data: &[u8]) -> String {
-        use sha2::{Sha256, Digest};
+    fn flush_added_images(handle: &Arc<Mutex<Option<AppHandle>>>, added_images: &mut AddedImagesBatch, force: bool) {
+        let Some(items) = added_images.take_due(Instant::now(), force) else {
+            return;
+        };
+        debug!("Emitting images-added for {} image(s)", items.len());
+        if let Some(handle) = handle.lock().unwrap().as_ref() {
+            let items = items.into_iter().map(crate::to_list_item).collect();
+            if let Err(e) = handle.emit("images-added", ImagesAddedEvent { items }) {
+                error!("Failed to emit images-added event: {}", e);
+            }
+        }
+    }
+
+    /// One pass over the clipboard for the listener: the image found, the
+    /// owning process and the clipboard sequence number. `None` when the
+    /// clipboard couldn't be opened.
+    #[cfg(all(target_os = "windows", not(feature = "winrt-clipboard")))]
+    unsafe fn read_clipboard(
+        config: &ClipboardListenerConfig,
+        format_ids: &HashMap<String, u32>,
+    ) -> Option<(ClipboardImage, Option<String>, u32)> {
+        use windows::Win32::System::DataExchange::{OpenClipboard, CloseClipboard, GetClipboardSequenceNumber};
         
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let result = hasher.finalize();
+        debug!("Attempting to open clipboard");
+        OpenClipboard(None).ok()?;
+        debug!("Clipboard opened successfully");
         
-        hex::encode(result)
+        let image = Self::extract_clipboard_image(config, format_ids);
+        let owner_app = Self::clipboard_owner_process();
+        let sequence = GetClipboardSequenceNumber();
+        let _ = CloseClipboard();
+        Some((image, owner_app, sequence))
+    }
+
+    /// `read_clipboard` through the WinRT `Clipboard` API, which negotiates
+    /// the bitmap format itself. It reads bitmaps, copied image files and
+    /// text; the extractor chain, custom formats, copied folders and raw
+    /// captures only apply to the Win32 path.
+    #[cfg(all(target_os = "windows", feature = "winrt-clipboard"))]
+    unsafe fn read_clipboard(
+        config: &ClipboardListenerConfig,
+        _format_ids: &HashMap<String, u32>,
+    ) -> Option<(ClipboardImage, Option<String>, u32)> {
+        use windows::Win32::System::DataExchange::GetClipboardSequenceNumber;
+        
+        let image = Self::extract_clipboard_image_winrt().unwrap_or_else(|e| {
+            debug!("Failed to read clipboard through WinRT: {}", e);
+            None
+        });
+        let (data, source) = image.map_or((None, CaptureSource::Screenshot), |(data, source)| (Some(data), source));
+        let text = if config.capture_text && data.is_none() {
+            Self::extract_clipboard_text_winrt().unwrap_or_else(|e| {
+                debug!("Failed to read clipboard text through WinRT: {}", e);
+                None
+            })
+        } else {
+            None
+        };
+        let image = ClipboardImage { data, source, tag: None, raw: None, dropped_folders: Vec::new(), text, too_large: None, extraction_errors: Vec::new(), formats: Vec::new() };
+        Some((image, Self::clipboard_owner_process(), GetClipboardSequenceNumber()))
     }
 
+    /// Size of the image one listener pass reads off the clipboard, through
+    /// whichever `read_clipboard` is built, with the default config. For the
+    /// benches.
     #[cfg(target_os = "windows")]
-    unsafe fn extract_image_from_dib(dib_ptr: *const u8) -> Option<Vec<u8>> {
-        use image::{ImageBuffer, Rgb, Rgba};
+    pub(crate) fn read_clipboard_image_len() -> Option<usize> {
+        let config = ClipboardListenerConfig::default();
+        let mut format_ids = HashMap::new();
+        Self::register_formats(&config, &mut format_ids);
+        let (image, ..) = unsafe { Self::read_clipboard(&config, &format_ids) }?;
+        image.data.map(|data| data.len())
+    }
+
+    #[cfg(all(target_os = "windows", feature = "winrt-clipboard"))]
+    fn extract_clipboard_image_winrt() -> windows::core::Result<Option<(Vec<u8>, CaptureSource)>> {
+        use windows::ApplicationModel::DataTransfer::{Clipboard, StandardDataFormats};
+        use windows::Storage::Streams::DataReader;
         
-        debug!("Starting DIB extraction from pointer: {:?}", dib_ptr);
+        let content = Clipboard::GetContent()?;
         
-        if dib_ptr.is_null() {
-            error!("DIB pointer is null");
-            return None;
+        if content.Contains(&StandardDataFormats::StorageItems()?)? {
+            for item in content.GetStorageItemsAsync()?.get()? {
+                let path = item.Path()?.to_string();
+                if !Self::has_image_extension(&path) {
+                    continue;
+                }
+                if let Some(data) = Self::read_image_file(&path) {
+                    let source = if Self::is_phone_sync_path(&path) { CaptureSource::Phone } else { CaptureSource::File };
+                    return Ok(Some((data, source)));
+                }
+            }
         }
         
-        let dib_header = dib_ptr as *const u32;
-        let bi_size = *dib_header as usize;
-        
-        debug!("DIB header size: {}", bi_size);
-        
-        if bi_size < 40 {
-            error!("Invalid DIB header size: {}", bi_size);
-            return None;
+        if content.Contains(&StandardDataFormats::Bitmap()?)? {
+            let stream = content.GetBitmapAsync()?.get()?.OpenReadAsync()?.get()?;
+            let size = u32::try_from(stream.Size()?).unwrap_or(u32::MAX);
+            let reader = DataReader::CreateDataReader(&stream.GetInputStreamAt(0)?)?;
+            let loaded = reader.LoadAsync(size)?.get()?;
+            let mut data = vec![0u8; loaded as usize];
+            reader.ReadBytes(&mut data)?;
+            debug!("Read {} byte bitmap through WinRT", data.len());
+            return Ok(Some((data, CaptureSource::Screenshot)));
         }
         
-        let bi_width = *dib_header.add(1) as i32;
-        let bi_height = *dib_header.add(2) as i32;
-        let bi_planes = *(dib_ptr.add(12) as *const u16);
-        let bi_bit_count = *(dib_ptr.add(14) as *const u16);
-        let bi_compression = *dib_header.add(4) as u32;
-        
-        debug!("DIB info - width: {}, height: {}, planes: {}, bit_count: {}, compression: {}", 
-              bi_width, bi_height, bi_planes, bi_bit_count, bi_compression);
+        Ok(None)
+    }
+
+    /// Copied text for a snippet, unless the content is marked private. The
+    /// value of `CanIncludeInClipboardHistory` isn't read through WinRT, so
+    /// the format being present at all keeps the text out.
+    #[cfg(all(target_os = "windows", feature = "winrt-clipboard"))]
+    fn extract_clipboard_text_winrt() -> windows::core::Result<Option<String>> {
+        use windows::ApplicationModel::DataTransfer::{Clipboard, StandardDataFormats};
+        use windows::core::HSTRING;
         
-        if bi_width <= 0 || bi_height == 0 || bi_bit_count == 0 || bi_width > 10000 || bi_height.abs() > 10000 {
-            error!("Invalid DIB parameters: width={}, height={}, bit_count={}", 
-                  bi_width, bi_height, bi_bit_count);
-            return None;
+        let content = Clipboard::GetContent()?;
+        for name in PRIVATE_FORMAT_NAMES.into_iter().chain([CAN_INCLUDE_IN_HISTORY_NAME]) {
+            if content.Contains(&HSTRING::from(name))? {
+                return Ok(None);
+            }
         }
-        
-        if bi_compression != 0 {
-            error!("Compressed DIB not supported: compression={}", bi_compression);
-            return None;
+        if !content.Contains(&StandardDataFormats::Text()?)? {
+            return Ok(None);
         }
+        Ok(Some(content.GetTextAsync()?.get()?.to_string()))
+    }
+
+    /// Whether the opened clipboard, holding `formats`, is marked private by
+    /// one of `PRIVATE_FORMAT_NAMES` or a `CanIncludeInClipboardHistory` of 0.
+    #[cfg(target_os = "windows")]
+    unsafe fn is_private_content(formats: &[u32], format_ids: &HashMap<String, u32>) -> bool {
+        use windows::Win32::System::DataExchange::GetClipboardData;
         
-        let abs_height = bi_height.abs();
-        let width = bi_width as u32;
-        let height = abs_height as u32;
-        
-        let bytes_per_pixel = (bi_bit_count as usize) / 8;
-        if bytes_per_pixel == 0 {
-            error!("Invalid bytes per pixel: {}", bytes_per_pixel);
-            return None;
+        let present = |name: &str| format_ids.get(name).copied().filter(|&id| id != 0 && formats.contains(&id));
+        if PRIVATE_FORMAT_NAMES.into_iter().any(|name| present(name).is_some()) {
+            return true;
         }
+        present(CAN_INCLUDE_IN_HISTORY_NAME).is_some_and(|id| {
+            GetClipboardData(id)
+                .ok()
+                .and_then(|handle| with_global_bytes(handle, |bytes| Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?))))
+                .is_some_and(|value| value == 0)
+        })
+    }
+
+    /// Pulls an image out of the already opened clipboard with the
+    /// configured extractor chain. `format_ids` holds the registered formats,
+    /// see `register_formats`.
+    #[cfg(target_os = "windows")]
+    unsafe fn extract_clipboard_image(
+        config: &ClipboardListenerConfig,
+        format_ids: &HashMap<String, u32>,
+    ) -> ClipboardImage {
+        use windows::Win32::System::DataExchange::{GetClipboardData, EnumClipboardFormats, CountClipboardFormats};
+        use windows::Win32::UI::Shell::HDROP;
         
-        let row_size = ((width as usize * bytes_per_pixel + 3) / 4) * 4;
-        let expected_data_size = row_size * height as usize;
-        
-        debug!("Image dimensions: {}x{}, bytes_per_pixel: {}, row_size: {}, expected_data_size: {}", 
-              width, height, bytes_per_pixel, row_size, expected_data_size);
+        let format_count = CountClipboardFormats();
+        debug!("Clipboard contains {} format(s)", format_count);
         
-        let pixel_data_offset = bi_size;
-        let pixel_data_ptr = dib_ptr.add(pixel_data_offset);
+        let mut formats = Vec::new();
+        let mut format = EnumClipboardFormats(0);
+        while format != 0 {
+            formats.push(format);
+            debug!("Found clipboard format: {}", format);
+            format = EnumClipboardFormats(format);
+        }
+        debug!("Available formats: {:?}", formats);
         
-        match bi_bit_count {
-            24 => {
-                let mut img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-                
-                debug!("Processing 24-bit DIB");
-                
-                for y in 0..height {
-                    let dib_y = if bi_height > 0 { height - 1 - y } else { y };
-                    let dib_row_offset = dib_y as usize * row_size;
-                    
-                    for x in 0..width {
-                        let dib_x = x as usize * 3;
-                        let pixel_offset = dib_row_offset + dib_x;
-                        
-                        let b = *pixel_data_ptr.add(pixel_offset);
-                        let g = *pixel_data_ptr.add(pixel_offset + 1);
-                        let r = *pixel_data_ptr.add(pixel_offset + 2);
-                        
-                        img.put_pixel(x, y, Rgb([r, g, b]));
-                    }
-                }
-                
-                let mut output = Vec::new();
-                if let Ok(()) = image::write_buffer_with_format(
-                    &mut std::io::Cursor::new(&mut output),
-                    img.as_raw(),
-                    width,
-                    height,
-                    image::ExtendedColorType::Rgb8,
-                    image::ImageFormat::Png,
-                ) {
-                    info!("Successfully converted DIB 24-bit to PNG, size: {} bytes", output.len());
-                    return Some(output);
-                } else {
-                    error!("Failed to write PNG buffer for DIB 24-bit");
+        let chain = ClipboardExtractorChain::from_config(config, format_ids, crate::dib::MAX_DIMENSION);
+        let mut extraction_errors = Vec::new();
+        let extracted = match chain.extract(&formats, &mut extraction_errors) {
+            Ok(extracted) => extracted,
+            Err(too_large) => {
+                return ClipboardImage {
+                    data: None,
+                    source: CaptureSource::Screenshot,
+                    tag: None,
+                    raw: None,
+                    dropped_folders: Vec::new(),
+                    text: None,
+                    too_large: Some(too_large),
+                    extraction_errors: Vec::new(),
+                    formats: Vec::new(),
+                };
+            }
+        };
+        let Some(extracted) = extracted else {
+            let mut dropped_folders = Vec::new();
+            if config.capture_dropped_folders && formats.contains(&CF_HDROP) {
+                if let Ok(hdrop_handle) = GetClipboardData(CF_HDROP) {
+                    dropped_folders = Self::dropped_paths(HDROP(hdrop_handle.0))
+                        .into_iter()
+                        .filter(|path| std::path::Path::new(path).is_dir())
+                        .collect();
                 }
             }
-            32 => {
-                let mut img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-                
-                debug!("Processing 32-bit DIB");
-                
-                for y in 0..height {
-                    let dib_y = if bi_height > 0 { height - 1 - y } else { y };
-                    let dib_row_offset = dib_y as usize * row_size;
-                    
-                    for x in 0..width {
-                        let dib_x = x as usize * 4;
-                        let pixel_offset = dib_row_offset + dib_x;
-                        
-                        let b = *pixel_data_ptr.add(pixel_offset);
-                        let g = *pixel_data_ptr.add(pixel_offset + 1);
-                        let r = *pixel_data_ptr.add(pixel_offset + 2);
-                        let a = *pixel_data_ptr.add(pixel_offset + 3);
-                        
-                        img.put_pixel(x, y, Rgba([r, g, b, a]));
-                    }
+            let text = if config.capture_text && formats.contains(&CF_UNICODETEXT) && !Self::is_private_content(&formats, format_ids) {
+                GetClipboardData(CF_UNICODETEXT)
+                    .ok()
+                    .and_then(|handle| with_global_bytes(handle, |bytes| Some(utf16_text(bytes))))
+            } else {
+                None
+            };
+            return ClipboardImage {
+                data: None,
+                source: CaptureSource::Screenshot,
+                tag: None,
+                raw: None,
+                dropped_folders,
+                text,
+                too_large: None,
+                extraction_errors,
+                formats,
+            };
+        };
+        
+        ClipboardImage {
+            data: Some(extracted.data),
+            source: extracted.source,
+            tag: extracted.tag,
+            raw: extracted.raw,
+            dropped_folders: Vec::new(),
+            text: None,
+            too_large: None,
+            extraction_errors: Vec::new(),
+            formats: Vec::new(),
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn record_extraction_errors(
+        handle: &Mutex<Option<AppHandle>>,
+        recent_errors: &Mutex<VecDeque<ClipboardError>>,
+        errors: Vec<ClipboardError>,
+    ) {
+        let mut recent = recent_errors.lock().unwrap();
+        for clipboard_error in errors {
+            error!("Clipboard extraction failed for format {}: {}", clipboard_error.format, clipboard_error.error_message);
+            if let Some(handle) = handle.lock().unwrap().as_ref() {
+                if let Err(e) = handle.emit("clipboard-extraction-error", &clipboard_error) {
+                    error!("Failed to emit clipboard-extraction-error event: {}", e);
                 }
-                
-                let mut output = Vec::new();
-                if let Ok(()) = image::write_buffer_with_format(
-                    &mut std::io::Cursor::new(&mut output),
-                    img.as_raw(),
-                    width,
-                    height,
-                    image::ExtendedColorType::Rgba8,
-                    image::ImageFormat::Png,
-                ) {
-                    info!("Successfully converted DIB 32-bit to PNG, size: {} bytes", output.len());
-                    return Some(output);
+            }
+            if recent.len() == MAX_RECENT_ERRORS {
+                recent.pop_front();
+            }
+            recent.push_back(clipboard_error);
+        }
+    }
+
+    /// Registers the named clipboard formats the extractors and the private
+    /// content check use (GIF, PNG, `PRIVATE_FORMAT_NAMES` and the configured
+    /// custom formats) that aren't in `ids` yet, recording 0 for names
+    /// Windows refused.
+    #[cfg(target_os = "windows")]
+    fn register_formats(config: &ClipboardListenerConfig, ids: &mut HashMap<String, u32>) {
+        use windows::Win32::System::DataExchange::RegisterClipboardFormatW;
+        
+        let names = [CF_GIF_NAME, CF_PNG_NAME, CAN_INCLUDE_IN_HISTORY_NAME]
+            .into_iter()
+            .chain(PRIVATE_FORMAT_NAMES)
+            .chain(config.custom_clipboard_formats.keys().map(String::as_str));
+        for name in names {
+            if !ids.contains_key(name) {
+                let wide: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+                let id = unsafe { RegisterClipboardFormatW(windows::core::PCWSTR(wide.as_ptr())) };
+                if id == 0 {
+                    error!("Failed to register clipboard format: {}", name);
                 } else {
-                    error!("Failed to write PNG buffer for DIB 32-bit");
+                    debug!("Registered clipboard format {}: {}", name, id);
                 }
+                ids.insert(name.to_string(), id);
             }
-            _ => {
-                error!("Unsupported bit count: {}", bi_bit_count);
+        }
+    }
+
+    /// Keeps `capture` as the one recoverable skipped capture and tells the
+    /// frontend, unless the same image is already stashed.
+    #[cfg(target_os = "windows")]
+    fn stash_skipped_capture(
+        skipped_capture: &Arc<Mutex<Option<SkippedCapture>>>,
+        handle: &Arc<Mutex<Option<AppHandle>>>,
+        capture: SkippedCapture,
+    ) {
+        {
+            let mut skipped = skipped_capture.lock().unwrap();
+            if skipped.as_ref().is_some_and(|skipped| skipped.hash == capture.hash) {
+                return;
             }
+            info!("Stashing image skipped by the cooldown (hash: {})", capture.hash);
+            *skipped = Some(capture);
         }
         
-        None
+        if let Some(handle) = handle.lock().unwrap().as_ref() {
+            if let Err(e) = handle.emit("capture-skipped", CaptureSkippedEvent { reason: "cooldown".to_string(), format: None, bytes: None, pixels: None }) {
+                error!("Failed to emit capture-skipped event: {}", e);
+            }
+        }
     }
 
     #[cfg(target_os = "windows")]
-    unsafe fn extract_image_from_dibv5(dib_ptr: *const u8) -> Option<Vec<u8>> {
-        use image::{ImageBuffer, Rgb, Rgba};
+    fn extract_image_from_dib(data: &[u8], max_dimension: u32) -> Option<Vec<u8>> {
+        debug!("Starting DIB extraction, size: {} bytes", data.len());
         
-        debug!("Starting DIBV5 extraction from pointer: {:?}", dib_ptr);
+        match crate::dib::decode_to_png(data, crate::dib::BITMAPINFOHEADER_SIZE, max_dimension) {
+            Ok(output) => {
+                debug!("Successfully converted DIB to PNG, size: {} bytes", output.len());
+                Some(output)
+            }
+            Err(e) => {
+                error!("Invalid DIB: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    pub(crate) fn extract_image_from_dibv5(data: &[u8], max_dimension: u32) -> Option<Vec<u8>> {
+        debug!("Starting DIBV5 extraction, size: {} bytes", data.len());
         
-        if dib_ptr.is_null() {
-            error!("DIBV5 pointer is null");
+        match crate::dib::decode_to_png(data, crate::dib::BITMAPV5HEADER_SIZE, max_dimension) {
+            Ok(output) => {
+                debug!("Successfully converted DIBV5 to PNG, size: {} bytes", output.len());
+                Some(output)
+            }
+            Err(e) => {
+                error!("Invalid DIBV5: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Decodes a GIF from the registered "GIF" clipboard format into PNG bytes.
+    /// Animated GIFs yield their first frame.
+    #[cfg(target_os = "windows")]
+    unsafe fn extract_image_from_gif(ptr: *const u8, size: usize) -> Option<Vec<u8>> {
+        if ptr.is_null() || size == 0 {
+            error!("GIF data is empty");
             return None;
         }
         
-        let dib_header = dib_ptr as *const u32;
-        let bi_size = *dib_header as usize;
-        
-        debug!("DIBV5 header size: {}", bi_size);
+        let gif_bytes = std::slice::from_raw_parts(ptr, size);
+        let img = match image::load_from_memory_with_format(gif_bytes, image::ImageFormat::Gif) {
+            Ok(img) => img,
+            Err(e) => {
+                error!("Failed to decode GIF: {}", e);
+                return None;
+            }
+        };
         
-        if bi_size < 124 {
-            error!("Invalid DIBV5 header size: {}", bi_size);
+        let mut png_data = Vec::new();
+        if let Err(e) = img.write_with_encoder(crate::image_ops::png_encoder(&mut png_data)) {
+            error!("Failed to write PNG buffer for GIF: {}", e);
             return None;
         }
+        debug!("Successfully converted GIF to PNG, size: {} bytes", png_data.len());
+        Some(png_data)
+    }
+
+    #[cfg(target_os = "windows")]
+    fn gif_is_animated(data: &[u8]) -> bool {
+        let mut options = gif::DecodeOptions::new();
+        options.skip_frame_decoding(true);
+        let Ok(mut decoder) = options.read_info(std::io::Cursor::new(data)) else {
+            return false;
+        };
         
-        let bi_width = *dib_header.add(1) as i32;
-        let bi_height = *dib_header.add(2) as i32;
-        let bi_planes = *(dib_ptr.add(12) as *const u16);
-        let bi_bit_count = *(dib_ptr.add(14) as *const u16);
-        let bi_compression = *dib_header.add(4) as u32;
-        
-        debug!("DIBV5 info - width: {}, height: {}, planes: {}, bit_count: {}, compression: {}", 
-              bi_width, bi_height, bi_planes, bi_bit_count, bi_compression);
-        
-        // 验证参数合理性
-        if bi_width <= 0 || bi_height == 0 || bi_bit_count == 0 || bi_width > 10000 || bi_height.abs() > 10000 {
-            error!("Invalid DIBV5 parameters: width={}, height={}, bit_count={}", 
-                  bi_width, bi_height, bi_bit_count);
-            return None;
+        let mut frame_count = 0;
+        while let Ok(Some(_)) = decoder.next_frame_info() {
+            frame_count += 1;
+            if frame_count > 1 {
+                return true;
+            }
         }
+        false
+    }
+
+    /// Width and height of a CF_BITMAP `HBITMAP`.
+    #[cfg(target_os = "windows")]
+    unsafe fn bitmap_dimensions(bitmap_handle: isize) -> Option<(i32, i32)> {
+        use windows::Win32::Graphics::Gdi::{GetObjectW, BITMAP, HGDIOBJ};
         
-        // 只支持无压缩的位图
-        if bi_compression != 0 {
-            error!("Compressed DIBV5 not supported: compression={}", bi_compression);
+        let mut bitmap = BITMAP::default();
+        let written = GetObjectW(
+            HGDIOBJ(bitmap_handle as *mut _),
+            std::mem::size_of::<BITMAP>() as i32,
+            Some(&mut bitmap as *mut BITMAP as *mut _),
+        );
+        if written == 0 {
+            error!("GetObjectW failed for CF_BITMAP handle");
             return None;
         }
+        Some((bitmap.bmWidth, bitmap.bmHeight.abs()))
+    }
+
+    #[cfg(target_os = "windows")]
+    pub(crate) unsafe fn extract_image_from_bitmap(bitmap_handle: isize) -> Option<Vec<u8>> {
+        use windows::Win32::Graphics::Gdi::{
+            GetDC, GetDIBits, ReleaseDC, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HBITMAP,
+        };
         
-        let abs_height = bi_height.abs();
-        let width = bi_width as u32;
-        let height = abs_height as u32;
+        debug!("Starting CF_BITMAP extraction from handle: {:#x}", bitmap_handle);
         
-        let bytes_per_pixel = (bi_bit_count as usize) / 8;
-        if bytes_per_pixel == 0 {
-            error!("Invalid bytes per pixel: {}", bytes_per_pixel);
+        let hbitmap = HBITMAP(bitmap_handle as *mut _);
+        let (width, height) = Self::bitmap_dimensions(bitmap_handle)?;
+        let max = crate::dib::MAX_DIMENSION as i32;
+        if width <= 0 || height == 0 || width > max || height > max {
+            error!("Invalid CF_BITMAP dimensions: {}x{}", width, height);
             return None;
         }
         
-        let row_size = ((width as usize * bytes_per_pixel + 3) / 4) * 4;
-        let expected_data_size = row_size * height as usize;
+        // Ask GDI for an uncompressed top-down 24-bit DIB, then decode it like CF_DIB.
+        let header = BITMAPINFOHEADER {
+            biSize: crate::dib::BITMAPINFOHEADER_SIZE,
+            biWidth: width,
+            biHeight: -height,
+            biPlanes: 1,
+            biBitCount: 24,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        };
+        let mut info = BITMAPINFO { bmiHeader: header, ..Default::default() };
+        let header_size = crate::dib::BITMAPINFOHEADER_SIZE as usize;
+        let row_size = (width as usize * 24).div_ceil(32) * 4;
         
-        debug!("DIBV5 Image dimensions: {}x{}, bytes_per_pixel: {}, row_size: {}, expected_data_size: {}", 
-              width, height, bytes_per_pixel, row_size, expected_data_size);
+        let mut packed = Vec::with_capacity(header_size + row_size * height as usize);
+        packed.extend_from_slice(std::slice::from_raw_parts(&header as *const BITMAPINFOHEADER as *const u8, header_size));
+        packed.resize(header_size + row_size * height as usize, 0);
         
-        let pixel_data_offset = bi_size;
-        let pixel_data_ptr = dib_ptr.add(pixel_data_offset);
+        // GetDIBits requires the bitmap not to be selected into any DC, so the
+        // screen DC is used directly instead of a compatible memory DC.
+        let screen_dc = GetDC(None);
+        if screen_dc.is_invalid() {
+            error!("GetDC failed while reading CF_BITMAP");
+            return None;
+        }
+        let lines = GetDIBits(
+            screen_dc,
+            hbitmap,
+            0,
+            height as u32,
+            Some(packed[header_size..].as_mut_ptr() as *mut _),
+            &mut info,
+            DIB_RGB_COLORS,
+        );
+        ReleaseDC(None, screen_dc);
         
-        match bi_bit_count {
-            24 => {
-                let mut img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-                
-                debug!("Processing DIBV5 24-bit");
-                
-                for y in 0..height {
-                    let dib_y = if bi_height > 0 { height - 1 - y } else { y };
-                    let dib_row_offset = dib_y as usize * row_size;
-                    
-                    for x in 0..width {
-                        let dib_x = x as usize * 3;
-                        let pixel_offset = dib_row_offset + dib_x;
-                        
-                        let b = *pixel_data_ptr.add(pixel_offset);
-                        let g = *pixel_data_ptr.add(pixel_offset + 1);
-                        let r = *pixel_data_ptr.add(pixel_offset + 2);
-                        
-                        img.put_pixel(x, y, Rgb([r, g, b]));
-                    }
-                }
-                
-                let mut output = Vec::new();
-                if let Ok(()) = image::write_buffer_with_format(
-                    &mut std::io::Cursor::new(&mut output),
-                    img.as_raw(),
-                    width,
-                    height,
-                    image::ExtendedColorType::Rgb8,
-                    image::ImageFormat::Png,
-                ) {
-                    debug!("Successfully converted DIBV5 24-bit to PNG, size: {} bytes", output.len());
-                    return Some(output);
-                } else {
-                    error!("Failed to write PNG buffer for DIBV5 24-bit");
-                }
-            }
-            32 => {
-                let mut img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-                
-                debug!("Processing DIBV5 32-bit");
-                
-                for y in 0..height {
-                    let dib_y = if bi_height > 0 { height - 1 - y } else { y };
-                    let dib_row_offset = dib_y as usize * row_size;
-                    
-                    for x in 0..width {
-                        let dib_x = x as usize * 4;
-                        let pixel_offset = dib_row_offset + dib_x;
-                        
-                        let b = *pixel_data_ptr.add(pixel_offset);
-                        let g = *pixel_data_ptr.add(pixel_offset + 1);
-                        let r = *pixel_data_ptr.add(pixel_offset + 2);
-                        let a = *pixel_data_ptr.add(pixel_offset + 3);
-                        
-                        img.put_pixel(x, y, Rgba([r, g, b, a]));
-                    }
-                }
-                
-                let mut output = Vec::new();
-                if let Ok(()) = image::write_buffer_with_format(
-                    &mut std::io::Cursor::new(&mut output),
-                    img.as_raw(),
-                    width,
-                    height,
-                    image::ExtendedColorType::Rgba8,
-                    image::ImageFormat::Png,
-                ) {
-                    debug!("Successfully converted DIBV5 32-bit to PNG, size: {} bytes", output.len());
-                    return Some(output);
-                } else {
-                    error!("Failed to write PNG buffer for DIBV5 32-bit");
-                }
+        if lines != height {
+            error!("GetDIBits copied {} of {} lines", lines, height);
+            return None;
+        }
+        
+        match crate::dib::decode_to_png(&packed, crate::dib::BITMAPINFOHEADER_SIZE, crate::dib::MAX_DIMENSION) {
+            Ok(output) => {
+                debug!("Successfully converted CF_BITMAP to PNG, size: {} bytes", output.len());
+                Some(output)
             }
-            _ => {
-                error!("Unsupported DIBV5 bit count: {}", bi_bit_count);
+            Err(e) => {
+                error!("Invalid CF_BITMAP data: {}", e);
+                None
             }
         }
+    }
+
+    /// Whether the shell reports a full-screen app, Direct3D game or
+    /// presentation in the foreground, the same check Windows uses to hold
+    /// back its own notifications.
+    #[cfg(target_os = "windows")]
+    unsafe fn fullscreen_app_active() -> bool {
+        use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_BUSY, QUNS_PRESENTATION_MODE, QUNS_RUNNING_D3D_FULL_SCREEN};
         
-        None
+        matches!(
+            SHQueryUserNotificationState(),
+            Ok(QUNS_BUSY | QUNS_RUNNING_D3D_FULL_SCREEN | QUNS_PRESENTATION_MODE)
+        )
     }
 
+    /// Title of the foreground window, e.g. `Figma – Homepage v3`.
     #[cfg(target_os = "windows")]
-    unsafe fn extract_image_from_bitmap(_bitmap_handle: isize) -> Option<Vec<u8>> {
-        None
+    unsafe fn foreground_window_title() -> Option<String> {
+        use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW};
+        
+        let window = GetForegroundWindow();
+        if window.is_invalid() {
+            return None;
+        }
+        let mut buffer = vec![0u16; 512];
+        let length = GetWindowTextW(window, &mut buffer);
+        let title = String::from_utf16_lossy(&buffer[..length.max(0) as usize]);
+        (!title.trim().is_empty()).then_some(title)
+    }
+
+    /// Executable file name of the process owning the clipboard, if any.
+    #[cfg(target_os = "windows")]
+    unsafe fn clipboard_owner_process() -> Option<String> {
+        use windows::core::PWSTR;
+        use windows::Win32::Foundation::CloseHandle;
+        use windows::Win32::System::DataExchange::GetClipboardOwner;
+        use windows::Win32::System::Threading::{OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION};
+        use windows::Win32::UI::WindowsAndMessaging::GetWindowThreadProcessId;
+        
+        let owner = GetClipboardOwner().ok()?;
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(owner, Some(&mut pid));
+        if pid == 0 {
+            return None;
+        }
+        
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buffer = vec![0u16; 1024];
+        let mut length = buffer.len() as u32;
+        let result = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut length);
+        let _ = CloseHandle(process);
+        if let Err(e) = result {
+            debug!("Failed to query clipboard owner process {}: {}", pid, e);
+            return None;
+        }
+        
+        let path = String::from_utf16_lossy(&buffer[..length as usize]);
+        std::path::Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
     }
 
     #[cfg(target_os = "windows")]
-    unsafe fn extract_image_from_files(hdrop: windows::Win32::UI::Shell::HDROP) -> Option<Vec<u8>> {
+    unsafe fn dropped_paths(hdrop: windows::Win32::UI::Shell::HDROP) -> Vec<String> {
         use windows::Win32::UI::Shell::DragQueryFileW;
         use std::ffi::OsString;
         use std::os::windows::ffi::OsStringExt;
         
-        debug!("Processing file drop from clipboard");
-        
         let file_count = DragQueryFileW(hdrop, 0xFFFFFFFF, None);
         debug!("Found {} files in clipboard", file_count);
         
+        let mut paths = Vec::new();
         for i in 0..file_count {
             let mut buffer = vec![0u16; 260]; // MAX_PATH
             let length = DragQueryFileW(hdrop, i, Some(&mut buffer));
             
             if length > 0 {
                 buffer.truncate(length as usize);
-                let file_path = OsString::from_wide(&buffer);
-                let file_path_str = file_path.to_string_lossy();
-                debug!("Processing file: {}", file_path_str);
+                paths.push(OsString::from_wide(&buffer).to_string_lossy().into_owned());
+            }
+        }
+        paths
+    }
+
+    #[cfg(target_os = "windows")]
+    unsafe fn extract_image_from_files(hdrop: windows::Win32::UI::Shell::HDROP) -> Option<(Vec<u8>, CaptureSource)> {
+        debug!("Processing file drop from clipboard");
+        
+        for file_path_str in Self::dropped_paths(hdrop) {
+            debug!("Processing file: {}", file_path_str);
+            
+            // 检查是否是图片文件
+            if Self::has_image_extension(&file_path_str) {
+                debug!("Found image file: {}", file_path_str);
                 
-                // 检查是否是图片文件
-                let lower_path = file_path_str.to_lowercase();
-                if lower_path.ends_with(".png") || 
-                   lower_path.ends_with(".jpg") || 
-                   lower_path.ends_with(".jpeg") || 
-                   lower_path.ends_with(".bmp") || 
-                   lower_path.ends_with(".gif") || 
-                   lower_path.ends_with(".webp") || 
-                   lower_path.ends_with(".tiff") || 
-                   lower_path.ends_with(".tif") {
-                    
-                    debug!("Found image file: {}", file_path_str);
-                    
-                    if let Ok(image_bytes) = std::fs::read(&file_path_str.to_string()) {
-                        debug!("Read file successfully, size: {} bytes", image_bytes.len());
-                        
-                        if let Ok(format) = image::guess_format(&image_bytes) {
-                            debug!("Detected image format: {:?}", format);
-                            
-                            match format {
-                                image::ImageFormat::Png | 
-                                image::ImageFormat::Jpeg | 
-                                image::ImageFormat::Gif | 
-                                image::ImageFormat::WebP | 
-                                image::ImageFormat::Bmp => {
-                                    debug!("Returning original format data");
-                                    return Some(image_bytes);
-                                }
-                                _ => {
-                                    debug!("Converting unsupported format to PNG");
-                                    if let Ok(img) = image::load_from_memory(&image_bytes) {
-                                        let mut png_data = Vec::new();
-                                        if img.write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png).is_ok() {
-                                            debug!("Successfully converted to PNG, size: {} bytes", png_data.len());
-                                            return Some(png_data);
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            debug!("Could not detect format, trying to load as image");
-                            if let Ok(img) = image::load_from_memory(&image_bytes) {
-                                let mut png_data = Vec::new();
-                                if img.write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png).is_ok() {
-                                    debug!("Successfully loaded and converted to PNG, size: {} bytes", png_data.len());
-                                    return Some(png_data);
-                                }
-                            }
-                        }
+                if let Some(image_bytes) = Self::read_image_file(&file_path_str) {
+                    let source = if Self::is_phone_sync_path(&file_path_str) {
+                        debug!("File was synced from a phone: {}", file_path_str);
+                        CaptureSource::Phone
                     } else {
-                        error!("Failed to read file: {}", file_path_str);
-                    }
-                } else {
-                    info!("Skipping non-image file: {}", file_path_str);
+                        CaptureSource::File
+                    };
+                    return Some((image_bytes, source));
                 }
+            } else {
+                info!("Skipping non-image file: {}", file_path_str);
             }
         }
         
         info!("No valid image files found in clipboard");
         None
     }
+
+    /// Phone Link and KDE Connect put synced images on the clipboard as files
+    /// in their package's `TempState` folder, which they clean up soon after.
+    #[cfg(target_os = "windows")]
+    fn is_phone_sync_path(path: &str) -> bool {
+        let lower_path = path.to_lowercase().replace('/', "\\");
+        lower_path.contains("\\appdata\\local\\packages\\") && lower_path.contains("\\tempstate\\")
+    }
+
+    #[cfg(target_os = "windows")]
+    fn has_image_extension(path: &str) -> bool {
+        let lower_path = path.to_lowercase();
+        [".png", ".jpg", ".jpeg", ".jfif", ".bmp", ".gif", ".webp", ".tiff", ".tif", ".ico"]
+            .iter()
+            .any(|ext| lower_path.ends_with(ext))
+    }
+
+    /// Reads an image file, passing formats the manager stores directly (and
+    /// ICO) through unchanged and converting anything else to PNG.
+    #[cfg(target_os = "windows")]
+    fn read_image_file(file_path_str: &str) -> Option<Vec<u8>> {
+        let image_bytes = Self::read_complete_file(file_path_str)?;
+        debug!("Read file successfully, size: {} bytes", image_bytes.len());
+        
+        if let Ok(format) = image::guess_format(&image_bytes) {
+            debug!("Detected image format: {:?}", format);
+            
+            match format {
+                image::ImageFormat::Png | 
+                image::ImageFormat::Jpeg | 
+                image::ImageFormat::Gif | 
+                image::ImageFormat::WebP | 
+                image::ImageFormat::Bmp |
+                image::ImageFormat::Tiff |
+                image::ImageFormat::Ico => {
+                    // TIFF is stored as-is like the formats above. ICO is
+                    // converted by ImageManager so it can record the original
+                    // format.
+                    debug!("Returning original format data");
+                    return Some(image_bytes);
+                }
+                _ => {
+                    debug!("Converting unsupported format to PNG");
+                }
+            }
+        } else {
+            debug!("Could not detect format, trying to load as image");
+        }
+        
+        let img = image::load_from_memory(&image_bytes).ok()?;
+        let mut png_data = Vec::new();
+        img.write_with_encoder(crate::image_ops::png_encoder(&mut png_data)).ok()?;
+        debug!("Successfully converted to PNG, size: {} bytes", png_data.len());
+        Some(png_data)
+    }
+
+    /// Reads a file that a sync app may still be writing: the read is retried
+    /// a few times while it fails or comes back shorter than the file's size.
+    #[cfg(target_os = "windows")]
+    fn read_complete_file(file_path_str: &str) -> Option<Vec<u8>> {
+        const ATTEMPTS: u32 = 3;
+        const RETRY_DELAY: Duration = Duration::from_millis(50);
+        
+        for attempt in 1..=ATTEMPTS {
+            match std::fs::read(file_path_str) {
+                Ok(bytes) => {
+                    let expected = std::fs::metadata(file_path_str).map(|m| m.len()).unwrap_or(bytes.len() as u64);
+                    if !bytes.is_empty() && bytes.len() as u64 == expected {
+                        return Some(bytes);
+                    }
+                    debug!("Read {} of {} bytes from {} (attempt {})", bytes.len(), expected, file_path_str, attempt);
+                    if attempt == ATTEMPTS {
+                        error!("File is still being written, giving up: {}", file_path_str);
+                        return None;
+                    }
+                }
+                Err(e) if attempt == ATTEMPTS => {
+                    error!("Failed to read file {}: {}", file_path_str, e);
+                    return None;
+                }
+                Err(e) => debug!("Failed to read file {} (attempt {}): {}", file_path_str, attempt, e),
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+        None
+    }
+
+    /// Image files directly inside the copied `folders` (not recursive), in
+    /// name order. Returns at most `max_files` paths and whether any were left out.
+    #[cfg(target_os = "windows")]
+    fn folder_image_paths(folders: &[String], max_files: usize) -> (Vec<std::path::PathBuf>, bool) {
+        let mut paths = Vec::new();
+        for folder in folders {
+            let mut entries: Vec<std::path::PathBuf> = match std::fs::read_dir(folder) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_file() && Self::has_image_extension(&path.to_string_lossy()))
+                    .collect(),
+                Err(e) => {
+                    error!("Failed to read folder {}: {}", folder, e);
+                    continue;
+                }
+            };
+            entries.sort();
+            paths.extend(entries);
+        }
+        
+        let truncated = paths.len() > max_files;
+        paths.truncate(max_files);
+        (paths, truncated)
+    }
+
+    /// Sends a failed save to the frontend as a `background-error`.
+    #[cfg(target_os = "windows")]
+    fn report_save_failure(handle: &Arc<Mutex<Option<AppHandle>>>, e: &anyhow::Error) {
+        if let Some(handle) = handle.lock().unwrap().as_ref() {
+            crate::diagnostics::report(handle, "clipboard", "save_failed", e.to_string());
+        }
+    }
+
+    /// Imports the images of copied folders through the normal save path. The
+    /// folder list stands in for the content hash so a folder left on the
+    /// clipboard is only imported once.
+    #[cfg(target_os = "windows")]
+    #[allow(clippy::too_many_arguments)]
+    fn import_dropped_folders(
+        folders: &[String],
+        handle: &Arc<Mutex<Option<AppHandle>>>,
+        image_manager: &Arc<Mutex<ImageManager>>,
+        last_hash: &Arc<Mutex<Option<String>>>,
+        stats: &ListenerStats,
+        config: &ClipboardListenerConfig,
+        capture_log: &Mutex<Option<CaptureLog>>,
+        source_app: Option<&str>,
+        added_images: &mut AddedImagesBatch,
+    ) {
+        let max_files = config.max_dropped_folder_files;
+        let key = format!("folders:{}", folders.join("|"));
+        {
+            let mut last = last_hash.lock().unwrap();
+            if last.as_deref() == Some(key.as_str()) {
+                debug!("Same folders on clipboard, skipping");
+                return;
+            }
+            *last = Some(key);
+        }
+        
+        let (paths, truncated) = Self::folder_image_paths(folders, max_files);
+        info!("Importing {} image(s) from {} copied folder(s)", paths.len(), folders.len());
+        
+        let mut imported = 0;
+        for path in &paths {
+            let Some(data) = Self::read_image_file(&path.to_string_lossy()) else {
+                continue;
+            };
+            
+            let (save_result, evicted_ids) = match crate::image_manager::validate_image(&data) {
+                Ok(()) => {
+                    let hash = crate::image_manager::content_hash(&data);
+                    let mut manager = image_manager.lock().unwrap();
+                    let result = manager.save_image_with_hash(&data, hash, CaptureSource::File);
+                    (result, manager.take_evicted_ids())
+                }
+                Err(e) => (Err(e.into()), Vec::new()),
+            };
+            if !evicted_ids.is_empty() {
+                if let Some(handle) = handle.lock().unwrap().as_ref() {
+                    if let Err(e) = handle.emit("images-removed", ImagesRemovedEvent { ids: evicted_ids }) {
+                        error!("Failed to emit images-removed event: {}", e);
+                    }
+                }
+            }
+            if let Ok(saved) = &save_result {
+                stats.files_scanned.fetch_add(saved.scanned_files as u64, Ordering::Relaxed);
+            }
+            match save_result {
+                Ok(saved) if !saved.is_duplicate => {
+                    imported += 1;
+                    stats.record_capture();
+                    Self::announce_capture(handle, config, capture_log, source_app, added_images, saved.metadata);
+                }
+                Ok(_) => {
+                    stats.duplicates_skipped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    stats.errors.fetch_add(1, Ordering::Relaxed);
+                    error!("Failed to save image {}: {}", path.display(), e);
+                    Self::report_save_failure(handle, &e);
+                }
+            }
+        }
+        info!("Imported {} new image(s) from copied folders", imported);
+        
+        if truncated {
+            log::warn!("Copied folders hold more than {} images, the rest were skipped", max_files);
+            if let Some(handle) = handle.lock().unwrap().as_ref() {
+                if let Err(e) = handle.emit("folder-import-truncated", FolderImportTruncatedEvent {
+                    folders: folders.to_vec(),
+                    max_files,
+                }) {
+                    error!("Failed to emit folder-import-truncated event: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(id: &str) -> ImageMetadata {
+        serde_json::from_value(serde_json::json!({ "id": id, "path": "", "created_at": 0 })).unwrap()
+    }
+
+    fn ids(items: Option<Vec<ImageMetadata>>) -> Option<Vec<String>> {
+        items.map(|items| items.into_iter().map(|metadata| metadata.id).collect())
+    }
+
+    #[test]
+    fn captures_in_quick_succession_go_out_together() {
+        let start = Instant::now();
+        let mut batch = AddedImagesBatch::default();
+        assert!(batch.take_due(start, true).is_none());
+
+        batch.push(metadata("a"), start);
+        batch.push(metadata("b"), start + Duration::from_millis(200));
+        assert!(batch.take_due(start + Duration::from_millis(400), false).is_none());
+        assert_eq!(ids(batch.take_due(start + Duration::from_millis(500), false)), Some(vec!["a".to_string(), "b".to_string()]));
+        assert!(batch.take_due(start + Duration::from_secs(5), false).is_none());
+    }
+
+    #[test]
+    fn forced_flush_sends_a_pending_batch_at_once() {
+        let start = Instant::now();
+        let mut batch = AddedImagesBatch::default();
+        batch.push(metadata("a"), start);
+        assert_eq!(ids(batch.take_due(start, true)), Some(vec!["a".to_string()]));
+        assert!(batch.take_due(start + IMAGES_ADDED_WINDOW, false).is_none());
+    }
 }
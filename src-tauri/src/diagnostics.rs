@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter};
+use crate::types::BackgroundErrorEvent;
+
+/// How many errors `recent_errors` keeps.
+const MAX_RECENT_ERRORS: usize = 50;
+/// A failure with the same component and code is emitted at most once per
+/// this many seconds; repeats are still kept in the recent list.
+const EMIT_INTERVAL_SECS: i64 = 60;
+
+struct ErrorLog {
+    recent: VecDeque<BackgroundErrorEvent>,
+    /// Component, code and unix time of the last emitted event.
+    last_emitted: Vec<(String, String, i64)>,
+}
+
+static ERROR_LOG: Mutex<ErrorLog> = Mutex::new(ErrorLog {
+    recent: VecDeque::new(),
+    last_emitted: Vec::new(),
+});
+
+/// Records a non-transient failure of a background task and tells the
+/// frontend through `background-error`, rate limited per component and code.
+pub fn report(app: &AppHandle, component: &str, code: &str, message: String) {
+    let event = BackgroundErrorEvent {
+        component: component.to_string(),
+        message,
+        code: code.to_string(),
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+    
+    let emit = {
+        let mut log = ERROR_LOG.lock().unwrap();
+        if log.recent.len() == MAX_RECENT_ERRORS {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(event.clone());
+        
+        let last = log.last_emitted
+            .iter_mut()
+            .find(|(component, code, _)| *component == event.component && *code == event.code);
+        match last {
+            Some((_, _, at)) if event.timestamp - *at < EMIT_INTERVAL_SECS => false,
+            Some((_, _, at)) => {
+                *at = event.timestamp;
+                true
+            }
+            None => {
+                log.last_emitted.push((event.component.clone(), event.code.clone(), event.timestamp));
+                true
+            }
+        }
+    };
+    
+    if emit {
+        if let Err(e) = app.emit("background-error", event) {
+            log::error!("Failed to emit background-error event: {}", e);
+        }
+    }
+}
+
+/// The last reported errors, newest first.
+pub fn recent_errors() -> Vec<BackgroundErrorEvent> {
+    ERROR_LOG.lock().unwrap().recent.iter().rev().cloned().collect()
+}
@@ -0,0 +1,294 @@
+use image::{ExtendedColorType, ImageEncoder, RgbaImage};
+use crate::error::DibError;
+
+/// Size of `BITMAPINFOHEADER`, the smallest header `CF_DIB` may carry.
+pub const BITMAPINFOHEADER_SIZE: u32 = 40;
+/// Size of `BITMAPV5HEADER`, required for `CF_DIBV5`.
+pub const BITMAPV5HEADER_SIZE: u32 = 124;
+
+/// Largest side decoded on capture; larger images are skipped as too large.
+pub const MAX_DIMENSION: u32 = 10000;
+/// Largest side decoded when a skipped capture is forced.
+pub const FORCED_MAX_DIMENSION: u32 = 65535;
+const BI_RGB: u32 = 0;
+
+/// Validated fields of a packed DIB header.
+#[derive(Debug, Clone, Copy)]
+pub struct DibHeader {
+    pub header_size: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Rows are stored top row first (negative `biHeight`).
+    pub top_down: bool,
+    pub bit_count: u16,
+    pub colors_used: u32,
+}
+
+impl DibHeader {
+    /// Parses and validates the header at the start of `data`. Only
+    /// uncompressed 24- and 32-bit images no wider or taller than
+    /// `max_dimension` are accepted.
+    pub fn parse(data: &[u8], min_header_size: u32, max_dimension: u32) -> Result<Self, DibError> {
+        let header_size = read_u32(data, 0)?;
+        if header_size < min_header_size {
+            return Err(DibError::HeaderSize(header_size));
+        }
+        ensure_len(data, header_size as usize)?;
+
+        let width = read_u32(data, 4)? as i32;
+        let height = read_u32(data, 8)? as i32;
+        let planes = read_u16(data, 12)?;
+        let bit_count = read_u16(data, 14)?;
+        let compression = read_u32(data, 16)?;
+        let colors_used = read_u32(data, 32)?;
+
+        if planes != 1 {
+            return Err(DibError::Planes(planes));
+        }
+        if width <= 0 || height == 0 || width as u32 > max_dimension || height.unsigned_abs() > max_dimension {
+            return Err(DibError::Dimensions { width, height });
+        }
+        if bit_count != 24 && bit_count != 32 {
+            return Err(DibError::BitCount(bit_count));
+        }
+        if compression != BI_RGB {
+            return Err(DibError::Compression(compression));
+        }
+        if colors_used > 256 {
+            return Err(DibError::Palette(colors_used));
+        }
+
+        Ok(Self {
+            header_size,
+            width: width as u32,
+            height: height.unsigned_abs(),
+            top_down: height < 0,
+            bit_count,
+            colors_used,
+        })
+    }
+
+    /// Offset of the first pixel: true-color DIBs may still carry a
+    /// `biClrUsed`-sized palette between the header and the pixels.
+    pub fn pixel_offset(&self) -> usize {
+        self.header_size as usize + self.colors_used as usize * 4
+    }
+
+    /// Bytes per stored row, padded to a 4-byte boundary.
+    pub fn row_size(&self) -> usize {
+        (self.width as usize * self.bit_count as usize).div_ceil(32) * 4
+    }
+}
+
+/// Decodes a packed DIB (header followed by pixels) into PNG bytes. 32-bit
+/// DIBs whose alpha is all 0 (many apps leave it unset) or all 255 become
+/// RGB, so only real transparency ends up in an alpha channel.
+pub fn decode_to_png(data: &[u8], min_header_size: u32, max_dimension: u32) -> Result<Vec<u8>, DibError> {
+    let header = DibHeader::parse(data, min_header_size, max_dimension)?;
+    let row_size = header.row_size();
+    let offset = header.pixel_offset();
+    ensure_len(data, offset + row_size * header.height as usize)?;
+    let pixels = &data[offset..];
+
+    let bytes_per_pixel = header.bit_count as usize / 8;
+    let rows = || (0..header.height as usize).map(|y| &pixels[y * row_size..][..header.width as usize * bytes_per_pixel]);
+    let has_alpha = header.bit_count == 32 && {
+        let mut alphas = rows().flat_map(|row| row.chunks_exact(4).map(|pixel| pixel[3]));
+        let first = alphas.next().unwrap_or(0);
+        !(first == 0 || first == 255) || alphas.any(|alpha| alpha != first)
+    };
+    let channels = if has_alpha { 4 } else { 3 };
+    let mut buffer = Vec::with_capacity(header.width as usize * header.height as usize * channels);
+    for y in 0..header.height as usize {
+        let dib_y = if header.top_down { y } else { header.height as usize - 1 - y };
+        let row = &pixels[dib_y * row_size..][..header.width as usize * bytes_per_pixel];
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            buffer.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            if channels == 4 {
+                buffer.push(pixel[3]);
+            }
+        }
+    }
+
+    let color_type = if channels == 4 { ExtendedColorType::Rgba8 } else { ExtendedColorType::Rgb8 };
+    let mut output = Vec::new();
+    crate::image_ops::png_encoder(&mut output)
+        .write_image(&buffer, header.width, header.height, color_type)
+        .map_err(|e| DibError::Encode(e.to_string()))?;
+    Ok(output)
+}
+
+/// Encodes `image` as a packed bottom-up 32-bit `CF_DIBV5`. The pixels are
+/// `BI_RGB` so `decode_to_png` reads them back; the alpha mask in the
+/// header tells apps that honor it the fourth byte is alpha. Windows
+/// synthesizes `CF_DIB` and `CF_BITMAP` from it.
+pub fn encode_dibv5(image: &RgbaImage) -> Vec<u8> {
+    const LCS_SRGB: u32 = 0x7352_4742;
+    const LCS_GM_IMAGES: u32 = 4;
+    let (width, height) = image.dimensions();
+    let pixel_bytes = width as usize * height as usize * 4;
+
+    let mut data = Vec::with_capacity(BITMAPV5HEADER_SIZE as usize + pixel_bytes);
+    data.extend_from_slice(&BITMAPV5HEADER_SIZE.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&32u16.to_le_bytes());
+    data.extend_from_slice(&BI_RGB.to_le_bytes());
+    data.extend_from_slice(&(pixel_bytes as u32).to_le_bytes());
+    // Resolution, colors used and colors important.
+    data.extend_from_slice(&[0; 16]);
+    for mask in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000] {
+        data.extend_from_slice(&mask.to_le_bytes());
+    }
+    data.extend_from_slice(&LCS_SRGB.to_le_bytes());
+    // Endpoints and gamma, unused with sRGB.
+    data.extend_from_slice(&[0; 48]);
+    data.extend_from_slice(&LCS_GM_IMAGES.to_le_bytes());
+    // Profile data, profile size and reserved.
+    data.extend_from_slice(&[0; 12]);
+
+    for row in image.rows().rev() {
+        for pixel in row {
+            let [r, g, b, a] = pixel.0;
+            data.extend_from_slice(&[b, g, r, a]);
+        }
+    }
+    data
+}
+
+fn ensure_len(data: &[u8], needed: usize) -> Result<(), DibError> {
+    if data.len() < needed {
+        return Err(DibError::Truncated { needed, available: data.len() });
+    }
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, DibError> {
+    ensure_len(data, offset + 2)?;
+    Ok(u16::from_le_bytes([data[offset], data[offset + 1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, DibError> {
+    ensure_len(data, offset + 4)?;
+    Ok(u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use proptest::prelude::*;
+
+    /// A bottom-up 24-bit `BITMAPINFOHEADER` DIB of `rows`, given top row
+    /// first with BGR pixels.
+    fn dib24(width: u32, rows: &[&[[u8; 3]]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&BITMAPINFOHEADER_SIZE.to_le_bytes());
+        data.extend_from_slice(&(width as i32).to_le_bytes());
+        data.extend_from_slice(&(rows.len() as i32).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&24u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+        for row in rows.iter().rev() {
+            let start = data.len();
+            for pixel in *row {
+                data.extend_from_slice(pixel);
+            }
+            data.resize(start + (width as usize * 24).div_ceil(32) * 4, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn parses_bottom_up_24_bit() {
+        let data = dib24(3, &[&[[0, 0, 255]; 3], &[[255, 0, 0]; 3]]);
+        let header = DibHeader::parse(&data, BITMAPINFOHEADER_SIZE, MAX_DIMENSION).unwrap();
+        assert_eq!((header.width, header.height), (3, 2));
+        assert!(!header.top_down);
+        assert_eq!(header.row_size(), 12);
+
+        let png = decode_to_png(&data, BITMAPINFOHEADER_SIZE, MAX_DIMENSION).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(image.get_pixel(2, 1).0, [0, 0, 255]);
+    }
+
+    #[test]
+    fn dibv5_round_trips_alpha() {
+        let mut image = RgbaImage::from_pixel(2, 2, Rgba([10, 20, 30, 255]));
+        image.put_pixel(1, 0, Rgba([40, 50, 60, 128]));
+        let data = encode_dibv5(&image);
+
+        let png = decode_to_png(&data, BITMAPV5HEADER_SIZE, MAX_DIMENSION).unwrap();
+        let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn opaque_32_bit_decodes_without_alpha() {
+        let data = encode_dibv5(&RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 0])));
+        let png = decode_to_png(&data, BITMAPINFOHEADER_SIZE, MAX_DIMENSION).unwrap();
+        assert!(!image::load_from_memory(&png).unwrap().color().has_alpha());
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let valid = dib24(1, &[&[[0, 0, 0]]]);
+        assert!(matches!(DibHeader::parse(&valid[..20], BITMAPINFOHEADER_SIZE, MAX_DIMENSION), Err(DibError::Truncated { .. })));
+        assert!(matches!(DibHeader::parse(&valid, BITMAPV5HEADER_SIZE, MAX_DIMENSION), Err(DibError::HeaderSize(40))));
+
+        let mut zero_height = valid.clone();
+        zero_height[8..12].copy_from_slice(&0i32.to_le_bytes());
+        assert!(matches!(DibHeader::parse(&zero_height, BITMAPINFOHEADER_SIZE, MAX_DIMENSION), Err(DibError::Dimensions { .. })));
+
+        let mut rle = valid.clone();
+        rle[16..20].copy_from_slice(&1u32.to_le_bytes());
+        assert!(matches!(DibHeader::parse(&rle, BITMAPINFOHEADER_SIZE, MAX_DIMENSION), Err(DibError::Compression(1))));
+
+        let missing_pixels = &valid[..BITMAPINFOHEADER_SIZE as usize];
+        assert!(matches!(decode_to_png(missing_pixels, BITMAPINFOHEADER_SIZE, MAX_DIMENSION), Err(DibError::Truncated { .. })));
+    }
+
+    #[test]
+    fn wide_headers_parse_only_within_the_forced_limit() {
+        let mut wide = dib24(1, &[&[[0, 0, 0]]]);
+        wide[4..8].copy_from_slice(&20000i32.to_le_bytes());
+        assert!(matches!(DibHeader::parse(&wide, BITMAPINFOHEADER_SIZE, MAX_DIMENSION), Err(DibError::Dimensions { .. })));
+        let header = DibHeader::parse(&wide, BITMAPINFOHEADER_SIZE, FORCED_MAX_DIMENSION).unwrap();
+        assert_eq!(header.width, 20000);
+    }
+
+    proptest! {
+        /// Clipboard data is written by other apps, so no header or pixel
+        /// data may panic the decoder.
+        #[test]
+        fn arbitrary_dibs_never_panic(
+            width in any::<i32>(),
+            height in any::<i32>(),
+            bit_count in prop_oneof![Just(24u16), Just(32u16), any::<u16>()],
+            colors_used in prop_oneof![0u32..=256, any::<u32>()],
+            header_size in prop_oneof![Just(BITMAPINFOHEADER_SIZE), Just(BITMAPV5HEADER_SIZE), any::<u32>()],
+            rest in proptest::collection::vec(any::<u8>(), 0..4096),
+        ) {
+            let mut data = Vec::new();
+            data.extend_from_slice(&header_size.to_le_bytes());
+            data.extend_from_slice(&width.to_le_bytes());
+            data.extend_from_slice(&height.to_le_bytes());
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&bit_count.to_le_bytes());
+            data.extend_from_slice(&BI_RGB.to_le_bytes());
+            data.extend_from_slice(&[0; 12]);
+            data.extend_from_slice(&colors_used.to_le_bytes());
+            data.extend_from_slice(&rest);
+            for min_header_size in [BITMAPINFOHEADER_SIZE, BITMAPV5HEADER_SIZE] {
+                let _ = decode_to_png(&data, min_header_size, MAX_DIMENSION);
+            }
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+            let _ = decode_to_png(&data, BITMAPINFOHEADER_SIZE, MAX_DIMENSION);
+        }
+    }
+}
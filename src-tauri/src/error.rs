@@ -0,0 +1,41 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SnapMagError {
+    #[error("Image not found: {0}")]
+    NotFound(String),
+    #[error("Image decode error: {0}")]
+    ImageDecodeError(String),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Pixel ({x}, {y}) is outside the {width}x{height} image")]
+    OutOfBounds { x: u32, y: u32, width: u32, height: u32 },
+    #[error("SnapMag is locked")]
+    Locked,
+    /// Only returned on platforms without the Win32 clipboard.
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    #[error("Not supported on this platform: {0}")]
+    Unsupported(String),
+}
+
+/// Why a clipboard DIB was rejected.
+#[cfg(any(target_os = "windows", test))]
+#[derive(Debug, Error)]
+pub enum DibError {
+    #[error("buffer of {available} bytes is shorter than the {needed} bytes required")]
+    Truncated { needed: usize, available: usize },
+    #[error("unsupported header size {0}")]
+    HeaderSize(u32),
+    #[error("invalid plane count {0}")]
+    Planes(u16),
+    #[error("invalid dimensions {width}x{height}")]
+    Dimensions { width: i32, height: i32 },
+    #[error("unsupported bit count {0}")]
+    BitCount(u16),
+    #[error("compressed DIB not supported: compression={0}")]
+    Compression(u32),
+    #[error("invalid palette size {0}")]
+    Palette(u32),
+    #[error("failed to encode PNG: {0}")]
+    Encode(String),
+}
@@ -1,46 +1,1044 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use image::ImageFormat;
-use crate::types::ImageMetadata;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use image::{ColorType, DynamicImage, ImageDecoder, ImageFormat};
+use serde::{Deserialize, Serialize};
+use crate::error::SnapMagError;
+use crate::image_ops;
+use crate::types::{AnnotationError, AnnotationOp, AnnotationResult, AverageSizeStats, CaptureRateComparison, CaptureSession, CaptureSource, CaptureStats, ClearSummary, DailyCaptureStats, DeleteFilter, DeletionSummary, DestructiveConfirmation, ExportFormat, ExtensionFix, ImageMetadata, ImagePage, MonitorIdentification, NearDuplicateGroup, OcrCorpusEntry, OcrCorpusFormat, OrphanedFile, PixelColor, PixelRect, RenameOperation, RetentionPreset, SourceCaptureStats, SpriteOffset, SpriteStripResult, StorageStats, StoragePerformance, TemporalOrderResult, UndoEntryInfo, VariantFormat};
+use crate::janitor::Janitor;
+use crate::thumbnails::ThumbnailCache;
+use crate::undo::{UndoItem, UndoJournal};
+
+/// Content hash used to find duplicate captures, see
+/// `ImageMetadata::content_hash`.
+pub type ContentHash = String;
+
+/// Hex SHA-256 of `data`. Captures with the same hash are taken as the same
+/// image, so the hash has to be collision resistant.
+pub fn content_hash(data: &[u8]) -> ContentHash {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Whether `hash` has the shape of a `content_hash`, so hash ids from before
+/// ids became ULIDs and malformed values are rejected.
+fn is_current_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Crockford base32, the ULID alphabet.
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// New image id: a ULID, 48 bits of Unix milliseconds followed by 80 random
+/// bits, so ids sort by creation time. New files are named after it.
+pub fn new_image_id() -> anyhow::Result<String> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis()) & ((1 << 48) - 1);
+    let mut random = [0u8; 16];
+    getrandom::getrandom(&mut random[6..]).map_err(|e| anyhow::anyhow!("Failed to generate image id: {}", e))?;
+    let value = (millis << 80) | u128::from_be_bytes(random);
+    Ok((0..26)
+        .map(|i| ULID_ALPHABET[(value >> (125 - 5 * i)) as usize & 31] as char)
+        .collect())
+}
+
+fn is_ulid(id: &str) -> bool {
+    id.len() == 26 && id.bytes().all(|b| ULID_ALPHABET.contains(&b))
+}
+
+const MAX_NOTES_CHARS: usize = 10_000;
+const MAX_INLINE_THUMB_PX: u32 = 128;
+const MAX_INLINE_THUMB_BYTES: usize = 20 * 1024;
+/// Upper bound on the base64 thumbnail payload of a single page.
+const MAX_INLINE_PAGE_BYTES: usize = 512 * 1024;
+/// Most images `create_webp_sprite_strip` puts in one strip.
+const MAX_SPRITE_IMAGES: usize = 100;
+/// How long a bulk-delete confirmation token stays valid.
+const CONFIRMATION_TTL: Duration = Duration::from_secs(30);
+/// Upper bound on frames `encode_gif_frames` will split a GIF into.
+const MAX_GIF_FRAMES: usize = 300;
+/// Most captures `get_average_capture_size` samples.
+const MAX_AVERAGE_SAMPLES: usize = 1000;
+/// Above this many images `find_near_duplicates` looks hashes up in a
+/// BK-tree instead of comparing every pair.
+const NEAR_DUPLICATE_TREE_MIN_IMAGES: usize = 1000;
+/// Largest distance `cleanup_near_duplicates` deletes at; past it, hashes of
+/// unrelated screenshots start to match.
+const MAX_CLEANUP_HAMMING_DISTANCE: u8 = 16;
+/// Largest relative aspect-ratio difference at which `identify_source_monitor`
+/// takes an image for a crop of a monitor.
+const MONITOR_ASPECT_TOLERANCE: f64 = 0.01;
+/// Confidence of a crop covering the monitor's full width; smaller crops
+/// score proportionally less.
+const MONITOR_CROP_CONFIDENCE: f64 = 0.5;
+/// Perceptual similarity above which `TemporalOrderScan::estimate` takes two
+/// captures of the same window as consecutive frames.
+const TIGHT_SEQUENCE_SIMILARITY: f64 = 0.95;
+/// Frame size of `encode_animated_preview`.
+const PREVIEW_WIDTH: u32 = 200;
+const PREVIEW_HEIGHT: u32 = 150;
+const MIN_PREVIEW_FRAME_DELAY_MS: u32 = 10;
+/// Parent of the per-process folders for friendly-named copies handed to
+/// other apps, see `Janitor`.
+const SESSION_DIR: &str = "session";
+/// Subfolder of the session folder holding `resized_variant` files.
+const RESIZED_DIR: &str = "resized";
+/// Size caps the janitor trims the caches to.
+const THUMBNAIL_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024;
+const RESIZED_CACHE_MAX_BYTES: u64 = 128 * 1024 * 1024;
+/// Saved image index, so startup doesn't have to look inside every file.
+const INDEX_FILE: &str = "index.json";
+/// Quiet time after the last change to the index before `save_index_if_due`
+/// writes it, so a burst of captures or edits costs one write.
+const INDEX_SAVE_DELAY: Duration = Duration::from_secs(2);
+/// Size of the probe file `measure_storage` writes and reads back.
+const STORAGE_PROBE_BYTES: usize = 1024;
+/// Largest chunk `read_image_chunk` returns in one call.
+const MAX_CHUNK_BYTES: usize = 1024 * 1024;
+/// Extension of raw clipboard captures kept next to their image.
+const RAW_CAPTURE_EXTENSION: &str = "dib";
+/// Stored-as-is images up to this size are fully decoded before saving to
+/// catch truncated data; larger ones only get header and end-marker checks.
+const MAX_FULL_VALIDATION_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageManagerConfig {
+    /// Keep the undo journal across restarts instead of discarding it on exit.
+    pub persist_undo: bool,
+    /// Library size limits enforced after each save; `None` disables a limit.
+    pub max_library_bytes: Option<u64>,
+    pub max_library_count: Option<usize>,
+    /// Retention hours per tag, overriding the `cleanup_old_images` threshold
+    /// for tagged images. `i64::MAX` keeps them forever.
+    pub tag_retention_policies: HashMap<String, i64>,
+    /// While set, new captures expire this many minutes after they are saved.
+    pub ephemeral_minutes: Option<u32>,
+    /// Compute the BlurHash of new captures right after they are saved.
+    pub eager_blurhash: bool,
+    /// `cleanup_old_images` runs removing more images than this need a
+    /// confirmation token; `None` never asks.
+    pub cleanup_confirm_threshold: Option<usize>,
+    /// Age limit applied by the background sweep, on top of tag retention.
+    pub retention_preset: RetentionPreset,
+    /// zlib level, 0–9, for the PNGs the app encodes. Lower is faster and
+    /// larger.
+    pub png_compression_level: u8,
+    /// `read_image_file` refuses larger files; they are read with
+    /// `read_image_chunk` instead.
+    pub max_ipc_read_bytes: u64,
+    /// A capture taken within this many minutes of the previous one joins
+    /// its session.
+    pub session_gap_minutes: u32,
+}
+
+impl Default for ImageManagerConfig {
+    fn default() -> Self {
+        Self {
+            persist_undo: false,
+            max_library_bytes: None,
+            max_library_count: None,
+            tag_retention_policies: HashMap::new(),
+            ephemeral_minutes: None,
+            eager_blurhash: false,
+            cleanup_confirm_threshold: Some(50),
+            retention_preset: RetentionPreset::default(),
+            png_compression_level: image_ops::DEFAULT_PNG_COMPRESSION_LEVEL,
+            max_ipc_read_bytes: 32 * 1024 * 1024,
+            session_gap_minutes: 3,
+        }
+    }
+}
+
+/// Outcome of `save_image`.
+#[derive(Debug)]
+pub struct SaveResult {
+    pub metadata: ImageMetadata,
+    /// The image was already in the library; `metadata` is the existing entry.
+    pub is_duplicate: bool,
+    /// Files read by the duplicate scan of the storage folder.
+    pub scanned_files: usize,
+}
+
+/// Outcome of a bulk cleanup that asks for confirmation above
+/// `cleanup_confirm_threshold`.
+#[derive(Debug)]
+pub enum CleanupOutcome {
+    Removed(Vec<String>),
+    NeedsConfirmation(DestructiveConfirmation),
+}
+
+/// Images and their cached perceptual hashes, copied out of the manager by
+/// `near_duplicate_scan` so the hashing in `find` runs without it.
+pub struct NearDuplicateScan {
+    /// Id, path and hash of every image, oldest first.
+    images: Vec<(String, String, Option<u64>)>,
+}
+
+/// Images of an `estimate_temporal_order` request, copied out of the
+/// manager by `temporal_order_scan` so the hashing in `estimate` runs
+/// without it.
+pub struct TemporalOrderScan {
+    /// In request order.
+    images: Vec<TemporalOrderImage>,
+}
+
+struct TemporalOrderImage {
+    /// Id as requested, which may be a legacy one.
+    id: String,
+    canonical_id: String,
+    path: String,
+    created_at: i64,
+    context_title: Option<String>,
+    hash: Option<u64>,
+}
+
+/// Images and their cached dominant hues, copied out of the manager by
+/// `color_scan` so the decoding in `sort` runs without it.
+pub struct ColorScan {
+    hue_bins: u32,
+    /// Every image, listed, and its hue once known, in id order.
+    images: Vec<(ImageMetadata, Option<Option<f32>>)>,
+}
+
+/// Library files copied out of the manager by `extension_scan`, so the
+/// sniffing in `sniff` runs without it.
+pub struct ExtensionScan {
+    /// Every image, in id order.
+    images: Vec<ScannedFile>,
+}
+
+struct ScannedFile {
+    id: String,
+    path: String,
+    /// Sniffed format and content extension, once `sniff` ran.
+    sniffed: Option<(String, String)>,
+}
+
+/// A scaled-down copy of an image, copied out of the manager by
+/// `resized_variant` so `write` decodes and encodes without it.
+pub struct ResizedVariant {
+    source: PathBuf,
+    path: PathBuf,
+    max_px: u32,
+    format: VariantFormat,
+}
+
+impl ResizedVariant {
+    /// Writes the copy unless an earlier request already did, and returns
+    /// its path. Smaller images keep their size.
+    pub fn write(&self) -> anyhow::Result<PathBuf> {
+        if self.path.exists() {
+            return Ok(self.path.clone());
+        }
+        let image = image::open(&self.source).map_err(|e| {
+            log::error!("Failed to decode image {}: {}", self.source.display(), e);
+            SnapMagError::ImageDecodeError(e.to_string())
+        })?;
+        let resized = if image.width().max(image.height()) > self.max_px {
+            image.resize(self.max_px, self.max_px, image::imageops::FilterType::Lanczos3)
+        } else {
+            image
+        };
+        let mut data = Vec::new();
+        match self.format {
+            VariantFormat::Png => resized.write_with_encoder(image_ops::png_encoder(&mut data))?,
+            VariantFormat::Jpeg { quality } => {
+                let flattened = image_ops::composite_on_background(&resized, [255, 255, 255]);
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality).encode_image(&flattened)?;
+            }
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic(&self.path, &data)?;
+        log::debug!("Wrote {}x{} variant of {} to {}", resized.width(), resized.height(), self.source.display(), self.path.display());
+        Ok(self.path.clone())
+    }
+}
+
+/// Bulk deletion waiting for its confirmation token.
+#[derive(Debug, Clone, PartialEq)]
+enum PendingOperation {
+    ClearAll,
+    CleanupOldImages { hours: i64 },
+    RetentionPreset { hours: i64 },
+    DeleteWhere(DeleteFilter),
+    /// The images a near-duplicate cleanup chose, deleted as chosen.
+    CleanupNearDuplicates { ids: Vec<String> },
+}
+
+struct PendingConfirmation {
+    operation: PendingOperation,
+    expires_at: Instant,
+}
 
 pub struct ImageManager {
     storage_dir: PathBuf,
     images: HashMap<String, ImageMetadata>,
+    config: ImageManagerConfig,
+    undo: UndoJournal,
+    thumbnails: ThumbnailCache,
+    janitor: Janitor,
+    evicted_ids: Vec<String>,
+    /// `(old, new)` paths of files moved since `take_moved_paths` was last
+    /// called.
+    moved_paths: Vec<(String, String)>,
+    /// Images `heal_missing_paths` found no file for. They are not looked
+    /// for again until `note_files_moved`.
+    unhealed: HashSet<String>,
+    /// Image files were created or renamed under the storage folder since
+    /// the last `heal_missing_paths`. Starts set so the first heal looks.
+    files_moved: bool,
+    /// A watcher calls `note_files_moved`. Without one, every newly missing
+    /// file is looked for once.
+    watching_files: bool,
+    pending_confirmations: HashMap<String, PendingConfirmation>,
+    /// Preset hours the user confirmed a held-back sweep for; later sweeps
+    /// of the same preset don't ask again.
+    retention_confirmed_hours: Option<i64>,
+    /// Preset hours a confirmation was already requested for, so the sweeper
+    /// asks once instead of every minute.
+    retention_requested_hours: Option<i64>,
+    /// Confirmation issued by a held-back sweep, for `take_retention_confirmation`.
+    retention_confirmation: Option<DestructiveConfirmation>,
+    /// Current unix time in seconds; `unix_now` unless given to `with_root`.
+    now_fn: fn() -> i64,
+    /// Id of the image with a given `content_hash`, for the duplicate check
+    /// of new captures. Deleted and edited images leave stale entries
+    /// behind, so it is only read through `find_by_content_hash`.
+    content_ids: HashMap<ContentHash, String>,
+    /// Image id by `legacy_id`, for `canonical_id`.
+    legacy_ids: HashMap<String, String>,
+    /// `perceptual_hash` by image id.
+    perceptual_hashes: HashMap<String, u64>,
+    /// `dominant_hue` by image id, `None` for grayscale images.
+    dominant_hues: HashMap<String, Option<f32>>,
+    /// The index is being read by `scan_storage` and `images` only holds
+    /// what was saved meanwhile.
+    loading: bool,
+    /// Last change to `images` not yet written by `save_index`.
+    index_changed_at: Option<Instant>,
+}
+
+/// The index read by `ImageManager::scan_storage`, for `finish_loading`.
+#[derive(Default)]
+pub struct LibraryScan {
+    images: HashMap<String, ImageMetadata>,
+    reindexed: usize,
 }
 
 impl ImageManager {
+    /// Opens the default storage folder with an empty index, so startup
+    /// doesn't wait for it; the index is read with `scan_storage` and merged
+    /// with `finish_loading`.
     pub fn new() -> anyhow::Result<Self> {
-        let storage_dir = std::env::temp_dir().join("screenshot-hub");
-        
+        Self::with_root(Self::default_storage_dir(), unix_now)
+    }
+
+    /// `new` on `storage_dir` instead of the default folder, reading the
+    /// time from `now_fn`.
+    pub fn with_root(storage_dir: PathBuf, now_fn: fn() -> i64) -> anyhow::Result<Self> {
         if !storage_dir.exists() {
             fs::create_dir_all(&storage_dir)?;
         }
         
+        Self::remove_partial_writes(&storage_dir);
+        Self::migrate_legacy_file_names(&storage_dir);
+        
+        let undo = UndoJournal::open(storage_dir.join("undo"))?;
         log::info!("ImageManager initialized with storage_dir: {}", storage_dir.display());
         
+        let mut manager = Self::with_journal(storage_dir, undo, now_fn, true)?;
+        manager.run_janitor();
+        Ok(manager)
+    }
+
+    /// Reads the index of `storage_dir` as `load_index` does, without a
+    /// manager, so it can run on a background thread while a `new` manager
+    /// is already in use.
+    pub fn scan_storage(storage_dir: &Path) -> anyhow::Result<LibraryScan> {
+        let (images, reindexed) = Self::read_index(storage_dir)?;
+        Ok(LibraryScan { images, reindexed })
+    }
+
+    /// Times writing (through to the disk) and reading back a small file in
+    /// `storage_dir`. The read comes from the OS cache, see
+    /// `cached_read_latency_ms`. Like `scan_storage` it needs no manager, so
+    /// the lock isn't held while a slow drive answers.
+    pub fn measure_storage(storage_dir: &Path) -> anyhow::Result<StoragePerformance> {
+        let probe = storage_dir.join(format!(".probe-{}.tmp", std::process::id()));
+        let data = vec![0x5a; STORAGE_PROBE_BYTES];
+
+        let started = Instant::now();
+        let written = fs::File::create(&probe).and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()
+        });
+        let write_latency = started.elapsed();
+        let started = Instant::now();
+        let read = written.and_then(|()| fs::read(&probe));
+        let read_latency = started.elapsed();
+        let _ = fs::remove_file(&probe);
+        if read? != data {
+            anyhow::bail!("Storage probe read back different bytes");
+        }
+
+        Ok(StoragePerformance {
+            is_network_drive: crate::platform::is_network_drive(storage_dir),
+            write_latency_ms: write_latency.as_secs_f64() * 1000.0,
+            cached_read_latency_ms: read_latency.as_secs_f64() * 1000.0,
+        })
+    }
+
+    /// Adds a `scan_storage` result to the index and ends loading. Entries
+    /// saved since the scan started are kept over the scanned ones, and
+    /// files deleted since are left out. Returns how many images are indexed.
+    pub fn finish_loading(&mut self, scan: LibraryScan) -> anyhow::Result<usize> {
+        let saved_meanwhile = self.images.len();
+        for (id, metadata) in scan.images {
+            if !self.images.contains_key(&id) && Path::new(&metadata.path).exists() {
+                self.images.insert(id, metadata);
+            }
+        }
+        self.rebuild_lookups();
+        self.loading = false;
+        if scan.reindexed > 0 || saved_meanwhile > 0 {
+            log::info!("Indexed {} new or changed image file(s)", scan.reindexed);
+            self.save_index()?;
+        }
+        Ok(self.images.len())
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    /// Opens the storage directory without the startup cleanup `new` does, so
+    /// it's safe while the GUI is running. The undo journal is left to its
+    /// owner and the index starts empty; see `load_index`.
+    pub fn open_shared() -> anyhow::Result<Self> {
+        let storage_dir = Self::default_storage_dir();
+        fs::create_dir_all(&storage_dir)?;
+        let undo = UndoJournal::detached(storage_dir.join("undo"));
+        Self::with_journal(storage_dir, undo, unix_now, false)
+    }
+
+    /// The manager `with_root` and `open_shared` build around their own
+    /// kind of undo journal, with an empty index.
+    fn with_journal(storage_dir: PathBuf, undo: UndoJournal, now_fn: fn() -> i64, loading: bool) -> anyhow::Result<Self> {
+        let thumbnails = ThumbnailCache::new(storage_dir.join("thumbs"))?;
+        let janitor = Janitor::new(storage_dir.join(SESSION_DIR), now_fn());
+        
         Ok(Self {
             storage_dir,
             images: HashMap::new(),
+            config: ImageManagerConfig::default(),
+            undo,
+            thumbnails,
+            janitor,
+            evicted_ids: Vec::new(),
+            moved_paths: Vec::new(),
+            unhealed: HashSet::new(),
+            files_moved: true,
+            watching_files: false,
+            pending_confirmations: HashMap::new(),
+            retention_confirmed_hours: None,
+            retention_requested_hours: None,
+            retention_confirmation: None,
+            now_fn,
+            content_ids: HashMap::new(),
+            legacy_ids: HashMap::new(),
+            perceptual_hashes: HashMap::new(),
+            dominant_hues: HashMap::new(),
+            loading,
+            index_changed_at: None,
         })
     }
 
-    pub fn save_image(&mut self, image_data: &[u8]) -> anyhow::Result<(ImageMetadata, bool)> {
-        let hash = Self::calculate_hash(image_data);
+    pub fn storage_dir(&self) -> &Path {
+        &self.storage_dir
+    }
+
+    fn now(&self) -> i64 {
+        (self.now_fn)()
+    }
+
+    fn default_storage_dir() -> PathBuf {
+        std::env::temp_dir().join("screenshot-hub")
+    }
+
+    /// Loads the index written by `save_index`. Entries whose file is
+    /// unchanged since the index was saved (same size, not modified later)
+    /// are taken as-is without opening the file; files modified after it are
+    /// re-read from their headers, keeping their tags and notes, and entries
+    /// whose file is gone are dropped. Only re-read files are hashed. Entries
+    /// still keyed by a content hash get a ULID, see `migrate_legacy_id`.
+    /// Returns how many files were re-read or migrated.
+    pub fn load_index(&mut self) -> anyhow::Result<usize> {
+        let (images, reindexed) = Self::read_index(&self.storage_dir)?;
+        self.images = images;
+        self.rebuild_lookups();
+        Ok(reindexed)
+    }
+
+    fn read_index(storage_dir: &Path) -> anyhow::Result<(HashMap<String, ImageMetadata>, usize)> {
+        let index_path = storage_dir.join(INDEX_FILE);
+        let saved: Vec<ImageMetadata> = match fs::read(&index_path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                log::warn!("Unreadable image index, rebuilding it: {}", e);
+                Self::set_index_aside(storage_dir);
+                Vec::new()
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let index_modified = if saved.is_empty() {
+            None
+        } else {
+            fs::metadata(&index_path)?.modified().ok()
+        };
+        let saved = saved.into_iter().map(|metadata| (metadata.id.clone(), metadata)).collect();
+        Self::index_files(storage_dir, saved, index_modified)
+    }
+
+    /// Renames an index that can't be read to `index.json.bad`, so it isn't
+    /// overwritten and tags and notes can be recovered from it by hand.
+    pub fn set_index_aside(storage_dir: &Path) {
+        let index_path = storage_dir.join(INDEX_FILE);
+        let bad_path = storage_dir.join(format!("{}.bad", INDEX_FILE));
+        match fs::rename(&index_path, &bad_path) {
+            Ok(()) => log::warn!("Moved the unreadable index to {}", bad_path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to move the unreadable index aside: {}", e),
+        }
+    }
+
+    /// Drops the saved index and re-reads the header of every file in the
+    /// storage directory, keeping the tags and notes of known images. For
+    /// recovering from an index that disagrees with the files. Returns how
+    /// many images are indexed.
+    pub fn force_reindex(&mut self) -> anyhow::Result<usize> {
+        let known = std::mem::take(&mut self.images);
+        self.images = Self::index_files(&self.storage_dir, known, None)?.0;
+        self.rebuild_lookups();
+        self.save_index()?;
+        Ok(self.images.len())
+    }
+
+    /// Indexes the image files on disk, reusing `known` entries as-is for
+    /// files not modified after `trusted_until`. Unknown files older than
+    /// that were left out of the index on purpose (e.g. by `clear_index`
+    /// keeping files) and stay out. Files are matched to entries by path,
+    /// falling back to the file stem for entries whose path is out of date.
+    /// Returns the index and how many files were re-read or migrated.
+    fn index_files(storage_dir: &Path, mut known: HashMap<String, ImageMetadata>, trusted_until: Option<SystemTime>) -> anyhow::Result<(HashMap<String, ImageMetadata>, usize)> {
+        let mut ids_by_path: HashMap<String, String> = known
+            .values()
+            .map(|metadata| (metadata.path.clone(), metadata.id.clone()))
+            .collect();
+        let mut images = HashMap::new();
+        let mut reindexed = 0;
+        for entry in fs::read_dir(storage_dir)?.flatten() {
+            let path = entry.path();
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if !path.is_file() || path.extension().is_some_and(|ext| ext == "tmp" || ext == "json" || ext == RAW_CAPTURE_EXTENSION) {
+                continue;
+            }
+            let file_metadata = entry.metadata()?;
+            let unmodified = trusted_until.is_some_and(|trusted| {
+                file_metadata.modified().is_ok_and(|modified| modified <= trusted)
+            });
+            let previous = ids_by_path
+                .remove(path.to_string_lossy().as_ref())
+                .and_then(|id| known.remove(&id))
+                .or_else(|| known.remove(stem));
+            match previous {
+                Some(mut metadata) if unmodified && metadata.size_bytes == file_metadata.len() => {
+                    if !is_current_hash(&metadata.content_hash) {
+                        metadata.content_hash = file_content_hash(&path);
+                        reindexed += 1;
+                    }
+                    images.insert(metadata.id.clone(), metadata);
+                    continue;
+                }
+                None if unmodified => continue,
+                _ => {}
+            }
+            
+            let Some(format) = sniff_file_format(&path) else {
+                continue;
+            };
+            let metadata = match previous {
+                Some(metadata) => ImageMetadata {
+                    path: path.to_string_lossy().to_string(),
+                    size_bytes: file_metadata.len(),
+                    format: Some(format),
+                    content_hash: file_content_hash(&path),
+                    ..metadata
+                },
+                None => disk_metadata(stem, &path, &file_metadata, format),
+            };
+            images.insert(metadata.id.clone(), metadata);
+            reindexed += 1;
+        }
+        
+        let mut migrated = 0;
+        let mut index = HashMap::with_capacity(images.len());
+        for (_, mut metadata) in images {
+            if migrate_legacy_id(&mut metadata)? {
+                migrated += 1;
+            }
+            index.insert(metadata.id.clone(), metadata);
+        }
+        if migrated > 0 {
+            log::info!("Gave {} image(s) a ULID in place of their hash id", migrated);
+        }
+        Ok((index, reindexed + migrated))
+    }
+
+    fn rebuild_lookups(&mut self) {
+        self.content_ids = self.images
+            .values()
+            .filter(|metadata| !metadata.content_hash.is_empty())
+            .map(|metadata| (metadata.content_hash.clone(), metadata.id.clone()))
+            .collect();
+        self.legacy_ids = self.images
+            .values()
+            .filter_map(|metadata| Some((metadata.legacy_id.clone()?, metadata.id.clone())))
+            .collect();
+    }
+
+    /// The image whose content hashes to `hash`, if it is still indexed.
+    fn find_by_content_hash(&self, hash: &str) -> Option<&ImageMetadata> {
+        self.content_ids
+            .get(hash)
+            .and_then(|id| self.images.get(id))
+            .filter(|metadata| metadata.content_hash == hash)
+    }
+
+    /// `id` as the index is keyed, resolving the `legacy_id` of an entry
+    /// migrated from a hash id.
+    fn canonical_id(&self, id: &str) -> String {
+        if self.images.contains_key(id) {
+            return id.to_string();
+        }
+        self.legacy_ids.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    fn entry(&self, id: &str) -> Option<&ImageMetadata> {
+        self.images.get(&self.canonical_id(id))
+    }
+
+    /// Mutable entry for `id`; the index is marked for saving.
+    fn entry_mut(&mut self, id: &str) -> Option<&mut ImageMetadata> {
+        let id = self.canonical_id(id);
+        self.mark_index_changed();
+        self.images.get_mut(&id)
+    }
+
+    /// Image files in the storage folder that the index doesn't know, such as
+    /// the files `clear_index` kept, oldest first.
+    pub fn find_orphaned_files(&self) -> anyhow::Result<Vec<OrphanedFile>> {
+        let indexed_paths: HashSet<&str> = self.images
+            .values()
+            .flat_map(|metadata| std::iter::once(metadata.path.as_str()).chain(metadata.raw_path.as_deref()))
+            .collect();
+        let mut orphans = Vec::new();
+        for entry in fs::read_dir(&self.storage_dir)?.flatten() {
+            let path = entry.path();
+            let indexed = indexed_paths.contains(path.to_string_lossy().as_ref());
+            if indexed || !path.is_file() || ImageFormat::from_path(&path).is_err() {
+                continue;
+            }
+            let file_metadata = entry.metadata()?;
+            let mtime = file_metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |age| age.as_secs() as i64);
+            orphans.push(OrphanedFile {
+                path: path.to_string_lossy().to_string(),
+                size_bytes: file_metadata.len(),
+                mtime,
+            });
+        }
+        orphans.sort_by(|a, b| a.mtime.cmp(&b.mtime).then_with(|| a.path.cmp(&b.path)));
+        Ok(orphans)
+    }
+
+    /// Saves every orphaned file again as an import, so it is re-hashed and
+    /// re-validated instead of trusted like `load_index` trusts indexed
+    /// files. The orphan itself is removed only once its bytes are saved or
+    /// found already in the library; one that fails to save stays where it
+    /// is. Returns how many new images were added.
+    pub fn adopt_orphaned_files(&mut self) -> anyhow::Result<usize> {
+        let mut adopted = 0;
+        for orphan in self.find_orphaned_files()? {
+            let path = PathBuf::from(&orphan.path);
+            let data = match fs::read(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    log::warn!("Failed to read orphaned file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let saved = validate_image(&data).map_err(anyhow::Error::from).and_then(|()| {
+                // The duplicate scan would otherwise match the orphan itself.
+                self.save_image_excluding(&data, content_hash(&data), CaptureSource::Import, Some(&path))
+            });
+            match saved {
+                Ok(saved) if !saved.is_duplicate => adopted += 1,
+                Ok(_) => log::info!("Orphaned file {} was already in the library", path.display()),
+                Err(e) => {
+                    log::warn!("Failed to adopt orphaned file {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("Failed to remove adopted orphan {}: {}", path.display(), e);
+            }
+        }
+        log::info!("Adopted {} orphaned file(s)", adopted);
+        Ok(adopted)
+    }
+
+    /// Writes the index for `load_index`, replacing the previous one
+    /// atomically. Does nothing while loading: the index on disk is still
+    /// the complete one, and files saved meanwhile are picked up from their
+    /// mtime.
+    pub fn save_index(&mut self) -> anyhow::Result<()> {
+        if self.loading {
+            return Ok(());
+        }
+        let mut images: Vec<&ImageMetadata> = self.images.values().collect();
+        images.sort_by(|a, b| a.id.cmp(&b.id));
+        let partial = self.storage_dir.join(format!("{}.tmp", INDEX_FILE));
+        fs::write(&partial, serde_json::to_vec(&images)?)?;
+        fs::rename(&partial, self.storage_dir.join(INDEX_FILE))?;
+        self.index_changed_at = None;
+        Ok(())
+    }
+
+    /// Saves the index once it has gone `INDEX_SAVE_DELAY` without a change;
+    /// called periodically so every change reaches the disk without a write
+    /// per change.
+    pub fn save_index_if_due(&mut self) -> anyhow::Result<()> {
+        if self.index_changed_at.is_some_and(|changed_at| changed_at.elapsed() >= INDEX_SAVE_DELAY) {
+            self.save_index()?;
+        }
+        Ok(())
+    }
+
+    fn mark_index_changed(&mut self) {
+        self.index_changed_at = Some(Instant::now());
+    }
+
+    /// Deletes `*.tmp` files left behind by a save interrupted by a crash.
+    fn remove_partial_writes(storage_dir: &Path) {
+        let Ok(entries) = fs::read_dir(storage_dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "tmp") {
+                log::info!("Removing partial write from previous session: {}", path.display());
+                if let Err(e) = fs::remove_file(&path) {
+                    log::warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Renames files named after a SHA-256 (64 hex digits) that isn't the
+    /// hash of the stored file to the one that is. Converted images were
+    /// named after their pre-conversion bytes, which are gone.
+    fn migrate_legacy_file_names(storage_dir: &Path) {
+        let Ok(entries) = fs::read_dir(storage_dir) else {
+            return;
+        };
+        let mut migrated = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_legacy = path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit()));
+            if !path.is_file() || !is_legacy {
+                continue;
+            }
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let mut target = storage_dir.join(content_hash(&data));
+            if let Some(ext) = path.extension() {
+                target.set_extension(ext);
+            }
+            if target == path {
+                continue;
+            }
+            let result = if target.exists() { fs::remove_file(&path) } else { fs::rename(&path, &target) };
+            match result {
+                Ok(()) => migrated += 1,
+                Err(e) => log::warn!("Failed to migrate {}: {}", path.display(), e),
+            }
+        }
+        if migrated > 0 {
+            log::info!("Migrated {} image file(s) to the current hash naming", migrated);
+        }
+    }
+
+    /// Sets how long images with `tag` are kept; `None` keeps them forever.
+    pub fn set_tag_retention(&mut self, tag: &str, hours: Option<i64>) -> anyhow::Result<()> {
+        if tag.trim().is_empty() {
+            return Err(SnapMagError::InvalidArgument("tag must not be empty".to_string()).into());
+        }
+        if hours.is_some_and(|hours| hours < 0) {
+            return Err(SnapMagError::InvalidArgument("retention hours must not be negative".to_string()).into());
+        }
+        self.config.tag_retention_policies.insert(tag.to_string(), hours.unwrap_or(i64::MAX));
+        Ok(())
+    }
+
+    /// Retention hours per tag, with `None` for tags kept forever.
+    pub fn get_tag_retentions(&self) -> HashMap<String, Option<i64>> {
+        self.config.tag_retention_policies
+            .iter()
+            .map(|(tag, &hours)| (tag.clone(), (hours != i64::MAX).then_some(hours)))
+            .collect()
+    }
+
+    /// Makes captures saved from now on expire after `minutes`; `None` stops
+    /// marking new captures but leaves existing expiries in place.
+    pub fn set_ephemeral_minutes(&mut self, minutes: Option<u32>) -> anyhow::Result<()> {
+        if minutes == Some(0) {
+            return Err(SnapMagError::InvalidArgument("minutes must be at least 1".to_string()).into());
+        }
+        self.config.ephemeral_minutes = minutes;
+        Ok(())
+    }
+
+    pub fn has_expiring_images(&self) -> bool {
+        self.images.values().any(|metadata| metadata.expires_at.is_some())
+    }
+
+    /// Permanently deletes ephemeral captures whose expiry has passed and
+    /// returns their ids.
+    pub fn purge_expired(&mut self) -> anyhow::Result<Vec<String>> {
+        let now = self.now();
+        
+        let expired: Vec<String> = self.images
+            .values()
+            .filter(|metadata| metadata.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .map(|metadata| metadata.id.clone())
+            .collect();
+        
+        for id in &expired {
+            self.purge_image(id)?;
+        }
+        Ok(expired)
+    }
+
+    pub fn config(&self) -> &ImageManagerConfig {
+        &self.config
+    }
+
+    /// Replaces the whole configuration. Limits and retention apply from the
+    /// next save or sweep.
+    pub fn set_config(&mut self, config: ImageManagerConfig) {
+        image_ops::set_png_compression_level(config.png_compression_level);
+        self.config = config;
+    }
+
+    pub fn set_max_ipc_read_bytes(&mut self, max_bytes: u64) -> Result<(), SnapMagError> {
+        if max_bytes == 0 {
+            return Err(SnapMagError::InvalidArgument("read limit must be positive".to_string()));
+        }
+        self.config.max_ipc_read_bytes = max_bytes;
+        Ok(())
+    }
+
+    pub fn max_ipc_read_bytes(&self) -> u64 {
+        self.config.max_ipc_read_bytes
+    }
+
+    pub fn set_png_compression(&mut self, level: u8) -> Result<(), SnapMagError> {
+        if level > 9 {
+            return Err(SnapMagError::InvalidArgument(format!("PNG compression level must be 0 to 9, got {}", level)));
+        }
+        image_ops::set_png_compression_level(level);
+        self.config.png_compression_level = level;
+        Ok(())
+    }
+
+    pub fn set_session_gap_minutes(&mut self, minutes: u32) {
+        self.config.session_gap_minutes = minutes;
+    }
+
+    pub fn set_eager_blurhash(&mut self, enabled: bool) {
+        self.config.eager_blurhash = enabled;
+    }
+
+    pub fn set_persist_undo(&mut self, enabled: bool) {
+        self.config.persist_undo = enabled;
+    }
+
+    /// Sets the library limits; zero is treated the same as `None` (no limit).
+    pub fn set_library_limits(&mut self, max_bytes: Option<u64>, max_count: Option<usize>) {
+        self.config.max_library_bytes = max_bytes.filter(|&bytes| bytes > 0);
+        self.config.max_library_count = max_count.filter(|&count| count > 0);
+    }
+
+    /// Called on app exit: keeps or discards the undo journal per `persist_undo`.
+    pub fn shutdown(&mut self) {
+        if let Err(e) = self.save_index() {
+            log::error!("Failed to save image index: {}", e);
+        }
+        if self.config.persist_undo {
+            if let Err(e) = self.undo.persist() {
+                log::error!("Failed to persist undo journal: {}", e);
+            }
+        } else {
+            self.undo.clear();
+        }
+        self.janitor.remove_session();
+    }
+
+    fn session_dir(&self) -> PathBuf {
+        self.janitor.session_dir().to_path_buf()
+    }
+
+    /// Deletes the session folders of processes that are gone and trims the
+    /// thumbnail and resized-copy caches to their caps. Returns the bytes
+    /// reclaimed.
+    pub fn run_janitor(&mut self) -> u64 {
+        let now = self.now();
+        let resized_dir = self.session_dir().join(RESIZED_DIR);
+        let caches = [
+            (self.thumbnails.dir(), THUMBNAIL_CACHE_MAX_BYTES),
+            (resized_dir.as_path(), RESIZED_CACHE_MAX_BYTES),
+        ];
+        self.janitor.run(&caches, now)
+    }
+
+    /// `run_janitor`, unless it ran recently.
+    pub fn run_janitor_if_due(&mut self) {
+        if self.janitor.is_due(self.now()) {
+            self.run_janitor();
+        }
+    }
+
+    /// Copies an image into the session folder under a readable name, for
+    /// apps that show the file name when it is pasted. Without `name` it is
+    /// called `Screenshot YYYY-MM-DD HHMMSS.ext` after its capture time.
+    pub fn create_named_copy(&self, id: &str, name: Option<&str>) -> anyhow::Result<PathBuf> {
+        let metadata = self.entry(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        let source = Path::new(&metadata.path);
+        let extension = content_extension(source)
+            .or_else(|| source.extension().map(|ext| ext.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "png".to_string());
+        
+        let stem = match name.map(sanitize_file_name).filter(|name| !name.is_empty()) {
+            Some(name) => Path::new(&name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or(name),
+            None => {
+                let captured = chrono::DateTime::from_timestamp(metadata.created_at, 0)
+                    .unwrap_or_default()
+                    .with_timezone(&chrono::Local);
+                format!("Screenshot {}", captured.format("%Y-%m-%d %H%M%S"))
+            }
+        };
+        
+        let session_dir = self.session_dir();
+        fs::create_dir_all(&session_dir)?;
+        let mut target = session_dir.join(format!("{}.{}", stem, extension));
+        let mut counter = 2;
+        while target.exists() {
+            target = session_dir.join(format!("{} ({}).{}", stem, counter, extension));
+            counter += 1;
+        }
         
+        fs::copy(source, &target).map_err(|e| {
+            log::error!("Failed to copy {} to {}: {}", source.display(), target.display(), e);
+            anyhow::anyhow!("Failed to copy image file: {}", e)
+        })?;
+        Ok(target)
+    }
+
+    /// Where image `id` scaled down to fit in `max_px` × `max_px` goes in
+    /// the session folder. The file name is made of the image's content hash
+    /// and the arguments, so a repeated request for unchanged pixels reuses
+    /// the file until the session folder is cleared; `ResizedVariant::write`
+    /// creates it.
+    pub fn resized_variant(&self, id: &str, max_px: u32, format: VariantFormat) -> anyhow::Result<ResizedVariant> {
+        if max_px == 0 {
+            return Err(SnapMagError::InvalidArgument("max_px must be positive".to_string()).into());
+        }
+        let metadata = self.entry(id).ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        let name = match format {
+            VariantFormat::Png => format!("{}-{}px.png", metadata.content_hash, max_px),
+            VariantFormat::Jpeg { quality: quality @ 1..=100 } => format!("{}-{}px-q{}.jpg", metadata.content_hash, max_px, quality),
+            VariantFormat::Jpeg { quality } => {
+                return Err(SnapMagError::InvalidArgument(format!("JPEG quality must be 1 to 100, got {}", quality)).into());
+            }
+        };
+        Ok(ResizedVariant {
+            source: PathBuf::from(&metadata.path),
+            path: self.session_dir().join(RESIZED_DIR).join(name),
+            max_px,
+            format,
+        })
+    }
+
+    /// Deletes session copies older than `max_age`.
+    pub fn prune_session_files(&self, max_age: std::time::Duration) {
+        let Ok(entries) = fs::read_dir(self.session_dir()) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let expired = entry.metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > max_age);
+            if expired {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    pub fn save_image(&mut self, image_data: &[u8], source: CaptureSource) -> anyhow::Result<SaveResult> {
+        validate_image(image_data)?;
+        self.save_image_with_hash(image_data, content_hash(image_data), source)
+    }
+
+    /// `save_image` for callers that already hashed `image_data` with
+    /// `content_hash` and checked it with `validate_image`, so neither runs
+    /// twice or under the lock.
+    pub fn save_image_with_hash(&mut self, image_data: &[u8], hash: ContentHash, source: CaptureSource) -> anyhow::Result<SaveResult> {
+        self.save_image_excluding(image_data, hash, source, None)
+    }
+
+    /// `save_image_with_hash` whose duplicate scan skips the file at
+    /// `excluded`, for saving the bytes of a file already in the storage
+    /// directory.
+    fn save_image_excluding(&mut self, image_data: &[u8], hash: ContentHash, source: CaptureSource, excluded: Option<&Path>) -> anyhow::Result<SaveResult> {
         log::debug!("[ImageManager] save_image called - hash: {}, data size: {} bytes", hash, image_data.len());
         
-        if let Some(metadata) = self.images.get(&hash) {
+        if let Some(metadata) = self.find_by_content_hash(&hash) {
             log::debug!("[ImageManager] Found in cache (hash: {}), returning cached metadata", hash);
-            return Ok((metadata.clone(), true));
+            return Ok(SaveResult { metadata: metadata.clone(), is_duplicate: true, scanned_files: 0 });
         }
         
-        log::debug!("[ImageManager] Hash not in cache, scanning storage dir for duplicates...");
-        
+        let mut scanned_files = 0;
         let mut found_duplicate = false;
         let mut existing_file_path = None;
-        let mut existing_file_hash = None;
+        let mut existing_file_stem = None;
         
         for entry in fs::read_dir(&self.storage_dir).map_err(|e| {
             log::error!("Failed to read storage directory: {}", e);
@@ -51,139 +1049,683 @@ impl ImageManager {
                 e
             })?;
             let path = entry.path();
-            if path.is_file() {
-                let file_hash = path.file_stem()
+            if excluded == Some(path.as_path()) {
+                continue;
+            }
+            if path.is_file() && path.extension().map_or(true, |ext| ext != RAW_CAPTURE_EXTENSION) {
+                // Files saved before ids became ULIDs are named by content hash.
+                let file_stem = path.file_stem()
                     .and_then(|n| n.to_str())
                     .unwrap_or("")
                     .to_string();
                 
-                log::debug!("[ImageManager] Checking file: {} (hash from filename: {})", path.display(), file_hash);
+                scanned_files += 1;
+                log::trace!("[ImageManager] Checking file: {}", path.display());
                 
                 if let Ok(existing_data) = fs::read(&path) {
                     let data_equal = existing_data == image_data;
-                    log::debug!("[ImageManager] Data comparison: data_equal={}, sizes: existing={}, new={}", 
+                    log::trace!("[ImageManager] Data comparison: data_equal={}, sizes: existing={}, new={}", 
                         data_equal, existing_data.len(), image_data.len());
                     
-                    if data_equal || file_hash == hash {
-                        log::info!("[ImageManager] Found duplicate! hash match: {}, content match: {}", file_hash == hash, data_equal);
-                        
+                    if data_equal || file_stem == hash {
                         found_duplicate = true;
                         existing_file_path = Some(path.to_string_lossy().to_string());
-                        existing_file_hash = Some(file_hash);
+                        existing_file_stem = Some(file_stem);
                         break;
                     }
                 }
             }
         }
         
+        log::debug!("[ImageManager] Duplicate scan read {} file(s)", scanned_files);
+        
         if found_duplicate {
             let existing_path = existing_file_path.unwrap();
-            let existing_hash = existing_file_hash.unwrap();
+            let existing_stem = existing_file_stem.unwrap();
             
             log::info!("[ImageManager] Found duplicate image at: {}", existing_path);
             
-            if let Some(existing_metadata) = self.images.get(&existing_hash) {
-                return Ok((existing_metadata.clone(), true));
+            if let Some(existing_metadata) = self.images.values().find(|metadata| metadata.path == existing_path) {
+                return Ok(SaveResult { metadata: existing_metadata.clone(), is_duplicate: true, scanned_files });
             }
             
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)?
-                .as_secs() as i64;
+            let now = self.now();
             
+            let size_bytes = fs::metadata(&existing_path).map(|m| m.len()).unwrap_or(0);
+            let (has_alpha, bit_depth) = file_color_info(Path::new(&existing_path));
             let metadata = ImageMetadata {
-                id: existing_hash,
+                id: existing_stem,
+                content_hash: hash,
+                legacy_id: None,
+                format: sniff_file_format(Path::new(&existing_path)),
+                has_alpha,
+                bit_depth,
                 path: existing_path,
                 created_at: now,
+                created_at_iso: iso_timestamp(now),
                 ocr_result: None,
+                original_format: None,
+                size_bytes,
+                source,
+                notes: None,
+                tags: Vec::new(),
+                inline_thumb: None,
+                expires_at: None,
+                blurhash: None,
+                raw_path: None,
+                context_title: None,
+                session_id: None,
             };
             
-            return Ok((metadata, true));
+            return Ok(SaveResult { metadata, is_duplicate: true, scanned_files });
         }
         
         log::debug!("[ImageManager] No duplicate found, checking if file already exists on disk...");
         
         let format = image::guess_format(image_data).unwrap_or(ImageFormat::Png);
-        let extension = match format {
-            ImageFormat::Png => "png",
-            ImageFormat::Jpeg => "jpg",
-            ImageFormat::Gif => "gif",
-            ImageFormat::WebP => "webp",
-            ImageFormat::Bmp => "bmp",
-            _ => "png",
+        let keep_original = is_stored_as_is(format);
+        // Anything outside the pass-through set is re-encoded as PNG, so the
+        // extension must follow the stored format rather than the sniffed one.
+        let stored_format = if keep_original { format } else { ImageFormat::Png };
+        let extension = file_extension(stored_format);
+        let original_format = if keep_original {
+            None
+        } else {
+            format.extensions_str().first().map(|ext| ext.to_string())
         };
         
-        let file_name = format!("{}.{}", hash, extension);
-        let file_path = self.storage_dir.join(&file_name);
+        let id = new_image_id()?;
+        let file_path = self.storage_dir.join(format!("{}.{}", id, extension));
         
-        if file_path.exists() {
-            log::info!("[ImageManager] File already exists on disk (hash: {}), returning as duplicate", hash);
-            
-            if let Some(existing_metadata) = self.images.get(&hash) {
-                return Ok((existing_metadata.clone(), true));
-            }
-            
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)?
-                .as_secs() as i64;
-            
-            let metadata = ImageMetadata {
-                id: hash.clone(),
-                path: file_path.to_string_lossy().to_string(),
-                created_at: now,
-                ocr_result: None,
-            };
-            
-            return Ok((metadata, true));
-        }
+        log::debug!("Saving new image with hash: {} to path: {} (format: {:?})", hash, file_path.display(), format);
         
-        log::debug!("File does not exist, saving new image with hash: {} to path: {} (format: {:?})", hash, file_path.display(), format);
-        
-        if matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Bmp) {
-            fs::write(&file_path, image_data).map_err(|e| {
+        let color = if keep_original {
+            write_atomic(&file_path, image_data).map_err(|e| {
                 log::error!("Failed to write image file: {}", e);
                 anyhow::anyhow!("Failed to write image file: {}", e)
             })?;
             log::debug!("Saved original format image to: {}", file_path.display());
+            image::ImageReader::with_format(std::io::Cursor::new(image_data), format)
+                .into_decoder()
+                .map(|decoder| decoder.color_type())
+                .ok()
         } else {
+            // For ICO files the decoder picks the largest embedded entry.
             let image = image::load_from_memory(image_data).map_err(|e| {
                 log::error!("Failed to load image from memory: {}", e);
                 anyhow::anyhow!("Failed to load image from memory: {}", e)
             })?;
-            image.save_with_format(&file_path, ImageFormat::Png).map_err(|e| {
+            let mut png_data = Vec::new();
+            image.write_with_encoder(image_ops::png_encoder(&mut png_data)).map_err(|e| {
+                log::error!("Failed to encode image: {}", e);
+                anyhow::anyhow!("Failed to encode image: {}", e)
+            })?;
+            write_atomic(&file_path, &png_data).map_err(|e| {
                 log::error!("Failed to save image: {}", e);
                 anyhow::anyhow!("Failed to save image: {}", e)
             })?;
             log::debug!("Converted and saved image to PNG: {}", file_path.display());
-        }
+            Some(image.color())
+        };
+        let (has_alpha, bit_depth) = color.map_or((false, 0), color_info);
         
         log::debug!("Image saved successfully to: {}", file_path.display());
         
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs() as i64;
+        let now = self.now();
         
+        let size_bytes = fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
         let metadata = ImageMetadata {
-            id: hash.clone(),
+            id: id.clone(),
+            content_hash: hash.clone(),
+            legacy_id: None,
             path: file_path.to_string_lossy().to_string(),
             created_at: now,
+            created_at_iso: iso_timestamp(now),
             ocr_result: None,
+            original_format,
+            format: Some(format_name(stored_format)),
+            has_alpha,
+            bit_depth,
+            size_bytes,
+            source,
+            notes: None,
+            tags: Vec::new(),
+            inline_thumb: None,
+            expires_at: self.config.ephemeral_minutes.map(|minutes| now + minutes as i64 * 60),
+            blurhash: None,
+            raw_path: None,
+            context_title: None,
+            session_id: self.session_for(&id, source, now),
         };
         
         log::info!("Created metadata with path: {}", metadata.path);
         
-        self.images.insert(hash.clone(), metadata.clone());
+        self.images.insert(id.clone(), metadata.clone());
+        self.content_ids.insert(hash, id.clone());
+        self.mark_index_changed();
+        self.enforce_library_limits(&id);
+        
+        Ok(SaveResult { metadata, is_duplicate: false, scanned_files })
+    }
+
+    /// Stores `raw`, the clipboard bytes image `id` was converted from, as
+    /// `<id>.dib` next to it and returns the updated metadata.
+    #[cfg(target_os = "windows")]
+    pub fn attach_raw_capture(&mut self, id: &str, raw: &[u8]) -> anyhow::Result<ImageMetadata> {
+        let raw_path = self.storage_dir.join(format!("{}.{}", id, RAW_CAPTURE_EXTENSION));
+        let metadata = self.entry_mut(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        write_atomic(&raw_path, raw)?;
+        metadata.raw_path = Some(raw_path.to_string_lossy().to_string());
+        Ok(Self::listed(metadata))
+    }
+
+    /// Evicts the oldest images until the library is within the configured
+    /// limits. `keep_id` (the image just saved) is never evicted.
+    fn enforce_library_limits(&mut self, keep_id: &str) {
+        let max_bytes = self.config.max_library_bytes;
+        let max_count = self.config.max_library_count;
+        if max_bytes.is_none() && max_count.is_none() {
+            return;
+        }
+        
+        let mut total_bytes: u64 = self.images.values().map(|metadata| metadata.size_bytes).sum();
+        let mut count = self.images.len();
+        let over_limit = |bytes: u64, count: usize| {
+            max_bytes.is_some_and(|max| bytes > max) || max_count.is_some_and(|max| count > max)
+        };
+        if !over_limit(total_bytes, count) {
+            return;
+        }
+        
+        let mut candidates: Vec<(i64, String, u64)> = self.images
+            .values()
+            .filter(|metadata| metadata.id != keep_id)
+            .map(|metadata| (metadata.created_at, metadata.id.clone(), metadata.size_bytes))
+            .collect();
+        candidates.sort();
+        
+        let mut freed_bytes = 0;
+        let mut evicted = 0;
+        for (_, id, size_bytes) in candidates {
+            if !over_limit(total_bytes, count) {
+                break;
+            }
+            if let Err(e) = self.purge_image(&id) {
+                log::error!("Failed to evict image {}: {}", id, e);
+                continue;
+            }
+            total_bytes = total_bytes.saturating_sub(size_bytes);
+            count -= 1;
+            freed_bytes += size_bytes;
+            evicted += 1;
+            self.evicted_ids.push(id);
+        }
+        
+        log::info!("Library limit reached: evicted {} images, freed {} bytes", evicted, freed_bytes);
+    }
+
+    /// Returns (and forgets) the ids evicted by library limits since the last call.
+    pub fn take_evicted_ids(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.evicted_ids)
+    }
+
+    /// Returns (and forgets) the `(old, new)` paths of files renamed or
+    /// found moved since the last call.
+    pub fn take_moved_paths(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.moved_paths)
+    }
+
+    /// Called by the storage watcher when image files are created or
+    /// renamed, so `heal_missing_paths` looks for missing files again.
+    pub fn note_files_moved(&mut self) {
+        self.files_moved = true;
+    }
+
+    pub fn set_watching_files(&mut self, watching: bool) {
+        self.watching_files = watching;
+    }
+
+    /// Checks, with one stat per image, that every indexed file still
+    /// exists. A missing file found elsewhere under the storage folder with
+    /// the same name, or named after the image id, is pointed to again,
+    /// reported by `take_moved_paths` and saved to the index. The folder is
+    /// only walked after `note_files_moved` (or, without a watcher, for newly
+    /// missing files); files not found are logged once and remembered.
+    pub fn heal_missing_paths(&mut self) {
+        let missing: HashSet<String> = self.images
+            .values()
+            .filter(|metadata| !Path::new(&metadata.path).exists())
+            .map(|metadata| metadata.id.clone())
+            .collect();
+        self.unhealed.retain(|id| missing.contains(id));
+        let newly_missing = missing.iter().any(|id| !self.unhealed.contains(id));
+        let walk = self.files_moved || (newly_missing && !self.watching_files);
+        self.files_moved = false;
+        if missing.is_empty() || !walk {
+            self.log_unhealed(missing);
+            return;
+        }
+        
+        let indexed: HashSet<PathBuf> = self.images.values().map(|metadata| PathBuf::from(&metadata.path)).collect();
+        let skipped = ["undo", "thumbs", "snippets", SESSION_DIR].map(|name| self.storage_dir.join(name));
+        let mut candidates = Vec::new();
+        collect_image_files(&self.storage_dir, &skipped, &mut candidates);
+        candidates.retain(|path| !indexed.contains(path));
+        
+        let mut not_found = HashSet::new();
+        let mut healed = false;
+        for id in missing {
+            let Some(metadata) = self.images.get_mut(&id) else {
+                continue;
+            };
+            let old_path = PathBuf::from(&metadata.path);
+            let found = candidates
+                .iter()
+                .position(|path| path.file_name() == old_path.file_name())
+                .or_else(|| {
+                    candidates.iter().position(|path| {
+                        path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| {
+                            stem == metadata.id || metadata.legacy_id.as_deref() == Some(stem)
+                        })
+                    })
+                });
+            match found {
+                Some(index) => {
+                    let new_path = candidates.swap_remove(index).to_string_lossy().to_string();
+                    log::info!("Image {} moved from {} to {}", id, metadata.path, new_path);
+                    self.moved_paths.push((std::mem::replace(&mut metadata.path, new_path.clone()), new_path));
+                    self.unhealed.remove(&id);
+                    healed = true;
+                }
+                None => {
+                    not_found.insert(id);
+                }
+            }
+        }
+        self.log_unhealed(not_found);
+        if healed {
+            if let Err(e) = self.save_index() {
+                log::error!("Failed to save the index after healing moved files: {}", e);
+                self.mark_index_changed();
+            }
+        }
+    }
+
+    /// Logs the missing files of `ids` not logged before and remembers them.
+    fn log_unhealed(&mut self, ids: HashSet<String>) {
+        for id in ids {
+            if self.unhealed.insert(id.clone()) {
+                if let Some(metadata) = self.images.get(&id) {
+                    log::warn!("File of image {} is missing: {}", id, metadata.path);
+                }
+            }
+        }
+    }
+
+    pub fn get_storage_stats(&self) -> StorageStats {
+        StorageStats {
+            image_count: self.images.len(),
+            total_bytes: self.images.values().map(|metadata| metadata.size_bytes).sum(),
+            max_library_bytes: self.config.max_library_bytes,
+            max_library_count: self.config.max_library_count,
+            janitor: self.janitor.stats(),
+        }
+    }
+
+    /// Average file size and dimensions of the `last_n` newest images (at
+    /// most 1000). Dimensions come from the file headers; images whose
+    /// header can't be read are left out of the sample.
+    pub fn get_average_capture_size(&self, last_n: usize) -> anyhow::Result<AverageSizeStats> {
+        if last_n == 0 {
+            return Err(SnapMagError::InvalidArgument("last_n must be at least 1".to_string()).into());
+        }
+        let last_n = last_n.min(MAX_AVERAGE_SAMPLES);
+        
+        let mut images: Vec<&ImageMetadata> = self.images.values().collect();
+        images.sort_by_key(|metadata| std::cmp::Reverse(metadata.created_at));
+        if images.len() < last_n {
+            log::warn!("Only {} images available for an average over the last {}", images.len(), last_n);
+        }
+        
+        let (mut count, mut bytes, mut width, mut height, mut pixels) = (0usize, 0f64, 0f64, 0f64, 0f64);
+        for metadata in images.into_iter().take(last_n) {
+            let (w, h) = match image::image_dimensions(&metadata.path) {
+                Ok(dimensions) => dimensions,
+                Err(e) => {
+                    log::warn!("Leaving {} out of the size average: {}", metadata.id, e);
+                    continue;
+                }
+            };
+            count += 1;
+            bytes += metadata.size_bytes as f64;
+            width += w as f64;
+            height += h as f64;
+            pixels += w as f64 * h as f64;
+        }
+        if count == 0 {
+            return Ok(AverageSizeStats::default());
+        }
         
-        Ok((metadata, false))
+        let n = count as f64;
+        Ok(AverageSizeStats {
+            sample_count: count,
+            avg_file_size_bytes: bytes / n,
+            avg_width: width / n,
+            avg_height: height / n,
+            avg_pixel_count: pixels / n,
+        })
     }
 
     pub fn get_images(&self) -> Vec<ImageMetadata> {
-        let mut images: Vec<ImageMetadata> = self.images.values().cloned().collect();
-        images.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        let mut images: Vec<ImageMetadata> = self.images.values().map(Self::listed).collect();
+        images.sort_by_key(|metadata| std::cmp::Reverse(metadata.created_at));
+        images
+    }
+
+    /// Returns one page of images, newest first, without inline thumbnails;
+    /// `add_inline_thumbs` renders those.
+    pub fn get_images_page(&self, page: usize, page_size: usize) -> anyhow::Result<ImagePage> {
+        if page_size == 0 {
+            return Err(SnapMagError::InvalidArgument("page_size must be greater than 0".to_string()).into());
+        }
+        
+        let all = self.get_images();
+        let total = all.len();
+        let images: Vec<ImageMetadata> = all
+            .into_iter()
+            .skip(page.saturating_mul(page_size))
+            .take(page_size)
+            .collect();
+        Ok(ImagePage { images, page, page_size, total })
+    }
+
+    /// The thumbnail cache, for rendering thumbnails after the lock is
+    /// released.
+    pub fn thumbnail_cache(&self) -> ThumbnailCache {
+        self.thumbnails.clone()
+    }
+
+    /// The images `ids`, in order, for work done outside the manager's
+    /// lock.
+    pub fn get_images_by_id(&self, ids: &[String]) -> anyhow::Result<Vec<ImageMetadata>> {
+        ids.iter()
+            .map(|id| self.get_image(id).ok_or_else(|| SnapMagError::InvalidArgument(format!("image {} not found", id)).into()))
+            .collect()
+    }
+
+    /// The `n` largest images by stored file size (at most 100). Sizes missing
+    /// from the metadata are read from disk once and cached.
+    pub fn get_largest_images(&mut self, n: usize) -> anyhow::Result<Vec<ImageMetadata>> {
+        if n > 100 {
+            return Err(SnapMagError::InvalidArgument(format!("n must be at most 100, got {}", n)).into());
+        }
+        
+        let mut changed = false;
+        for metadata in self.images.values_mut().filter(|metadata| metadata.size_bytes == 0) {
+            if let Ok(file_metadata) = fs::metadata(&metadata.path) {
+                metadata.size_bytes = file_metadata.len();
+                changed = true;
+            }
+        }
+        if changed {
+            self.mark_index_changed();
+        }
+        
+        let mut images: Vec<ImageMetadata> = self.images.values().map(Self::listed).collect();
+        images.sort_by_key(|metadata| std::cmp::Reverse(metadata.size_bytes));
+        images.truncate(n);
+        Ok(images)
+    }
+
+    /// Up to `limit` (at most 200) images captured after `since_ts`, oldest
+    /// first, for polling with the `created_at` of the newest known image.
+    pub fn get_images_after(&self, since_ts: i64, limit: usize) -> anyhow::Result<Vec<ImageMetadata>> {
+        if limit > 200 {
+            return Err(SnapMagError::InvalidArgument(format!("limit must be at most 200, got {}", limit)).into());
+        }
+        
+        let mut images: Vec<ImageMetadata> = self.images
+            .values()
+            .filter(|metadata| metadata.created_at > since_ts)
+            .map(Self::listed)
+            .collect();
+        images.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        images.truncate(limit);
+        Ok(images)
+    }
+
+    /// Ties on `created_at` go to the larger id, as in `get_images_after`.
+    pub fn get_newest_image(&self) -> Option<ImageMetadata> {
+        self.images.values().max_by_key(|metadata| (metadata.created_at, &metadata.id)).map(Self::listed)
+    }
+
+    pub fn get_image(&self, id: &str) -> Option<ImageMetadata> {
+        self.entry(id).map(Self::listed)
+    }
+
+    /// File of image `id`, for work done outside the manager's lock.
+    pub fn image_path(&self, id: &str) -> anyhow::Result<PathBuf> {
+        self.entry(id)
+            .map(|metadata| PathBuf::from(&metadata.path))
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()).into())
+    }
+
+    /// Files of `ids`, in order, for work done outside the manager's lock.
+    pub fn image_paths(&self, ids: &[String]) -> anyhow::Result<Vec<PathBuf>> {
+        if ids.is_empty() {
+            return Err(SnapMagError::InvalidArgument("no images given".to_string()).into());
+        }
+        ids.iter().map(|id| self.image_path(id)).collect()
+    }
+
+    /// Reads up to `len` bytes, at most `MAX_CHUNK_BYTES`, of image `id`'s
+    /// file from `offset`. A chunk shorter than that ends the file; an
+    /// offset at the end of the file gives an empty one.
+    pub fn read_image_chunk(&self, id: &str, offset: u64, len: usize) -> anyhow::Result<Vec<u8>> {
+        use std::io::{Read, Seek, SeekFrom};
+        
+        let metadata = self.entry(id).ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        let mut file = fs::File::open(&metadata.path)?;
+        let total_size = file.metadata()?.len();
+        if offset > total_size {
+            return Err(SnapMagError::InvalidArgument(format!("offset {} is past the end of the {}-byte file", offset, total_size)).into());
+        }
+        
+        let len = len.min(MAX_CHUNK_BYTES).min((total_size - offset) as usize);
+        let mut data = vec![0; len];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Copy of `metadata` for callers, with the derived `created_at_iso`
+    /// filled in (entries restored from the undo journal may predate it).
+    fn listed(metadata: &ImageMetadata) -> ImageMetadata {
+        ImageMetadata {
+            created_at_iso: iso_timestamp(metadata.created_at),
+            ..metadata.clone()
+        }
+    }
+
+    /// Capture sessions, newest first.
+    pub fn get_sessions(&self) -> Vec<CaptureSession> {
+        let mut sessions: HashMap<&str, CaptureSession> = HashMap::new();
+        for metadata in self.images.values() {
+            let Some(session_id) = metadata.session_id.as_deref() else {
+                continue;
+            };
+            let session = sessions.entry(session_id).or_insert_with(|| CaptureSession {
+                session_id: session_id.to_string(),
+                start: metadata.created_at,
+                end: metadata.created_at,
+                count: 0,
+            });
+            session.start = session.start.min(metadata.created_at);
+            session.end = session.end.max(metadata.created_at);
+            session.count += 1;
+        }
+        let mut sessions: Vec<CaptureSession> = sessions.into_values().collect();
+        sessions.sort_by(|a, b| b.end.cmp(&a.end).then_with(|| a.session_id.cmp(&b.session_id)));
+        sessions
+    }
+
+    /// Images of one session, oldest first.
+    pub fn get_session_images(&self, session_id: &str) -> anyhow::Result<Vec<ImageMetadata>> {
+        let mut images: Vec<ImageMetadata> = self.images
+            .values()
+            .filter(|metadata| metadata.session_id.as_deref() == Some(session_id))
+            .map(Self::listed)
+            .collect();
+        if images.is_empty() {
+            return Err(SnapMagError::NotFound(format!("session {}", session_id)).into());
+        }
+        images.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        Ok(images)
+    }
+
+    /// Session of a new image saved at `now`. Clipboard captures join the
+    /// session of the latest clipboard capture when it is recent enough;
+    /// imports always start their own, and derived images get none.
+    fn session_for(&self, id: &str, source: CaptureSource, now: i64) -> Option<String> {
+        if source == CaptureSource::Derived {
+            return None;
+        }
+        if source != CaptureSource::Import {
+            let gap = self.config.session_gap_minutes as i64 * 60;
+            let previous = self.images
+                .values()
+                .filter(|metadata| metadata.session_id.is_some() && !matches!(metadata.source, CaptureSource::Import | CaptureSource::Derived))
+                .max_by_key(|metadata| metadata.created_at);
+            if let Some(previous) = previous.filter(|previous| now - previous.created_at <= gap) {
+                return previous.session_id.clone();
+            }
+        }
+        // Ids are unique, so the first image's id names the session.
+        Some(id.to_string())
+    }
+
+    /// Case-insensitive substring search over OCR text, notes and window
+    /// titles, newest first.
+    pub fn search_images(&self, query: &str) -> Vec<ImageMetadata> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return self.get_images();
+        }
+
+        let matches = |text: &Option<String>| {
+            text.as_ref().is_some_and(|text| text.to_lowercase().contains(&query))
+        };
+        let mut images: Vec<ImageMetadata> = self.images
+            .values()
+            .filter(|metadata| matches(&metadata.ocr_result) || matches(&metadata.notes) || matches(&metadata.context_title))
+            .map(Self::listed)
+            .collect();
+        images.sort_by_key(|metadata| std::cmp::Reverse(metadata.created_at));
         images
     }
 
+    #[cfg(target_os = "windows")]
+    pub fn set_context_title(&mut self, id: &str, title: Option<String>) -> anyhow::Result<ImageMetadata> {
+        let metadata = self.entry_mut(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        metadata.context_title = title.filter(|title| !title.trim().is_empty());
+        Ok(metadata.clone())
+    }
+
+    pub fn set_image_notes(&mut self, id: &str, notes: Option<String>) -> anyhow::Result<()> {
+        let notes = notes.filter(|notes| !notes.trim().is_empty());
+        if let Some(notes) = &notes {
+            let len = notes.chars().count();
+            if len > MAX_NOTES_CHARS {
+                return Err(SnapMagError::InvalidArgument(format!(
+                    "notes are {} characters long, the maximum is {}", len, MAX_NOTES_CHARS
+                )).into());
+            }
+        }
+
+        let metadata = self.entry_mut(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        metadata.notes = notes;
+        Ok(())
+    }
+
+    /// Adds `tag` to an image if it isn't already present and returns the updated metadata.
+    pub fn add_tag(&mut self, id: &str, tag: &str) -> anyhow::Result<ImageMetadata> {
+        let metadata = self.entry_mut(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        if !metadata.tags.iter().any(|existing| existing == tag) {
+            metadata.tags.push(tag.to_string());
+        }
+        Ok(metadata.clone())
+    }
+
+    /// Renames an image's file on disk to `{new_stem}.{ext}`, keeping its id.
+    /// `ext` follows the file's content, so a mislabeled file is fixed on the
+    /// way. Thumbnails are keyed by id, so they stay valid.
+    pub fn rename_image_file(&mut self, id: &str, new_stem: &str) -> anyhow::Result<ImageMetadata> {
+        if new_stem.trim().is_empty() || new_stem.contains(['/', '\\', '\0']) || new_stem == "." || new_stem == ".." {
+            return Err(SnapMagError::InvalidArgument(format!("invalid file name: {:?}", new_stem)).into());
+        }
+        
+        let mut new_path = self.storage_dir.join(new_stem);
+        let metadata = self.entry_mut(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        let old_path = PathBuf::from(&metadata.path);
+        let extension = content_extension(&old_path).map(Into::into).or_else(|| old_path.extension().map(ToOwned::to_owned));
+        if let Some(ext) = extension {
+            new_path.as_mut_os_string().push(".");
+            new_path.as_mut_os_string().push(ext);
+        }
+        if new_path == old_path {
+            return Ok(metadata.clone());
+        }
+        if new_path.exists() {
+            return Err(SnapMagError::InvalidArgument(format!("{} already exists", new_path.display())).into());
+        }
+        
+        fs::rename(&old_path, &new_path).map_err(|e| {
+            log::error!("Failed to rename {} to {}: {}", old_path.display(), new_path.display(), e);
+            anyhow::anyhow!("Failed to rename image file: {}", e)
+        })?;
+        metadata.path = new_path.to_string_lossy().to_string();
+        let metadata = metadata.clone();
+        self.moved_paths.push((old_path.to_string_lossy().to_string(), metadata.path.clone()));
+        Ok(metadata)
+    }
+
+    pub fn get_image_notes(&self, id: &str) -> anyhow::Result<Option<String>> {
+        let metadata = self.entry(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        Ok(metadata.notes.clone())
+    }
+
+    /// Deletes an image, keeping its file in the undo journal when it fits.
     pub fn delete_image(&mut self, id: &str) -> anyhow::Result<()> {
-        if let Some(metadata) = self.images.remove(id) {
+        let id = &self.canonical_id(id);
+        if let Some(metadata) = self.entry(id).cloned() {
+            if self.undo.record("delete", vec![metadata])? {
+                self.forget_image(id);
+                return Ok(());
+            }
+        }
+        self.purge_image(id)
+    }
+
+    /// Drops `id` from the index and its cached thumbnail, leaving its file.
+    fn forget_image(&mut self, id: &str) -> Option<ImageMetadata> {
+        let metadata = self.images.remove(id)?;
+        if let Some(legacy_id) = &metadata.legacy_id {
+            self.legacy_ids.remove(legacy_id);
+        }
+        self.thumbnails.remove(id);
+        self.mark_index_changed();
+        Some(metadata)
+    }
+
+    /// Deletes an image and its file without journaling.
+    fn purge_image(&mut self, id: &str) -> anyhow::Result<()> {
+        if let Some(metadata) = self.forget_image(id) {
             let path = Path::new(&metadata.path);
             if path.exists() {
                 fs::remove_file(path).map_err(|e| {
@@ -191,53 +1733,2378 @@ impl ImageManager {
                     anyhow::anyhow!("Failed to delete image file: {}", e)
                 })?;
             }
+            remove_raw_capture(&metadata);
         }
         Ok(())
     }
 
-    pub fn clear_all(&mut self) -> anyhow::Result<()> {
+    /// Empties the index. With `keep_files` the image files stay in the storage
+    /// directory for use outside the app; otherwise they are removed like
+    /// `clear_all` removes them, minus the sweep of unindexed files.
+    pub fn clear_index(&mut self, keep_files: bool) -> anyhow::Result<ClearSummary> {
+        let index_entries = self.images.len();
+        self.thumbnails.clear();
+        let files_removed = if keep_files {
+            self.images.clear();
+            self.mark_index_changed();
+            0
+        } else {
+            self.remove_indexed_files("clear_index")?
+        };
+        Ok(ClearSummary { index_entries, files_removed })
+    }
+
+    /// Deletes every image, including files in the storage directory that the
+    /// index doesn't know about. Only reachable through `confirm_clear_all`.
+    fn clear_all(&mut self) -> anyhow::Result<ClearSummary> {
+        let index_entries = self.images.len();
+        self.thumbnails.clear();
+        let files_removed = self.remove_indexed_files("clear_all")? + self.remove_stray_files();
+        Ok(ClearSummary { index_entries, files_removed })
+    }
+
+    /// Journals the files of every indexed image as one undo entry, or deletes
+    /// them when they don't fit, then empties the index. Returns how many
+    /// files were moved or deleted.
+    fn remove_indexed_files(&mut self, operation: &str) -> anyhow::Result<usize> {
+        let images: Vec<ImageMetadata> = self.images.values().cloned().collect();
+        let existing = images.iter().filter(|metadata| Path::new(&metadata.path).exists()).count();
+        self.mark_index_changed();
+        if !images.is_empty() && self.undo.record(operation, images)? {
+            self.images.clear();
+            return Ok(existing);
+        }
+        
         let paths: Vec<PathBuf> = self.images.values()
             .map(|metadata| PathBuf::from(&metadata.path))
             .collect();
         
+        let mut removed = 0;
         for path in &paths {
             if path.exists() {
-                if let Err(e) = fs::remove_file(path) {
-                    log::error!("Failed to delete file during clear_all: {} - {}", path.display(), e);
+                match fs::remove_file(path) {
+                    Ok(()) => removed += 1,
+                    Err(e) => log::error!("Failed to delete file during {}: {} - {}", operation, path.display(), e),
                 }
             }
         }
+        for metadata in self.images.values() {
+            remove_raw_capture(metadata);
+        }
         
         self.images.clear();
-        Ok(())
+        Ok(removed)
+    }
+
+    /// Deletes files directly in the storage directory that no indexed image
+    /// points to. Subdirectories (undo journal, thumbnails) and the saved
+    /// index are left alone.
+    fn remove_stray_files(&self) -> usize {
+        let Ok(entries) = fs::read_dir(&self.storage_dir) else {
+            return 0;
+        };
+        
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_index = path.file_name().is_some_and(|name| name == INDEX_FILE);
+            let is_indexed = self.images.values().any(|metadata| {
+                Path::new(&metadata.path) == path || metadata.raw_path.as_deref().is_some_and(|raw| Path::new(raw) == path)
+            });
+            if !path.is_file() || is_index || is_indexed {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    log::debug!("Removed stray file: {}", path.display());
+                    removed += 1;
+                }
+                Err(e) => log::warn!("Failed to remove stray file {}: {}", path.display(), e),
+            }
+        }
+        removed
+    }
+
+    /// First phase of `clear_all`: reports what would be deleted and issues
+    /// the token `confirm_clear_all` requires.
+    pub fn request_clear_all(&mut self) -> DestructiveConfirmation {
+        let ids: Vec<String> = self.images.keys().cloned().collect();
+        self.issue_confirmation(PendingOperation::ClearAll, &ids)
+    }
+
+    pub fn confirm_clear_all(&mut self, token: &str) -> anyhow::Result<ClearSummary> {
+        self.take_confirmation(token, PendingOperation::ClearAll)?;
+        self.clear_all()
     }
 
-    pub fn cleanup_old_images(&mut self, hours: i64) -> anyhow::Result<()> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)?
-            .as_secs() as i64;
+    /// Deletes images older than `hours` (or their tags' retention). A run
+    /// that would remove more than `cleanup_confirm_threshold` images deletes
+    /// nothing and returns a token for `confirm_cleanup_old_images` instead.
+    pub fn cleanup_old_images(&mut self, hours: i64) -> anyhow::Result<Option<DestructiveConfirmation>> {
+        let to_remove = self.old_image_ids(hours)?;
+        if self.config.cleanup_confirm_threshold.is_some_and(|threshold| to_remove.len() > threshold) {
+            return Ok(Some(self.issue_confirmation(PendingOperation::CleanupOldImages { hours }, &to_remove)));
+        }
         
-        let threshold = now - (hours * 3600);
+        for id in to_remove {
+            self.purge_image(&id)?;
+        }
         
-        let to_remove: Vec<String> = self.images
+        Ok(None)
+    }
+
+    /// Runs a cleanup confirmed by its token and returns how many images were
+    /// removed. The age cut-off is evaluated again at confirmation time.
+    pub fn confirm_cleanup_old_images(&mut self, token: &str) -> anyhow::Result<usize> {
+        let PendingOperation::CleanupOldImages { hours } = self.take_confirmation(token, None)? else {
+            return Err(SnapMagError::InvalidArgument("token does not confirm a cleanup".to_string()).into());
+        };
+        let to_remove = self.old_image_ids(hours)?;
+        for id in &to_remove {
+            self.purge_image(id)?;
+        }
+        Ok(to_remove.len())
+    }
+
+    pub fn set_cleanup_confirm_threshold(&mut self, threshold: Option<usize>) {
+        self.config.cleanup_confirm_threshold = threshold;
+    }
+
+    /// The images `cleanup_old_images(hours)` would delete, oldest first.
+    /// Nothing is removed.
+    pub fn preview_cleanup(&self, hours: i64) -> anyhow::Result<Vec<ImageMetadata>> {
+        let mut images: Vec<ImageMetadata> = self.old_image_ids(hours)?
             .iter()
-            .filter(|(_, metadata)| metadata.created_at < threshold)
-            .map(|(id, _)| id.clone())
+            .filter_map(|id| self.entry(id))
+            .map(Self::listed)
             .collect();
+        images.sort_by_key(|metadata| metadata.created_at);
+        Ok(images)
+    }
+
+    pub fn set_retention_preset(&mut self, preset: RetentionPreset) -> anyhow::Result<()> {
+        if preset.hours().is_some_and(|hours| hours <= 0) {
+            return Err(SnapMagError::InvalidArgument("retention hours must be positive".to_string()).into());
+        }
+        self.config.retention_preset = preset;
+        Ok(())
+    }
+
+    pub fn get_retention_preset(&self) -> RetentionPreset {
+        self.config.retention_preset
+    }
+
+    /// Deletes the images the retention preset no longer keeps and returns
+    /// their ids. Like `cleanup_old_images`, a first run of a preset that
+    /// would remove more than `cleanup_confirm_threshold` images deletes
+    /// nothing; it queues a token for `confirm_retention_preset` instead, see
+    /// `take_retention_confirmation`.
+    pub fn apply_retention_preset(&mut self) -> anyhow::Result<Vec<String>> {
+        let Some(hours) = self.config.retention_preset.hours() else {
+            return Ok(Vec::new());
+        };
+        let to_remove = self.old_image_ids(hours)?;
+        if self.retention_needs_confirmation(hours, to_remove.len()) {
+            if self.retention_requested_hours != Some(hours) {
+                self.retention_requested_hours = Some(hours);
+                self.retention_confirmation = Some(self.issue_confirmation(PendingOperation::RetentionPreset { hours }, &to_remove));
+            }
+            return Ok(Vec::new());
+        }
+        for id in &to_remove {
+            self.purge_image(id)?;
+        }
+        Ok(to_remove)
+    }
+
+    fn retention_needs_confirmation(&self, hours: i64, count: usize) -> bool {
+        self.retention_confirmed_hours != Some(hours)
+            && self.config.cleanup_confirm_threshold.is_some_and(|threshold| count > threshold)
+    }
+
+    /// The confirmation queued by the last held-back `apply_retention_preset`.
+    pub fn take_retention_confirmation(&mut self) -> Option<DestructiveConfirmation> {
+        self.retention_confirmation.take()
+    }
+
+    /// A fresh token for the retention sweep being held back, for when the
+    /// queued one expired unanswered. `None` when nothing is held back.
+    pub fn request_retention_confirmation(&mut self) -> anyhow::Result<Option<DestructiveConfirmation>> {
+        let Some(hours) = self.config.retention_preset.hours() else {
+            return Ok(None);
+        };
+        let to_remove = self.old_image_ids(hours)?;
+        if !self.retention_needs_confirmation(hours, to_remove.len()) {
+            return Ok(None);
+        }
+        self.retention_requested_hours = Some(hours);
+        Ok(Some(self.issue_confirmation(PendingOperation::RetentionPreset { hours }, &to_remove)))
+    }
+
+    /// Runs the held-back retention sweep confirmed by its token, and lets
+    /// later sweeps of the same preset run unconfirmed. Returns the removed ids.
+    pub fn confirm_retention_preset(&mut self, token: &str) -> anyhow::Result<Vec<String>> {
+        let PendingOperation::RetentionPreset { hours } = self.take_confirmation(token, None)? else {
+            return Err(SnapMagError::InvalidArgument("token does not confirm a retention sweep".to_string()).into());
+        };
+        if self.config.retention_preset.hours() != Some(hours) {
+            return Err(SnapMagError::InvalidArgument("the retention preset changed since the token was issued".to_string()).into());
+        }
+        self.retention_confirmed_hours = Some(hours);
+        let to_remove = self.old_image_ids(hours)?;
+        for id in &to_remove {
+            self.purge_image(id)?;
+        }
+        Ok(to_remove)
+    }
+
+    fn old_image_ids(&self, hours: i64) -> anyhow::Result<Vec<String>> {
+        let now = self.now();
         
-        for id in to_remove {
-            self.delete_image(&id)?;
+        // Tagged images use the longest retention among their tags' policies.
+        let policies = &self.config.tag_retention_policies;
+        Ok(self.images
+            .iter()
+            .filter(|(_, metadata)| {
+                let retention = metadata.tags
+                    .iter()
+                    .filter_map(|tag| policies.get(tag).copied())
+                    .max()
+                    .unwrap_or(hours);
+                metadata.created_at < now.saturating_sub(retention.saturating_mul(3600))
+            })
+            .map(|(id, _)| id.clone())
+            .collect())
+    }
+
+    fn issue_confirmation(&mut self, operation: PendingOperation, ids: &[String]) -> DestructiveConfirmation {
+        let now = Instant::now();
+        self.pending_confirmations.retain(|_, pending| pending.expires_at > now);
+        
+        let token = random_token();
+        self.pending_confirmations.insert(token.clone(), PendingConfirmation {
+            operation,
+            expires_at: now + CONFIRMATION_TTL,
+        });
+        DestructiveConfirmation {
+            token,
+            image_count: ids.len(),
+            total_bytes: ids.iter().filter_map(|id| self.entry(id)).map(|metadata| metadata.size_bytes).sum(),
+            expires_in_secs: CONFIRMATION_TTL.as_secs(),
+        }
+    }
+
+    /// Consumes `token`, checking it is unexpired and, when `expected` is
+    /// given, that it was issued for that operation.
+    fn take_confirmation(&mut self, token: &str, expected: impl Into<Option<PendingOperation>>) -> anyhow::Result<PendingOperation> {
+        let invalid = || SnapMagError::InvalidArgument("invalid or expired confirmation token".to_string());
+        let pending = self.pending_confirmations.remove(token).ok_or_else(invalid)?;
+        if pending.expires_at <= Instant::now() {
+            return Err(invalid().into());
+        }
+        if expected.into().is_some_and(|expected| expected != pending.operation) {
+            return Err(SnapMagError::InvalidArgument("token was issued for a different operation".to_string()).into());
+        }
+        Ok(pending.operation)
+    }
+
+    /// Images the user never organized: no tags, no OCR text and no notes.
+    pub fn find_orphaned_images(&self, page: usize, page_size: usize) -> anyhow::Result<ImagePage> {
+        if page_size == 0 {
+            return Err(SnapMagError::InvalidArgument("page_size must be greater than 0".to_string()).into());
         }
         
-        Ok(())
+        let mut orphans: Vec<&ImageMetadata> = self.images
+            .values()
+            .filter(|metadata| Self::is_orphaned(metadata))
+            .collect();
+        orphans.sort_by_key(|metadata| std::cmp::Reverse(metadata.created_at));
+        
+        let total = orphans.len();
+        let images = orphans
+            .into_iter()
+            .skip(page.saturating_mul(page_size))
+            .take(page_size)
+            .map(Self::listed)
+            .collect();
+        
+        Ok(ImagePage { images, page, page_size, total })
     }
 
-    fn calculate_hash(data: &[u8]) -> String {
-        use sha2::{Sha256, Digest};
+    /// Deletes orphaned images older than `older_than_hours`, returning how many were removed.
+    pub fn auto_cleanup_orphaned(&mut self, older_than_hours: i64) -> anyhow::Result<usize> {
+        let now = self.now();
+        
+        let threshold = now - older_than_hours.saturating_mul(3600);
+        
+        let to_remove: Vec<String> = self.images
+            .values()
+            .filter(|metadata| metadata.created_at < threshold && Self::is_orphaned(metadata))
+            .map(|metadata| metadata.id.clone())
+            .collect();
+        
+        for id in &to_remove {
+            self.purge_image(id)?;
+        }
         
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        let result = hasher.finalize();
-        hex::encode(result)
+        log::info!("Removed {} orphaned images older than {} hours", to_remove.len(), older_than_hours);
+        Ok(to_remove.len())
+    }
+
+    /// Copies what `ExtensionScan::sniff` needs, so the file reads run after
+    /// the lock is released. Hand the scan to `fix_extensions` afterwards.
+    pub fn extension_scan(&self) -> ExtensionScan {
+        let mut images: Vec<ScannedFile> = self.images
+            .values()
+            .map(|metadata| ScannedFile { id: metadata.id.clone(), path: metadata.path.clone(), sniffed: None })
+            .collect();
+        images.sort_by(|a, b| a.id.cmp(&b.id));
+        ExtensionScan { images }
+    }
+
+    /// Renames image files a sniffed `scan` found with an extension that
+    /// doesn't match their content, such as JPEG data saved as `.png`, to the
+    /// extension of that format, and records the format. Images removed or
+    /// moved since the scan, and files that can't be renamed, are skipped.
+    /// Returns what was renamed.
+    pub fn fix_extensions(&mut self, scan: ExtensionScan) -> anyhow::Result<Vec<ExtensionFix>> {
+        let mut fixes = Vec::new();
+        let mut changed = false;
+        for file in scan.images {
+            let Some((format, extension)) = file.sniffed else {
+                continue;
+            };
+            let Some(metadata) = self.images.get_mut(&file.id) else {
+                continue;
+            };
+            if metadata.path != file.path {
+                continue;
+            }
+            if metadata.format.as_deref() != Some(format.as_str()) {
+                metadata.format = Some(format.clone());
+                changed = true;
+            }
+            let path = PathBuf::from(&metadata.path);
+            if path.extension().is_some_and(|ext| ext == extension.as_str()) {
+                continue;
+            }
+
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| metadata.id.clone());
+            let mut new_path = path.with_file_name(format!("{}.{}", stem, extension));
+            let mut counter = 2;
+            while new_path.exists() {
+                new_path = path.with_file_name(format!("{}-{}.{}", stem, counter, extension));
+                counter += 1;
+            }
+            if let Err(e) = fs::rename(&path, &new_path) {
+                log::warn!("Failed to rename {} to {}: {}", path.display(), new_path.display(), e);
+                continue;
+            }
+            metadata.path = new_path.to_string_lossy().to_string();
+            self.moved_paths.push((path.to_string_lossy().to_string(), metadata.path.clone()));
+            fixes.push(ExtensionFix {
+                id: metadata.id.clone(),
+                old_path: path.to_string_lossy().to_string(),
+                new_path: metadata.path.clone(),
+                format,
+            });
+        }
+        if !fixes.is_empty() {
+            log::info!("Fixed the extension of {} image file(s)", fixes.len());
+        }
+        if changed || !fixes.is_empty() {
+            self.save_index()?;
+        }
+        Ok(fixes)
+    }
+
+    /// Renames the files of images with OCR text to
+    /// `{slug of the text}_{last 8 chars of the id}.{ext}`, adding a counter
+    /// when the name is taken. With `dry_run` nothing is renamed. Returns the
+    /// planned or done renames, in id order.
+    pub fn humanize_filenames(&mut self, dry_run: bool) -> anyhow::Result<Vec<RenameOperation>> {
+        let mut ids: Vec<String> = self.images
+            .values()
+            .filter(|metadata| metadata.ocr_result.as_deref().is_some_and(|text| !ocr_slug(text).is_empty()))
+            .map(|metadata| metadata.id.clone())
+            .collect();
+        ids.sort();
+
+        let mut taken = HashSet::new();
+        let mut operations = Vec::new();
+        for id in ids {
+            let metadata = &self.images[&id];
+            let path = PathBuf::from(&metadata.path);
+            let Some(old_name) = path.file_name().map(|name| name.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let extension = content_extension(&path)
+                .or_else(|| path.extension().map(|ext| ext.to_string_lossy().into_owned()))
+                .unwrap_or_else(|| "png".to_string());
+            // The head of a ULID is its timestamp, the same for a burst of
+            // captures; the tail is random.
+            let stem = format!("{}_{}", ocr_slug(metadata.ocr_result.as_deref().unwrap_or_default()), &id[id.len().saturating_sub(8)..]);
+            let mut new_name = format!("{}.{}", stem, extension);
+            let mut counter = 2;
+            while new_name != old_name && (taken.contains(&new_name) || self.storage_dir.join(&new_name).exists()) {
+                new_name = format!("{}_{}.{}", stem, counter, extension);
+                counter += 1;
+            }
+            if new_name == old_name {
+                continue;
+            }
+            taken.insert(new_name.clone());
+
+            if !dry_run {
+                let new_path = path.with_file_name(&new_name);
+                if let Err(e) = fs::rename(&path, &new_path) {
+                    log::warn!("Failed to rename {} to {}: {}", path.display(), new_path.display(), e);
+                    continue;
+                }
+                if let Some(metadata) = self.images.get_mut(&id) {
+                    metadata.path = new_path.to_string_lossy().to_string();
+                    self.moved_paths.push((path.to_string_lossy().to_string(), metadata.path.clone()));
+                }
+            }
+            operations.push(RenameOperation { old_name, new_name, id });
+        }
+        if !dry_run && !operations.is_empty() {
+            log::info!("Renamed {} image file(s) after their text", operations.len());
+            self.save_index()?;
+        }
+        Ok(operations)
+    }
+
+    fn is_orphaned(metadata: &ImageMetadata) -> bool {
+        metadata.tags.is_empty() && metadata.ocr_result.is_none() && metadata.notes.is_none()
+    }
+
+    /// First phase of `delete_images_where`: reports what `filter` matches
+    /// and issues the token `confirm_delete_images_where` requires. Images
+    /// with a tag kept forever (see `set_tag_retention`) never match. A filter
+    /// with no criteria is rejected rather than matching the whole library.
+    pub fn request_delete_images_where(&mut self, filter: DeleteFilter) -> anyhow::Result<DestructiveConfirmation> {
+        let ids = self.ids_matching(&filter)?;
+        Ok(self.issue_confirmation(PendingOperation::DeleteWhere(filter), &ids))
+    }
+
+    /// Deletes the images matching the filter confirmed by `token`, as one
+    /// undo entry. The filter is evaluated again at confirmation time.
+    pub fn confirm_delete_images_where(&mut self, token: &str) -> anyhow::Result<DeletionSummary> {
+        let PendingOperation::DeleteWhere(filter) = self.take_confirmation(token, None)? else {
+            return Err(SnapMagError::InvalidArgument("token does not confirm a filtered delete".to_string()).into());
+        };
+        let ids = self.ids_matching(&filter)?;
+        let freed_bytes = ids.iter().filter_map(|id| self.entry(id)).map(|metadata| metadata.size_bytes).sum();
+        if !ids.is_empty() {
+            self.remove_journaled("delete_images_where", &ids)?;
+        }
+        log::info!("Removed {} image(s) matching {:?}", ids.len(), filter);
+        Ok(DeletionSummary { ids, freed_bytes })
+    }
+
+    fn ids_matching(&self, filter: &DeleteFilter) -> anyhow::Result<Vec<String>> {
+        if filter.older_than.is_none() && filter.source.is_none() && !filter.untagged_only && filter.min_size_bytes.is_none() {
+            return Err(SnapMagError::InvalidArgument("filter must set at least one criterion".to_string()).into());
+        }
+        
+        let policies = &self.config.tag_retention_policies;
+        Ok(self.images
+            .values()
+            .filter(|metadata| {
+                filter.older_than.map_or(true, |cutoff| metadata.created_at < cutoff)
+                    && filter.source.map_or(true, |source| metadata.source == source)
+                    && (!filter.untagged_only || metadata.tags.is_empty())
+                    && filter.min_size_bytes.map_or(true, |min| metadata.size_bytes >= min)
+                    && !metadata.tags.iter().any(|tag| policies.get(tag) == Some(&i64::MAX))
+            })
+            .map(|metadata| metadata.id.clone())
+            .collect())
+    }
+
+    /// Removes `ids` from the index as one undo entry. Unlike `delete_image`
+    /// there is no fallback to deleting outright: a bulk removal too large
+    /// for the undo budget fails without removing anything.
+    fn remove_journaled(&mut self, operation: &str, ids: &[String]) -> anyhow::Result<()> {
+        let images: Vec<ImageMetadata> = ids.iter().filter_map(|id| self.entry(id).cloned()).collect();
+        if !self.undo.record(operation, images)? {
+            return Err(SnapMagError::InvalidArgument(format!(
+                "{} of {} images is too large to undo; select fewer images",
+                operation,
+                ids.len()
+            ))
+            .into());
+        }
+        for id in ids {
+            self.forget_image(id);
+        }
+        Ok(())
+    }
+
+    /// Restores the most recent journaled operation. Returns `None` when the
+    /// undo stack is empty; `image_ids` lists the entries actually restored.
+    /// Items whose files fail to come back stay on the stack as the top
+    /// entry, so the next undo retries them.
+    pub fn undo_last(&mut self) -> anyhow::Result<Option<UndoEntryInfo>> {
+        let Some(entry) = self.undo.peek() else {
+            return Ok(None);
+        };
+        let mut info = entry.info();
+        info.image_ids.clear();
+        let mut restored = Vec::new();
+        let mut unrestored: Vec<UndoItem> = Vec::new();
+        for item in &entry.items {
+            if let Err(e) = item.restore() {
+                log::error!("Failed to restore image {} from undo: {}", item.metadata.id, e);
+                unrestored.push(item.clone());
+                continue;
+            }
+            // Entries journaled before ids became ULIDs come back migrated.
+            let mut metadata = item.metadata.clone();
+            if let Err(e) = migrate_legacy_id(&mut metadata) {
+                log::error!("Failed to migrate restored image {}: {}", item.metadata.id, e);
+                unrestored.push(item.clone());
+                continue;
+            }
+            restored.push(metadata);
+        }
+        
+        let mut entry = self.undo.pop().expect("peeked entry is on the stack");
+        if !unrestored.is_empty() {
+            entry.items = unrestored;
+            self.undo.push_back(entry);
+        }
+        for metadata in restored {
+            let id = metadata.id.clone();
+            if !metadata.content_hash.is_empty() {
+                self.content_ids.insert(metadata.content_hash.clone(), id.clone());
+            }
+            if let Some(legacy_id) = &metadata.legacy_id {
+                self.legacy_ids.insert(legacy_id.clone(), id.clone());
+            }
+            self.images.entry(id.clone()).or_insert(metadata);
+            self.mark_index_changed();
+            info.image_ids.push(id);
+        }
+        
+        log::info!("Undid {} ({} images restored)", info.operation, info.image_ids.len());
+        Ok(Some(info))
+    }
+
+    pub fn get_undo_stack(&self) -> Vec<UndoEntryInfo> {
+        self.undo.entries()
+    }
+
+    /// Compares capture counts between two `[start, end)` windows of Unix seconds.
+    /// `percent_change` is relative to window A; when window A is empty it is
+    /// 0.0 if window B is empty too and 100.0 otherwise.
+    pub fn compare_capture_rates(&self, window_a: (i64, i64), window_b: (i64, i64)) -> anyhow::Result<CaptureRateComparison> {
+        let (a_start, a_end) = window_a;
+        let (b_start, b_end) = window_b;
+
+        if a_end <= a_start || b_end <= b_start {
+            anyhow::bail!("Invalid time window: end must be greater than start");
+        }
+        if a_start < b_end && b_start < a_end {
+            anyhow::bail!("Time windows must not overlap");
+        }
+
+        let count_in = |start: i64, end: i64| {
+            self.images
+                .values()
+                .filter(|metadata| metadata.created_at >= start && metadata.created_at < end)
+                .count()
+        };
+        let window_a_count = count_in(a_start, a_end);
+        let window_b_count = count_in(b_start, b_end);
+
+        let window_a_rate_per_hour = window_a_count as f64 / ((a_end - a_start) as f64 / 3600.0);
+        let window_b_rate_per_hour = window_b_count as f64 / ((b_end - b_start) as f64 / 3600.0);
+
+        let percent_change = if window_a_rate_per_hour > 0.0 {
+            (window_b_rate_per_hour - window_a_rate_per_hour) / window_a_rate_per_hour * 100.0
+        } else if window_b_rate_per_hour > 0.0 {
+            100.0
+        } else {
+            0.0
+        };
+
+        Ok(CaptureRateComparison {
+            window_a_count,
+            window_b_count,
+            window_a_rate_per_hour,
+            window_b_rate_per_hour,
+            percent_change,
+        })
+    }
+
+    /// Per-day capture counts for the last `days` local days (today included),
+    /// plus totals by source over the same window, computed in one pass.
+    pub fn get_capture_stats(&self, days: usize) -> CaptureStats {
+        use chrono::{DateTime, Duration, Local};
+
+        let today = DateTime::from_timestamp(self.now(), 0)
+            .unwrap_or_default()
+            .with_timezone(&Local)
+            .date_naive();
+        let first_day = today - Duration::days(days.saturating_sub(1) as i64);
+
+        let mut daily: Vec<DailyCaptureStats> = (0..days)
+            .map(|offset| DailyCaptureStats {
+                date: (first_day + Duration::days(offset as i64)).format("%Y-%m-%d").to_string(),
+                count: 0,
+                total_bytes: 0,
+            })
+            .collect();
+        let mut by_source: HashMap<CaptureSource, SourceCaptureStats> = HashMap::new();
+        let mut total_count = 0;
+        let mut total_bytes = 0;
+
+        for metadata in self.images.values() {
+            let Some(created) = DateTime::from_timestamp(metadata.created_at, 0) else {
+                continue;
+            };
+            let offset = (created.with_timezone(&Local).date_naive() - first_day).num_days();
+            if offset < 0 || offset as usize >= days {
+                continue;
+            }
+
+            let day = &mut daily[offset as usize];
+            day.count += 1;
+            day.total_bytes += metadata.size_bytes;
+
+            let source_stats = by_source.entry(metadata.source).or_default();
+            source_stats.count += 1;
+            source_stats.total_bytes += metadata.size_bytes;
+
+            total_count += 1;
+            total_bytes += metadata.size_bytes;
+        }
+
+        CaptureStats {
+            days: daily,
+            by_source,
+            total_count,
+            total_bytes,
+        }
+    }
+
+    /// Writes the OCR text of every image that has some, oldest first, to
+    /// `dest` and returns the number of records. In TSV, backslashes, tabs
+    /// and line breaks in the text are escaped as `\\`, `\t`, `\n` and `\r`
+    /// so each image stays on one line.
+    pub fn export_ocr_corpus(&self, dest: &Path, format: OcrCorpusFormat) -> anyhow::Result<usize> {
+        let mut images: Vec<&ImageMetadata> = self.images.values().filter(|metadata| metadata.ocr_result.is_some()).collect();
+        images.sort_by_key(|metadata| (metadata.created_at, &metadata.id));
+        let entries: Vec<OcrCorpusEntry> = images
+            .iter()
+            .map(|metadata| OcrCorpusEntry {
+                id: metadata.id.clone(),
+                created_at_iso: iso_timestamp(metadata.created_at),
+                ocr_result: metadata.ocr_result.clone().unwrap_or_default(),
+            })
+            .collect();
+        
+        let mut writer = BufWriter::new(fs::File::create(dest)?);
+        match format {
+            OcrCorpusFormat::Tsv => {
+                for entry in &entries {
+                    let text = entry.ocr_result
+                        .replace('\\', "\\\\")
+                        .replace('\t', "\\t")
+                        .replace('\n', "\\n")
+                        .replace('\r', "\\r");
+                    writeln!(writer, "{}\t{}\t{}", entry.id, entry.created_at_iso, text)?;
+                }
+            }
+            OcrCorpusFormat::Json => serde_json::to_writer_pretty(&mut writer, &entries)?,
+            OcrCorpusFormat::Csv => {
+                writeln!(writer, "id,created_at_iso,ocr_result")?;
+                for entry in &entries {
+                    writeln!(writer, "{},{},{}", csv_field(&entry.id), csv_field(&entry.created_at_iso), csv_field(&entry.ocr_result))?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(entries.len())
+    }
+
+    /// Writes the metadata of every image, oldest first, to `dest` and
+    /// returns the number of records. Paths are real filesystem paths; OCR
+    /// text is left out unless `include_ocr`, and captured window titles,
+    /// which can hold private text, with `strip_window_titles`.
+    pub fn export_metadata(&self, dest: &Path, format: ExportFormat, include_ocr: bool, strip_window_titles: bool) -> anyhow::Result<usize> {
+        let mut images: Vec<&ImageMetadata> = self.images.values().collect();
+        images.sort_by_key(|metadata| (metadata.created_at, &metadata.id));
+        
+        let mut writer = BufWriter::new(fs::File::create(dest)?);
+        match format {
+            ExportFormat::Json => {
+                let records: Vec<ImageMetadata> = images
+                    .iter()
+                    .map(|metadata| ImageMetadata {
+                        ocr_result: if include_ocr { metadata.ocr_result.clone() } else { None },
+                        context_title: if strip_window_titles { None } else { metadata.context_title.clone() },
+                        inline_thumb: None,
+                        ..(*metadata).clone()
+                    })
+                    .collect();
+                serde_json::to_writer_pretty(&mut writer, &records)?;
+            }
+            ExportFormat::Csv => {
+                // Columns added since the first export go last, so existing
+                // readers keep finding theirs in place.
+                let mut header = vec!["id", "path", "created_at", "source", "size_bytes", "original_format", "tags", "notes", "expires_at"];
+                if include_ocr {
+                    header.push("ocr_result");
+                }
+                header.extend(["format", "raw_path", "context_title"]);
+                writeln!(writer, "{}", header.join(","))?;
+                
+                for metadata in &images {
+                    let source = serde_json::to_value(metadata.source)?;
+                    let mut fields = vec![
+                        metadata.id.clone(),
+                        metadata.path.clone(),
+                        metadata.created_at.to_string(),
+                        source.as_str().unwrap_or_default().to_string(),
+                        metadata.size_bytes.to_string(),
+                        metadata.original_format.clone().unwrap_or_default(),
+                        metadata.tags.join(";"),
+                        metadata.notes.clone().unwrap_or_default(),
+                        metadata.expires_at.map(|t| t.to_string()).unwrap_or_default(),
+                    ];
+                    if include_ocr {
+                        fields.push(metadata.ocr_result.clone().unwrap_or_default());
+                    }
+                    fields.extend([
+                        metadata.format.clone().unwrap_or_default(),
+                        metadata.raw_path.clone().unwrap_or_default(),
+                        if strip_window_titles { String::new() } else { metadata.context_title.clone().unwrap_or_default() },
+                    ]);
+                    let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                    writeln!(writer, "{}", row.join(","))?;
+                }
+            }
+        }
+        writer.flush()?;
+        
+        log::info!("Exported {} image records to {}", images.len(), dest.display());
+        Ok(images.len())
+    }
+
+    /// The image's BlurHash if it has been computed; `compute_blurhash`
+    /// makes one from its file.
+    pub fn cached_blurhash(&self, id: &str) -> anyhow::Result<Option<String>> {
+        let metadata = self.entry(id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        Ok(metadata.blurhash.clone())
+    }
+
+    /// Keeps `blurhash`, computed from `path`, if the image is still stored
+    /// there, and returns the updated image.
+    pub fn cache_blurhash(&mut self, id: &str, path: &str, blurhash: String) -> Option<ImageMetadata> {
+        let metadata = self.entry_mut(id).filter(|metadata| metadata.path == path)?;
+        metadata.blurhash = Some(blurhash);
+        Some(Self::listed(metadata))
+    }
+
+    /// Saves the frames `encode_gif_frames` made of GIF `id`, each as its
+    /// own image tagged with its 1-based frame number and the source id.
+    pub fn save_gif_frames(&mut self, id: &str, frames: &[Vec<u8>]) -> anyhow::Result<Vec<ImageMetadata>> {
+        let source_tag = format!("auto:gif-source-{}", id);
+        let mut saved = Vec::with_capacity(frames.len());
+        for (index, png_data) in frames.iter().enumerate() {
+            let metadata = self.save_derived_png(png_data)?;
+            self.add_tag(&metadata.id, &format!("auto:gif-frame-{}", index + 1))?;
+            saved.push(self.add_tag(&metadata.id, &source_tag)?);
+        }
+        Ok(saved)
+    }
+
+    /// Saves a copy drawn by `annotate_image`, tagged `annotated`.
+    pub fn save_annotated(&mut self, png_data: &[u8], errors: Vec<AnnotationError>) -> anyhow::Result<AnnotationResult> {
+        let metadata = self.save_derived_png(png_data)?;
+        let image = self.add_tag(&metadata.id, "annotated")?;
+        Ok(AnnotationResult { image, errors })
+    }
+
+    /// Saves an animated preview from `encode_animated_preview`, tagged as
+    /// one.
+    pub fn save_animated_preview(&mut self, apng_data: &[u8]) -> anyhow::Result<ImageMetadata> {
+        let metadata = self.save_image(apng_data, CaptureSource::Derived)?.metadata;
+        self.add_tag(&metadata.id, "auto:animated-preview")
+    }
+
+    /// Color of one pixel of image `id`, in stored-image pixel coordinates.
+    pub fn get_pixel_color(&self, id: &str, x: u32, y: u32) -> anyhow::Result<PixelColor> {
+        let image = self.load_image(id)?;
+        Ok(image_ops::sample_pixel(&image, x, y)?)
+    }
+
+    /// Average color of `rect` in image `id`, in stored-image pixel
+    /// coordinates.
+    pub fn get_region_average(&self, id: &str, rect: PixelRect) -> anyhow::Result<PixelColor> {
+        let image = self.load_image(id)?;
+        Ok(image_ops::region_average(&image, rect)?)
+    }
+
+    /// Copies what `NearDuplicateScan::find` needs, so the decoding runs
+    /// after the lock is released. Hand the scan back to
+    /// `cache_perceptual_hashes` afterwards.
+    pub fn near_duplicate_scan(&self) -> NearDuplicateScan {
+        let mut images: Vec<&ImageMetadata> = self.images.values().collect();
+        images.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        NearDuplicateScan {
+            images: images
+                .into_iter()
+                .map(|metadata| (metadata.id.clone(), metadata.path.clone(), self.perceptual_hashes.get(&metadata.id).copied()))
+                .collect(),
+        }
+    }
+
+    /// Keeps the hashes a scan computed, for images still in the library.
+    pub fn cache_perceptual_hashes(&mut self, scan: NearDuplicateScan) {
+        for (id, _, hash) in scan.images {
+            if let Some(hash) = hash {
+                self.perceptual_hashes.insert(id, hash);
+            }
+        }
+        self.perceptual_hashes.retain(|id, _| self.images.contains_key(id));
+    }
+
+    /// Deletes all but one image of every group of `groups` (from
+    /// `NearDuplicateScan::find_for_cleanup`), keeping its
+    /// `"oldest"` or `"newest"` image, as one undo entry. Groups are
+    /// re-checked against the library, so a stale scan removes nothing it
+    /// shouldn't. A cleanup of more than `cleanup_confirm_threshold` images
+    /// deletes nothing and returns a token for `confirm_cleanup_near_duplicates`.
+    pub fn cleanup_near_duplicates(&mut self, groups: Vec<NearDuplicateGroup>, keep: &str) -> anyhow::Result<CleanupOutcome> {
+        let keep_newest = match keep {
+            "oldest" => false,
+            "newest" => true,
+            _ => return Err(SnapMagError::InvalidArgument(format!("keep must be \"oldest\" or \"newest\", not {:?}", keep)).into()),
+        };
+        
+        let mut to_remove = Vec::new();
+        for group in groups {
+            // Groups are oldest first, so the newest image is the last duplicate.
+            let mut members = group.duplicate_ids;
+            if keep_newest {
+                members.pop();
+                members.push(group.canonical_id);
+            }
+            to_remove.extend(members);
+        }
+        to_remove.retain(|id| self.images.contains_key(id));
+        if self.config.cleanup_confirm_threshold.is_some_and(|threshold| to_remove.len() > threshold) {
+            let confirmation = self.issue_confirmation(PendingOperation::CleanupNearDuplicates { ids: to_remove.clone() }, &to_remove);
+            return Ok(CleanupOutcome::NeedsConfirmation(confirmation));
+        }
+        self.remove_near_duplicates(to_remove).map(CleanupOutcome::Removed)
+    }
+
+    /// Runs a near-duplicate cleanup confirmed by its token and returns the
+    /// removed ids.
+    pub fn confirm_cleanup_near_duplicates(&mut self, token: &str) -> anyhow::Result<Vec<String>> {
+        let PendingOperation::CleanupNearDuplicates { mut ids } = self.take_confirmation(token, None)? else {
+            return Err(SnapMagError::InvalidArgument("token does not confirm a near-duplicate cleanup".to_string()).into());
+        };
+        ids.retain(|id| self.images.contains_key(id));
+        self.remove_near_duplicates(ids)
+    }
+
+    fn remove_near_duplicates(&mut self, ids: Vec<String>) -> anyhow::Result<Vec<String>> {
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+        self.remove_journaled("cleanup_near_duplicates", &ids)?;
+        log::info!("Removed {} near-duplicate image(s)", ids.len());
+        Ok(ids)
+    }
+
+    /// Copies what `ColorScan::sort` needs, so the decoding runs after the
+    /// lock is released. Hand the scan back to `cache_dominant_hues`
+    /// afterwards.
+    pub fn color_scan(&self, hue_bins: u32) -> anyhow::Result<ColorScan> {
+        if !(2..=36).contains(&hue_bins) {
+            return Err(SnapMagError::InvalidArgument(format!("hue_bins must be 2 to 36, got {}", hue_bins)).into());
+        }
+        let mut images: Vec<&ImageMetadata> = self.images.values().collect();
+        images.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(ColorScan {
+            hue_bins,
+            images: images
+                .into_iter()
+                .map(|metadata| (Self::listed(metadata), self.dominant_hues.get(&metadata.id).copied()))
+                .collect(),
+        })
+    }
+
+    /// Keeps the hues a scan computed, for images still in the library.
+    pub fn cache_dominant_hues(&mut self, scan: ColorScan) {
+        for (metadata, hue) in scan.images {
+            if let Some(hue) = hue {
+                self.dominant_hues.insert(metadata.id, hue);
+            }
+        }
+        self.dominant_hues.retain(|id, _| self.images.contains_key(id));
+    }
+
+    /// Picks the monitor of `monitors` (physical sizes, in system order)
+    /// image `id` was most likely captured from: one it exactly matches, or
+    /// else the one it is the largest same-aspect-ratio crop of.
+    pub fn identify_source_monitor(&self, id: &str, monitors: &[(u32, u32)]) -> anyhow::Result<Option<MonitorIdentification>> {
+        let metadata = self.entry(id).ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        let (width, height) = image::image_dimensions(&metadata.path)
+            .map_err(|e| SnapMagError::ImageDecodeError(e.to_string()))?;
+        
+        let score = |&(monitor_width, monitor_height): &(u32, u32)| -> Option<f64> {
+            if (width, height) == (monitor_width, monitor_height) {
+                return Some(1.0);
+            }
+            if width == 0 || height == 0 || width > monitor_width || height > monitor_height {
+                return None;
+            }
+            let aspect = width as f64 / height as f64;
+            let monitor_aspect = monitor_width as f64 / monitor_height as f64;
+            ((aspect - monitor_aspect).abs() / monitor_aspect <= MONITOR_ASPECT_TOLERANCE)
+                .then(|| MONITOR_CROP_CONFIDENCE * width as f64 / monitor_width as f64)
+        };
+        
+        let mut best: Option<MonitorIdentification> = None;
+        for (index, monitor) in monitors.iter().enumerate() {
+            let Some(confidence) = score(monitor) else {
+                continue;
+            };
+            if best.as_ref().map_or(true, |best| confidence > best.confidence) {
+                best = Some(MonitorIdentification {
+                    monitor_index: index as u32,
+                    confidence,
+                    monitor_resolution: *monitor,
+                });
+            }
+        }
+        Ok(best)
+    }
+
+    /// Copies what `TemporalOrderScan::estimate` needs for the images
+    /// `ids`, so the decoding runs after the lock is released. Hand the scan
+    /// back to `cache_temporal_order_hashes` afterwards.
+    pub fn temporal_order_scan(&self, ids: &[String]) -> anyhow::Result<TemporalOrderScan> {
+        if ids.len() < 2 {
+            return Err(SnapMagError::InvalidArgument("at least two images are needed".to_string()).into());
+        }
+        let images = ids
+            .iter()
+            .map(|id| {
+                let metadata = self.entry(id).ok_or_else(|| SnapMagError::NotFound(id.clone()))?;
+                Ok(TemporalOrderImage {
+                    id: id.clone(),
+                    canonical_id: metadata.id.clone(),
+                    path: metadata.path.clone(),
+                    created_at: metadata.created_at,
+                    context_title: metadata.context_title.clone(),
+                    hash: self.perceptual_hashes.get(&metadata.id).copied(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(TemporalOrderScan { images })
+    }
+
+    /// Keeps the hashes a temporal order scan computed, for images still in
+    /// the library.
+    pub fn cache_temporal_order_hashes(&mut self, scan: TemporalOrderScan) {
+        for image in scan.images {
+            if let (Some(hash), true) = (image.hash, self.images.contains_key(&image.canonical_id)) {
+                self.perceptual_hashes.insert(image.canonical_id, hash);
+            }
+        }
+    }
+
+    fn load_image(&self, id: &str) -> anyhow::Result<DynamicImage> {
+        open_image(&self.image_path(id)?)
+    }
+
+    /// Stores a processed image already encoded by `encode_derived_image`.
+    pub fn save_derived_png(&mut self, png_data: &[u8]) -> anyhow::Result<ImageMetadata> {
+        Ok(self.save_image(png_data, CaptureSource::Derived)?.metadata)
+    }
+}
+
+impl ExtensionScan {
+    /// Sniffs the format of every scanned file from its header, and the
+    /// extension `content_extension` gives it.
+    pub fn sniff(&mut self) {
+        for file in &mut self.images {
+            let path = Path::new(&file.path);
+            file.sniffed = sniff_file_format(path).zip(content_extension(path));
+        }
+    }
+}
+
+impl TemporalOrderScan {
+    /// Scores each consecutive pair of images as possible back-to-back
+    /// frames, most similar first. A pair above `TIGHT_SEQUENCE_SIMILARITY`
+    /// captured from the same window is taken as a tight sequence and gets
+    /// the time between its captures. Hashes the scan has no cached value
+    /// for are computed here.
+    pub fn estimate(&mut self) -> anyhow::Result<Vec<TemporalOrderResult>> {
+        let hashes = self.images
+            .iter_mut()
+            .map(|image| {
+                if let Some(hash) = image.hash {
+                    return Ok(hash);
+                }
+                let decoded = image::open(&image.path).map_err(|e| {
+                    log::warn!("Failed to decode {} for temporal order: {}", image.id, e);
+                    SnapMagError::ImageDecodeError(format!("{} could not be decoded", image.id))
+                })?;
+                Ok(*image.hash.insert(image_ops::perceptual_hash(&decoded)))
+            })
+            .collect::<anyhow::Result<Vec<u64>>>()?;
+        
+        let mut results: Vec<TemporalOrderResult> = self.images
+            .windows(2)
+            .zip(hashes.windows(2))
+            .map(|(pair, hashes)| {
+                let (from, to) = (&pair[0], &pair[1]);
+                let similarity = 1.0 - image_ops::perceptual_distance(hashes[0], hashes[1]);
+                let same_window = from.context_title.is_some() && from.context_title == to.context_title;
+                let tight = same_window && similarity > TIGHT_SEQUENCE_SIMILARITY;
+                TemporalOrderResult {
+                    id_from: from.id.clone(),
+                    id_to: to.id.clone(),
+                    similarity,
+                    confidence: if same_window { similarity } else { similarity * 0.5 },
+                    estimated_transition_ms: tight
+                        .then(|| u32::try_from(to.created_at.abs_diff(from.created_at).saturating_mul(1000)).unwrap_or(u32::MAX)),
+                }
+            })
+            .collect();
+        results.sort_by(|a, b| b.similarity.total_cmp(&a.similarity));
+        Ok(results)
+    }
+}
+
+impl ColorScan {
+    /// Images grouped into `hue_bins` equal arcs of the hue circle by their
+    /// dominant color, each bucket sorted by hue, followed by one bucket of
+    /// grayscale images. Empty buckets are kept so the index gives the arc.
+    /// Hues the scan has no cached value for are computed here.
+    pub fn sort(&mut self) -> Vec<Vec<ImageMetadata>> {
+        let hue_bins = self.hue_bins as usize;
+        let mut hued: Vec<Vec<(f32, &ImageMetadata)>> = vec![Vec::new(); hue_bins];
+        let mut grayscale = Vec::new();
+        for (metadata, cached) in &mut self.images {
+            let hue = match *cached {
+                Some(hue) => hue,
+                None => match open_image(Path::new(&metadata.path)) {
+                    Ok(image) => *cached.insert(image_ops::dominant_hue(&image)),
+                    Err(e) => {
+                        log::warn!("Leaving {} out of the color view: {}", metadata.id, e);
+                        continue;
+                    }
+                },
+            };
+            match hue {
+                Some(hue) => {
+                    let bin = ((hue / 360.0 * hue_bins as f32) as usize).min(hue_bins - 1);
+                    hued[bin].push((hue, metadata));
+                }
+                None => grayscale.push(metadata.clone()),
+            }
+        }
+        
+        let mut buckets: Vec<Vec<ImageMetadata>> = hued
+            .into_iter()
+            .map(|mut bucket| {
+                bucket.sort_by(|a, b| a.0.total_cmp(&b.0));
+                bucket.into_iter().map(|(_, metadata)| metadata.clone()).collect()
+            })
+            .collect();
+        buckets.push(grayscale);
+        buckets
+    }
+}
+
+impl NearDuplicateScan {
+    /// Hashes the images the scan has no hash for and groups them as
+    /// `ImageManager::find_near_duplicates` describes.
+    pub fn find(&mut self, max_hamming_distance: u8) -> anyhow::Result<Vec<NearDuplicateGroup>> {
+        if max_hamming_distance > 64 {
+            return Err(SnapMagError::InvalidArgument("max Hamming distance is 64 bits".to_string()).into());
+        }
+        let max_distance = max_hamming_distance as u32;
+        
+        for (id, path, hash) in self.images.iter_mut().filter(|(_, _, hash)| hash.is_none()) {
+            match image::open(&*path) {
+                Ok(image) => *hash = Some(image_ops::perceptual_hash(&image)),
+                Err(e) => log::warn!("Skipping {} in the near-duplicate scan: {}", id, e),
+            }
+        }
+        let hashes: Vec<(usize, u64)> = self.images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, _, hash))| Some((index, (*hash)?)))
+            .collect();
+        
+        let tree = (hashes.len() > NEAR_DUPLICATE_TREE_MIN_IMAGES).then(|| {
+            let mut tree = image_ops::HashTree::default();
+            for (position, &(_, hash)) in hashes.iter().enumerate() {
+                tree.insert(hash, position);
+            }
+            tree
+        });
+        
+        let mut grouped = vec![false; hashes.len()];
+        let mut groups = Vec::new();
+        for (position, &(index, hash)) in hashes.iter().enumerate() {
+            if grouped[position] {
+                continue;
+            }
+            let mut matches: Vec<usize> = match &tree {
+                Some(tree) => tree.find(hash, max_distance),
+                None => (0..hashes.len())
+                    .filter(|&other| image_ops::hamming_distance(hash, hashes[other].1) <= max_distance)
+                    .collect(),
+            };
+            matches.retain(|&other| other > position && !grouped[other]);
+            if matches.is_empty() {
+                continue;
+            }
+            matches.sort_unstable();
+            
+            let mut worst = 0;
+            let mut duplicate_ids = Vec::with_capacity(matches.len());
+            for other in matches {
+                grouped[other] = true;
+                worst = worst.max(image_ops::hamming_distance(hash, hashes[other].1));
+                duplicate_ids.push(self.images[hashes[other].0].0.clone());
+            }
+            groups.push(NearDuplicateGroup {
+                canonical_id: self.images[index].0.clone(),
+                duplicate_ids,
+                similarity: 1.0 - worst as f64 / 64.0,
+            });
+        }
+        Ok(groups)
+    }
+
+    /// `find` for `ImageManager::cleanup_near_duplicates`, with the distance
+    /// clamped to `MAX_CLEANUP_HAMMING_DISTANCE`.
+    pub fn find_for_cleanup(&mut self, max_hamming_distance: u8) -> anyhow::Result<Vec<NearDuplicateGroup>> {
+        if max_hamming_distance > MAX_CLEANUP_HAMMING_DISTANCE {
+            log::warn!("Clamping near-duplicate cleanup distance {} to {}", max_hamming_distance, MAX_CLEANUP_HAMMING_DISTANCE);
+        }
+        self.find(max_hamming_distance.min(MAX_CLEANUP_HAMMING_DISTANCE))
+    }
+}
+
+/// 128 random bits from the OS, hex-encoded, for confirmation tokens.
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("the OS random source is unavailable");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `created_at` as UTC ISO 8601, e.g. `2024-05-01T08:30:00Z`.
+fn iso_timestamp(created_at: i64) -> String {
+    chrono::DateTime::from_timestamp(created_at, 0)
+        .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_default()
+}
+
+/// Lowercase format name recorded in `ImageMetadata::format`, e.g. `jpeg`.
+fn format_name(format: ImageFormat) -> String {
+    format!("{:?}", format).to_lowercase()
+}
+
+/// Extension new files of `format` are saved with.
+pub fn file_extension(format: ImageFormat) -> &'static str {
+    match format {
+        ImageFormat::Png => "png",
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Gif => "gif",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::Tiff => "tif",
+        ImageFormat::Ico => "ico",
+        _ => "png",
+    }
+}
+
+/// Extension the file at `path` should have given its content: its own if
+/// that already names the sniffed format (so `.jpeg` stays), otherwise
+/// `file_extension` of it. `None` when the content isn't a known image.
+pub fn content_extension(path: &Path) -> Option<String> {
+    let reader = image::ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    let format = reader.format()?;
+    let current = path.extension().and_then(|ext| ext.to_str());
+    Some(match current {
+        Some(ext) if format.extensions_str().contains(&ext.to_ascii_lowercase().as_str()) => ext.to_string(),
+        _ => file_extension(format).to_string(),
+    })
+}
+
+/// Format of the file at `path`, sniffed from its header rather than taken
+/// from the extension.
+fn sniff_file_format(path: &Path) -> Option<String> {
+    let reader = image::ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    reader.format().map(format_name)
+}
+
+/// Formats `save_image` stores as they are rather than re-encoding as PNG.
+fn is_stored_as_is(format: ImageFormat) -> bool {
+    matches!(format, ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP | ImageFormat::Bmp | ImageFormat::Tiff)
+}
+
+/// Rejects empty data, and data `save_image` would store as-is that isn't
+/// a complete image, such as a buffer cut short by a copy race. Decodes
+/// up to `MAX_FULL_VALIDATION_BYTES`, so callers run it before taking the
+/// manager's lock.
+pub fn validate_image(data: &[u8]) -> Result<(), SnapMagError> {
+    if data.is_empty() {
+        return Err(SnapMagError::ImageDecodeError("image data is empty".to_string()));
+    }
+    let format = image::guess_format(data).unwrap_or(ImageFormat::Png);
+    if !is_stored_as_is(format) {
+        return Ok(());
+    }
+    validate_image_data(data, format).map_err(|e| {
+        log::warn!("Refusing to save image that doesn't decode: {}", e);
+        e
+    })
+}
+
+/// Checks that `data` really is a complete `format` image.
+fn validate_image_data(data: &[u8], format: ImageFormat) -> Result<(), SnapMagError> {
+    let decode_error = |e: image::ImageError| SnapMagError::ImageDecodeError(e.to_string());
+    if data.len() <= MAX_FULL_VALIDATION_BYTES {
+        return image::load_from_memory_with_format(data, format).map(|_| ()).map_err(decode_error);
+    }
+    
+    image::ImageReader::with_format(std::io::Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(decode_error)?;
+    let complete = match format {
+        // IEND chunk type and its CRC.
+        ImageFormat::Png => data.ends_with(&[0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82]),
+        // EOI marker, allowing for a little trailing padding.
+        ImageFormat::Jpeg => data[data.len() - 16..].windows(2).any(|marker| marker == [0xFF, 0xD9]),
+        _ => true,
+    };
+    if !complete {
+        return Err(SnapMagError::ImageDecodeError("image data is truncated".to_string()));
+    }
+    Ok(())
+}
+
+/// `has_alpha` and `bit_depth` for an image of `color`.
+fn color_info(color: ColorType) -> (bool, u8) {
+    (color.has_alpha(), (color.bits_per_pixel() / color.channel_count() as u16) as u8)
+}
+
+/// `color_info` of the file at `path`, read from its header.
+fn file_color_info(path: &Path) -> (bool, u8) {
+    image::ImageReader::open(path)
+        .ok()
+        .and_then(|reader| reader.with_guessed_format().ok())
+        .and_then(|reader| reader.into_decoder().ok())
+        .map_or((false, 0), |decoder| color_info(decoder.color_type()))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |age| age.as_secs() as i64)
+}
+
+/// Deletes the raw capture kept for `metadata`, if any.
+fn remove_raw_capture(metadata: &ImageMetadata) {
+    let Some(raw_path) = &metadata.raw_path else {
+        return;
+    };
+    if let Err(e) = fs::remove_file(raw_path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            log::warn!("Failed to delete raw capture {}: {}", raw_path, e);
+        }
+    }
+}
+
+/// Index entry for a file found on disk without one, dated by its
+/// modification time.
+fn disk_metadata(id: &str, path: &Path, file_metadata: &fs::Metadata, format: String) -> ImageMetadata {
+    let (has_alpha, bit_depth) = file_color_info(path);
+    let created_at = file_metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |age| age.as_secs() as i64);
+    ImageMetadata {
+        id: id.to_string(),
+        content_hash: file_content_hash(path),
+        legacy_id: None,
+        path: path.to_string_lossy().to_string(),
+        created_at,
+        created_at_iso: iso_timestamp(created_at),
+        ocr_result: None,
+        original_format: None,
+        format: Some(format),
+        has_alpha,
+        bit_depth,
+        size_bytes: file_metadata.len(),
+        source: CaptureSource::Import,
+        notes: None,
+        tags: Vec::new(),
+        inline_thumb: None,
+        expires_at: None,
+        blurhash: None,
+        raw_path: None,
+        context_title: None,
+        session_id: None,
+    }
+}
+
+/// Draws the thumbnails of `images` side by side, each centered in a
+/// `thumb_size` (capped at 128) pixel square cell, and returns the strip
+/// as a lossless WebP data URL with each cell's offset.
+pub fn create_webp_sprite_strip(thumbnails: &ThumbnailCache, images: &[ImageMetadata], thumb_size: u32) -> anyhow::Result<SpriteStripResult> {
+    use base64::Engine;
+    use image::codecs::webp::WebPEncoder;
+    
+    if images.is_empty() || images.len() > MAX_SPRITE_IMAGES {
+        return Err(SnapMagError::InvalidArgument(format!("a sprite strip takes 1 to {} images, got {}", MAX_SPRITE_IMAGES, images.len())).into());
+    }
+    
+    let cell = thumb_size.clamp(1, MAX_INLINE_THUMB_PX);
+    let mut strip = image::RgbaImage::new(cell * images.len() as u32, cell);
+    let mut offsets = Vec::with_capacity(images.len());
+    for (index, metadata) in images.iter().enumerate() {
+        let x = index as u32 * cell;
+        match thumbnails.get_or_create(&metadata.id, Path::new(&metadata.path), cell, usize::MAX) {
+            Ok(thumbnail) => {
+                let thumbnail = image::load_from_memory(&thumbnail)?.to_rgba8();
+                let left = x + (cell - thumbnail.width().min(cell)) / 2;
+                let top = (cell - thumbnail.height().min(cell)) / 2;
+                image::imageops::overlay(&mut strip, &thumbnail, left as i64, top as i64);
+            }
+            Err(e) => log::warn!("Failed to generate thumbnail for {}: {}", metadata.id, e),
+        }
+        offsets.push(SpriteOffset { id: metadata.id.clone(), x, y: 0 });
+    }
+    
+    let mut data = Vec::new();
+    WebPEncoder::new_lossless(&mut data).encode(strip.as_raw(), strip.width(), strip.height(), image::ExtendedColorType::Rgba8)?;
+    Ok(SpriteStripResult {
+        data_url: format!("data:image/webp;base64,{}", base64::engine::general_purpose::STANDARD.encode(&data)),
+        offsets,
+        strip_width: strip.width(),
+        strip_height: strip.height(),
+    })
+}
+
+/// BlurHash of the image file at `path`, `None` when it can't be decoded.
+pub fn compute_blurhash(path: &Path) -> Option<String> {
+    match image::open(path) {
+        Ok(image) => image_ops::blurhash(&image),
+        Err(e) => {
+            log::warn!("Failed to decode {} for blurhash: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Gives each entry of `page` a base64 JPEG of at most `thumb_px` (capped
+/// at 128) pixels; entries whose thumbnail is too large, or that would push
+/// the page over its payload budget, are left without one.
+pub fn add_inline_thumbs(thumbnails: &ThumbnailCache, page: &mut ImagePage, thumb_px: u32) {
+    use base64::Engine;
+    
+    let px = thumb_px.clamp(1, MAX_INLINE_THUMB_PX);
+    let mut page_bytes = 0;
+    for metadata in &mut page.images {
+        let thumbnail = match thumbnails.get_or_create(&metadata.id, Path::new(&metadata.path), px, MAX_INLINE_THUMB_BYTES) {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                log::warn!("Failed to generate thumbnail for {}: {}", metadata.id, e);
+                continue;
+            }
+        };
+        if thumbnail.len() > MAX_INLINE_THUMB_BYTES {
+            continue;
+        }
+        
+        let encoded = format!("data:image/jpeg;base64,{}", base64::engine::general_purpose::STANDARD.encode(&thumbnail));
+        if page_bytes + encoded.len() > MAX_INLINE_PAGE_BYTES {
+            log::debug!("Inline thumbnail budget exhausted on page {}", page.page);
+            continue;
+        }
+        page_bytes += encoded.len();
+        metadata.inline_thumb = Some(encoded);
+    }
+}
+
+/// Decodes the image file at `path`, e.g. one from `ImageManager::image_path`.
+pub fn open_image(path: &Path) -> anyhow::Result<DynamicImage> {
+    image::open(path).map_err(|e| {
+        log::error!("Failed to decode image {}: {}", path.display(), e);
+        SnapMagError::ImageDecodeError(e.to_string()).into()
+    })
+}
+
+/// Perceptual distance between the image at `path` and undecoded
+/// `image_data`, from 0.0 (same picture) to 1.0. Callers run it without the
+/// manager's lock.
+pub fn perceptual_distance_to(path: &Path, image_data: &[u8]) -> anyhow::Result<f64> {
+    let stored = open_image(path)?;
+    let other = image::load_from_memory(image_data)
+        .map_err(|e| SnapMagError::ImageDecodeError(e.to_string()))?;
+    Ok(image_ops::perceptual_distance(image_ops::perceptual_hash(&stored), image_ops::perceptual_hash(&other)))
+}
+
+/// Decodes every frame of the GIF at `path`, composited, and encodes each
+/// as PNG for `save_gif_frames`. Callers run it without the manager's lock.
+pub fn encode_gif_frames(path: &Path) -> anyhow::Result<Vec<Vec<u8>>> {
+    let data = fs::read(path)?;
+    if image::guess_format(&data).ok() != Some(ImageFormat::Gif) {
+        return Err(SnapMagError::ImageDecodeError(format!("{} is not a GIF", path.display())).into());
+    }
+    let frames = image_ops::gif_frames(&data, MAX_GIF_FRAMES).map_err(|e| {
+        log::error!("Failed to decode GIF frames of {}: {}", path.display(), e);
+        SnapMagError::ImageDecodeError(e)
+    })?;
+    frames.into_iter().map(|frame| encode_derived_image(&DynamicImage::ImageRgba8(frame))).collect()
+}
+
+/// Burns `ops` into a copy of the image at `path` and encodes it for
+/// `save_annotated`. Ops that don't validate are returned and skipped; it
+/// is an error only when none apply. Decodes, draws and encodes, so
+/// callers run it without the manager's lock.
+pub fn annotate_image(path: &Path, ops: &[AnnotationOp]) -> anyhow::Result<(Vec<u8>, Vec<AnnotationError>)> {
+    if ops.is_empty() {
+        return Err(SnapMagError::InvalidArgument("no annotations given".to_string()).into());
+    }
+    image_ops::check_annotation_bounds(ops)?;
+    let mut image = open_image(path)?.to_rgba8();
+    let errors = image_ops::annotate(&mut image, ops);
+    if errors.len() == ops.len() {
+        let messages: Vec<String> = errors.iter().map(|e| format!("#{}: {}", e.index, e.message)).collect();
+        return Err(SnapMagError::InvalidArgument(format!("no annotation applied: {}", messages.join("; "))).into());
+    }
+    Ok((encode_derived_image(&DynamicImage::ImageRgba8(image))?, errors))
+}
+
+/// Formats the requested `fields` of an image as `name: value` lines,
+/// in the order asked for. Known fields are `dimensions`, `file_size`,
+/// `created_at` (local time), `sha256`, `path` and `source` (the capture
+/// source; the owning app isn't recorded). Unknown names are listed on a
+/// trailing `warning:` line instead of failing. Reads the image file, so
+/// callers run it without the manager's lock.
+pub fn image_info(metadata: &ImageMetadata, fields: &[String]) -> anyhow::Result<String> {
+    use sha2::{Digest, Sha256};
+    
+    let mut lines = Vec::new();
+    let mut unknown = Vec::new();
+    for field in fields {
+        let value = match field.as_str() {
+            "dimensions" => {
+                let (width, height) = image::image_dimensions(&metadata.path)?;
+                format!("{}x{}", width, height)
+            }
+            "file_size" => metadata.size_bytes.to_string(),
+            "created_at" => chrono::DateTime::from_timestamp(metadata.created_at, 0)
+                .map(|time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S %:z").to_string())
+                .unwrap_or_default(),
+            "sha256" => format!("{:x}", Sha256::digest(fs::read(&metadata.path)?)),
+            "path" => metadata.path.clone(),
+            "source" => serde_json::to_value(metadata.source)?
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            _ => {
+                unknown.push(field.as_str());
+                continue;
+            }
+        };
+        lines.push(format!("{}: {}", field, value));
+    }
+    if !unknown.is_empty() {
+        lines.push(format!("warning: unknown field(s) ignored: {}", unknown.join(", ")));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// An animated PNG cycling through `images`, each letterboxed to 200×150,
+/// as a slideshow thumbnail for a collection.
+pub fn encode_animated_preview(images: &[DynamicImage], frame_delay_ms: u32) -> anyhow::Result<Vec<u8>> {
+    if images.len() < 2 {
+        return Err(SnapMagError::InvalidArgument("an animated preview needs at least 2 images".to_string()).into());
+    }
+    if frame_delay_ms < MIN_PREVIEW_FRAME_DELAY_MS {
+        return Err(SnapMagError::InvalidArgument(format!(
+            "frame delay must be at least {} ms",
+            MIN_PREVIEW_FRAME_DELAY_MS
+        ))
+        .into());
+    }
+    
+    let frames: Vec<_> = images
+        .iter()
+        .map(|image| image_ops::letterbox(image, PREVIEW_WIDTH, PREVIEW_HEIGHT))
+        .collect();
+    image_ops::encode_apng(&frames, frame_delay_ms).map_err(|e| {
+        log::error!("Failed to encode animated preview: {}", e);
+        anyhow::anyhow!("Failed to encode animated preview: {}", e)
+    })
+}
+
+/// `open_image` for each of `paths`, e.g. from `ImageManager::image_paths`.
+pub fn open_images(paths: &[PathBuf]) -> anyhow::Result<Vec<DynamicImage>> {
+    paths.iter().map(|path| open_image(path)).collect()
+}
+
+/// Encodes a processed image as PNG for `ImageManager::save_derived_png`.
+pub fn encode_derived_image(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut png_data = Vec::new();
+    image.write_with_encoder(image_ops::png_encoder(&mut png_data)).map_err(|e| {
+        log::error!("Failed to encode derived image: {}", e);
+        anyhow::anyhow!("Failed to encode derived image: {}", e)
+    })?;
+    Ok(png_data)
+}
+
+/// `content_hash` of the file at `path`, empty when it can't be read.
+fn file_content_hash(path: &Path) -> ContentHash {
+    fs::read(path).map(|data| content_hash(&data)).unwrap_or_default()
+}
+
+/// Gives an entry whose id is not a ULID, i.e. one keyed by content hash
+/// before ids were split from hashes, a new id. The old id is kept as
+/// `legacy_id`, and an entry without a current `content_hash` is re-hashed.
+/// Returns whether anything changed.
+fn migrate_legacy_id(metadata: &mut ImageMetadata) -> anyhow::Result<bool> {
+    if is_ulid(&metadata.id) {
+        return Ok(false);
+    }
+    if !is_current_hash(&metadata.content_hash) {
+        metadata.content_hash = file_content_hash(Path::new(&metadata.path));
+    }
+    metadata.legacy_id = Some(std::mem::replace(&mut metadata.id, new_image_id()?));
+    Ok(true)
+}
+
+/// Drops characters Windows doesn't allow in file names.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'))
+        .collect::<String>()
+        .trim()
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Image files under `dir`, leaving out the folders in `skipped` and the
+/// index, partial writes and raw captures.
+fn collect_image_files(dir: &Path, skipped: &[PathBuf], files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if !skipped.contains(&path) {
+                collect_image_files(&path, skipped, files);
+            }
+        } else if !path.extension().is_some_and(|ext| ext == "tmp" || ext == "json" || ext == RAW_CAPTURE_EXTENSION) {
+            files.push(path);
+        }
+    }
+}
+
+/// File name slug of OCR `text`: its first 40 non-whitespace characters,
+/// with whitespace between them as underscores and anything else that isn't
+/// a letter or digit dropped, lowercased.
+fn ocr_slug(text: &str) -> String {
+    let mut slug = String::new();
+    let mut kept = 0;
+    let mut pending_space = false;
+    for c in text.trim().chars() {
+        if kept == 40 {
+            break;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        kept += 1;
+        if !c.is_alphanumeric() {
+            continue;
+        }
+        if pending_space && !slug.is_empty() {
+            slug.push('_');
+        }
+        pending_space = false;
+        slug.extend(c.to_lowercase());
+    }
+    slug
+}
+
+/// Quotes a CSV field when it contains a delimiter, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes `data` to `{path}.tmp`, checks the written size, then renames it over
+/// `path`, so a crash mid-write never leaves a truncated image under the final
+/// name. The rename is only atomic when both paths are on the same filesystem,
+/// which holds here because the temp file is a sibling of the target.
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+        
+        let written = fs::metadata(&tmp_path)?.len();
+        if written != data.len() as u64 {
+            anyhow::bail!("short write: {} of {} bytes", written, data.len());
+        }
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    })();
+    
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use image::{RgbImage, Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    thread_local! {
+        static NOW: Cell<i64> = const { Cell::new(1_700_000_000) };
+    }
+
+    fn test_now() -> i64 {
+        NOW.with(Cell::get)
+    }
+
+    fn advance_hours(hours: i64) {
+        NOW.with(|now| now.set(now.get() + hours * 3600));
+    }
+
+    /// A loaded manager on an empty storage folder inside `dir`.
+    fn open(dir: &TempDir) -> ImageManager {
+        let storage_dir = dir.path().join("library");
+        let mut manager = ImageManager::with_root(storage_dir.clone(), test_now).unwrap();
+        let scan = ImageManager::scan_storage(&storage_dir).unwrap();
+        manager.finish_loading(scan).unwrap();
+        manager
+    }
+
+    fn png(width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(width, height, Rgba(color));
+        let mut data = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn save_writes_file_and_detects_duplicates() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let data = png(4, 3, [255, 0, 0, 255]);
+
+        let saved = manager.save_image(&data, CaptureSource::Screenshot).unwrap();
+        assert!(!saved.is_duplicate);
+        assert!(Path::new(&saved.metadata.path).exists());
+        assert_eq!(saved.metadata.created_at, test_now());
+
+        let again = manager.save_image(&data, CaptureSource::Screenshot).unwrap();
+        assert!(again.is_duplicate);
+        assert_eq!(again.metadata.id, saved.metadata.id);
+        assert_eq!(manager.get_images().len(), 1);
+    }
+
+    #[test]
+    fn saved_index_loads_back_with_tags_and_notes() {
+        let dir = TempDir::new().unwrap();
+        let id = {
+            let mut manager = open(&dir);
+            let id = manager.save_image(&png(2, 2, [0, 0, 255, 255]), CaptureSource::Import).unwrap().metadata.id;
+            manager.add_tag(&id, "receipt").unwrap();
+            manager.set_image_notes(&id, Some("lunch".to_string())).unwrap();
+            manager.save_index().unwrap();
+            id
+        };
+
+        let manager = open(&dir);
+        let metadata = manager.get_image(&id).expect("image survives a reload");
+        assert_eq!(metadata.tags, vec!["receipt".to_string()]);
+        assert_eq!(metadata.notes.as_deref(), Some("lunch"));
+        assert_eq!(metadata.source, CaptureSource::Import);
+    }
+
+    #[test]
+    fn changes_are_saved_once_quiet() {
+        let dir = TempDir::new().unwrap();
+        let id = {
+            let mut manager = open(&dir);
+            let id = manager.save_image(&png(2, 2, [5, 5, 5, 255]), CaptureSource::Screenshot).unwrap().metadata.id;
+            manager.add_tag(&id, "later").unwrap();
+            manager.save_index_if_due().unwrap();
+            assert!(manager.index_changed_at.is_some(), "saved before the delay passed");
+
+            manager.index_changed_at = Some(Instant::now() - INDEX_SAVE_DELAY);
+            manager.save_index_if_due().unwrap();
+            assert!(manager.index_changed_at.is_none());
+            id
+        };
+        assert_eq!(open(&dir).get_image(&id).unwrap().tags, vec!["later".to_string()]);
+    }
+
+    #[test]
+    fn unreadable_index_is_kept_aside_and_rebuilt() {
+        let dir = TempDir::new().unwrap();
+        let id = {
+            let mut manager = open(&dir);
+            let id = manager.save_image(&png(2, 2, [6, 6, 6, 255]), CaptureSource::Screenshot).unwrap().metadata.id;
+            manager.save_index().unwrap();
+            id
+        };
+        let storage_dir = dir.path().join("library");
+        fs::write(storage_dir.join(INDEX_FILE), b"{ not json").unwrap();
+
+        let manager = open(&dir);
+        assert_eq!(fs::read(storage_dir.join("index.json.bad")).unwrap(), b"{ not json");
+        assert!(manager.get_image(&id).is_some(), "the file is indexed again from its name");
+    }
+
+    #[test]
+    fn index_entries_without_optional_fields_load() {
+        let metadata: ImageMetadata = serde_json::from_str(r#"{
+            "id": "01HZZZZZZZZZZZZZZZZZZZZZZZ",
+            "path": "/tmp/missing.png",
+            "created_at": 0,
+            "ocr_result": null,
+            "original_format": null
+        }"#).unwrap();
+        assert_eq!(metadata.source, CaptureSource::Screenshot);
+        assert!(metadata.tags.is_empty() && metadata.notes.is_none() && metadata.inline_thumb.is_none());
+        assert_eq!(metadata.size_bytes, 0);
+    }
+
+    #[test]
+    fn thousand_file_library_loads_quickly_and_reloads_from_the_index() {
+        let dir = TempDir::new().unwrap();
+        let storage_dir = dir.path().join("library");
+        fs::create_dir_all(&storage_dir).unwrap();
+        let data = png(4, 4, [10, 20, 30, 255]);
+        for _ in 0..1000 {
+            fs::write(storage_dir.join(format!("{}.png", new_image_id().unwrap())), &data).unwrap();
+        }
+
+        let started = Instant::now();
+        let mut manager = open(&dir);
+        let first_load = started.elapsed();
+        assert_eq!(manager.get_images().len(), 1000);
+        manager.save_index().unwrap();
+
+        let started = Instant::now();
+        let scan = ImageManager::scan_storage(&storage_dir).unwrap();
+        let reload = started.elapsed();
+        assert_eq!(scan.reindexed, 0, "unchanged files are taken from the index");
+        assert!(first_load < Duration::from_secs(10), "first load took {:?}", first_load);
+        assert!(reload < Duration::from_secs(2), "reload took {:?}", reload);
+    }
+
+    #[test]
+    fn moved_files_are_healed_after_a_move_event() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        manager.set_watching_files(true);
+        manager.heal_missing_paths();
+        let saved = manager.save_image(&png(2, 2, [7, 7, 7, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let moved_dir = manager.storage_dir().join("moved");
+        fs::create_dir_all(&moved_dir).unwrap();
+        let moved = moved_dir.join(Path::new(&saved.path).file_name().unwrap());
+        fs::rename(&saved.path, &moved).unwrap();
+
+        manager.heal_missing_paths();
+        assert_eq!(manager.get_image(&saved.id).unwrap().path, saved.path, "no walk without a move event");
+
+        manager.note_files_moved();
+        manager.heal_missing_paths();
+        assert_eq!(manager.get_image(&saved.id).unwrap().path, moved.to_string_lossy());
+        assert!(manager.index_changed_at.is_none(), "healed paths are saved");
+        let index: Vec<ImageMetadata> = serde_json::from_slice(&fs::read(manager.storage_dir().join(INDEX_FILE)).unwrap()).unwrap();
+        assert_eq!(index[0].path, moved.to_string_lossy());
+    }
+
+    #[test]
+    fn hash_named_files_get_a_ulid_and_keep_resolving() {
+        let dir = TempDir::new().unwrap();
+        let storage_dir = dir.path().join("library");
+        fs::create_dir_all(&storage_dir).unwrap();
+        let data = png(2, 2, [9, 9, 9, 255]);
+        let legacy_id = "0123456789abcdef0123456789abcdef";
+        fs::write(storage_dir.join(format!("{}.png", legacy_id)), &data).unwrap();
+
+        let mut manager = open(&dir);
+        let metadata = manager.get_image(legacy_id).expect("legacy id resolves");
+        assert!(is_ulid(&metadata.id));
+        assert_eq!(metadata.content_hash, content_hash(&data));
+        assert_eq!(metadata.content_hash.len(), 64);
+
+        manager.delete_image(legacy_id).unwrap();
+        assert!(manager.get_image(&metadata.id).is_none());
+        assert!(manager.get_image(legacy_id).is_none());
+    }
+
+    #[test]
+    fn delete_is_undoable() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let saved = manager.save_image(&png(3, 3, [0, 255, 0, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        manager.delete_image(&saved.id).unwrap();
+        assert!(manager.get_image(&saved.id).is_none());
+        assert!(!Path::new(&saved.path).exists());
+
+        let info = manager.undo_last().unwrap().expect("delete was journaled");
+        assert_eq!(info.image_ids, vec![saved.id.clone()]);
+        assert!(manager.get_image(&saved.id).is_some());
+        assert!(Path::new(&saved.path).exists());
+    }
+
+    #[test]
+    fn undo_keeps_what_failed_to_restore() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let kept = manager.save_image(&png(3, 3, [1, 0, 0, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let stuck = manager.save_image(&png(3, 3, [2, 0, 0, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let filter = DeleteFilter { older_than: None, source: Some(CaptureSource::Screenshot), untagged_only: false, min_size_bytes: None };
+        let confirmation = manager.request_delete_images_where(filter).unwrap();
+        manager.confirm_delete_images_where(&confirmation.token).unwrap();
+
+        // Park the stash of one image where restoring can't find it.
+        let undo_dir = dir.path().join("library").join("undo");
+        let stash = fs::read_dir(&undo_dir)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.to_string_lossy().ends_with(Path::new(&stuck.path).file_name().unwrap().to_str().unwrap()))
+            .unwrap();
+        let parked = dir.path().join("parked");
+        fs::rename(&stash, &parked).unwrap();
+
+        let info = manager.undo_last().unwrap().unwrap();
+        assert_eq!(info.image_ids, vec![kept.id.clone()]);
+        let stack = manager.get_undo_stack();
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack[0].image_ids, vec![stuck.id.clone()]);
+
+        fs::rename(&parked, &stash).unwrap();
+        assert_eq!(manager.undo_last().unwrap().unwrap().image_ids, vec![stuck.id.clone()]);
+        assert!(manager.get_undo_stack().is_empty());
+        assert!(manager.get_image(&kept.id).is_some());
+    }
+
+    #[test]
+    fn cleanup_removes_only_old_images() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let old = manager.save_image(&png(2, 2, [1, 2, 3, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        advance_hours(48);
+        let new = manager.save_image(&png(2, 2, [4, 5, 6, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        assert!(manager.cleanup_old_images(24).unwrap().is_none());
+        assert!(manager.get_image(&old.id).is_none());
+        assert!(!Path::new(&old.path).exists());
+        assert!(manager.get_image(&new.id).is_some());
+    }
+
+    #[test]
+    fn cleanup_over_threshold_waits_for_confirmation() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        manager.set_cleanup_confirm_threshold(Some(1));
+        for shade in 0..3 {
+            manager.save_image(&png(2, 2, [shade, 0, 0, 255]), CaptureSource::Screenshot).unwrap();
+        }
+        advance_hours(48);
+
+        let confirmation = manager.cleanup_old_images(24).unwrap().expect("3 images exceed the threshold");
+        assert_eq!(confirmation.image_count, 3);
+        assert_eq!(manager.get_images().len(), 3);
+
+        assert!(manager.confirm_cleanup_old_images("not-a-token").is_err());
+        assert_eq!(manager.confirm_cleanup_old_images(&confirmation.token).unwrap(), 3);
+        assert!(manager.get_images().is_empty());
+    }
+
+    #[test]
+    fn filtered_delete_needs_token_and_is_undoable() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let file = manager.save_image(&png(2, 2, [7, 7, 7, 255]), CaptureSource::File).unwrap().metadata;
+        let screenshot = manager.save_image(&png(2, 2, [8, 8, 8, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let filter = DeleteFilter { older_than: None, source: Some(CaptureSource::File), untagged_only: false, min_size_bytes: None };
+
+        let confirmation = manager.request_delete_images_where(filter).unwrap();
+        assert_eq!(confirmation.image_count, 1);
+        assert_eq!(manager.get_images().len(), 2);
+
+        let summary = manager.confirm_delete_images_where(&confirmation.token).unwrap();
+        assert_eq!(summary.ids, vec![file.id.clone()]);
+        assert!(manager.get_image(&file.id).is_none());
+        assert!(manager.get_image(&screenshot.id).is_some());
+        assert!(manager.confirm_delete_images_where(&confirmation.token).is_err());
+
+        manager.undo_last().unwrap().expect("the delete was journaled");
+        assert!(Path::new(&manager.get_image(&file.id).unwrap().path).exists());
+    }
+
+    /// A diagonal gradient, flipped by `invert`, with the corner pixel set
+    /// to `tweak` so the bytes differ while the perceptual hash doesn't.
+    fn gradient_png(invert: bool, tweak: u8) -> Vec<u8> {
+        let mut image = RgbaImage::from_fn(32, 32, |x, y| {
+            let value = ((x + y) * 4) as u8;
+            let value = if invert { 255 - value } else { value };
+            Rgba([value, value, value, 255])
+        });
+        image.put_pixel(0, 0, Rgba([tweak, 0, 0, 255]));
+        let mut data = Vec::new();
+        image.write_to(&mut std::io::Cursor::new(&mut data), ImageFormat::Png).unwrap();
+        data
+    }
+
+    #[test]
+    fn near_duplicate_cleanup_keeps_one_and_is_undoable() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let first = manager.save_image(&gradient_png(false, 0), CaptureSource::Screenshot).unwrap().metadata;
+        advance_hours(1);
+        let second = manager.save_image(&gradient_png(false, 1), CaptureSource::Screenshot).unwrap().metadata;
+        manager.save_image(&gradient_png(true, 0), CaptureSource::Screenshot).unwrap();
+
+        let mut scan = manager.near_duplicate_scan();
+        let groups = scan.find_for_cleanup(64).unwrap();
+        manager.cache_perceptual_hashes(scan);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].canonical_id, first.id);
+
+        manager.set_cleanup_confirm_threshold(Some(0));
+        let CleanupOutcome::NeedsConfirmation(confirmation) = manager.cleanup_near_duplicates(groups, "oldest").unwrap() else {
+            panic!("a cleanup over the threshold must be confirmed");
+        };
+        assert_eq!(manager.get_images().len(), 3);
+        assert_eq!(manager.confirm_cleanup_near_duplicates(&confirmation.token).unwrap(), vec![second.id.clone()]);
+        assert!(manager.get_image(&first.id).is_some());
+
+        manager.undo_last().unwrap().expect("the cleanup was journaled");
+        assert!(manager.get_image(&second.id).is_some());
+    }
+
+    #[test]
+    fn retention_sweep_over_threshold_waits_for_confirmation() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        manager.set_cleanup_confirm_threshold(Some(1));
+        manager.set_retention_preset(RetentionPreset::Keep24Hours).unwrap();
+        for shade in 0..2 {
+            manager.save_image(&png(2, 2, [0, shade, 0, 255]), CaptureSource::Screenshot).unwrap();
+        }
+        advance_hours(48);
+
+        assert!(manager.apply_retention_preset().unwrap().is_empty());
+        let confirmation = manager.take_retention_confirmation().expect("the sweep asks once");
+        assert!(manager.apply_retention_preset().unwrap().is_empty());
+        assert!(manager.take_retention_confirmation().is_none());
+        assert_eq!(manager.get_images().len(), 2);
+
+        assert_eq!(manager.confirm_retention_preset(&confirmation.token).unwrap().len(), 2);
+        manager.save_image(&png(2, 2, [9, 9, 9, 255]), CaptureSource::Screenshot).unwrap();
+        manager.save_image(&png(2, 2, [8, 8, 8, 255]), CaptureSource::Screenshot).unwrap();
+        advance_hours(48);
+        assert_eq!(manager.apply_retention_preset().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resized_variants_are_keyed_by_content() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let wide = manager.save_image(&png(40, 20, [1, 2, 3, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let other = manager.save_image(&png(40, 20, [4, 5, 6, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        let path = manager.resized_variant(&wide.id, 10, VariantFormat::Png).unwrap().write().unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with(&wide.content_hash));
+        assert_eq!(image::image_dimensions(&path).unwrap(), (10, 5));
+        assert_eq!(manager.resized_variant(&wide.id, 10, VariantFormat::Png).unwrap().write().unwrap(), path);
+        assert_ne!(manager.resized_variant(&other.id, 10, VariantFormat::Png).unwrap().write().unwrap(), path);
+        assert!(manager.resized_variant(&wide.id, 10, VariantFormat::Jpeg { quality: 0 }).is_err());
+    }
+
+    #[test]
+    fn gif_frames_are_saved_in_order() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif, 4, 4, &[]).unwrap();
+            for color in colors {
+                let mut pixels = color.repeat(16);
+                encoder.write_frame(&gif::Frame::from_rgba(4, 4, &mut pixels)).unwrap();
+            }
+        }
+        let source = manager.save_image(&gif, CaptureSource::Screenshot).unwrap().metadata;
+
+        let encoded = encode_gif_frames(Path::new(&source.path)).unwrap();
+        let frames = manager.save_gif_frames(&source.id, &encoded).unwrap();
+        assert_eq!(frames.len(), 3);
+        for (index, (frame, color)) in frames.iter().zip(colors).enumerate() {
+            assert!(frame.tags.contains(&format!("auto:gif-frame-{}", index + 1)));
+            assert!(frame.tags.contains(&format!("auto:gif-source-{}", source.id)));
+            let pixel = image::open(&frame.path).unwrap().to_rgba8().get_pixel(0, 0).0;
+            assert_eq!(pixel, color);
+        }
+    }
+
+    #[test]
+    fn orphaned_images_are_the_untouched_ones_newest_first() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let mut ids = Vec::new();
+        for shade in 0..4 {
+            ids.push(manager.save_image(&png(2, 2, [shade, 0, 0, 255]), CaptureSource::Screenshot).unwrap().metadata.id);
+            advance_hours(1);
+        }
+        manager.add_tag(&ids[0], "kept").unwrap();
+        manager.set_image_notes(&ids[1], Some("for the report".to_string())).unwrap();
+
+        let page = manager.find_orphaned_images(0, 10).unwrap();
+        assert_eq!(page.total, 2);
+        let found: Vec<_> = page.images.iter().map(|metadata| metadata.id.clone()).collect();
+        assert_eq!(found, [ids[3].clone(), ids[2].clone()]);
+
+        let second = manager.find_orphaned_images(1, 1).unwrap();
+        assert_eq!(second.images.len(), 1);
+        assert_eq!(second.images[0].id, ids[2]);
+        assert!(manager.find_orphaned_images(0, 0).is_err());
+    }
+
+    #[test]
+    fn orphans_are_removed_only_once_saved() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let library = dir.path().join("library");
+        let kept = manager.save_image(&png(2, 2, [1, 0, 0, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let orphan = library.join("orphan.png");
+        fs::write(&orphan, png(2, 2, [2, 0, 0, 255])).unwrap();
+        let copy = library.join("copy.png");
+        fs::write(&copy, fs::read(&kept.path).unwrap()).unwrap();
+        let broken = library.join("broken.png");
+        fs::write(&broken, b"not a png").unwrap();
+
+        assert_eq!(manager.adopt_orphaned_files().unwrap(), 1);
+        assert!(!orphan.exists());
+        assert!(!copy.exists());
+        assert!(broken.exists());
+        assert_eq!(manager.images.len(), 2);
+    }
+
+    #[test]
+    fn tiff_is_stored_untouched_and_exported_with_its_format_last() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let mut tiff = Vec::new();
+        RgbaImage::from_pixel(3, 2, Rgba([9, 8, 7, 255])).write_to(&mut std::io::Cursor::new(&mut tiff), ImageFormat::Tiff).unwrap();
+        let metadata = manager.save_image(&tiff, CaptureSource::Screenshot).unwrap().metadata;
+        assert_eq!(metadata.format.as_deref(), Some("tiff"));
+        assert_eq!(fs::read(&metadata.path).unwrap(), tiff);
+
+        let dest = dir.path().join("export.csv");
+        manager.export_metadata(&dest, ExportFormat::Csv, true, false).unwrap();
+        let csv = fs::read_to_string(&dest).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "id,path,created_at,source,size_bytes,original_format,tags,notes,expires_at,ocr_result,format,raw_path,context_title"
+        );
+        assert!(lines.next().unwrap().ends_with(",tiff,,"));
+    }
+
+    #[test]
+    fn mislabeled_files_are_renamed_after_their_content() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let mislabeled = manager.save_image(&png(3, 2, [1, 2, 3, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let labeled = manager.save_image(&png(2, 3, [4, 5, 6, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let mut jpeg = Vec::new();
+        RgbImage::from_pixel(3, 2, image::Rgb([1, 2, 3])).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+        fs::write(&mislabeled.path, &jpeg).unwrap();
+
+        let mut scan = manager.extension_scan();
+        scan.sniff();
+        let fixes = manager.fix_extensions(scan).unwrap();
+
+        assert_eq!(fixes.len(), 1);
+        assert_eq!(fixes[0].id, mislabeled.id);
+        assert_eq!(fixes[0].old_path, mislabeled.path);
+        assert_eq!(fixes[0].format, "jpeg");
+        assert!(fixes[0].new_path.ends_with(".jpg"));
+        assert!(!Path::new(&mislabeled.path).exists());
+        assert_eq!(fs::read(&fixes[0].new_path).unwrap(), jpeg);
+        let renamed = manager.get_image(&mislabeled.id).unwrap();
+        assert_eq!(renamed.path, fixes[0].new_path);
+        assert_eq!(renamed.format.as_deref(), Some("jpeg"));
+        assert_eq!(manager.get_image(&labeled.id).unwrap().path, labeled.path);
+    }
+
+    #[test]
+    fn temporal_order_times_tight_sequences_and_caches_hashes() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let first = manager.save_image(&gradient_png(false, 0), CaptureSource::Screenshot).unwrap().metadata;
+        advance_hours(1);
+        let second = manager.save_image(&gradient_png(false, 1), CaptureSource::Screenshot).unwrap().metadata;
+        for id in [&first.id, &second.id] {
+            manager.images.get_mut(id).unwrap().context_title = Some("Editor".to_string());
+        }
+        assert!(manager.temporal_order_scan(std::slice::from_ref(&first.id)).is_err());
+
+        let ids = [first.id.clone(), second.id.clone()];
+        let mut scan = manager.temporal_order_scan(&ids).unwrap();
+        let results = scan.estimate().unwrap();
+        manager.cache_temporal_order_hashes(scan);
+
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].id_from.as_str(), results[0].id_to.as_str()), (first.id.as_str(), second.id.as_str()));
+        assert!(results[0].similarity > TIGHT_SEQUENCE_SIMILARITY);
+        assert_eq!(results[0].estimated_transition_ms, Some(3_600_000));
+        assert!(manager.perceptual_hashes.contains_key(&first.id));
+        assert!(manager.perceptual_hashes.contains_key(&second.id));
+    }
+
+    #[test]
+    fn animated_previews_are_letterboxed_apngs_tagged_as_such() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let images = [
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 10, Rgba([255, 0, 0, 255]))),
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 40, Rgba([0, 0, 255, 255]))),
+        ];
+        assert!(encode_animated_preview(&images[..1], MIN_PREVIEW_FRAME_DELAY_MS).is_err());
+        assert!(encode_animated_preview(&images, MIN_PREVIEW_FRAME_DELAY_MS - 1).is_err());
+
+        let apng = encode_animated_preview(&images, MIN_PREVIEW_FRAME_DELAY_MS).unwrap();
+        let metadata = manager.save_animated_preview(&apng).unwrap();
+        assert_eq!(metadata.tags, ["auto:animated-preview"]);
+        assert_eq!(image::image_dimensions(&metadata.path).unwrap(), (PREVIEW_WIDTH, PREVIEW_HEIGHT));
+    }
+
+    #[test]
+    fn empty_and_cut_short_data_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let data = gradient_png(false, 0);
+        assert!(validate_image(&data).is_ok());
+        assert!(validate_image(&[]).is_err());
+        assert!(validate_image(&data[..data.len() - 20]).is_err());
+        assert!(manager.save_image(&data[..data.len() / 2], CaptureSource::Screenshot).is_err());
+        assert!(manager.get_images().is_empty());
+    }
+
+    #[test]
+    fn image_info_lists_fields_in_order_and_warns_about_unknown_ones() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let data = png(5, 7, [1, 2, 3, 255]);
+        let metadata = manager.save_image(&data, CaptureSource::Screenshot).unwrap().metadata;
+
+        let fields = ["sha256", "dimensions", "owner", "source"].map(String::from);
+        let info = image_info(&metadata, &fields).unwrap();
+        assert_eq!(
+            info,
+            format!("sha256: {}\ndimensions: 5x7\nsource: screenshot\nwarning: unknown field(s) ignored: owner", content_hash(&data))
+        );
+    }
+
+    #[test]
+    fn blurhash_is_cached_only_for_the_file_it_came_from() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let metadata = manager.save_image(&gradient_png(false, 0), CaptureSource::Screenshot).unwrap().metadata;
+        assert_eq!(manager.cached_blurhash(&metadata.id).unwrap(), None);
+        assert!(manager.cached_blurhash("missing").is_err());
+
+        let blurhash = compute_blurhash(Path::new(&metadata.path)).unwrap();
+        assert!(manager.cache_blurhash(&metadata.id, "elsewhere.png", blurhash.clone()).is_none());
+        assert_eq!(manager.cached_blurhash(&metadata.id).unwrap(), None);
+
+        let cached = manager.cache_blurhash(&metadata.id, &metadata.path, blurhash.clone()).unwrap();
+        assert_eq!(cached.blurhash.as_deref(), Some(blurhash.as_str()));
+        assert_eq!(manager.cached_blurhash(&metadata.id).unwrap(), Some(blurhash));
+    }
+
+    #[test]
+    fn pages_get_inline_thumbs_after_listing() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        manager.save_image(&png(40, 30, [1, 2, 3, 255]), CaptureSource::Screenshot).unwrap();
+        advance_hours(1);
+        let newest = manager.save_image(&png(30, 40, [4, 5, 6, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        let mut page = manager.get_images_page(0, 1).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.images.len(), 1);
+        assert_eq!(page.images[0].id, newest.id);
+        assert!(page.images[0].inline_thumb.is_none());
+        assert!(manager.get_images_page(0, 0).is_err());
+
+        add_inline_thumbs(&manager.thumbnail_cache(), &mut page, 16);
+        assert!(page.images[0].inline_thumb.as_deref().is_some_and(|thumb| thumb.starts_with("data:image/jpeg;base64,")));
+    }
+
+    #[test]
+    fn color_scan_buckets_by_hue_and_caches_what_it_decoded() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let red = manager.save_image(&png(4, 4, [250, 10, 10, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let blue = manager.save_image(&png(4, 4, [10, 10, 250, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let gray = manager.save_image(&png(4, 4, [128, 128, 128, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        let mut scan = manager.color_scan(3).unwrap();
+        let buckets = scan.sort();
+        manager.cache_dominant_hues(scan);
+
+        fn ids(bucket: &[ImageMetadata]) -> Vec<&str> {
+            bucket.iter().map(|metadata| metadata.id.as_str()).collect()
+        }
+        assert_eq!(buckets.len(), 4);
+        assert_eq!(ids(&buckets[0]), [red.id.as_str()]);
+        assert_eq!(ids(&buckets[2]), [blue.id.as_str()]);
+        assert_eq!(ids(&buckets[3]), [gray.id.as_str()]);
+        assert_eq!(manager.dominant_hues.len(), 3);
+
+        // Cached hues are used as they are, without reading the file.
+        fs::remove_file(&red.path).unwrap();
+        let mut scan = manager.color_scan(3).unwrap();
+        assert_eq!(ids(&scan.sort()[0]), [red.id.as_str()]);
+        assert!(manager.color_scan(1).is_err());
+    }
+
+    #[test]
+    fn files_moved_since_the_scan_are_left_alone() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let metadata = manager.save_image(&png(3, 2, [1, 2, 3, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let mut jpeg = Vec::new();
+        RgbImage::from_pixel(3, 2, image::Rgb([1, 2, 3])).write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg).unwrap();
+        fs::write(&metadata.path, &jpeg).unwrap();
+
+        let mut scan = manager.extension_scan();
+        scan.sniff();
+        manager.delete_image(&metadata.id).unwrap();
+
+        assert!(manager.fix_extensions(scan).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sprite_strips_render_from_looked_up_images() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let a = manager.save_image(&png(8, 4, [250, 10, 10, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let b = manager.save_image(&png(4, 8, [10, 10, 250, 255]), CaptureSource::Screenshot).unwrap().metadata;
+
+        let images = manager.get_images_by_id(&[a.id.clone(), b.id.clone()]).unwrap();
+        let strip = create_webp_sprite_strip(&manager.thumbnail_cache(), &images, 16).unwrap();
+
+        assert_eq!((strip.strip_width, strip.strip_height), (32, 16));
+        assert_eq!(strip.offsets.len(), 2);
+        assert!(strip.data_url.starts_with("data:image/webp;base64,"));
+        assert!(manager.get_images_by_id(&["missing".to_string()]).is_err());
+        assert!(create_webp_sprite_strip(&manager.thumbnail_cache(), &[], 16).is_err());
+    }
+
+    #[test]
+    fn image_chunks_cover_the_file() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let metadata = manager.save_image(&png(16, 16, [5, 6, 7, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let file = fs::read(&metadata.path).unwrap();
+
+        let head = manager.read_image_chunk(&metadata.id, 0, 10).unwrap();
+        let tail = manager.read_image_chunk(&metadata.id, 10, file.len()).unwrap();
+        assert_eq!([head, tail].concat(), file);
+        assert!(manager.read_image_chunk(&metadata.id, file.len() as u64, 10).unwrap().is_empty());
+        assert!(manager.read_image_chunk(&metadata.id, file.len() as u64 + 1, 10).is_err());
+    }
+
+    #[test]
+    fn humanized_names_use_the_random_end_of_the_id() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let first = manager.save_image(&png(2, 2, [1, 1, 1, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let second = manager.save_image(&png(2, 2, [2, 2, 2, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        for id in [&first.id, &second.id] {
+            manager.images.get_mut(id).unwrap().ocr_result = Some("Build failed".to_string());
+        }
+
+        let operations = manager.humanize_filenames(true).unwrap();
+        assert_eq!(operations.len(), 2);
+        for operation in &operations {
+            assert!(operation.new_name.contains(&operation.id[18..]), "{} lacks the id tail", operation.new_name);
+        }
+        assert_ne!(operations[0].new_name, operations[1].new_name);
+    }
+
+    #[test]
+    fn newest_image_breaks_timestamp_ties_by_id() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let saved: Vec<ImageMetadata> = (0..4)
+            .map(|shade| manager.save_image(&png(2, 2, [shade, 9, 9, 255]), CaptureSource::Screenshot).unwrap().metadata)
+            .collect();
+        for metadata in &saved {
+            manager.images.get_mut(&metadata.id).unwrap().created_at = 1_000;
+        }
+
+        let last = saved.iter().map(|metadata| &metadata.id).max().unwrap();
+        assert_eq!(&manager.get_newest_image().unwrap().id, last);
+        assert_eq!(&manager.get_images_after(999, 200).unwrap().last().unwrap().id, last);
+    }
+
+    #[test]
+    fn annotations_are_drawn_apart_from_saving() {
+        let dir = TempDir::new().unwrap();
+        let mut manager = open(&dir);
+        let source = manager.save_image(&png(8, 8, [0, 0, 0, 255]), CaptureSource::Screenshot).unwrap().metadata;
+        let rect = |x: i32| AnnotationOp::Rect { x, y: 0, w: 4, h: 4, color: "#fff".to_string(), stroke: 1 };
+
+        let path = manager.image_path(&source.id).unwrap();
+        let (png_data, errors) = annotate_image(&path, &[rect(0), rect(100)]).unwrap();
+        assert_eq!(errors.len(), 1);
+        let result = manager.save_annotated(&png_data, errors).unwrap();
+        assert!(result.image.tags.contains(&"annotated".to_string()));
+        assert_eq!(open_image(Path::new(&result.image.path)).unwrap().to_rgba8().get_pixel(0, 0).0, [255, 255, 255, 255]);
+
+        assert!(annotate_image(&path, &[rect(100)]).is_err());
+        assert!(annotate_image(&path, &[]).is_err());
     }
 }
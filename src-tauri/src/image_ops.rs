@@ -0,0 +1,879 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage, RgbImage};
+use imageproc::contours::find_contours;
+use imageproc::edges::canny;
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::geometric_transformations::{warp_into, Interpolation, Projection};
+use imageproc::geometry::{approximate_polygon_dp, arc_length, contour_area};
+use imageproc::point::Point;
+use crate::error::SnapMagError;
+use crate::types::{AnnotationError, AnnotationOp, PixelColor, PixelRect, UiRegion};
+
+/// zlib level used by `png_encoder` when nothing else was configured.
+pub const DEFAULT_PNG_COMPRESSION_LEVEL: u8 = 6;
+static PNG_COMPRESSION_LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_PNG_COMPRESSION_LEVEL);
+
+/// Sets the zlib level, 0–9, for every PNG the app writes. Process-wide
+/// because the clipboard decoders encode before the library sees the data;
+/// the library's `png_compression_level` setting is the source of truth.
+pub fn set_png_compression_level(level: u8) {
+    PNG_COMPRESSION_LEVEL.store(level.min(9), Ordering::Relaxed);
+}
+
+/// A PNG encoder at the configured compression level. Level 1 encodes a 4K
+/// screenshot about ten times faster than 6 for a file about 1.6x larger.
+pub fn png_encoder<W: std::io::Write>(writer: W) -> PngEncoder<W> {
+    let compression = match PNG_COMPRESSION_LEVEL.load(Ordering::Relaxed) {
+        0 => CompressionType::Uncompressed,
+        level => CompressionType::Level(level),
+    };
+    PngEncoder::new_with_quality(writer, compression, FilterType::Adaptive)
+}
+
+/// Longest side of the downscale a BlurHash is computed from.
+const BLURHASH_MAX_PX: u32 = 64;
+
+/// Smallest share of the image a quadrilateral must cover to be treated as a document.
+const MIN_DOCUMENT_AREA_RATIO: f64 = 0.1;
+
+/// Finds the largest quadrilateral outline in `image` and returns it warped
+/// into an upright rectangle, or `None` when no plausible document is found.
+pub fn detect_and_crop_document(image: &DynamicImage) -> Option<DynamicImage> {
+    let gray = image.to_luma8();
+    let blurred = gaussian_blur_f32(&gray, 1.5);
+    let edges = canny(&blurred, 50.0, 100.0);
+
+    let min_area = gray.width() as f64 * gray.height() as f64 * MIN_DOCUMENT_AREA_RATIO;
+    let quad = find_contours::<i32>(&edges)
+        .into_iter()
+        .filter_map(|contour| {
+            let epsilon = 0.02 * arc_length(&contour.points, true);
+            let polygon = approximate_polygon_dp(&contour.points, epsilon, true);
+            let corners: [Point<i32>; 4] = polygon.try_into().ok()?;
+            let area = contour_area(&corners);
+            (area >= min_area).then_some((area, corners))
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, corners)| corners)?;
+
+    let [top_left, top_right, bottom_right, bottom_left] = order_corners(&quad);
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right)).round() as u32;
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right)).round() as u32;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let projection = Projection::from_control_points(
+        [top_left, top_right, bottom_right, bottom_left],
+        [(0.0, 0.0), (width as f32, 0.0), (width as f32, height as f32), (0.0, height as f32)],
+    )?;
+
+    let source = image.to_rgba8();
+    let mut output = RgbaImage::new(width, height);
+    warp_into(&source, &projection, Interpolation::Bilinear, Rgba([255, 255, 255, 255]), &mut output);
+    Some(DynamicImage::ImageRgba8(output))
+}
+
+/// Decodes every frame of a GIF composited onto the full canvas, honoring
+/// each frame's disposal method. Fails past `max_frames` frames.
+pub fn gif_frames(data: &[u8], max_frames: usize) -> Result<Vec<RgbaImage>, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(data).map_err(|e| e.to_string())?;
+    let mut canvas = RgbaImage::new(decoder.width() as u32, decoder.height() as u32);
+
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| e.to_string())? {
+        if frames.len() == max_frames {
+            return Err(format!("GIF has more than {} frames", max_frames));
+        }
+        let previous = (frame.dispose == gif::DisposalMethod::Previous).then(|| canvas.clone());
+
+        let (left, top) = (frame.left as u32, frame.top as u32);
+        for (i, pixel) in frame.buffer.chunks_exact(4).enumerate() {
+            let x = left + i as u32 % frame.width as u32;
+            let y = top + i as u32 / frame.width as u32;
+            if pixel[3] != 0 && x < canvas.width() && y < canvas.height() {
+                canvas.put_pixel(x, y, Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            }
+        }
+        frames.push(canvas.clone());
+
+        match frame.dispose {
+            gif::DisposalMethod::Background => {
+                for y in top..(top + frame.height as u32).min(canvas.height()) {
+                    for x in left..(left + frame.width as u32).min(canvas.width()) {
+                        canvas.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+            gif::DisposalMethod::Previous => {
+                if let Some(previous) = previous {
+                    canvas = previous;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+/// BlurHash placeholder of `image` with 4x3 components.
+pub fn blurhash(image: &DynamicImage) -> Option<String> {
+    let small = image.thumbnail(BLURHASH_MAX_PX, BLURHASH_MAX_PX).to_rgba8();
+    blurhash::encode(4, 3, small.width(), small.height(), small.as_raw()).ok()
+}
+
+/// Splits `image` into at most `num_regions` full-width or full-height strips
+/// at its strongest color discontinuities. Rows and columns are compared by
+/// their mean color, and whichever axis changes more sharply is cut.
+pub fn detect_ui_regions(image: &DynamicImage, num_regions: u32) -> Vec<UiRegion> {
+    let rgb = image.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    if width == 0 || height == 0 || num_regions == 0 {
+        return Vec::new();
+    }
+
+    let cuts_wanted = num_regions as usize - 1;
+    let row_diffs = line_diffs(&rgb, true);
+    let column_diffs = line_diffs(&rgb, false);
+    let row_cuts = pick_cuts(&row_diffs, cuts_wanted);
+    let column_cuts = pick_cuts(&column_diffs, cuts_wanted);
+    let strength = |diffs: &[f64], cuts: &[usize]| cuts.iter().map(|&i| diffs[i - 1]).sum::<f64>();
+    let horizontal = strength(&row_diffs, &row_cuts) >= strength(&column_diffs, &column_cuts);
+
+    let (cuts, length) = if horizontal { (row_cuts, height) } else { (column_cuts, width) };
+    let mut bounds = vec![0];
+    bounds.extend(cuts.iter().map(|&cut| cut as u32));
+    bounds.push(length);
+
+    bounds
+        .windows(2)
+        .map(|span| {
+            let (x, y, w, h) = if horizontal {
+                (0, span[0], width, span[1] - span[0])
+            } else {
+                (span[0], 0, span[1] - span[0], height)
+            };
+            UiRegion { x, y, width: w, height: h, dominant_color: dominant_color(&rgb, x, y, w, h) }
+        })
+        .collect()
+}
+
+/// Color distance between each pair of adjacent rows (or columns) means;
+/// entry `i - 1` is the jump between lines `i - 1` and `i`.
+fn line_diffs(rgb: &RgbImage, rows: bool) -> Vec<f64> {
+    let (width, height) = rgb.dimensions();
+    let (lines, span) = if rows { (height, width) } else { (width, height) };
+    let means: Vec<[f64; 3]> = (0..lines)
+        .map(|line| {
+            let mut sum = [0u64; 3];
+            for offset in 0..span {
+                let pixel = if rows { rgb.get_pixel(offset, line) } else { rgb.get_pixel(line, offset) };
+                for channel in 0..3 {
+                    sum[channel] += pixel[channel] as u64;
+                }
+            }
+            sum.map(|total| total as f64 / span as f64)
+        })
+        .collect();
+    means
+        .windows(2)
+        .map(|pair| (0..3).map(|channel| (pair[1][channel] - pair[0][channel]).abs()).sum())
+        .collect()
+}
+
+/// Positions of the `count` largest non-zero jumps, keeping cuts from
+/// clustering on one gradient edge. Returned in ascending order.
+fn pick_cuts(diffs: &[f64], count: usize) -> Vec<usize> {
+    let min_gap = (diffs.len() / (count.max(1) * 4)).max(1);
+    let mut order: Vec<usize> = (0..diffs.len()).filter(|&i| diffs[i] > 0.0).collect();
+    order.sort_by(|&a, &b| diffs[b].total_cmp(&diffs[a]));
+
+    let mut cuts: Vec<usize> = Vec::with_capacity(count);
+    for i in order {
+        if cuts.len() == count {
+            break;
+        }
+        let cut = i + 1;
+        if cuts.iter().all(|&existing| existing.abs_diff(cut) >= min_gap) {
+            cuts.push(cut);
+        }
+    }
+    cuts.sort_unstable();
+    cuts
+}
+
+/// Average color of the most common 4-bit-per-channel color bucket in the rectangle.
+fn dominant_color(rgb: &RgbImage, x: u32, y: u32, width: u32, height: u32) -> [u8; 3] {
+    let mut buckets: HashMap<[u8; 3], (u64, [u64; 3])> = HashMap::new();
+    for py in y..y + height {
+        for px in x..x + width {
+            let pixel = rgb.get_pixel(px, py);
+            let (count, sum) = buckets.entry(pixel.0.map(|c| c >> 4)).or_default();
+            *count += 1;
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as u64;
+            }
+        }
+    }
+    buckets
+        .into_values()
+        .max_by_key(|(count, _)| *count)
+        .map(|(count, sum)| sum.map(|total| (total / count) as u8))
+        .unwrap_or_default()
+}
+
+/// Longest side of the downscale `dominant_hue` samples.
+const DOMINANT_HUE_MAX_PX: u32 = 64;
+/// Below this HSV saturation a dominant color counts as gray.
+const GRAYSCALE_MAX_SATURATION: f32 = 0.1;
+
+/// Hue in degrees (0.0..360.0) of the image's dominant color, or `None` when
+/// that color is grayscale.
+pub fn dominant_hue(image: &DynamicImage) -> Option<f32> {
+    let small = image.thumbnail(DOMINANT_HUE_MAX_PX, DOMINANT_HUE_MAX_PX).to_rgb8();
+    let [r, g, b] = dominant_color(&small, 0, 0, small.width(), small.height()).map(|c| c as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if max == 0.0 || delta / max < GRAYSCALE_MAX_SATURATION {
+        return None;
+    }
+    let sector = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    Some(sector * 60.0)
+}
+
+/// Parses `#rrggbb`, `rrggbb`, `#rgb` or `rgb`.
+pub fn parse_hex_color(hex: &str) -> Option<[u8; 3]> {
+    let digits = hex.trim().strip_prefix('#').unwrap_or(hex.trim());
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize, len: usize| u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).ok();
+    match digits.len() {
+        6 => Some([channel(0, 2)?, channel(1, 2)?, channel(2, 2)?]),
+        3 => Some([channel(0, 1)? * 17, channel(1, 1)? * 17, channel(2, 1)? * 17]),
+        _ => None,
+    }
+}
+
+fn pixel_color([r, g, b, a]: [u8; 4]) -> PixelColor {
+    PixelColor { r, g, b, a, hex: format!("#{:02x}{:02x}{:02x}", r, g, b) }
+}
+
+/// Color of the pixel at (`x`, `y`) in image pixels.
+pub fn sample_pixel(image: &DynamicImage, x: u32, y: u32) -> Result<PixelColor, SnapMagError> {
+    let (width, height) = image.dimensions();
+    if x >= width || y >= height {
+        return Err(SnapMagError::OutOfBounds { x, y, width, height });
+    }
+    Ok(pixel_color(image.get_pixel(x, y).0))
+}
+
+/// Per-channel mean of the pixels inside `rect`, which must lie within the
+/// image.
+pub fn region_average(image: &DynamicImage, rect: PixelRect) -> Result<PixelColor, SnapMagError> {
+    if rect.width == 0 || rect.height == 0 {
+        return Err(SnapMagError::InvalidArgument("region is empty".to_string()));
+    }
+    let (width, height) = image.dimensions();
+    let right = rect.x.saturating_add(rect.width - 1);
+    let bottom = rect.y.saturating_add(rect.height - 1);
+    if right >= width || bottom >= height {
+        return Err(SnapMagError::OutOfBounds { x: right, y: bottom, width, height });
+    }
+    
+    let mut sums = [0u64; 4];
+    for (_, _, pixel) in image.view(rect.x, rect.y, rect.width, rect.height).pixels() {
+        for (sum, channel) in sums.iter_mut().zip(pixel.0) {
+            *sum += channel as u64;
+        }
+    }
+    let count = rect.width as u64 * rect.height as u64;
+    Ok(pixel_color(sums.map(|sum| ((sum + count / 2) / count) as u8)))
+}
+
+/// Flattens `image` onto an opaque `background` with Porter-Duff "over".
+pub fn composite_on_background(image: &DynamicImage, background: [u8; 3]) -> RgbImage {
+    let source = image.to_rgba8();
+    RgbImage::from_fn(source.width(), source.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *source.get_pixel(x, y);
+        let alpha = a as u32;
+        let blend = |fg: u8, bg: u8| ((fg as u32 * alpha + bg as u32 * (255 - alpha) + 127) / 255) as u8;
+        image::Rgb([blend(r, background[0]), blend(g, background[1]), blend(b, background[2])])
+    })
+}
+
+/// Unsharp mask: `original + amount * (original - blurred)` with a Gaussian
+/// blur of sigma `amount / 2`. Alpha is kept as is.
+pub fn sharpen(image: &DynamicImage, amount: f32) -> RgbaImage {
+    let original = image.to_rgba8();
+    let blurred = image::imageops::blur(&original, amount * 0.5);
+    RgbaImage::from_fn(original.width(), original.height(), |x, y| {
+        let Rgba(o) = *original.get_pixel(x, y);
+        let Rgba(b) = *blurred.get_pixel(x, y);
+        let channel = |i: usize| (o[i] as f32 + amount * (o[i] as f32 - b[i] as f32)).round().clamp(0.0, 255.0) as u8;
+        Rgba([channel(0), channel(1), channel(2), o[3]])
+    })
+}
+
+/// Relief effect from a diagonal gradient kernel. The kernel sums to 0, so
+/// the result is offset by 128 to keep flat areas mid-gray instead of black.
+pub fn emboss(image: &DynamicImage) -> RgbaImage {
+    const KERNEL: [i32; 9] = [-1, -1, 0, -1, 0, 1, 0, 1, 1];
+    let original = image.to_rgba8();
+    let filtered: image::ImageBuffer<image::Rgb<i16>, Vec<i16>> = imageproc::filter::filter3x3(&image.to_rgb8(), &KERNEL);
+    RgbaImage::from_fn(original.width(), original.height(), |x, y| {
+        let image::Rgb(f) = *filtered.get_pixel(x, y);
+        let channel = |i: usize| (f[i] + 128).clamp(0, 255) as u8;
+        Rgba([channel(0), channel(1), channel(2), original.get_pixel(x, y)[3]])
+    })
+}
+
+/// Largest `stroke` of a rectangle annotation.
+const MAX_ANNOTATION_STROKE: u32 = 200;
+/// Text sizes an annotation accepts, in pixels.
+const ANNOTATION_TEXT_SIZES: std::ops::RangeInclusive<f32> = 4.0..=512.0;
+
+/// Rejects rectangles whose right or bottom edge overflows `i32`. Those are
+/// malformed requests rather than ops that miss the image, so they fail
+/// the whole call.
+pub fn check_annotation_bounds(ops: &[AnnotationOp]) -> Result<(), SnapMagError> {
+    for (index, op) in ops.iter().enumerate() {
+        if let AnnotationOp::Rect { x, y, w, h, .. } = op {
+            if rect_end(*x, *w).is_none() || rect_end(*y, *h).is_none() {
+                return Err(SnapMagError::InvalidArgument(format!("annotation #{}: rectangle {}x{} at ({}, {}) overflows", index, w, h, x, y)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `start + len`, or `None` when it doesn't fit an `i32`.
+fn rect_end(start: i32, len: u32) -> Option<i32> {
+    i32::try_from(len).ok().and_then(|len| start.checked_add(len))
+}
+
+/// Draws `ops` onto `image` in order. Ops that don't validate are skipped and
+/// returned, so the rest still apply.
+pub fn annotate(image: &mut RgbaImage, ops: &[AnnotationOp]) -> Vec<AnnotationError> {
+    let mut errors = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        if let Err(message) = draw_annotation(image, op) {
+            errors.push(AnnotationError { index, message });
+        }
+    }
+    errors
+}
+
+fn draw_annotation(image: &mut RgbaImage, op: &AnnotationOp) -> Result<(), String> {
+    use imageproc::drawing::{draw_filled_rect_mut, draw_line_segment_mut, draw_polygon_mut, draw_text_mut};
+    use imageproc::rect::Rect;
+    
+    let color_of = |hex: &str| {
+        parse_hex_color(hex).map(|[r, g, b]| Rgba([r, g, b, 255])).ok_or_else(|| format!("invalid hex color: {}", hex))
+    };
+    let (width, height) = (image.width() as i32, image.height() as i32);
+    let on_image = |x: i32, y: i32| (0..width).contains(&x) && (0..height).contains(&y);
+    
+    match op {
+        AnnotationOp::Rect { x, y, w, h, color, stroke } => {
+            let color = color_of(color)?;
+            if *w == 0 || *h == 0 {
+                return Err("rectangle has no area".to_string());
+            }
+            if !(1..=MAX_ANNOTATION_STROKE).contains(stroke) {
+                return Err(format!("stroke must be 1 to {}", MAX_ANNOTATION_STROKE));
+            }
+            let (Some(x_end), Some(y_end)) = (rect_end(*x, *w), rect_end(*y, *h)) else {
+                return Err("rectangle overflows".to_string());
+            };
+            if *x >= width || *y >= height || x_end <= 0 || y_end <= 0 {
+                return Err("rectangle is outside the image".to_string());
+            }
+            let (sx, sy) = ((*stroke).min(*w), (*stroke).min(*h));
+            // The stroke fits inside the rectangle, so these can't overflow.
+            let right = x_end - sx as i32;
+            let bottom = y_end - sy as i32;
+            for edge in [
+                Rect::at(*x, *y).of_size(*w, sy),
+                Rect::at(*x, bottom).of_size(*w, sy),
+                Rect::at(*x, *y).of_size(sx, *h),
+                Rect::at(right, *y).of_size(sx, *h),
+            ] {
+                draw_filled_rect_mut(image, edge, color);
+            }
+        }
+        AnnotationOp::Arrow { from, to, color } => {
+            let color = color_of(color)?;
+            if !on_image(from[0], from[1]) || !on_image(to[0], to[1]) {
+                return Err("arrow ends must be inside the image".to_string());
+            }
+            let (dx, dy) = ((to[0] - from[0]) as f32, (to[1] - from[1]) as f32);
+            let length = dx.hypot(dy);
+            if length < 2.0 {
+                return Err("arrow is too short".to_string());
+            }
+            // Scaled to the image so arrows stay visible on large screenshots.
+            let thickness = (width.min(height) as f32 / 200.0).max(2.0);
+            let head = (thickness * 4.0).min(length);
+            let (ux, uy) = (dx / length, dy / length);
+            let (nx, ny) = (-uy, ux);
+            let base = (to[0] as f32 - ux * head, to[1] as f32 - uy * head);
+            let point = |x: f32, y: f32| Point::new(x.round() as i32, y.round() as i32);
+            
+            // Parallel lines half a pixel apart; a thin diagonal polygon
+            // fills with gaps.
+            let strands = (thickness * 2.0).ceil() as i32;
+            for strand in 0..=strands {
+                let offset = thickness * (strand as f32 / strands as f32 - 0.5);
+                let start = (from[0] as f32 + nx * offset, from[1] as f32 + ny * offset);
+                draw_line_segment_mut(image, start, (base.0 + nx * offset, base.1 + ny * offset), color);
+            }
+            let arrowhead = [
+                point(to[0] as f32, to[1] as f32),
+                point(base.0 + nx * head / 2.0, base.1 + ny * head / 2.0),
+                point(base.0 - nx * head / 2.0, base.1 - ny * head / 2.0),
+            ];
+            draw_polygon_mut(image, &arrowhead, color);
+        }
+        AnnotationOp::Text { x, y, content, size, color } => {
+            let color = color_of(color)?;
+            if content.trim().is_empty() {
+                return Err("text is empty".to_string());
+            }
+            if !ANNOTATION_TEXT_SIZES.contains(size) {
+                return Err(format!("text size must be {} to {}", ANNOTATION_TEXT_SIZES.start(), ANNOTATION_TEXT_SIZES.end()));
+            }
+            if !on_image(*x, *y) {
+                return Err("text must start inside the image".to_string());
+            }
+            let font = annotation_font().ok_or_else(|| "no font available for text".to_string())?;
+            draw_text_mut(image, color, *x, *y, *size, font, content);
+        }
+    }
+    Ok(())
+}
+
+/// A system sans-serif font for text annotations, loaded once. No font is
+/// bundled with the app.
+fn annotation_font() -> Option<&'static ab_glyph::FontArc> {
+    static FONT: std::sync::OnceLock<Option<ab_glyph::FontArc>> = std::sync::OnceLock::new();
+    FONT.get_or_init(|| {
+        let windows_fonts = std::env::var_os("WINDIR").map(|dir| std::path::PathBuf::from(dir).join("Fonts"));
+        let candidates = windows_fonts
+            .iter()
+            .flat_map(|dir| [dir.join("segoeui.ttf"), dir.join("arial.ttf")])
+            .chain([
+                "/System/Library/Fonts/Supplemental/Arial.ttf".into(),
+                "/Library/Fonts/Arial.ttf".into(),
+                "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".into(),
+            ]);
+        for path in candidates {
+            if let Some(font) = std::fs::read(&path).ok().and_then(|data| ab_glyph::FontArc::try_from_vec(data).ok()) {
+                log::debug!("Using {} for text annotations", path.display());
+                return Some(font);
+            }
+        }
+        log::warn!("No font found for text annotations");
+        None
+    })
+    .as_ref()
+}
+
+/// 64-bit difference hash: each bit tells whether a pixel of a 9x8 grayscale
+/// downscale is brighter than its right neighbour. Robust to rescaling and
+/// recompression, unlike the content hash.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let small = image
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | brighter as u64;
+        }
+    }
+    hash
+}
+
+/// Hamming distance between two `perceptual_hash` values, normalized to
+/// 0.0 (identical) ..= 1.0 (every bit differs).
+pub fn perceptual_distance(a: u64, b: u64) -> f64 {
+    hamming_distance(a, b) as f64 / 64.0
+}
+
+/// Bits that differ between two `perceptual_hash` values.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// BK-tree over perceptual hashes under the Hamming metric, so a radius
+/// query only visits children whose edge distance can still hold a match.
+#[derive(Default)]
+pub struct HashTree {
+    nodes: Vec<HashTreeNode>,
+}
+
+struct HashTreeNode {
+    hash: u64,
+    value: usize,
+    /// Child node index by its distance to this node.
+    children: Vec<(u32, usize)>,
+}
+
+impl HashTree {
+    pub fn insert(&mut self, hash: u64, value: usize) {
+        let new_index = self.nodes.len();
+        self.nodes.push(HashTreeNode { hash, value, children: Vec::new() });
+        if new_index == 0 {
+            return;
+        }
+        
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+            match self.nodes[current].children.iter().find(|(edge, _)| *edge == distance) {
+                Some(&(_, child)) => current = child,
+                None => {
+                    self.nodes[current].children.push((distance, new_index));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Values of every hash within `max_distance` of `hash`.
+    pub fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+        while let Some(index) = pending.pop() {
+            let node = &self.nodes[index];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= max_distance {
+                found.push(node.value);
+            }
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(edge, _)| edge.abs_diff(distance) <= max_distance)
+                    .map(|&(_, child)| child),
+            );
+        }
+        found
+    }
+}
+
+/// Longest side a stitched panorama may have.
+const MAX_PANORAMA_PX: u64 = 65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StitchDirection {
+    Horizontal,
+    Vertical,
+}
+
+impl std::str::FromStr for StitchDirection {
+    type Err = SnapMagError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "horizontal" => Ok(Self::Horizontal),
+            "vertical" => Ok(Self::Vertical),
+            _ => Err(SnapMagError::InvalidArgument(format!("unknown stitch direction: {}", s))),
+        }
+    }
+}
+
+impl StitchDirection {
+    /// Picks the direction `images` can be joined in: side by side when the
+    /// heights agree, otherwise stacked when the widths do.
+    pub fn detect(images: &[DynamicImage]) -> Option<Self> {
+        let first = images.first()?;
+        if images.iter().all(|image| image.height() == first.height()) {
+            Some(Self::Horizontal)
+        } else if images.iter().all(|image| image.width() == first.width()) {
+            Some(Self::Vertical)
+        } else {
+            None
+        }
+    }
+}
+
+/// Joins `images` edge to edge in order. Every image must share the height
+/// (horizontal) or width (vertical) of the first.
+pub fn stitch_panorama(images: &[DynamicImage], direction: StitchDirection) -> Result<RgbaImage, SnapMagError> {
+    let first = images
+        .first()
+        .ok_or_else(|| SnapMagError::InvalidArgument("no images to stitch".to_string()))?;
+    let (base_width, base_height) = first.dimensions();
+    
+    let mut length: u64 = 0;
+    for (index, image) in images.iter().enumerate() {
+        let (width, height) = image.dimensions();
+        let (shared, expected, along) = match direction {
+            StitchDirection::Horizontal => (height, base_height, width),
+            StitchDirection::Vertical => (width, base_width, height),
+        };
+        if shared != expected {
+            return Err(SnapMagError::InvalidArgument(format!(
+                "image {} is {}x{}, expected {} {}",
+                index + 1,
+                width,
+                height,
+                if direction == StitchDirection::Horizontal { "height" } else { "width" },
+                expected,
+            )));
+        }
+        length += along as u64;
+    }
+    if length > MAX_PANORAMA_PX {
+        return Err(SnapMagError::InvalidArgument(format!("panorama would be {} pixels long", length)));
+    }
+    
+    let (canvas_width, canvas_height) = match direction {
+        StitchDirection::Horizontal => (length as u32, base_height),
+        StitchDirection::Vertical => (base_width, length as u32),
+    };
+    let mut canvas = RgbaImage::new(canvas_width, canvas_height);
+    let mut offset: i64 = 0;
+    for image in images {
+        let (x, y) = match direction {
+            StitchDirection::Horizontal => (offset, 0),
+            StitchDirection::Vertical => (0, offset),
+        };
+        image::imageops::replace(&mut canvas, &image.to_rgba8(), x, y);
+        offset += match direction {
+            StitchDirection::Horizontal => image.width(),
+            StitchDirection::Vertical => image.height(),
+        } as i64;
+    }
+    Ok(canvas)
+}
+
+/// Scales `image` to fit inside `width`×`height` keeping its aspect ratio,
+/// centered on a transparent canvas of exactly that size.
+pub fn letterbox(image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+    let scaled = image.resize(width, height, image::imageops::FilterType::Lanczos3).to_rgba8();
+    let mut canvas = RgbaImage::new(width, height);
+    let x = (width - scaled.width()) / 2;
+    let y = (height - scaled.height()) / 2;
+    image::imageops::replace(&mut canvas, &scaled, x as i64, y as i64);
+    canvas
+}
+
+/// Encodes same-sized `frames` as a looping APNG showing each frame for
+/// `frame_delay_ms`.
+pub fn encode_apng(frames: &[RgbaImage], frame_delay_ms: u32) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or_else(|| "no frames to encode".to_string())?;
+    let delay = frame_delay_ms.min(u16::MAX as u32) as u16;
+    
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, first.width(), first.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0).map_err(|e| e.to_string())?;
+    encoder.set_frame_delay(delay, 1000).map_err(|e| e.to_string())?;
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    for frame in frames {
+        writer.write_image_data(frame.as_raw()).map_err(|e| e.to_string())?;
+    }
+    writer.finish().map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+/// Badge counts above this are shown as `99+`.
+const MAX_BADGE_COUNT: usize = 99;
+const BADGE_COLOR: Rgba<u8> = Rgba([220, 38, 38, 255]);
+const BADGE_TEXT_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// 3x5 glyphs for the badge, one row per byte with the low three bits set
+/// left to right. There is no font bundled for `draw_text_mut`, and digits
+/// this small read better unantialiased anyway.
+fn badge_glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        _ => [0; 5],
+    }
+}
+
+/// Draws a red count badge in the lower-right corner of `icon`.
+pub fn draw_count_badge(icon: &mut RgbaImage, count: usize) {
+    use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut};
+    use imageproc::rect::Rect;
+    
+    let text = if count > MAX_BADGE_COUNT { format!("{}+", MAX_BADGE_COUNT) } else { count.to_string() };
+    let (width, height) = icon.dimensions();
+    let radius = (width.min(height) as f32 * 0.3).round().max(3.0) as i32;
+    let center = (width as i32 - radius - 1, height as i32 - radius - 1);
+    draw_filled_circle_mut(icon, center, radius, BADGE_COLOR);
+    
+    let columns = text.chars().count() as i32 * 4 - 1;
+    let scale = (radius * 3 / 2 / columns).min(radius / 5).max(1);
+    let mut x = center.0 - columns * scale / 2;
+    let y = center.1 - 5 * scale / 2;
+    for c in text.chars() {
+        for (row, bits) in badge_glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) != 0 {
+                    let rect = Rect::at(x + column * scale, y + row as i32 * scale).of_size(scale as u32, scale as u32);
+                    draw_filled_rect_mut(icon, rect, BADGE_TEXT_COLOR);
+                }
+            }
+        }
+        x += 4 * scale;
+    }
+}
+
+/// Redraws `icon` in the single color `rgb`, keeping its shape: dark parts
+/// stay opaque and light parts fade, so details survive on either taskbar.
+pub fn monochrome_icon(icon: &mut RgbaImage, rgb: [u8; 3]) {
+    for pixel in icon.pixels_mut() {
+        let [r, g, b, alpha] = pixel.0;
+        let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+        let ink = 80 + (255 - luma) * 175 / 255;
+        *pixel = Rgba([rgb[0], rgb[1], rgb[2], (alpha as u32 * ink / 255) as u8]);
+    }
+}
+
+/// Whether `crop` appears somewhere inside `source`: some placement of it must
+/// have a mean absolute difference of at most `tolerance_px` per channel.
+/// Each placement is first checked along the crop's border, and abandoned as
+/// soon as its running difference exceeds the budget.
+pub fn is_cropped_from(crop: &DynamicImage, source: &DynamicImage, tolerance_px: u32) -> bool {
+    let crop = crop.to_rgb8();
+    let source = source.to_rgb8();
+    let (crop_width, crop_height) = crop.dimensions();
+    let (source_width, source_height) = source.dimensions();
+    if crop_width == 0 || crop_height == 0 || crop_width > source_width || crop_height > source_height {
+        return false;
+    }
+
+    let per_pixel_budget = tolerance_px as u64 * 3;
+    let on_border = |x: u32, y: u32| x == 0 || y == 0 || x == crop_width - 1 || y == crop_height - 1;
+    let (border, interior): (Vec<_>, Vec<_>) = (0..crop_height)
+        .flat_map(|y| (0..crop_width).map(move |x| (x, y)))
+        .partition(|&(x, y)| on_border(x, y));
+    let total_budget = per_pixel_budget * (crop_width as u64 * crop_height as u64);
+
+    let sad = |points: &[(u32, u32)], offset_x: u32, offset_y: u32, budget: u64| -> Option<u64> {
+        let mut total = 0u64;
+        for &(x, y) in points {
+            let a = crop.get_pixel(x, y);
+            let b = source.get_pixel(offset_x + x, offset_y + y);
+            total += (0..3).map(|channel| a[channel].abs_diff(b[channel]) as u64).sum::<u64>();
+            if total > budget {
+                return None;
+            }
+        }
+        Some(total)
+    };
+
+    for offset_y in 0..=source_height - crop_height {
+        for offset_x in 0..=source_width - crop_width {
+            let Some(border_sad) = sad(&border, offset_x, offset_y, per_pixel_budget * border.len() as u64) else {
+                continue;
+            };
+            if sad(&interior, offset_x, offset_y, total_budget - border_sad).is_some() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Orders corners as top-left, top-right, bottom-right, bottom-left.
+fn order_corners(corners: &[Point<i32>; 4]) -> [(f32, f32); 4] {
+    let points = corners.map(|p| (p.x as f32, p.y as f32));
+    let sum = |p: &(f32, f32)| p.0 + p.1;
+    let diff = |p: &(f32, f32)| p.0 - p.1;
+
+    let mut top_left = points[0];
+    let mut bottom_right = points[0];
+    let mut top_right = points[0];
+    let mut bottom_left = points[0];
+    for point in &points[1..] {
+        if sum(point) < sum(&top_left) {
+            top_left = *point;
+        }
+        if sum(point) > sum(&bottom_right) {
+            bottom_right = *point;
+        }
+        if diff(point) > diff(&top_right) {
+            top_right = *point;
+        }
+        if diff(point) < diff(&bottom_left) {
+            bottom_left = *point;
+        }
+    }
+
+    [top_left, top_right, bottom_right, bottom_left]
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_color_image_splits_into_two_regions() {
+        let image = RgbImage::from_fn(40, 30, |_, y| if y < 10 { image::Rgb([200, 30, 30]) } else { image::Rgb([20, 40, 220]) });
+        let regions = detect_ui_regions(&DynamicImage::ImageRgb8(image), 2);
+
+        assert_eq!(regions.len(), 2);
+        let bounds: Vec<_> = regions.iter().map(|region| (region.x, region.y, region.width, region.height)).collect();
+        assert_eq!(bounds, [(0, 0, 40, 10), (0, 10, 40, 20)]);
+        assert_eq!(regions[0].dominant_color, [200, 30, 30]);
+        assert_eq!(regions[1].dominant_color, [20, 40, 220]);
+    }
+
+    #[test]
+    fn transparency_is_flattened_onto_the_background() {
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, Rgba([255, 0, 0, 0]));
+        let background = parse_hex_color("#00f").unwrap();
+        let flattened = composite_on_background(&DynamicImage::ImageRgba8(image.clone()), background);
+        assert_eq!(flattened.get_pixel(0, 0).0, [255, 0, 0]);
+        assert_eq!(flattened.get_pixel(1, 0).0, [0, 0, 255]);
+
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 128]));
+        let half = composite_on_background(&DynamicImage::ImageRgba8(image), [0, 0, 0]);
+        assert_eq!(half.get_pixel(1, 0).0, [128, 128, 128]);
+    }
+
+    #[test]
+    fn overflowing_rectangles_are_rejected() {
+        let rect = |x: i32, w: u32| AnnotationOp::Rect { x, y: 0, w, h: 4, color: "#f00".to_string(), stroke: 1 };
+        assert!(check_annotation_bounds(&[rect(0, 4)]).is_ok());
+        assert!(matches!(check_annotation_bounds(&[rect(0, 4), rect(i32::MAX, 1)]), Err(SnapMagError::InvalidArgument(_))));
+        assert!(check_annotation_bounds(&[rect(0, u32::MAX)]).is_err());
+
+        let mut image = RgbaImage::new(8, 8);
+        let errors = annotate(&mut image, &[rect(1, u32::MAX), rect(-4, 6)]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 0);
+        assert_eq!(image.get_pixel(1, 0).0, [255, 0, 0, 255]);
+    }
+}
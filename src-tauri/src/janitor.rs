@@ -0,0 +1,179 @@
+//! Cleanup of temporary files a crash would otherwise leave behind. Each
+//! process keeps its temporary files in its own folder under `session/`,
+//! named `{start time}-{pid}`. Folders of processes that are gone are
+//! deleted, and cache folders are trimmed to a size cap.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::platform;
+use crate::types::JanitorStats;
+
+/// How often `run_if_due` sweeps.
+const JANITOR_INTERVAL_SECS: i64 = 10 * 60;
+/// How much later than its folder's name a process may have started and
+/// still own it, for clocks read at different precisions.
+const START_TIME_SLACK_SECS: i64 = 2;
+
+pub struct Janitor {
+    /// Parent of the per-process session folders.
+    root: PathBuf,
+    session_dir: PathBuf,
+    stats: JanitorStats,
+}
+
+impl Janitor {
+    /// Janitor for the session folders under `root`. This process's folder
+    /// is named after `started_at` and created on first use.
+    pub fn new(root: PathBuf, started_at: i64) -> Self {
+        let session_dir = root.join(format!("{}-{}", started_at, std::process::id()));
+        Self { root, session_dir, stats: JanitorStats::default() }
+    }
+
+    pub fn session_dir(&self) -> &Path {
+        &self.session_dir
+    }
+
+    pub fn stats(&self) -> JanitorStats {
+        self.stats.clone()
+    }
+
+    pub fn is_due(&self, now: i64) -> bool {
+        self.stats.last_run.map_or(true, |last_run| now - last_run >= JANITOR_INTERVAL_SECS)
+    }
+
+    /// Deletes the session folders of processes that are gone, then trims
+    /// each `(folder, max_bytes)` of `caches` to its cap. Returns the bytes
+    /// reclaimed.
+    pub fn run(&mut self, caches: &[(&Path, u64)], now: i64) -> u64 {
+        let mut reclaimed = self.sweep_dead_sessions();
+        for &(dir, max_bytes) in caches {
+            reclaimed += trim_cache(dir, max_bytes);
+        }
+        self.stats.reclaimed_bytes += reclaimed;
+        self.stats.last_run = Some(now);
+        if reclaimed > 0 {
+            log::info!("Janitor reclaimed {} bytes", reclaimed);
+        }
+        reclaimed
+    }
+
+    /// Removes this process's session folder, on exit.
+    pub fn remove_session(&self) {
+        if let Err(e) = fs::remove_dir_all(&self.session_dir) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Failed to remove session folder: {}", e);
+            }
+        }
+    }
+
+    /// Deletes folders under `root` whose process is no longer running, and
+    /// anything else there that isn't a session folder, such as the loose
+    /// files of versions that shared one session folder.
+    fn sweep_dead_sessions(&self) -> u64 {
+        let Ok(entries) = fs::read_dir(&self.root) else {
+            return 0;
+        };
+        let mut reclaimed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path == self.session_dir {
+                continue;
+            }
+            let owner = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split_once('-'))
+                .and_then(|(started_at, pid)| Some((started_at.parse::<i64>().ok()?, pid.parse::<u32>().ok()?)));
+            if path.is_dir() && owner.is_some_and(|(started_at, pid)| owns_session(pid, started_at)) {
+                continue;
+            }
+            let size = disk_usage(&path);
+            let result = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            match result {
+                Ok(()) => reclaimed += size,
+                Err(e) => log::warn!("Failed to remove stale session file {}: {}", path.display(), e),
+            }
+        }
+        reclaimed
+    }
+}
+
+/// Whether process `pid` is the one that named its session folder after
+/// `started_at`: alive, and not started after it, which would make it a
+/// later process that was given the same PID. Where the start time can't
+/// be read, being alive is enough.
+fn owns_session(pid: u32, started_at: i64) -> bool {
+    platform::process_alive(pid)
+        && platform::process_started_at(pid).map_or(true, |process_start| process_start <= started_at + START_TIME_SLACK_SECS)
+}
+
+/// Deletes the least recently modified files directly in `dir` until the
+/// rest fit in `max_bytes`. Returns the bytes removed.
+fn trim_cache(dir: &Path, max_bytes: u64) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    let mut files: Vec<(std::time::SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+            Some((metadata.modified().unwrap_or(UNIX_EPOCH), metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    if total <= max_bytes {
+        return 0;
+    }
+
+    files.sort();
+    let mut removed = 0;
+    for (_, len, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                total -= len;
+                removed += len;
+            }
+            Err(e) => log::warn!("Failed to trim cache file {}: {}", path.display(), e),
+        }
+    }
+    log::debug!("Trimmed {} bytes from {}", removed, dir.display());
+    removed
+}
+
+/// Size of a file, or of everything under a folder.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|entries| entries.flatten().map(|entry| disk_usage(&entry.path())).sum())
+        .unwrap_or(0)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn folders_of_a_reused_pid_are_swept() {
+        let dir = TempDir::new().unwrap();
+        let now = chrono::Utc::now().timestamp();
+        let pid = std::process::id();
+        // This process's PID, but named before this process started.
+        let stale = dir.path().join(format!("{}-{}", now - 24 * 3600, pid));
+        let live = dir.path().join(format!("{}-{}", now + 60, pid));
+        fs::create_dir_all(&stale).unwrap();
+        fs::create_dir_all(&live).unwrap();
+
+        Janitor::new(dir.path().to_path_buf(), now).run(&[], now);
+        assert!(!stale.exists());
+        assert!(live.exists());
+    }
+}
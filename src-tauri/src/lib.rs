@@ -1,21 +1,99 @@
 mod types;
+mod error;
 mod image_manager;
+mod image_ops;
 mod clipboard;
+mod undo;
+mod thumbnails;
+#[cfg(any(target_os = "windows", test))]
+mod dib;
+mod diagnostics;
+mod peer;
+mod settings;
+mod snippets;
+mod lock;
+mod lut;
+mod pdf;
+mod capture_log;
+mod slideshow;
+mod janitor;
+/// Window and tray icons as raw RGBA, decoded by `build.rs`.
+mod icons {
+    include!(concat!(env!("OUT_DIR"), "/icons.rs"));
+}
+pub mod platform;
+pub mod cli;
+#[doc(hidden)]
+pub mod bench;
 
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tauri::{
+    Emitter,
+    Listener,
     Manager,
-    tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent},
+    tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent},
     menu::{Menu, MenuItem},
     image::Image,
 };
-use types::ImageMetadata;
-use image_manager::ImageManager;
-use clipboard::ClipboardListener;
+use types::{AnnotationOp, AnnotationResult, AverageSizeStats, ClipboardError, ImageList, ImagesAddedEvent, BackgroundErrorEvent, LibraryReadyEvent, PdfProgressEvent, CaptureRateComparison, CaptureSession, CaptureSource, CaptureStats, ClearSummary, ClipboardEvent, DeleteFilter, DeletionSummary, ClipboardStats, DestructiveConfirmation, ExportFormat, ExtensionFix, ImageMetadata, ImagePage, ImagesRemovedEvent, MonitorIdentification, PathsInvalidatedEvent, NearDuplicateGroup, SettingsImportPreview, OrphanedFile, OcrCorpusFormat, ImagesRestoredEvent, PixelColor, PixelRect, RenameOperation, RetentionPreset, SpriteStripResult, StoragePerformance, StorageStats, TemporalOrderResult, TextSnippet, TrayIconStyle, UiRegion, UndoEntryInfo, VariantFormat};
+use error::SnapMagError;
+use image_manager::{CleanupOutcome, ImageManager, LibraryScan};
+use clipboard::{ClipboardListener, CustomFormatExtractor, ThreadPriority};
+use image_ops::StitchDirection;
+use peer::PeerTransfer;
+use settings::{InterfaceSettings, Settings};
+use snippets::SnippetStore;
+use lock::AppLock;
+use capture_log::CaptureLog;
+use slideshow::Slideshows;
 
 struct AppState {
     image_manager: Arc<Mutex<ImageManager>>,
     clipboard_listener: Arc<Mutex<ClipboardListener>>,
+    snippets: Arc<Mutex<SnippetStore>>,
+    peer: Mutex<PeerTransfer>,
+    /// Image shown as the window icon by `set_window_icon_from_image`.
+    window_icon_id: Mutex<Option<String>>,
+    lock: Mutex<AppLock>,
+    tray: TrayIcon,
+    badge: Mutex<TrayBadge>,
+    tray_theme: Mutex<TrayTheme>,
+    /// Open while `enable_capture_log` is in effect; written by the listener.
+    capture_log: Arc<Mutex<Option<CaptureLog>>>,
+    slideshows: Slideshows,
+    /// Settings read by `preview_settings_import`, until confirmed.
+    pending_import: Mutex<Option<PendingImport>>,
+}
+
+struct PendingImport {
+    token: String,
+    settings: Settings,
+    expires_at: std::time::Instant,
+}
+
+/// Captures since the window was last shown, drawn on the tray icon.
+struct TrayBadge {
+    enabled: bool,
+    count: usize,
+}
+
+#[derive(Clone, Copy)]
+struct TrayTheme {
+    style: TrayIconStyle,
+    /// Last `SystemUsesLightTheme` read; false where it can't be read.
+    light_taskbar: bool,
+}
+
+impl TrayTheme {
+    /// `style` with `Auto` resolved against the taskbar theme.
+    fn resolved_style(&self) -> TrayIconStyle {
+        match self.style {
+            TrayIconStyle::Auto if self.light_taskbar => TrayIconStyle::Dark,
+            TrayIconStyle::Auto => TrayIconStyle::Light,
+            style => style,
+        }
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -24,11 +102,7 @@ pub fn run() {
         .setup(|app| {
             let window = app.get_webview_window("main").unwrap();
             
-            let icon_bytes = include_bytes!("../icons/256x256.png");
-            let img = image::load_from_memory(icon_bytes).expect("Failed to load window icon");
-            let rgba = img.to_rgba8();
-            let icon_image = Image::new_owned(rgba.to_vec(), 256, 256);
-            window.set_icon(icon_image).expect("Failed to set window icon");
+            window.set_icon(default_window_icon()).expect("Failed to set window icon");
             
             if cfg!(debug_assertions) {
                 app.handle().plugin(
@@ -39,48 +113,40 @@ pub fn run() {
             }
             
             let image_manager = Arc::new(Mutex::new(ImageManager::new()?));
-            let clipboard_listener = Arc::new(Mutex::new(ClipboardListener::new(image_manager.clone())));
+            let snippets_dir = image_manager.lock().unwrap().storage_dir().join("snippets");
+            let snippets = Arc::new(Mutex::new(SnippetStore::open(snippets_dir)?));
+            let capture_log = Arc::new(Mutex::new(None));
+            let clipboard_listener = Arc::new(Mutex::new(ClipboardListener::new(image_manager.clone(), snippets.clone(), capture_log.clone())));
+            let lock = AppLock::open(image_manager.lock().unwrap().storage_dir().join("lock"))?;
+            let peer = PeerTransfer::open(image_manager.lock().unwrap().storage_dir().join("peer"))?;
             
             clipboard_listener.lock().unwrap().start(app.handle().clone());
+            #[cfg(unix)]
+            platform::serve_instance_requests(app.handle().clone());
+            #[cfg(target_os = "windows")]
+            platform::spawn_ipc_window(app.handle().clone());
+            spawn_library_scan(app.handle().clone(), image_manager.clone());
+            spawn_expiry_sweeper(app.handle().clone(), image_manager.clone(), snippets.clone());
+            spawn_index_saver(app.handle().clone(), image_manager.clone());
+            spawn_storage_watcher(image_manager.clone());
             
-            let app_state = AppState {
-                image_manager: image_manager.clone(),
-                clipboard_listener: clipboard_listener.clone(),
-            };
-            
-            app.manage(app_state);
-
             let show_item = MenuItem::with_id(app, "show", "显示窗口", true, None::<&str>)?;
             let quit_item = MenuItem::with_id(app, "quit", "退出", true, None::<&str>)?;
             let menu = Menu::with_items(app, &[&show_item, &quit_item])?;
 
-            let icon_bytes = include_bytes!("../icons/tray-icon.png");
-            let img = image::load_from_memory(icon_bytes).expect("Failed to load tray icon");
-            let rgba = img.to_rgba8();
-            let tray_icon = Image::new_owned(rgba.to_vec(), 64, 64);
-            let _tray = TrayIconBuilder::new()
-                .icon(tray_icon)
+            let tray_theme = TrayTheme {
+                style: settings::load_tray_icon_style(image_manager.lock().unwrap().storage_dir()),
+                light_taskbar: platform::taskbar_uses_light_theme().unwrap_or(false),
+            };
+            let tray = TrayIconBuilder::with_id(TRAY_ID)
+                .icon(tray_icon(tray_theme, 0))
                 .menu(&menu)
                 .tooltip("SnapMag")
                 .on_menu_event(|app, event| {
                     match event.id.as_ref() {
                         "show" => {
                             if let Some(window) = app.get_webview_window("main") {
-                                #[cfg(target_os = "windows")]
-                                {
-                                    use winapi::um::winuser::{ShowWindow, SW_RESTORE, SetForegroundWindow, SetFocus};
-                                    if let Ok(hwnd) = window.hwnd() {
-                                        let hwnd_ptr = hwnd.0 as *mut _;
-                                        unsafe {
-                                            ShowWindow(hwnd_ptr, SW_RESTORE);
-                                            SetForegroundWindow(hwnd_ptr);
-                                            SetFocus(hwnd_ptr);
-                                        }
-                                        return;
-                                    }
-                                }
-                                let _ = window.show();
-                                let _ = window.set_focus();
+                                platform::restore_and_focus(&window);
                             }
                         }
                         "quit" => {
@@ -98,43 +164,67 @@ pub fn run() {
                     {
                         let app = tray.app_handle();
                         if let Some(window) = app.get_webview_window("main") {
-                            #[cfg(target_os = "windows")]
-                            {
-                                use winapi::um::winuser::{ShowWindow, SW_RESTORE, SetForegroundWindow, SetFocus};
-                                if let Ok(hwnd) = window.hwnd() {
-                                    let hwnd_ptr = hwnd.0 as *mut _;
-                                    unsafe {
-                                        ShowWindow(hwnd_ptr, SW_RESTORE);
-                                        SetForegroundWindow(hwnd_ptr);
-                                        SetFocus(hwnd_ptr);
-                                    }
-                                    return;
-                                }
-                            }
-                            let _ = window.show();
-                            let _ = window.set_focus();
+                            platform::restore_and_focus(&window);
                         }
                     }
                 })
                 .build(app)?;
+            #[cfg(target_os = "windows")]
+            spawn_tray_theme_watch(app.handle().clone());
+
+            let app_state = AppState {
+                image_manager: image_manager.clone(),
+                clipboard_listener: clipboard_listener.clone(),
+                snippets,
+                peer: Mutex::new(peer),
+                window_icon_id: Mutex::new(None),
+                lock: Mutex::new(lock),
+                tray,
+                badge: Mutex::new(TrayBadge { enabled: true, count: 0 }),
+                tray_theme: Mutex::new(tray_theme),
+                capture_log,
+                slideshows: Slideshows::default(),
+                pending_import: Mutex::new(None),
+            };
+            
+            app.manage(app_state);
+            
+            let handle = app.handle().clone();
+            app.listen("images-added", move |event| {
+                let Ok(added) = serde_json::from_str::<ImagesAddedEvent>(event.payload()) else {
+                    return;
+                };
+                let state = handle.state::<AppState>();
+                let enabled = {
+                    let mut badge = state.badge.lock().unwrap();
+                    badge.count += added.items.len();
+                    badge.enabled
+                };
+                if enabled {
+                    refresh_tray_icon(&state);
+                }
+            });
             
             Ok(())
         })
         .on_window_event(|window, event| {
-            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
-                #[cfg(target_os = "windows")]
-                {
-                    use winapi::um::winuser::{ShowWindow, SW_HIDE};
-                    if let Ok(hwnd) = window.hwnd() {
-                        unsafe {
-                            ShowWindow(hwnd.0 as *mut _, SW_HIDE);
+            match event {
+                tauri::WindowEvent::CloseRequested { api, .. } => {
+                    platform::hide(window);
+                    api.prevent_close();
+                }
+                // Showing the window counts as having seen the new captures.
+                tauri::WindowEvent::Focused(true) => {
+                    if let Some(state) = window.try_state::<AppState>() {
+                        let seen = std::mem::take(&mut state.badge.lock().unwrap().count);
+                        if seen > 0 {
+                            refresh_tray_icon(&state);
                         }
-                        api.prevent_close();
-                        return;
                     }
                 }
-                let _ = window.hide();
-                api.prevent_close();
+                // Sent on WM_SETTINGCHANGE, which also covers the taskbar theme.
+                tauri::WindowEvent::ThemeChanged(_) => refresh_tray_theme(window.app_handle()),
+                _ => {}
             }
         })
         .invoke_handler(tauri::generate_handler![
@@ -143,193 +233,2428 @@ pub fn run() {
             save_image_from_clipboard,
             cleanup_old_images,
             read_image_file,
-            clear_all_images,
+            request_clear_all,
+            confirm_clear_all,
             reset_clipboard_hash,
-            copy_file_to_clipboard
+            copy_file_to_clipboard,
+            compare_capture_rates,
+            detect_and_crop_document,
+            get_capture_stats,
+            search_images,
+            get_image_detail,
+            set_image_notes,
+            get_image_notes,
+            undo_last,
+            get_undo_stack,
+            set_persist_undo,
+            set_save_animated_gifs_as_gif,
+            set_library_limits,
+            get_storage_stats,
+            get_storage_performance,
+            find_orphaned_images,
+            auto_cleanup_orphaned,
+            fix_extensions,
+            humanize_filenames,
+            get_images_page,
+            create_webp_sprite_strip,
+            get_largest_images,
+            set_capture_dropped_folders,
+            set_clipboard_thread_priority,
+            clear_index,
+            get_clipboard_stats,
+            reset_clipboard_stats,
+            set_tag_retention,
+            get_tag_retentions,
+            detect_ui_regions,
+            enable_ephemeral_mode,
+            disable_ephemeral_mode,
+            is_cropped_from,
+            export_metadata,
+            rename_image_file,
+            copy_as_named_file,
+            set_clipboard_inclusion_mode,
+            set_clipboard_excluded_apps,
+            get_blurhash,
+            set_eager_blurhash,
+            extract_gif_frames,
+            confirm_cleanup_old_images,
+            request_retention_confirmation,
+            confirm_retention_preset,
+            set_cleanup_confirm_threshold,
+            stitch_panorama,
+            auto_stitch_panorama,
+            get_images_after,
+            get_newest_image,
+            recover_skipped_capture,
+            add_custom_clipboard_format,
+            compare_with_clipboard,
+            set_emit_legacy_clipboard_update,
+            watch_clipboard_all_formats,
+            format_timestamp,
+            composite_on_background,
+            preview_cleanup,
+            set_retention_preset,
+            get_retention_preset,
+            set_clipboard_extractor_chain,
+            copy_image_info,
+            get_clipboard_image_hash,
+            get_recent_errors,
+            force_reindex,
+            set_keep_raw_capture,
+            set_peer_token,
+            export_image_to_peer,
+            start_import_server,
+            stop_import_server,
+            export_ocr_corpus,
+            set_window_icon_from_image,
+            set_window_icon_default,
+            set_capture_text,
+            get_snippets,
+            search_snippets,
+            delete_snippet,
+            set_snippet_retention,
+            create_animated_preview,
+            get_pixel_color,
+            get_region_average,
+            find_near_duplicates,
+            cleanup_near_duplicates,
+            confirm_cleanup_near_duplicates,
+            get_images_sorted_by_color,
+            export_settings,
+            preview_settings_import,
+            confirm_settings_import,
+            reset_settings,
+            find_orphaned_files,
+            adopt_orphaned_files,
+            set_capture_window_title,
+            get_average_capture_size,
+            set_capture_size_limits,
+            force_capture_last_skipped,
+            set_lock_pin,
+            unlock,
+            is_locked,
+            clear_lock_pin,
+            set_lock_timeout,
+            is_clipboard_capture_supported,
+            set_show_badge,
+            set_tray_icon_style,
+            set_window_title,
+            set_png_compression,
+            read_image_chunk,
+            set_max_ipc_read_bytes,
+            get_recent_clipboard_errors,
+            clear_clipboard_errors,
+            set_fullscreen_quiet,
+            apply_sharpen,
+            apply_emboss,
+            annotate_image,
+            estimate_temporal_order,
+            get_sessions,
+            get_session_images,
+            set_session_gap,
+            apply_lut,
+            get_bundled_luts,
+            copy_resized_to_clipboard,
+            identify_source_monitor,
+            export_images_to_pdf,
+            request_delete_images_where,
+            confirm_delete_images_where,
+            enable_capture_log,
+            disable_capture_log,
+            get_capture_log_path,
+            start_slideshow,
+            pause_slideshow,
+            resume_slideshow,
+            stop_slideshow,
+            run_janitor_now
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            match event {
+                tauri::RunEvent::Exit => {
+                    if let Some(state) = app.try_state::<AppState>() {
+                        state.clipboard_listener.lock().unwrap().stop();
+                        state.peer.lock().unwrap().stop_server();
+                        state.image_manager.lock().unwrap().shutdown();
+                    }
+                    #[cfg(unix)]
+                    platform::release_single_instance();
+                }
+                // Clicking the dock icon while the window is hidden in the tray.
+                #[cfg(target_os = "macos")]
+                tauri::RunEvent::Reopen { has_visible_windows: false, .. } => {
+                    if let Some(window) = app.get_webview_window("main") {
+                        platform::restore_and_focus(&window);
+                    }
+                }
+                _ => {}
+            }
+        });
+}
+
+/// Every image, newest first. Entries whose file moved within the storage
+/// folder are re-pointed on the way, see `heal_missing_paths`.
+#[tauri::command]
+async fn get_images(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageList, String> {
+    ensure_unlocked(&state)?;
+    let (images, loading) = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        if !image_manager.is_loading() {
+            image_manager.heal_missing_paths();
+            emit_moved_paths(&app, &mut image_manager);
+        }
+        (image_manager.get_images(), image_manager.is_loading())
+    };
+    
+    Ok(ImageList {
+        images: images.into_iter().map(to_list_item).collect(),
+        loading,
+    })
+}
+
+/// Formats Unix seconds as ISO 8601 in the zone `tz_offset_seconds` east of UTC.
+#[tauri::command]
+async fn format_timestamp(ts: i64, tz_offset_seconds: i32) -> Result<String, String> {
+    let offset = chrono::FixedOffset::east_opt(tz_offset_seconds)
+        .ok_or_else(|| SnapMagError::InvalidArgument(format!("invalid UTC offset: {} seconds", tz_offset_seconds)).to_string())?;
+    let time = chrono::DateTime::from_timestamp(ts, 0)
+        .ok_or_else(|| SnapMagError::InvalidArgument(format!("timestamp out of range: {}", ts)).to_string())?;
+    Ok(time.with_timezone(&offset).format("%Y-%m-%dT%H:%M:%S%:z").to_string())
 }
 
 #[tauri::command]
-async fn get_images(state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+async fn get_images_after(since_ts: i64, limit: usize, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
     let images = state.image_manager
         .lock()
         .unwrap()
-        .get_images();
+        .get_images_after(since_ts, limit)
+        .map_err(|e| e.to_string())?;
     
     Ok(images
         .into_iter()
-        .map(|mut metadata| {
-            metadata.path = convert_path_protocol(&metadata.path);
-            metadata
-        })
+        .map(to_list_item)
         .collect::<Vec<_>>())
 }
 
 #[tauri::command]
-async fn delete_image(id: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
-    state.image_manager
+async fn get_newest_image(state: tauri::State<'_, AppState>) -> Result<Option<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let image = state.image_manager
         .lock()
         .unwrap()
-        .delete_image(&id)
-        .map_err(|e| e.to_string())
+        .get_newest_image();
+    
+    Ok(image.map(to_list_item))
+}
+
+#[tauri::command]
+async fn get_images_page(
+    page: usize,
+    page_size: usize,
+    include_inline_thumbs: bool,
+    thumb_px: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<ImagePage, String> {
+    ensure_unlocked(&state)?;
+    // Thumbnails are rendered without the lock.
+    let (mut result, thumbnails) = {
+        let image_manager = state.image_manager.lock().unwrap();
+        (image_manager.get_images_page(page, page_size).map_err(|e| e.to_string())?, image_manager.thumbnail_cache())
+    };
+    if include_inline_thumbs {
+        image_manager::add_inline_thumbs(&thumbnails, &mut result, thumb_px);
+    }
+    
+    result.images = result.images.into_iter().map(to_list_item).collect();
+    Ok(result)
+}
+
+/// The thumbnails of `ids` (at most 100) in one WebP strip, so a gallery
+/// needs one request instead of one per image.
+#[tauri::command]
+async fn create_webp_sprite_strip(ids: Vec<String>, thumb_size: u32, state: tauri::State<'_, AppState>) -> Result<SpriteStripResult, String> {
+    ensure_unlocked(&state)?;
+    // Thumbnails are rendered and encoded without the lock.
+    let (thumbnails, images) = {
+        let image_manager = state.image_manager.lock().unwrap();
+        (image_manager.thumbnail_cache(), image_manager.get_images_by_id(&ids).map_err(|e| e.to_string())?)
+    };
+    image_manager::create_webp_sprite_strip(&thumbnails, &images, thumb_size).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn save_image_from_clipboard(image_data: Vec<u8>, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
-    let (metadata, _) = state.image_manager
+async fn get_largest_images(n: usize, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let images = state.image_manager
         .lock()
         .unwrap()
-        .save_image(&image_data)
+        .get_largest_images(n)
         .map_err(|e| e.to_string())?;
     
-    let metadata = ImageMetadata {
+    Ok(images
+        .into_iter()
+        .map(to_list_item)
+        .collect::<Vec<_>>())
+}
+
+/// Images by dominant hue: `hue_bins` buckets around the color wheel starting
+/// at red, then a last bucket for grayscale images.
+#[tauri::command]
+async fn get_images_sorted_by_color(hue_bins: u32, state: tauri::State<'_, AppState>) -> Result<Vec<Vec<ImageMetadata>>, String> {
+    ensure_unlocked(&state)?;
+    // Only copying the list and caching the hues hold the lock; decoding
+    // runs without it.
+    let mut scan = state.image_manager.lock().unwrap().color_scan(hue_bins).map_err(|e| e.to_string())?;
+    let buckets = scan.sort();
+    state.image_manager.lock().unwrap().cache_dominant_hues(scan);
+    
+    Ok(buckets
+        .into_iter()
+        .map(|bucket| bucket.into_iter().map(to_list_item).collect())
+        .collect())
+}
+
+#[tauri::command]
+async fn search_images(query: String, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let images = state.image_manager
+        .lock()
+        .unwrap()
+        .search_images(&query);
+    
+    Ok(images
+        .into_iter()
+        .map(to_list_item)
+        .collect::<Vec<_>>())
+}
+
+/// Bursts of captures, newest first, for a grouped gallery view.
+#[tauri::command]
+async fn get_sessions(state: tauri::State<'_, AppState>) -> Result<Vec<CaptureSession>, String> {
+    ensure_unlocked(&state)?;
+    Ok(state.image_manager.lock().unwrap().get_sessions())
+}
+
+#[tauri::command]
+async fn get_session_images(session_id: String, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let images = state.image_manager
+        .lock()
+        .unwrap()
+        .get_session_images(&session_id)
+        .map_err(|e| e.to_string())?;
+    
+    Ok(images
+        .into_iter()
+        .map(to_list_item)
+        .collect::<Vec<_>>())
+}
+
+/// Minutes between captures after which a new session starts.
+#[tauri::command]
+async fn set_session_gap(minutes: u32, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_session_gap_minutes(minutes);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_image_detail(id: String, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let metadata = state.image_manager
+        .lock()
+        .unwrap()
+        .get_image(&id)
+        .ok_or_else(|| format!("Image not found: {}", id))?;
+    
+    Ok(ImageMetadata {
         path: convert_path_protocol(&metadata.path),
         ..metadata
-    };
-    
-    Ok(metadata)
+    })
 }
 
 #[tauri::command]
-async fn cleanup_old_images(hours: i64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+async fn set_image_notes(id: String, notes: Option<String>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
     state.image_manager
         .lock()
         .unwrap()
-        .cleanup_old_images(hours)
+        .set_image_notes(&id, notes)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn clear_all_images(state: tauri::State<'_, AppState>) -> Result<(), String> {
+async fn get_image_notes(id: String, state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
+    ensure_unlocked(&state)?;
     state.image_manager
         .lock()
         .unwrap()
-        .clear_all()
+        .get_image_notes(&id)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn reset_clipboard_hash(state: tauri::State<'_, AppState>) -> Result<(), String> {
-    state.clipboard_listener
+async fn delete_image(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
         .lock()
         .unwrap()
-        .reset_hash();
+        .delete_image(&id)
+        .map_err(|e| e.to_string())?;
+    restore_window_icon_if(&app, |shown| shown == id);
     Ok(())
 }
 
 #[tauri::command]
-async fn copy_file_to_clipboard(path: String, _state: tauri::State<'_, AppState>) -> Result<(), String> {
-    use std::os::windows::ffi::OsStrExt;
-use std::path::Path;
-use windows::Win32::System::DataExchange::{OpenClipboard, EmptyClipboard, SetClipboardData, CloseClipboard};
-use windows::Win32::UI::Shell::DROPFILES;
-use windows::Win32::Foundation::HANDLE;
-    
-    let actual_path = path.replace("asset://localhost/", "").replace("asset://", "");
-    let path_obj = Path::new(&actual_path);
-    
-    if !path_obj.exists() {
-        return Err(format!("File not found: {}", actual_path));
-    }
-    
-    let file_path_wide: Vec<u16> = path_obj
-        .as_os_str()
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-    
-    let drop_struct_size = std::mem::size_of::<DROPFILES>() as u32;
-    let file_path_size = (file_path_wide.len() * 2) as u32;
-    let total_size = drop_struct_size + file_path_size + 2;
-    
-    let mut buffer = vec![0u8; total_size as usize];
-    
-    let drop_files = DROPFILES {
-        pFiles: drop_struct_size,
-        pt: windows::Win32::Foundation::POINT { x: 0, y: 0 },
-        fNC: false.into(),
-        fWide: true.into(),
+async fn save_image_from_clipboard(image_data: Vec<u8>, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    // Checking and hashing the data run without the lock.
+    image_manager::validate_image(&image_data).map_err(|e| e.to_string())?;
+    let hash = image_manager::content_hash(&image_data);
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let result = image_manager
+            .save_image_with_hash(&image_data, hash, CaptureSource::Import)
+            .map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        result.metadata
     };
     
-    let drop_files_bytes = unsafe {
-        std::slice::from_raw_parts(
-            &drop_files as *const _ as *const u8,
-            std::mem::size_of::<DROPFILES>(),
-        )
+    let metadata = ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
     };
-    buffer[..drop_files_bytes.len()].copy_from_slice(drop_files_bytes);
-    
-    let offset = drop_struct_size as usize;
-    for (i, &code) in file_path_wide.iter().enumerate() {
-        let byte_offset = offset + i * 2;
-        if byte_offset + 1 < buffer.len() {
-            buffer[byte_offset] = (code & 0xFF) as u8;
-            buffer[byte_offset + 1] = (code >> 8) as u8;
-        }
-    }
-    
-    unsafe {
-        if let Err(e) = OpenClipboard(None).map_err(|_| "Failed to open clipboard".to_string()) {
-            log::error!("Clipboard error: {}", e);
-            return Err(e);
-        }
-        
-        let _ = EmptyClipboard();
-          
-          let global_alloc = match windows::Win32::System::Memory::GlobalAlloc(
-            windows::Win32::System::Memory::GMEM_MOVEABLE,
-            total_size as usize,
-        ) {
-            Ok(h) => h,
-            Err(e) => {
-                let _ = CloseClipboard();
-                let err = format!("Failed to allocate global memory: {:?}", e);
-                log::error!("{}", err);
-                return Err(err);
-            }
-        };
-        
-        let global_lock = windows::Win32::System::Memory::GlobalLock(global_alloc);
-        if global_lock.is_null() {
-            let _ = CloseClipboard();
-            let err = "Failed to lock global memory".to_string();
-            log::error!("{}", err);
-            return Err(err);
-        }
-        
-        std::ptr::copy_nonoverlapping(
-            buffer.as_ptr(),
-            global_lock as *mut u8,
-            total_size as usize,
-        );
-        
-        let _ = windows::Win32::System::Memory::GlobalUnlock(global_alloc);
-        
-        if SetClipboardData(15u32, Some(HANDLE(global_alloc.0 as *mut _))).is_err() {
-            let _ = CloseClipboard();
-            let err = "Failed to set clipboard data".to_string();
-            log::error!("{}", err);
-            return Err(err);
-        }
-        
-        let _ = CloseClipboard();
-      }
     
-    log::info!("Copied file path to clipboard: {}", actual_path);
-    Ok(())
+    Ok(metadata)
 }
 
 #[tauri::command]
-async fn read_image_file(path: String) -> Result<Vec<u8>, String> {
-    let actual_path = path.replace("asset://localhost/", "").replace("asset://", "");
-    log::debug!("Reading image file from: {}", actual_path);
+async fn cleanup_old_images(hours: i64, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Option<DestructiveConfirmation>, String> {
+    ensure_unlocked(&state)?;
+    let confirmation = state.image_manager
+        .lock()
+        .unwrap()
+        .cleanup_old_images(hours)
+        .map_err(|e| e.to_string())?;
+    restore_window_icon_if(&app, |shown| state.image_manager.lock().unwrap().get_image(shown).is_none());
+    Ok(confirmation)
+}
+
+#[tauri::command]
+async fn preview_cleanup(hours: i64, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let images = state.image_manager
+        .lock()
+        .unwrap()
+        .preview_cleanup(hours)
+        .map_err(|e| e.to_string())?;
     
-    std::fs::read(&actual_path).map_err(|e| {
-        log::error!("Failed to read image file: {}", e);
-        e.to_string()
-    })
+    Ok(images
+        .into_iter()
+        .map(to_list_item)
+        .collect::<Vec<_>>())
+}
+
+#[tauri::command]
+async fn set_retention_preset(preset: RetentionPreset, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_retention_preset(preset)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_retention_preset(state: tauri::State<'_, AppState>) -> Result<RetentionPreset, String> {
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .get_retention_preset())
+}
+
+#[tauri::command]
+async fn confirm_cleanup_old_images(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let removed = state.image_manager
+        .lock()
+        .unwrap()
+        .confirm_cleanup_old_images(&token)
+        .map_err(|e| e.to_string())?;
+    restore_window_icon_if(&app, |shown| state.image_manager.lock().unwrap().get_image(shown).is_none());
+    Ok(removed)
+}
+
+/// A fresh token for a retention sweep held back for confirmation, for
+/// when the one sent with `retention-confirmation-required` expired.
+#[tauri::command]
+async fn request_retention_confirmation(state: tauri::State<'_, AppState>) -> Result<Option<DestructiveConfirmation>, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .request_retention_confirmation()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn confirm_retention_preset(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let ids = state.image_manager
+        .lock()
+        .unwrap()
+        .confirm_retention_preset(&token)
+        .map_err(|e| e.to_string())?;
+    let removed = ids.len();
+    emit_removed(&app, ids);
+    Ok(removed)
+}
+
+#[tauri::command]
+async fn set_cleanup_confirm_threshold(threshold: Option<usize>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_cleanup_confirm_threshold(threshold);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_tag_retention(tag: String, hours: Option<i64>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_tag_retention(&tag, hours)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_tag_retentions(state: tauri::State<'_, AppState>) -> Result<HashMap<String, Option<i64>>, String> {
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .get_tag_retentions())
+}
+
+#[tauri::command]
+async fn find_orphaned_images(page: usize, page_size: usize, state: tauri::State<'_, AppState>) -> Result<ImagePage, String> {
+    ensure_unlocked(&state)?;
+    let mut result = state.image_manager
+        .lock()
+        .unwrap()
+        .find_orphaned_images(page, page_size)
+        .map_err(|e| e.to_string())?;
+    
+    result.images = result.images.into_iter().map(to_list_item).collect();
+    Ok(result)
+}
+
+#[tauri::command]
+async fn auto_cleanup_orphaned(older_than_hours: i64, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let removed = state.image_manager
+        .lock()
+        .unwrap()
+        .auto_cleanup_orphaned(older_than_hours)
+        .map_err(|e| e.to_string())?;
+    restore_window_icon_if(&app, |shown| state.image_manager.lock().unwrap().get_image(shown).is_none());
+    Ok(removed)
+}
+
+/// Renames library files whose extension doesn't match their content.
+#[tauri::command]
+async fn fix_extensions(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Vec<ExtensionFix>, String> {
+    ensure_unlocked(&state)?;
+    // Only copying the paths and renaming hold the lock; reading every file
+    // runs without it.
+    let mut scan = state.image_manager.lock().unwrap().extension_scan();
+    scan.sniff();
+    let fixes = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let fixes = image_manager.fix_extensions(scan).map_err(|e| e.to_string())?;
+        emit_moved_paths(&app, &mut image_manager);
+        fixes
+    };
+    Ok(fixes
+        .into_iter()
+        .map(|fix| ExtensionFix { new_path: convert_path_protocol(&fix.new_path), ..fix })
+        .collect())
+}
+
+/// Renames files of images with OCR text after that text; with `dry_run`
+/// only lists the renames.
+#[tauri::command]
+async fn humanize_filenames(dry_run: bool, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Vec<RenameOperation>, String> {
+    ensure_unlocked(&state)?;
+    let mut image_manager = state.image_manager.lock().unwrap();
+    let operations = image_manager.humanize_filenames(dry_run).map_err(|e| e.to_string())?;
+    emit_moved_paths(&app, &mut image_manager);
+    Ok(operations)
+}
+
+#[tauri::command]
+async fn find_orphaned_files(state: tauri::State<'_, AppState>) -> Result<Vec<OrphanedFile>, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .find_orphaned_files()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn adopt_orphaned_files(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let mut image_manager = state.image_manager.lock().unwrap();
+    let adopted = image_manager
+        .adopt_orphaned_files()
+        .map_err(|e| e.to_string())?;
+    emit_evictions(&app, &mut image_manager);
+    Ok(adopted)
+}
+
+#[tauri::command]
+async fn find_near_duplicates(max_hamming_distance: u8, state: tauri::State<'_, AppState>) -> Result<Vec<NearDuplicateGroup>, String> {
+    ensure_unlocked(&state)?;
+    let mut scan = state.image_manager.lock().unwrap().near_duplicate_scan();
+    let groups = scan.find(max_hamming_distance).map_err(|e| e.to_string());
+    state.image_manager.lock().unwrap().cache_perceptual_hashes(scan);
+    groups
+}
+
+/// Which of the currently connected monitors image `id` was captured from,
+/// judged by its dimensions; `None` when it fits none of them.
+#[tauri::command]
+async fn identify_source_monitor(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Option<MonitorIdentification>, String> {
+    ensure_unlocked(&state)?;
+    let monitors: Vec<(u32, u32)> = app
+        .available_monitors()
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|monitor| (monitor.size().width, monitor.size().height))
+        .collect();
+    state.image_manager
+        .lock()
+        .unwrap()
+        .identify_source_monitor(&id, &monitors)
+        .map_err(|e| e.to_string())
+}
+
+/// First phase of deleting the images matching `filter`, e.g. file drops
+/// over 10 MB from before a date: what would go and the confirming token.
+#[tauri::command]
+async fn request_delete_images_where(filter: DeleteFilter, state: tauri::State<'_, AppState>) -> Result<DestructiveConfirmation, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .request_delete_images_where(filter)
+        .map_err(|e| e.to_string())
+}
+
+/// Deletes the images matched by a `request_delete_images_where` token as
+/// one undo entry, and returns their ids and the bytes freed.
+#[tauri::command]
+async fn confirm_delete_images_where(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<DeletionSummary, String> {
+    ensure_unlocked(&state)?;
+    let summary = state.image_manager
+        .lock()
+        .unwrap()
+        .confirm_delete_images_where(&token)
+        .map_err(|e| e.to_string())?;
+    emit_removed(&app, summary.ids.clone());
+    Ok(summary)
+}
+
+/// Scores consecutive pairs of `ids` as back-to-back frames, for
+/// reconstructing a workflow from its screenshots.
+#[tauri::command]
+async fn estimate_temporal_order(ids: Vec<String>, state: tauri::State<'_, AppState>) -> Result<Vec<TemporalOrderResult>, String> {
+    ensure_unlocked(&state)?;
+    let mut scan = state.image_manager.lock().unwrap().temporal_order_scan(&ids).map_err(|e| e.to_string())?;
+    let results = scan.estimate().map_err(|e| e.to_string());
+    state.image_manager.lock().unwrap().cache_temporal_order_hashes(scan);
+    results
+}
+
+/// Deletes all but one image of each near-duplicate group, at most 16 bits
+/// apart; `keep` is `"oldest"` or `"newest"`. A cleanup over the confirm
+/// threshold returns a token for `confirm_cleanup_near_duplicates` instead.
+#[tauri::command]
+async fn cleanup_near_duplicates(max_hamming_distance: u8, keep: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Option<DestructiveConfirmation>, String> {
+    ensure_unlocked(&state)?;
+    let mut scan = state.image_manager.lock().unwrap().near_duplicate_scan();
+    let groups = scan.find_for_cleanup(max_hamming_distance).map_err(|e| e.to_string())?;
+    let outcome = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        image_manager.cache_perceptual_hashes(scan);
+        image_manager.cleanup_near_duplicates(groups, &keep).map_err(|e| e.to_string())?
+    };
+    match outcome {
+        CleanupOutcome::Removed(ids) => {
+            emit_removed(&app, ids);
+            Ok(None)
+        }
+        CleanupOutcome::NeedsConfirmation(confirmation) => Ok(Some(confirmation)),
+    }
+}
+
+#[tauri::command]
+async fn confirm_cleanup_near_duplicates(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let ids = state.image_manager
+        .lock()
+        .unwrap()
+        .confirm_cleanup_near_duplicates(&token)
+        .map_err(|e| e.to_string())?;
+    let removed = ids.len();
+    emit_removed(&app, ids);
+    Ok(removed)
+}
+
+#[tauri::command]
+async fn request_clear_all(state: tauri::State<'_, AppState>) -> Result<DestructiveConfirmation, String> {
+    ensure_unlocked(&state)?;
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .request_clear_all())
+}
+
+#[tauri::command]
+async fn confirm_clear_all(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ClearSummary, String> {
+    ensure_unlocked(&state)?;
+    let summary = state.image_manager
+        .lock()
+        .unwrap()
+        .confirm_clear_all(&token)
+        .map_err(|e| e.to_string())?;
+    
+    restore_window_icon_if(&app, |_| true);
+    if let Err(e) = app.emit("images-cleared", summary.clone()) {
+        log::error!("Failed to emit images-cleared event: {}", e);
+    }
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn clear_index(keep_files: bool, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ClearSummary, String> {
+    ensure_unlocked(&state)?;
+    let summary = state.image_manager
+        .lock()
+        .unwrap()
+        .clear_index(keep_files)
+        .map_err(|e| e.to_string())?;
+    
+    restore_window_icon_if(&app, |_| true);
+    if let Err(e) = app.emit("images-cleared", summary.clone()) {
+        log::error!("Failed to emit images-cleared event: {}", e);
+    }
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn compare_capture_rates(
+    window_a_start: i64,
+    window_a_end: i64,
+    window_b_start: i64,
+    window_b_end: i64,
+    state: tauri::State<'_, AppState>,
+) -> Result<CaptureRateComparison, String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .compare_capture_rates((window_a_start, window_a_end), (window_b_start, window_b_end))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_capture_stats(days: usize, state: tauri::State<'_, AppState>) -> Result<CaptureStats, String> {
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .get_capture_stats(days))
+}
+
+#[tauri::command]
+async fn detect_and_crop_document(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    // Decoding, detection and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    let document = image_ops::detect_and_crop_document(&image)
+        .ok_or_else(|| SnapMagError::ImageDecodeError("no document detected".to_string()).to_string())?;
+    let png = image_manager::encode_derived_image(&document).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+#[tauri::command]
+async fn detect_ui_regions(id: String, num_regions: u32, state: tauri::State<'_, AppState>) -> Result<Vec<UiRegion>, String> {
+    ensure_unlocked(&state)?;
+    if num_regions == 0 || num_regions > 20 {
+        return Err(SnapMagError::InvalidArgument(format!("num_regions must be between 1 and 20, got {}", num_regions)).to_string());
+    }
+    // Only the lookup holds the lock; decoding and scanning run without it.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    Ok(image_ops::detect_ui_regions(&image, num_regions))
+}
+
+/// Color picker: the pixel at (`x`, `y`) in stored-image pixels, not the
+/// scaled preview the webview shows.
+#[tauri::command]
+async fn get_pixel_color(id: String, x: u32, y: u32, state: tauri::State<'_, AppState>) -> Result<PixelColor, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .get_pixel_color(&id, x, y)
+        .map_err(|e| e.to_string())
+}
+
+/// Averaged swatch of `rect`, in stored-image pixels.
+#[tauri::command]
+async fn get_region_average(id: String, rect: PixelRect, state: tauri::State<'_, AppState>) -> Result<PixelColor, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .get_region_average(&id, rect)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn is_cropped_from(id_crop: String, id_source: String, tolerance_px: u32, state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    ensure_unlocked(&state)?;
+    // Only the lookups hold the lock; decoding and the search run without it.
+    let (crop_path, source_path) = {
+        let image_manager = state.image_manager.lock().unwrap();
+        (
+            image_manager.image_path(&id_crop).map_err(|e| e.to_string())?,
+            image_manager.image_path(&id_source).map_err(|e| e.to_string())?,
+        )
+    };
+    let crop = image_manager::open_image(&crop_path).map_err(|e| e.to_string())?;
+    let source = image_manager::open_image(&source_path).map_err(|e| e.to_string())?;
+    Ok(image_ops::is_cropped_from(&crop, &source, tolerance_px))
+}
+
+#[tauri::command]
+async fn export_metadata(
+    dest_path: String,
+    format: ExportFormat,
+    include_ocr: bool,
+    strip_window_titles: Option<bool>,
+    state: tauri::State<'_, AppState>,
+) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .export_metadata(std::path::Path::new(&dest_path), format, include_ocr, strip_window_titles.unwrap_or(false))
+        .map_err(|e| e.to_string())
+}
+
+/// Writes `ids` to a PDF at `output_path`, one image per `page_size` page
+/// (`"A4"`, `"Letter"` or `"A3"`) after a title page, and returns the
+/// number of pages.
+#[tauri::command]
+async fn export_images_to_pdf(ids: Vec<String>, output_path: String, page_size: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    let page_size = pdf::PageSize::parse(&page_size).map_err(|e| e.to_string())?;
+    // Encoding every page takes a while, so only the lookup holds the lock.
+    let paths = state.image_manager
+        .lock()
+        .unwrap()
+        .image_paths(&ids)
+        .map_err(|e| e.to_string())?;
+    pdf::export_images(&paths, std::path::Path::new(&output_path), page_size, |current, total| {
+        if let Err(e) = app.emit("pdf-progress", PdfProgressEvent { current, total }) {
+            log::error!("Failed to emit pdf-progress event: {}", e);
+        }
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// Writes the OCR text of the library to `output_path`, TSV unless `format`
+/// says otherwise, and returns the number of images written.
+#[tauri::command]
+async fn export_ocr_corpus(output_path: String, format: Option<OcrCorpusFormat>, state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .export_ocr_corpus(std::path::Path::new(&output_path), format.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rename_image_file(id: String, new_stem: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.rename_image_file(&id, &new_stem).map_err(|e| e.to_string())?;
+        emit_moved_paths(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+#[tauri::command]
+async fn get_blurhash(id: String, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    ensure_unlocked(&state)?;
+    // Only the lookups hold the lock; decoding runs without it.
+    let path = {
+        let image_manager = state.image_manager.lock().unwrap();
+        if let Some(blurhash) = image_manager.cached_blurhash(&id).map_err(|e| e.to_string())? {
+            return Ok(blurhash);
+        }
+        image_manager.image_path(&id).map_err(|e| e.to_string())?
+    };
+    let blurhash = image_manager::compute_blurhash(&path)
+        .ok_or_else(|| SnapMagError::ImageDecodeError(format!("cannot compute blurhash for {}", id)).to_string())?;
+    state.image_manager.lock().unwrap().cache_blurhash(&id, &path.to_string_lossy(), blurhash.clone());
+    Ok(blurhash)
+}
+
+#[tauri::command]
+async fn set_eager_blurhash(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_eager_blurhash(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn extract_gif_frames(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Vec<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    // Decoding and encoding the frames run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let encoded = image_manager::encode_gif_frames(&path).map_err(|e| e.to_string())?;
+    let frames = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let frames = image_manager
+            .save_gif_frames(&id, &encoded)
+            .map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        frames
+    };
+    
+    Ok(frames
+        .into_iter()
+        .map(to_list_item)
+        .collect::<Vec<_>>())
+}
+
+#[tauri::command]
+async fn composite_on_background(id: String, background_hex: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let background = image_ops::parse_hex_color(&background_hex)
+        .ok_or_else(|| SnapMagError::InvalidArgument(format!("invalid hex color: {}", background_hex)).to_string())?;
+    // Decoding, flattening and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    let flattened = image_ops::composite_on_background(&image, background);
+    let png = image_manager::encode_derived_image(&image::DynamicImage::ImageRgb8(flattened)).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+/// Draws rectangles, arrows and text onto a copy of the image. Ops that
+/// don't validate come back in `errors`; the others are still drawn.
+#[tauri::command]
+async fn annotate_image(id: String, ops: Vec<AnnotationOp>, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<AnnotationResult, String> {
+    ensure_unlocked(&state)?;
+    // Decoding, drawing and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let (png, errors) = image_manager::annotate_image(&path, &ops).map_err(|e| e.to_string())?;
+    let mut result = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let result = image_manager.save_annotated(&png, errors).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        result
+    };
+    
+    result.image.path = convert_path_protocol(&result.image.path);
+    Ok(result)
+}
+
+/// Maps the colors of image `id` through a LUT: one of `get_bundled_luts`
+/// by name, or a `.png` or `.cube` file.
+#[tauri::command]
+async fn apply_lut(id: String, lut_path: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let lut = match lut::bundled(&lut_path) {
+        Some(lut) => lut,
+        None => lut::Lut::load(std::path::Path::new(&lut_path)).map_err(|e| e.to_string())?,
+    };
+    // Decoding, the per-pixel lookup and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    let mapped = lut.apply(&image);
+    let png = image_manager::encode_derived_image(&image::DynamicImage::ImageRgba8(mapped)).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+#[tauri::command]
+async fn get_bundled_luts() -> Result<Vec<String>, String> {
+    Ok(lut::bundled_names())
+}
+
+/// Saves an unsharp-masked copy of image `id`; `amount` is 0.1–5.0.
+#[tauri::command]
+async fn apply_sharpen(id: String, amount: f32, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    if !(0.1..=5.0).contains(&amount) {
+        return Err(SnapMagError::InvalidArgument(format!("sharpen amount must be 0.1 to 5.0, got {}", amount)).to_string());
+    }
+    // Decoding, sharpening and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    let sharpened = image_ops::sharpen(&image, amount);
+    let png = image_manager::encode_derived_image(&image::DynamicImage::ImageRgba8(sharpened)).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+/// Saves an embossed copy of image `id`.
+#[tauri::command]
+async fn apply_emboss(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    // Decoding, embossing and encoding run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    let image = image_manager::open_image(&path).map_err(|e| e.to_string())?;
+    let embossed = image_ops::emboss(&image);
+    let png = image_manager::encode_derived_image(&image::DynamicImage::ImageRgba8(embossed)).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+/// Joins the images `ids` in order into one new image.
+#[tauri::command]
+async fn stitch_panorama(ids: Vec<String>, direction: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let direction: StitchDirection = direction.parse().map_err(|e: SnapMagError| e.to_string())?;
+    // Decoding, stitching and encoding run without the lock.
+    let paths = state.image_manager.lock().unwrap().image_paths(&ids).map_err(|e| e.to_string())?;
+    let images = image_manager::open_images(&paths).map_err(|e| e.to_string())?;
+    let metadata = save_panorama(&images, direction, &app, &state)?;
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+/// Stitches `images` and saves the result, holding the lock only to save.
+fn save_panorama(
+    images: &[image::DynamicImage],
+    direction: StitchDirection,
+    app: &tauri::AppHandle,
+    state: &AppState,
+) -> Result<ImageMetadata, String> {
+    let panorama = image_ops::stitch_panorama(images, direction).map_err(|e| e.to_string())?;
+    let png = image_manager::encode_derived_image(&image::DynamicImage::ImageRgba8(panorama)).map_err(|e| e.to_string())?;
+    let mut image_manager = state.image_manager.lock().unwrap();
+    let metadata = image_manager.save_derived_png(&png).map_err(|e| e.to_string())?;
+    emit_evictions(app, &mut image_manager);
+    Ok(metadata)
+}
+
+/// Saves a looping APNG of the images `ids` letterboxed to 200×150, for a
+/// slideshow thumbnail of a collection.
+#[tauri::command]
+async fn create_animated_preview(ids: Vec<String>, frame_delay_ms: u32, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    // Decoding, letterboxing and encoding run without the lock.
+    let paths = state.image_manager.lock().unwrap().image_paths(&ids).map_err(|e| e.to_string())?;
+    let images = image_manager::open_images(&paths).map_err(|e| e.to_string())?;
+    let apng = image_manager::encode_animated_preview(&images, frame_delay_ms).map_err(|e| e.to_string())?;
+    let metadata = {
+        let mut image_manager = state.image_manager.lock().unwrap();
+        let metadata = image_manager
+            .save_animated_preview(&apng)
+            .map_err(|e| e.to_string())?;
+        emit_evictions(&app, &mut image_manager);
+        metadata
+    };
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+/// `stitch_panorama` in whichever direction the image dimensions allow.
+#[tauri::command]
+async fn auto_stitch_panorama(ids: Vec<String>, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<ImageMetadata, String> {
+    ensure_unlocked(&state)?;
+    let paths = state.image_manager.lock().unwrap().image_paths(&ids).map_err(|e| e.to_string())?;
+    let images = image_manager::open_images(&paths).map_err(|e| e.to_string())?;
+    let direction = StitchDirection::detect(&images)
+        .ok_or_else(|| SnapMagError::InvalidArgument("images share neither a height nor a width".to_string()).to_string())?;
+    let metadata = save_panorama(&images, direction, &app, &state)?;
+    
+    Ok(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    })
+}
+
+#[tauri::command]
+async fn undo_last(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Option<UndoEntryInfo>, String> {
+    ensure_unlocked(&state)?;
+    let info = state.image_manager
+        .lock()
+        .unwrap()
+        .undo_last()
+        .map_err(|e| e.to_string())?;
+    
+    if let Some(info) = &info {
+        if let Err(e) = app.emit("images-restored", ImagesRestoredEvent { ids: info.image_ids.clone() }) {
+            log::error!("Failed to emit images-restored event: {}", e);
+        }
+    }
+    
+    Ok(info)
+}
+
+#[tauri::command]
+async fn get_undo_stack(state: tauri::State<'_, AppState>) -> Result<Vec<UndoEntryInfo>, String> {
+    ensure_unlocked(&state)?;
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .get_undo_stack())
+}
+
+#[tauri::command]
+async fn enable_ephemeral_mode(minutes: u32, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_ephemeral_minutes(Some(minutes))
+        .map_err(|e| e.to_string())?;
+    update_tray_tooltip(&app, true);
+    Ok(())
+}
+
+#[tauri::command]
+async fn disable_ephemeral_mode(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_ephemeral_minutes(None)
+        .map_err(|e| e.to_string())?;
+    update_tray_tooltip(&app, false);
+    Ok(())
+}
+
+#[tauri::command]
+async fn export_settings(dest_path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    current_settings(&state)
+        .export(std::path::Path::new(&dest_path))
+        .map_err(|e| e.to_string())
+}
+
+/// Reads a settings file from `export_settings` without applying it: the
+/// fields it would change, a warning for every field that was ignored,
+/// reset or clamped, and the token `confirm_settings_import` applies it with.
+#[tauri::command]
+async fn preview_settings_import(src_path: String, state: tauri::State<'_, AppState>) -> Result<SettingsImportPreview, String> {
+    let (mut settings, mut warnings) = Settings::import(std::path::Path::new(&src_path)).map_err(|e| e.to_string())?;
+    let current = current_settings(&state);
+    settings.keep_confirm_threshold(current.library.cleanup_confirm_threshold, &mut warnings);
+    let changes = current.diff(&settings).map_err(|e| e.to_string())?;
+    let token = image_manager::random_token();
+    *state.pending_import.lock().unwrap() = Some(PendingImport {
+        token: token.clone(),
+        settings,
+        expires_at: std::time::Instant::now() + SETTINGS_IMPORT_TTL,
+    });
+    Ok(SettingsImportPreview { token, changes, warnings })
+}
+
+#[tauri::command]
+async fn confirm_settings_import(token: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let pending = state.pending_import.lock().unwrap().take();
+    let settings = match pending {
+        Some(pending) if pending.token == token && pending.expires_at > std::time::Instant::now() => pending.settings,
+        _ => return Err(SnapMagError::InvalidArgument("invalid or expired confirmation token".to_string()).to_string()),
+    };
+    apply_settings(&app, &state, settings);
+    log::info!("Applied imported settings");
+    Ok(())
+}
+
+#[tauri::command]
+async fn reset_settings(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    apply_settings(&app, &state, Settings::default());
+    Ok(())
+}
+
+fn current_settings(state: &AppState) -> Settings {
+    let clipboard = state.clipboard_listener.lock().unwrap().config();
+    let library = state.image_manager.lock().unwrap().config().clone();
+    let interface = InterfaceSettings {
+        show_badge: state.badge.lock().unwrap().enabled,
+        tray_icon_style: state.tray_theme.lock().unwrap().style,
+        snippet_retention_hours: state.snippets.lock().unwrap().retention_hours(),
+    };
+    Settings::new(clipboard, library, interface)
+}
+
+fn apply_settings(app: &tauri::AppHandle, state: &AppState, settings: Settings) {
+    let ephemeral = settings.library.ephemeral_minutes.is_some();
+    state.clipboard_listener.lock().unwrap().set_config(settings.clipboard);
+    state.image_manager.lock().unwrap().set_config(settings.library);
+    let interface = settings.interface;
+    state.badge.lock().unwrap().enabled = interface.show_badge;
+    state.tray_theme.lock().unwrap().style = interface.tray_icon_style;
+    refresh_tray_icon(state);
+    if let Err(e) = settings::persist_tray_icon_style(state.image_manager.lock().unwrap().storage_dir(), interface.tray_icon_style) {
+        log::error!("Failed to save tray icon style: {}", e);
+    }
+    let now = chrono::Utc::now().timestamp();
+    if let Err(e) = state.snippets.lock().unwrap().set_retention_hours(interface.snippet_retention_hours, now) {
+        log::error!("Failed to apply snippet retention: {}", e);
+    }
+    update_tray_tooltip(app, ephemeral);
+}
+
+/// Fails with `SnapMagError::Locked` while the history is locked, otherwise
+/// counts as activity for the inactivity lock.
+fn ensure_unlocked(state: &AppState) -> Result<(), String> {
+    state.lock.lock().unwrap().check().map_err(|e| e.to_string())
+}
+
+/// Sets or changes the 4–8 digit PIN that locks the image history.
+#[tauri::command]
+async fn set_lock_pin(pin: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.lock.lock().unwrap().set_pin(&pin).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn unlock(pin: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.lock.lock().unwrap().unlock(&pin).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn is_locked(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    Ok(state.lock.lock().unwrap().is_locked())
+}
+
+#[tauri::command]
+async fn clear_lock_pin(pin: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.lock.lock().unwrap().clear_pin(&pin).map_err(|e| e.to_string())
+}
+
+/// Locks after `minutes` without an image-reading command; `None` only
+/// locks at startup.
+#[tauri::command]
+async fn set_lock_timeout(minutes: Option<u32>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.lock.lock().unwrap().set_timeout_minutes(minutes).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_persist_undo(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_persist_undo(enabled);
+    Ok(())
+}
+
+/// zlib level 0–9 for the PNGs SnapMag encodes; 1 is much faster than the
+/// default 6 for large screenshots at the cost of bigger files.
+#[tauri::command]
+async fn set_png_compression(level: u8, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_png_compression(level)
+        .map_err(|e| e.to_string())
+}
+
+/// Appends a JSON line with the id, hash, format, size and source app of
+/// every new capture to `log_path`, replacing any log already open.
+#[tauri::command]
+async fn enable_capture_log(log_path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let log = CaptureLog::open(std::path::Path::new(&log_path)).map_err(|e| e.to_string())?;
+    *state.capture_log.lock().unwrap() = Some(log);
+    log::info!("Capture log enabled: {}", log_path);
+    Ok(())
+}
+
+#[tauri::command]
+async fn disable_capture_log(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    if let Some(log) = state.capture_log.lock().unwrap().take() {
+        log::info!("Capture log disabled: {}", log.path().display());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_capture_log_path(state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
+    Ok(state.capture_log
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|log| log.path().to_string_lossy().into_owned()))
+}
+
+/// Emits `slideshow-next` for each of `ids` in turn, `interval_ms` (at
+/// least 100) apart, starting over at the end with `loop_`. Returns the
+/// slideshow id for the other slideshow commands.
+#[tauri::command]
+async fn start_slideshow(ids: Vec<String>, interval_ms: u64, loop_: bool, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    ensure_unlocked(&state)?;
+    let ids = {
+        let image_manager = state.image_manager.lock().unwrap();
+        ids.iter()
+            .map(|id| image_manager.get_image(id).map(|metadata| metadata.id).ok_or_else(|| format!("Image not found: {}", id)))
+            .collect::<Result<Vec<String>, String>>()?
+    };
+    state.slideshows
+        .start(app, ids, interval_ms, loop_)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pause_slideshow(id: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.slideshows.pause(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resume_slideshow(id: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.slideshows.resume(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_slideshow(id: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.slideshows.stop(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_library_limits(max_bytes: Option<u64>, max_count: Option<usize>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_library_limits(max_bytes, max_count);
+    Ok(())
+}
+
+/// Sweeps leftover temporary files now instead of waiting for the periodic
+/// run; returns the bytes reclaimed.
+#[tauri::command]
+async fn run_janitor_now(state: tauri::State<'_, AppState>) -> Result<u64, String> {
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .run_janitor())
+}
+
+#[tauri::command]
+async fn get_storage_stats(state: tauri::State<'_, AppState>) -> Result<StorageStats, String> {
+    Ok(state.image_manager
+        .lock()
+        .unwrap()
+        .get_storage_stats())
+}
+
+/// Probes the storage folder, emitting `slow-storage-detected` with the
+/// result when writes are slow enough to hold up captures.
+#[tauri::command]
+async fn get_storage_performance(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<StoragePerformance, String> {
+    let storage_dir = state.image_manager.lock().unwrap().storage_dir().to_path_buf();
+    let performance = ImageManager::measure_storage(&storage_dir).map_err(|e| e.to_string())?;
+    if performance.write_latency_ms > SLOW_STORAGE_WRITE_MS {
+        log::warn!(
+            "Storage at {} is slow: {:.0} ms per write{}",
+            storage_dir.display(),
+            performance.write_latency_ms,
+            if performance.is_network_drive { " on a network drive" } else { "" }
+        );
+        if let Err(e) = app.emit("slow-storage-detected", performance.clone()) {
+            log::error!("Failed to emit slow-storage-detected event: {}", e);
+        }
+    }
+    Ok(performance)
+}
+
+#[tauri::command]
+async fn get_average_capture_size(last_n: usize, state: tauri::State<'_, AppState>) -> Result<AverageSizeStats, String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .get_average_capture_size(last_n)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn reset_clipboard_hash(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .reset_hash();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_save_animated_gifs_as_gif(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_save_animated_gifs_as_gif(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_capture_dropped_folders(enabled: bool, max_files: usize, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    if max_files == 0 {
+        return Err(SnapMagError::InvalidArgument("max_files must be at least 1".to_string()).to_string());
+    }
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_capture_dropped_folders(enabled, max_files);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_clipboard_inclusion_mode(enabled: bool, apps: Vec<String>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_inclusion_mode(enabled, apps);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_clipboard_excluded_apps(apps: Vec<String>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_excluded_apps(apps);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_clipboard_thread_priority(priority: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let priority: ThreadPriority = priority.parse().map_err(|e: SnapMagError| e.to_string())?;
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_thread_priority(priority);
+    Ok(())
+}
+
+#[tauri::command]
+async fn add_custom_clipboard_format(name: String, extractor: CustomFormatExtractor, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .add_custom_clipboard_format(&name, extractor)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn compare_with_clipboard(id: String, state: tauri::State<'_, AppState>) -> Result<f64, String> {
+    ensure_unlocked(&state)?;
+    let clipboard_image = state.clipboard_listener
+        .lock()
+        .unwrap()
+        .read_clipboard_image()
+        .ok_or_else(|| SnapMagError::NotFound("no image on clipboard".to_string()).to_string())?;
+    
+    // Decoding and hashing both images run without the lock.
+    let path = state.image_manager.lock().unwrap().image_path(&id).map_err(|e| e.to_string())?;
+    image_manager::perceptual_distance_to(&path, &clipboard_image).map_err(|e| e.to_string())
+}
+
+/// Hash of the image on the clipboard, extracted as the listener would but
+/// not saved. It can be matched against the `content_hash` of known images.
+#[tauri::command]
+async fn get_clipboard_image_hash(state: tauri::State<'_, AppState>) -> Result<Option<String>, String> {
+    let clipboard_image = state.clipboard_listener
+        .lock()
+        .unwrap()
+        .read_clipboard_image();
+    Ok(clipboard_image.map(|data| image_manager::content_hash(&data)))
+}
+
+/// Rebuilds the image index from the files on disk; returns how many
+/// images it holds.
+#[tauri::command]
+async fn force_reindex(state: tauri::State<'_, AppState>) -> Result<usize, String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .force_reindex()
+        .map_err(|e| e.to_string())
+}
+
+/// Sets the token shared with the SnapMag instances images are exchanged with.
+#[tauri::command]
+async fn set_peer_token(token: Option<String>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.peer.lock().unwrap().set_token(token).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_image_to_peer(id: String, peer_addr: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    let metadata = state.image_manager
+        .lock()
+        .unwrap()
+        .get_image(&id)
+        .ok_or_else(|| SnapMagError::NotFound(id).to_string())?;
+    state.peer
+        .lock()
+        .unwrap()
+        .export_image(&metadata, &peer_addr)
+        .map_err(|e| e.to_string())
+}
+
+/// Listens for peer transfers on `port`, on loopback unless `bind_address`
+/// names the interface to accept them on.
+#[tauri::command]
+async fn start_import_server(port: u16, bind_address: Option<String>, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let bind_address = bind_address
+        .map(|address| address.parse::<std::net::IpAddr>())
+        .transpose()
+        .map_err(|e| SnapMagError::InvalidArgument(format!("invalid bind address: {}", e)).to_string())?;
+    state.peer
+        .lock()
+        .unwrap()
+        .start_server(bind_address, port, app, state.image_manager.clone())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_import_server(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.peer.lock().unwrap().stop_server();
+    Ok(())
+}
+
+/// The last 50 background failures, newest first.
+#[tauri::command]
+async fn get_recent_errors() -> Result<Vec<BackgroundErrorEvent>, String> {
+    Ok(diagnostics::recent_errors())
+}
+
+#[tauri::command]
+async fn set_clipboard_extractor_chain(chain: Vec<String>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_extractor_chain(chain)
+        .map_err(|e| e.to_string())
+}
+
+/// Turns text snippet capture on or off; `min_chars` also changes the
+/// shortest text kept.
+#[tauri::command]
+async fn set_capture_text(enabled: bool, min_chars: Option<usize>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().set_capture_text(enabled, min_chars);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_snippets(state: tauri::State<'_, AppState>) -> Result<Vec<TextSnippet>, String> {
+    ensure_unlocked(&state)?;
+    Ok(state.snippets.lock().unwrap().list())
+}
+
+#[tauri::command]
+async fn search_snippets(query: String, state: tauri::State<'_, AppState>) -> Result<Vec<TextSnippet>, String> {
+    ensure_unlocked(&state)?;
+    Ok(state.snippets.lock().unwrap().search(&query))
+}
+
+#[tauri::command]
+async fn delete_snippet(id: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state.snippets
+        .lock()
+        .unwrap()
+        .delete(&id)
+        .map_err(|e| e.to_string())
+}
+
+/// Sets how many hours snippets are kept; `None` keeps them until the
+/// store is full.
+#[tauri::command]
+async fn set_snippet_retention(hours: Option<i64>, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    let now = chrono::Utc::now().timestamp();
+    state.snippets
+        .lock()
+        .unwrap()
+        .set_retention_hours(hours, now)
+        .map_err(|e| e.to_string())
+}
+
+/// Records the foreground window title with each capture. Off by default,
+/// since titles can hold private text.
+#[tauri::command]
+async fn set_capture_window_title(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().set_capture_window_title(enabled);
+    Ok(())
+}
+
+/// Clipboard data with more pixels or bytes than these is skipped with a
+/// `capture-skipped` event instead of being decoded.
+#[tauri::command]
+async fn set_capture_size_limits(max_decode_pixels: u64, max_clipboard_bytes: u64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    if max_decode_pixels == 0 || max_clipboard_bytes == 0 {
+        return Err(SnapMagError::InvalidArgument("capture size limits must be positive".to_string()).to_string());
+    }
+    state.clipboard_listener.lock().unwrap().set_capture_size_limits(max_decode_pixels, max_clipboard_bytes);
+    Ok(())
+}
+
+/// Captures the last clipboard data skipped as too large. Returns once
+/// the decode has started; the result arrives as `images-added`.
+#[tauri::command]
+async fn force_capture_last_skipped(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    state
+        .clipboard_listener
+        .lock()
+        .unwrap()
+        .force_capture_last_skipped()
+        .map_err(|e| e.to_string())
+}
+
+/// `false` where the clipboard listener doesn't run, so the frontend can
+/// say so instead of waiting for captures.
+#[tauri::command]
+async fn is_clipboard_capture_supported() -> Result<bool, String> {
+    Ok(ClipboardListener::is_capture_supported())
+}
+
+#[tauri::command]
+async fn set_keep_raw_capture(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().set_keep_raw_capture(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_emit_legacy_clipboard_update(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .set_emit_legacy_clipboard_update(enabled);
+    Ok(())
+}
+
+/// Emits `clipboard-non-image` whenever the clipboard changes to something
+/// without an image. Off by default.
+#[tauri::command]
+async fn watch_clipboard_all_formats(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().set_notify_on_non_image(enabled);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_clipboard_stats(state: tauri::State<'_, AppState>) -> Result<ClipboardStats, String> {
+    Ok(state.clipboard_listener
+        .lock()
+        .unwrap()
+        .get_stats())
+}
+
+/// Goes quiet while a full-screen app is in the foreground, announced with
+/// `quiet-mode-changed`; `pause_capture` also stops capturing meanwhile.
+#[tauri::command]
+async fn set_fullscreen_quiet(enabled: bool, pause_capture: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().set_fullscreen_quiet(enabled, pause_capture);
+    Ok(())
+}
+
+/// Clipboard copies that offered an image SnapMag couldn't read, newest first,
+/// like `get_recent_errors`.
+#[tauri::command]
+async fn get_recent_clipboard_errors(state: tauri::State<'_, AppState>) -> Result<Vec<ClipboardError>, String> {
+    Ok(state.clipboard_listener.lock().unwrap().get_recent_errors())
+}
+
+#[tauri::command]
+async fn clear_clipboard_errors(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener.lock().unwrap().clear_recent_errors();
+    Ok(())
+}
+
+#[tauri::command]
+async fn reset_clipboard_stats(state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.clipboard_listener
+        .lock()
+        .unwrap()
+        .reset_stats();
+    Ok(())
+}
+
+#[tauri::command]
+async fn recover_skipped_capture(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<Option<ImageMetadata>, String> {
+    ensure_unlocked(&state)?;
+    let recovered = state.clipboard_listener
+        .lock()
+        .unwrap()
+        .recover_skipped_capture()
+        .map_err(|e| e.to_string())?;
+    emit_evictions(&app, &mut state.image_manager.lock().unwrap());
+    
+    let Some(metadata) = recovered else {
+        return Ok(None);
+    };
+    if let Err(e) = app.emit("clipboard-update", ClipboardEvent { image_path: metadata.path.clone() }) {
+        log::error!("Failed to emit clipboard-update event: {}", e);
+    }
+    Ok(Some(ImageMetadata {
+        path: convert_path_protocol(&metadata.path),
+        ..metadata
+    }))
+}
+
+#[tauri::command]
+async fn copy_file_to_clipboard(path: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    let actual_path = path.replace("asset://localhost/", "").replace("asset://", "");
+    put_file_on_clipboard(&actual_path)
+}
+
+#[tauri::command]
+async fn copy_as_named_file(id: String, name: Option<String>, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    ensure_unlocked(&state)?;
+    let path = {
+        let image_manager = state.image_manager.lock().unwrap();
+        image_manager.prune_session_files(SESSION_FILE_MAX_AGE);
+        image_manager
+            .create_named_copy(&id, name.as_deref())
+            .map_err(|e| e.to_string())?
+    };
+    let path = path.to_string_lossy().to_string();
+    put_file_on_clipboard(&path)?;
+    Ok(path)
+}
+
+/// Copies image `id` scaled down to fit in `max_px` × `max_px`, as pixels and
+/// as a file, for apps that choke on full-size screenshots. Returns the path
+/// of the copied file. The copy itself is not captured.
+#[tauri::command]
+async fn copy_resized_to_clipboard(id: String, max_px: u32, format: VariantFormat, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    ensure_unlocked(&state)?;
+    // Only the lookup holds the lock; the resize runs without it.
+    let variant = state.image_manager
+        .lock()
+        .unwrap()
+        .resized_variant(&id, max_px, format)
+        .map_err(|e| e.to_string())?;
+    let path = variant.write().map_err(|e| e.to_string())?;
+    let data = std::fs::read(&path).map_err(|e| e.to_string())?;
+    let image = image::load_from_memory(&data)
+        .map_err(|e| SnapMagError::ImageDecodeError(e.to_string()).to_string())?
+        .to_rgba8();
+    let png = (format == VariantFormat::Png).then_some(data.as_slice());
+    let sequence = put_image_on_clipboard(&image, png, &path)?;
+    state.clipboard_listener.lock().unwrap().ignore_own_write(sequence);
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Puts the requested metadata `fields` of image `id` on the clipboard as
+/// text and returns it; see `image_manager::image_info`.
+#[tauri::command]
+async fn copy_image_info(id: String, fields: Vec<String>, state: tauri::State<'_, AppState>) -> Result<String, String> {
+    ensure_unlocked(&state)?;
+    let metadata = state.image_manager
+        .lock()
+        .unwrap()
+        .get_image(&id)
+        .ok_or_else(|| SnapMagError::NotFound(id.clone()).to_string())?;
+    let info = image_manager::image_info(&metadata, &fields).map_err(|e| e.to_string())?;
+    put_text_on_clipboard(&info)?;
+    Ok(info)
+}
+
+/// Replaces the clipboard contents with `text` as CF_UNICODETEXT.
+#[cfg(target_os = "windows")]
+fn put_text_on_clipboard(text: &str) -> Result<(), String> {
+    use windows::Win32::System::DataExchange::{OpenClipboard, EmptyClipboard, SetClipboardData, CloseClipboard};
+    use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use windows::Win32::Foundation::{GlobalFree, HANDLE};
+    
+    const CF_UNICODETEXT: u32 = 13;
+    let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    let size = wide.len() * 2;
+    
+    unsafe {
+        OpenClipboard(None).map_err(|_| "Failed to open clipboard".to_string())?;
+        let _ = EmptyClipboard();
+        
+        let result = (|| {
+            let global_alloc = GlobalAlloc(GMEM_MOVEABLE, size)
+                .map_err(|e| format!("Failed to allocate global memory: {:?}", e))?;
+            let global_lock = GlobalLock(global_alloc);
+            if global_lock.is_null() {
+                let _ = GlobalFree(Some(global_alloc));
+                return Err("Failed to lock global memory".to_string());
+            }
+            std::ptr::copy_nonoverlapping(wide.as_ptr(), global_lock as *mut u16, wide.len());
+            let _ = GlobalUnlock(global_alloc);
+            
+            if SetClipboardData(CF_UNICODETEXT, Some(HANDLE(global_alloc.0 as *mut _))).is_err() {
+                let _ = GlobalFree(Some(global_alloc));
+                return Err("Failed to set clipboard data".to_string());
+            }
+            Ok(())
+        })();
+        
+        let _ = CloseClipboard();
+        if let Err(e) = &result {
+            log::error!("Clipboard error: {}", e);
+        }
+        result
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn put_text_on_clipboard(_text: &str) -> Result<(), String> {
+    Err(SnapMagError::Unsupported("writing text to the clipboard".to_string()).to_string())
+}
+
+/// Replaces the clipboard contents with `image` as CF_DIBV5, `png` as the
+/// registered `PNG` format when given, and `path` as CF_HDROP. Returns the
+/// clipboard sequence number after the write.
+#[cfg(target_os = "windows")]
+fn put_image_on_clipboard(image: &image::RgbaImage, png: Option<&[u8]>, path: &std::path::Path) -> Result<u32, String> {
+    use windows::Win32::System::DataExchange::{CloseClipboard, EmptyClipboard, GetClipboardSequenceNumber, OpenClipboard, RegisterClipboardFormatW};
+    
+    const CF_HDROP: u32 = 15;
+    const CF_DIBV5: u32 = 17;
+    let dib = dib::encode_dibv5(image);
+    let hdrop = hdrop_buffer(path);
+    
+    unsafe {
+        OpenClipboard(None).map_err(|_| "Failed to open clipboard".to_string())?;
+        let _ = EmptyClipboard();
+        
+        let result = (|| {
+            set_clipboard_bytes(CF_DIBV5, &dib)?;
+            if let Some(png) = png {
+                let png_format = RegisterClipboardFormatW(windows::core::w!("PNG"));
+                if png_format != 0 {
+                    set_clipboard_bytes(png_format, png)?;
+                }
+            }
+            set_clipboard_bytes(CF_HDROP, &hdrop)
+        })();
+        
+        let _ = CloseClipboard();
+        if let Err(e) = &result {
+            log::error!("Clipboard error: {}", e);
+        }
+        result.map(|()| GetClipboardSequenceNumber())
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn put_image_on_clipboard(_image: &image::RgbaImage, _png: Option<&[u8]>, _path: &std::path::Path) -> Result<u32, String> {
+    Err(SnapMagError::Unsupported("copying images to the clipboard".to_string()).to_string())
+}
+
+/// Hands a copy of `data` to the open clipboard as `format`.
+#[cfg(target_os = "windows")]
+unsafe fn set_clipboard_bytes(format: u32, data: &[u8]) -> Result<(), String> {
+    use windows::Win32::System::DataExchange::SetClipboardData;
+    use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use windows::Win32::Foundation::{GlobalFree, HANDLE};
+    
+    let global_alloc = GlobalAlloc(GMEM_MOVEABLE, data.len())
+        .map_err(|e| format!("Failed to allocate global memory: {:?}", e))?;
+    let global_lock = GlobalLock(global_alloc);
+    if global_lock.is_null() {
+        let _ = GlobalFree(Some(global_alloc));
+        return Err("Failed to lock global memory".to_string());
+    }
+    std::ptr::copy_nonoverlapping(data.as_ptr(), global_lock as *mut u8, data.len());
+    let _ = GlobalUnlock(global_alloc);
+    
+    if SetClipboardData(format, Some(HANDLE(global_alloc.0 as *mut _))).is_err() {
+        let _ = GlobalFree(Some(global_alloc));
+        return Err(format!("Failed to set clipboard format {}", format));
+    }
+    Ok(())
+}
+
+/// Places a single file on the clipboard as CF_HDROP.
+#[cfg(not(target_os = "windows"))]
+fn put_file_on_clipboard(_actual_path: &str) -> Result<(), String> {
+    Err(SnapMagError::Unsupported("copying files to the clipboard".to_string()).to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn put_file_on_clipboard(actual_path: &str) -> Result<(), String> {
+use std::path::Path;
+use windows::Win32::System::DataExchange::{OpenClipboard, EmptyClipboard, SetClipboardData, CloseClipboard};
+use windows::Win32::Foundation::HANDLE;
+    
+    let path_obj = Path::new(actual_path);
+    
+    if !path_obj.exists() {
+        return Err(format!("File not found: {}", actual_path));
+    }
+    
+    let buffer = hdrop_buffer(path_obj);
+    let total_size = buffer.len() as u32;
+    
+    unsafe {
+        if let Err(e) = OpenClipboard(None).map_err(|_| "Failed to open clipboard".to_string()) {
+            log::error!("Clipboard error: {}", e);
+            return Err(e);
+        }
+        
+        let _ = EmptyClipboard();
+          
+          let global_alloc = match windows::Win32::System::Memory::GlobalAlloc(
+            windows::Win32::System::Memory::GMEM_MOVEABLE,
+            total_size as usize,
+        ) {
+            Ok(h) => h,
+            Err(e) => {
+                let _ = CloseClipboard();
+                let err = format!("Failed to allocate global memory: {:?}", e);
+                log::error!("{}", err);
+                return Err(err);
+            }
+        };
+        
+        let global_lock = windows::Win32::System::Memory::GlobalLock(global_alloc);
+        if global_lock.is_null() {
+            let _ = CloseClipboard();
+            let err = "Failed to lock global memory".to_string();
+            log::error!("{}", err);
+            return Err(err);
+        }
+        
+        std::ptr::copy_nonoverlapping(
+            buffer.as_ptr(),
+            global_lock as *mut u8,
+            total_size as usize,
+        );
+        
+        let _ = windows::Win32::System::Memory::GlobalUnlock(global_alloc);
+        
+        if SetClipboardData(15u32, Some(HANDLE(global_alloc.0 as *mut _))).is_err() {
+            let _ = CloseClipboard();
+            let err = "Failed to set clipboard data".to_string();
+            log::error!("{}", err);
+            return Err(err);
+        }
+        
+        let _ = CloseClipboard();
+      }
+    
+    log::info!("Copied file path to clipboard: {}", actual_path);
+    Ok(())
+}
+
+/// `DROPFILES` followed by the wide, double-NUL-terminated `path`: the
+/// CF_HDROP payload for a single file.
+#[cfg(target_os = "windows")]
+fn hdrop_buffer(path: &std::path::Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::Win32::UI::Shell::DROPFILES;
+    
+    let file_path_wide: Vec<u16> = path
+        .as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    
+    let drop_struct_size = std::mem::size_of::<DROPFILES>() as u32;
+    let file_path_size = (file_path_wide.len() * 2) as u32;
+    let total_size = drop_struct_size + file_path_size + 2;
+    
+    let mut buffer = vec![0u8; total_size as usize];
+    
+    let drop_files = DROPFILES {
+        pFiles: drop_struct_size,
+        pt: windows::Win32::Foundation::POINT { x: 0, y: 0 },
+        fNC: false.into(),
+        fWide: true.into(),
+    };
+    
+    let drop_files_bytes = unsafe {
+        std::slice::from_raw_parts(
+            &drop_files as *const _ as *const u8,
+            std::mem::size_of::<DROPFILES>(),
+        )
+    };
+    buffer[..drop_files_bytes.len()].copy_from_slice(drop_files_bytes);
+    
+    let offset = drop_struct_size as usize;
+    for (i, &code) in file_path_wide.iter().enumerate() {
+        let byte_offset = offset + i * 2;
+        if byte_offset + 1 < buffer.len() {
+            buffer[byte_offset] = (code & 0xFF) as u8;
+            buffer[byte_offset + 1] = (code >> 8) as u8;
+        }
+    }
+    buffer
+}
+
+#[tauri::command]
+async fn read_image_file(path: String, state: tauri::State<'_, AppState>) -> Result<Vec<u8>, String> {
+    ensure_unlocked(&state)?;
+    let actual_path = path.replace("asset://localhost/", "").replace("asset://", "");
+    log::debug!("Reading image file from: {}", actual_path);
+    
+    let max_bytes = state.image_manager.lock().unwrap().max_ipc_read_bytes();
+    let size = std::fs::metadata(&actual_path).map_err(|e| e.to_string())?.len();
+    if size > max_bytes {
+        return Err(SnapMagError::InvalidArgument(format!(
+            "file is {} bytes, over the {}-byte limit; read it with read_image_chunk",
+            size, max_bytes
+        ))
+        .to_string());
+    }
+    
+    read_file_bytes(&actual_path).map_err(|e| {
+        log::error!("Failed to read image file: {}", e);
+        e.to_string()
+    })
+}
+
+/// Streams a large original in pieces of at most 1 MiB instead of one
+/// `read_image_file` call. The chunk goes back as raw bytes (an
+/// `ArrayBuffer` on the JS side) rather than a JSON array of numbers; the
+/// file's size is the image's `size_bytes`. A custom protocol handler should
+/// serve Range requests the same way once there is one.
+#[tauri::command]
+async fn read_image_chunk(id: String, offset: u64, len: usize, state: tauri::State<'_, AppState>) -> Result<tauri::ipc::Response, String> {
+    ensure_unlocked(&state)?;
+    state.image_manager
+        .lock()
+        .unwrap()
+        .read_image_chunk(&id, offset, len)
+        .map(tauri::ipc::Response::new)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_max_ipc_read_bytes(max_bytes: u64, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.image_manager
+        .lock()
+        .unwrap()
+        .set_max_ipc_read_bytes(max_bytes)
+        .map_err(|e| e.to_string())
+}
+
+/// Files smaller than this are read with `fs::read`; mapping them costs more
+/// than it saves.
+#[cfg(feature = "mmap")]
+const MMAP_MIN_BYTES: u64 = 1024 * 1024;
+
+/// `fs::read`, or with the `mmap` feature a copy out of a memory map for
+/// large files. See PERFORMANCE.md for why the feature is off by default.
+fn read_file_bytes(path: &str) -> std::io::Result<Vec<u8>> {
+    #[cfg(feature = "mmap")]
+    {
+        let file = std::fs::File::open(path)?;
+        if file.metadata()?.len() >= MMAP_MIN_BYTES {
+            // Stored images are replaced by rename, never rewritten in place,
+            // so the mapped file doesn't change under us.
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            return Ok(map.to_vec());
+        }
+    }
+    std::fs::read(path)
+}
+
+const TRAY_ID: &str = "main";
+/// Write latency above which `get_storage_performance` warns.
+const SLOW_STORAGE_WRITE_MS: f64 = 200.0;
+/// How long a `preview_settings_import` token stays valid; long enough to
+/// read the list of changes.
+const SETTINGS_IMPORT_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// How often the taskbar theme is re-read, in case `ThemeChanged` is missed.
+#[cfg(target_os = "windows")]
+const TRAY_THEME_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Edge of the window icon built by `set_window_icon_from_image`.
+const WINDOW_ICON_PX: u32 = 32;
+
+/// Second-instance activation finds the IPC window by class, so the title
+/// can be anything, e.g. the current collection name.
+#[tauri::command]
+async fn set_window_title(title: String, app: tauri::AppHandle) -> Result<(), String> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(SnapMagError::InvalidArgument("window title must not be empty".to_string()).to_string());
+    }
+    let window = app.get_webview_window("main").ok_or_else(|| "Main window not found".to_string())?;
+    window.set_title(title).map_err(|e| e.to_string())
+}
+
+fn default_window_icon() -> Image<'static> {
+    let (width, height) = icons::WINDOW_ICON_SIZE;
+    Image::new(icons::WINDOW_ICON, width, height)
+}
+
+fn set_main_window_icon(app: &tauri::AppHandle, icon: Image<'static>) -> Result<(), String> {
+    let window = app
+        .get_webview_window("main")
+        .ok_or_else(|| "main window not found".to_string())?;
+    window.set_icon(icon).map_err(|e| e.to_string())
+}
+
+/// Shows a preview of image `id` as the window (taskbar) icon, center-cropped
+/// to a square.
+#[tauri::command]
+async fn set_window_icon_from_image(id: String, app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    ensure_unlocked(&state)?;
+    let metadata = state.image_manager
+        .lock()
+        .unwrap()
+        .get_image(&id)
+        .ok_or_else(|| SnapMagError::NotFound(id.clone()).to_string())?;
+    let image = image::open(&metadata.path)
+        .map_err(|e| SnapMagError::ImageDecodeError(e.to_string()).to_string())?;
+    let icon = image
+        .resize_to_fill(WINDOW_ICON_PX, WINDOW_ICON_PX, image::imageops::FilterType::Lanczos3)
+        .to_rgba8();
+    set_main_window_icon(&app, Image::new_owned(icon.into_raw(), WINDOW_ICON_PX, WINDOW_ICON_PX))?;
+    *state.window_icon_id.lock().unwrap() = Some(id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_window_icon_default(app: tauri::AppHandle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    restore_window_icon(&app, &state)
+}
+
+fn restore_window_icon(app: &tauri::AppHandle, state: &AppState) -> Result<(), String> {
+    set_main_window_icon(app, default_window_icon())?;
+    *state.window_icon_id.lock().unwrap() = None;
+    Ok(())
+}
+
+/// Puts the default window icon back when the image it shows is one
+/// `removed` says is gone.
+fn restore_window_icon_if(app: &tauri::AppHandle, removed: impl FnOnce(&str) -> bool) {
+    let Some(state) = app.try_state::<AppState>() else {
+        return;
+    };
+    let shown = state.window_icon_id.lock().unwrap().clone();
+    if shown.is_some_and(|id| removed(&id)) {
+        if let Err(e) = restore_window_icon(app, &state) {
+            log::error!("Failed to restore the window icon: {}", e);
+        }
+    }
+}
+
+const EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Friendly-named clipboard copies are kept this long, long enough to paste.
+const SESSION_FILE_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const INDEX_SAVE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Reads the library index off the setup path, so the window shows before
+/// a large library is indexed, then emits `library-ready`. When the index
+/// can't be read it is kept aside as `index.json.bad` and loading ends with
+/// what was saved meanwhile; the next start re-reads the files.
+fn spawn_library_scan(app: tauri::AppHandle, image_manager: Arc<Mutex<ImageManager>>) {
+    let storage_dir = image_manager.lock().unwrap().storage_dir().to_path_buf();
+    std::thread::spawn(move || {
+        let started = std::time::Instant::now();
+        let scan = ImageManager::scan_storage(&storage_dir).unwrap_or_else(|e| {
+            log::error!("Failed to read the image library, starting without it: {}", e);
+            diagnostics::report(&app, "library", "load_failed", e.to_string());
+            ImageManager::set_index_aside(&storage_dir);
+            LibraryScan::default()
+        });
+        match image_manager.lock().unwrap().finish_loading(scan) {
+            Ok(count) => {
+                log::info!("Loaded {} images in {:?}", count, started.elapsed());
+                if let Err(e) = app.emit("library-ready", LibraryReadyEvent { count }) {
+                    log::error!("Failed to emit library-ready event: {}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("Failed to save the loaded image index: {}", e);
+                diagnostics::report(&app, "library", "index_save_failed", e.to_string());
+            }
+        }
+    });
+}
+
+/// Deletes expired ephemeral captures once a minute, telling the frontend
+/// through `images-removed`, and prunes old session copies and snippets past
+/// their retention.
+fn spawn_expiry_sweeper(app: tauri::AppHandle, image_manager: Arc<Mutex<ImageManager>>, snippets: Arc<Mutex<SnippetStore>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(EXPIRY_CHECK_INTERVAL);
+        
+        let (removed, confirmation) = {
+            let mut manager = image_manager.lock().unwrap();
+            manager.prune_session_files(SESSION_FILE_MAX_AGE);
+            manager.run_janitor_if_due();
+            let expired = if manager.has_expiring_images() { manager.purge_expired() } else { Ok(Vec::new()) };
+            let removed = expired.and_then(|mut ids| {
+                ids.extend(manager.apply_retention_preset()?);
+                Ok(ids)
+            });
+            (removed, manager.take_retention_confirmation())
+        };
+        if let Some(confirmation) = confirmation {
+            log::info!("Retention preset would delete {} image(s), waiting for confirmation", confirmation.image_count);
+            if let Err(e) = app.emit("retention-confirmation-required", confirmation) {
+                log::error!("Failed to emit retention-confirmation-required event: {}", e);
+            }
+        }
+        match removed {
+            Ok(ids) if !ids.is_empty() => {
+                log::info!("Deleted {} expired image(s)", ids.len());
+                emit_removed(&app, ids);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::error!("Failed to delete expired images: {}", e);
+                diagnostics::report(&app, "scheduler", "cleanup_failed", e.to_string());
+            }
+        }
+        match snippets.lock().unwrap().prune_expired(chrono::Utc::now().timestamp()) {
+            Ok(0) => {}
+            Ok(dropped) => log::info!("Dropped {} expired snippet(s)", dropped),
+            Err(e) => log::error!("Failed to save pruned snippets: {}", e),
+        }
+    });
+}
+
+/// Writes the image index shortly after it changes, so a crash loses at
+/// most the last few seconds of tags, notes and captures.
+fn spawn_index_saver(app: tauri::AppHandle, image_manager: Arc<Mutex<ImageManager>>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(INDEX_SAVE_CHECK_INTERVAL);
+        if let Err(e) = image_manager.lock().unwrap().save_index_if_due() {
+            log::error!("Failed to save image index: {}", e);
+            diagnostics::report(&app, "library", "index_save_failed", e.to_string());
+        }
+    });
+}
+
+/// Tells the manager when image files are created or renamed under the
+/// storage folder, so `heal_missing_paths` only walks it after a move. When
+/// the folder can't be watched, healing looks for every newly missing file.
+fn spawn_storage_watcher(image_manager: Arc<Mutex<ImageManager>>) {
+    use notify::{EventKind, RecursiveMode, Watcher};
+    use notify::event::ModifyKind;
+
+    let storage_dir = image_manager.lock().unwrap().storage_dir().to_path_buf();
+    std::thread::spawn(move || {
+        let (sender, events) = std::sync::mpsc::channel();
+        let watched = notify::recommended_watcher(sender)
+            .and_then(|mut watcher| watcher.watch(&storage_dir, RecursiveMode::Recursive).map(|()| watcher));
+        let _watcher = match watched {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log::warn!("Failed to watch {}, moved files are looked for on every listing: {}", storage_dir.display(), e);
+                return;
+            }
+        };
+        image_manager.lock().unwrap().set_watching_files(true);
+        for event in events {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)))
+                    && event.paths.iter().any(|path| image::ImageFormat::from_path(path).is_ok()) =>
+                {
+                    image_manager.lock().unwrap().note_files_moved();
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Storage watcher error: {}", e),
+            }
+        }
+    });
+}
+
+/// The tray icon for `theme`, with a badge for `count` new captures unless
+/// it is 0.
+fn tray_icon(theme: TrayTheme, count: usize) -> Image<'static> {
+    let style = theme.resolved_style();
+    let (rgba, (width, height)) = match style {
+        TrayIconStyle::Dark => (icons::TRAY_ICON_DARK, icons::TRAY_ICON_DARK_SIZE),
+        _ => (icons::TRAY_ICON, icons::TRAY_ICON_SIZE),
+    };
+    if count == 0 && style != TrayIconStyle::Mono {
+        return Image::new(rgba, width, height);
+    }
+    let mut icon = image::RgbaImage::from_raw(width, height, rgba.to_vec()).expect("tray icon size mismatch");
+    if style == TrayIconStyle::Mono {
+        image_ops::monochrome_icon(&mut icon, if theme.light_taskbar { [0, 0, 0] } else { [255, 255, 255] });
+    }
+    if count > 0 {
+        image_ops::draw_count_badge(&mut icon, count);
+    }
+    Image::new_owned(icon.into_raw(), width, height)
+}
+
+fn refresh_tray_icon(state: &AppState) {
+    let count = {
+        let badge = state.badge.lock().unwrap();
+        if badge.enabled { badge.count } else { 0 }
+    };
+    let theme = *state.tray_theme.lock().unwrap();
+    if let Err(e) = state.tray.set_icon(Some(tray_icon(theme, count))) {
+        log::error!("Failed to update tray icon: {}", e);
+    }
+}
+
+/// Re-reads the taskbar theme and redraws the tray icon if it flipped.
+fn refresh_tray_theme(app: &tauri::AppHandle) {
+    let Some(light_taskbar) = platform::taskbar_uses_light_theme() else {
+        return;
+    };
+    let Some(state) = app.try_state::<AppState>() else {
+        return;
+    };
+    let flipped = std::mem::replace(&mut state.tray_theme.lock().unwrap().light_taskbar, light_taskbar) != light_taskbar;
+    if flipped {
+        log::info!("Taskbar theme changed to {}", if light_taskbar { "light" } else { "dark" });
+        refresh_tray_icon(&state);
+    }
+}
+
+/// Polls the taskbar theme, for changes that arrive while the window is
+/// hidden and gets no `ThemeChanged`.
+#[cfg(target_os = "windows")]
+fn spawn_tray_theme_watch(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(TRAY_THEME_POLL_INTERVAL);
+        refresh_tray_theme(&app);
+    });
+}
+
+/// Shows the number of captures since the window was last shown on the
+/// tray icon. On by default.
+#[tauri::command]
+async fn set_show_badge(enabled: bool, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    state.badge.lock().unwrap().enabled = enabled;
+    refresh_tray_icon(&state);
+    Ok(())
+}
+
+/// Picks the tray icon: `auto` follows the taskbar theme, `light` and
+/// `dark` force one icon, and `mono` draws it in one color. Kept across
+/// restarts.
+#[tauri::command]
+async fn set_tray_icon_style(style: TrayIconStyle, state: tauri::State<'_, AppState>) -> Result<(), String> {
+    settings::persist_tray_icon_style(state.image_manager.lock().unwrap().storage_dir(), style).map_err(|e| e.to_string())?;
+    state.tray_theme.lock().unwrap().style = style;
+    refresh_tray_icon(&state);
+    Ok(())
+}
+
+fn update_tray_tooltip(app: &tauri::AppHandle, ephemeral: bool) {
+    let tooltip = if ephemeral { "SnapMag（临时模式）" } else { "SnapMag" };
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        if let Err(e) = tray.set_tooltip(Some(tooltip)) {
+            log::error!("Failed to update tray tooltip: {}", e);
+        }
+    }
+}
+
+/// Emits `images-removed` for images evicted by the library limits.
+fn emit_evictions(app: &tauri::AppHandle, image_manager: &mut ImageManager) {
+    emit_removed(app, image_manager.take_evicted_ids());
+}
+
+/// Emits `images-removed` for `ids` unless there are none, restoring the
+/// window icon if it showed one of them.
+fn emit_removed(app: &tauri::AppHandle, ids: Vec<String>) {
+    if ids.is_empty() {
+        return;
+    }
+    restore_window_icon_if(app, |shown| ids.iter().any(|id| id == shown));
+    if let Err(e) = app.emit("images-removed", ImagesRemovedEvent { ids }) {
+        log::error!("Failed to emit images-removed event: {}", e);
+    }
+}
+
+/// Emits `paths-invalidated` with the asset URLs of files that moved, so
+/// the webview can drop URLs it cached.
+fn emit_moved_paths(app: &tauri::AppHandle, image_manager: &mut ImageManager) {
+    let moved = image_manager.take_moved_paths();
+    if moved.is_empty() {
+        return;
+    }
+    let mapping = moved
+        .into_iter()
+        .map(|(old, new)| (convert_path_protocol(&old), convert_path_protocol(&new)))
+        .collect();
+    if let Err(e) = app.emit("paths-invalidated", PathsInvalidatedEvent { mapping }) {
+        log::error!("Failed to emit paths-invalidated event: {}", e);
+    }
+}
+
+/// Shape of an entry in list responses: asset URL path and no long-form notes,
+/// which are only returned by `get_image_detail`.
+fn to_list_item(mut metadata: ImageMetadata) -> ImageMetadata {
+    metadata.path = convert_path_protocol(&metadata.path);
+    metadata.notes = None;
+    metadata
 }
 
 fn convert_path_protocol(path: &str) -> String {
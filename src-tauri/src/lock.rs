@@ -0,0 +1,174 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::error::SnapMagError;
+
+const LOCK_FILE: &str = "lock.json";
+/// SHA-256 rounds per PIN check. bcrypt isn't a dependency; stretching a
+/// salted SHA-256 gives the same property of making each guess slow.
+const PIN_HASH_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+/// Wrong PINs accepted before `unlock` refuses for `LOCKOUT`.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LockFile {
+    /// Hex-encoded salt and hash; both `None` when no PIN is set.
+    pin_salt: Option<String>,
+    pin_hash: Option<String>,
+    lock_timeout_minutes: Option<u32>,
+}
+
+/// The PIN gate in front of the image history, kept in `lock/lock.json`.
+/// The app starts locked when a PIN is set, and locks again after
+/// `lock_timeout_minutes` without a gated command.
+pub struct AppLock {
+    dir: PathBuf,
+    file: LockFile,
+    locked: bool,
+    last_activity: Instant,
+    failed_attempts: u32,
+    locked_out_until: Option<Instant>,
+}
+
+impl AppLock {
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let file: LockFile = fs::read(dir.join(LOCK_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let locked = file.pin_hash.is_some();
+        Ok(Self {
+            dir,
+            file,
+            locked,
+            last_activity: Instant::now(),
+            failed_attempts: 0,
+            locked_out_until: None,
+        })
+    }
+
+    /// Whether gated commands are refused, locking first if the inactivity
+    /// timeout has passed.
+    pub fn is_locked(&mut self) -> bool {
+        if !self.locked && self.file.pin_hash.is_some() {
+            if let Some(minutes) = self.file.lock_timeout_minutes {
+                if self.last_activity.elapsed() >= Duration::from_secs(minutes as u64 * 60) {
+                    log::info!("Locking after {} minutes of inactivity", minutes);
+                    self.locked = true;
+                }
+            }
+        }
+        self.locked
+    }
+
+    /// Called by every gated command: fails with `Locked` or counts as
+    /// activity for the inactivity timeout.
+    pub fn check(&mut self) -> Result<(), SnapMagError> {
+        if self.is_locked() {
+            return Err(SnapMagError::Locked);
+        }
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Sets a new PIN of 4–8 digits. Changing an existing PIN needs the app
+    /// to be unlocked.
+    pub fn set_pin(&mut self, pin: &str) -> anyhow::Result<()> {
+        self.check()?;
+        if !(4..=8).contains(&pin.len()) || !pin.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(SnapMagError::InvalidArgument("PIN must be 4 to 8 digits".to_string()).into());
+        }
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).map_err(|e| anyhow::anyhow!("Failed to generate PIN salt: {}", e))?;
+        self.file.pin_hash = Some(to_hex(&hash_pin(pin, &salt)));
+        self.file.pin_salt = Some(to_hex(&salt));
+        self.persist()?;
+        log::info!("Lock PIN set");
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, pin: &str) -> anyhow::Result<()> {
+        self.verify(pin)?;
+        self.locked = false;
+        self.last_activity = Instant::now();
+        log::info!("Unlocked");
+        Ok(())
+    }
+
+    /// Removes the PIN, which must be given, and unlocks.
+    pub fn clear_pin(&mut self, pin: &str) -> anyhow::Result<()> {
+        self.verify(pin)?;
+        self.file.pin_hash = None;
+        self.file.pin_salt = None;
+        self.locked = false;
+        self.persist()?;
+        log::info!("Lock PIN cleared");
+        Ok(())
+    }
+
+    /// `None` turns the inactivity lock off.
+    pub fn set_timeout_minutes(&mut self, minutes: Option<u32>) -> anyhow::Result<()> {
+        self.check()?;
+        if minutes == Some(0) {
+            return Err(SnapMagError::InvalidArgument("lock timeout must be at least one minute".to_string()).into());
+        }
+        self.file.lock_timeout_minutes = minutes;
+        self.persist()
+    }
+
+    fn verify(&mut self, pin: &str) -> Result<(), SnapMagError> {
+        let (Some(salt), Some(expected)) = (&self.file.pin_salt, &self.file.pin_hash) else {
+            return Err(SnapMagError::InvalidArgument("no PIN is set".to_string()));
+        };
+        if self.locked_out_until.is_some_and(|until| Instant::now() < until) {
+            return Err(SnapMagError::InvalidArgument("too many wrong PINs, try again later".to_string()));
+        }
+        let salt = from_hex(salt).unwrap_or_default();
+        let matches = from_hex(expected).is_some_and(|expected| constant_time_eq(&hash_pin(pin, &salt), &expected));
+        if !matches {
+            self.failed_attempts += 1;
+            if self.failed_attempts >= MAX_FAILED_ATTEMPTS {
+                log::warn!("{} wrong PINs, refusing unlock for {:?}", self.failed_attempts, LOCKOUT);
+                self.failed_attempts = 0;
+                self.locked_out_until = Some(Instant::now() + LOCKOUT);
+            }
+            return Err(SnapMagError::InvalidArgument("wrong PIN".to_string()));
+        }
+        self.failed_attempts = 0;
+        self.locked_out_until = None;
+        Ok(())
+    }
+
+    fn persist(&self) -> anyhow::Result<()> {
+        fs::write(self.dir.join(LOCK_FILE), serde_json::to_vec(&self.file)?)?;
+        Ok(())
+    }
+}
+
+fn hash_pin(pin: &str, salt: &[u8]) -> Vec<u8> {
+    let mut digest = Sha256::new().chain_update(salt).chain_update(pin.as_bytes()).finalize();
+    for _ in 1..PIN_HASH_ROUNDS {
+        digest = Sha256::new().chain_update(salt).chain_update(digest).finalize();
+    }
+    digest.to_vec()
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
@@ -0,0 +1,230 @@
+//! Color lookup tables for `apply_lut`.
+//!
+//! A table is either three per-channel curves (a 256×1 PNG, a `.cube` 1D
+//! LUT or one of the bundled looks) or a 3D cube mapping every RGB triple
+//! (a 256×16 or 64×64 PNG of a 16³ cube, or a `.cube` 3D LUT).
+
+use std::fs;
+use std::path::Path;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use crate::error::SnapMagError;
+
+/// Edge length of the cubes stored as PNG.
+const PNG_CUBE_SIZE: u32 = 16;
+/// Largest `LUT_3D_SIZE` accepted from a `.cube` file.
+const MAX_CUBE_SIZE: usize = 128;
+/// Largest `LUT_1D_SIZE` accepted from a `.cube` file.
+const MAX_CURVE_SIZE: usize = 65536;
+
+pub enum Lut {
+    /// Output value of the red, green and blue channel for each input value.
+    Curves(Box<[[u8; 256]; 3]>),
+    /// `size`³ RGB outputs from 0.0 to 1.0, red varying fastest.
+    Cube { size: usize, entries: Vec<[f32; 3]> },
+}
+
+/// Looks built into the app, as red, green and blue curves.
+const BUNDLED: [(&str, [[u8; 256]; 3]); 3] = [
+    ("warm", [
+        curve(&[(0, 0), (128, 146), (255, 255)]),
+        curve(&[(0, 0), (128, 132), (255, 250)]),
+        curve(&[(0, 0), (128, 110), (255, 228)]),
+    ]),
+    ("cool", [
+        curve(&[(0, 0), (128, 112), (255, 232)]),
+        curve(&[(0, 0), (128, 128), (255, 250)]),
+        curve(&[(0, 12), (128, 148), (255, 255)]),
+    ]),
+    // Lifted blacks, dimmed whites and a yellow cast.
+    ("vintage", [
+        curve(&[(0, 40), (64, 84), (128, 150), (255, 240)]),
+        curve(&[(0, 30), (64, 72), (128, 135), (255, 225)]),
+        curve(&[(0, 50), (64, 70), (128, 108), (255, 188)]),
+    ]),
+];
+
+/// Piecewise-linear curve through `points`, which run from input 0 to 255.
+const fn curve(points: &[(u8, u8)]) -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut segment = 0;
+    let mut x = 0;
+    while x < 256 {
+        while segment + 2 < points.len() && x > points[segment + 1].0 as usize {
+            segment += 1;
+        }
+        let (x0, y0) = (points[segment].0 as usize, points[segment].1 as usize);
+        let (x1, y1) = (points[segment + 1].0 as usize, points[segment + 1].1 as usize);
+        let span = x1 - x0;
+        // Interpolated as `y0 * (1 - t) + y1 * t`, which never goes negative.
+        table[x] = ((y0 * (x1 - x) + y1 * (x - x0) + span / 2) / span) as u8;
+        x += 1;
+    }
+    table
+}
+
+pub fn bundled_names() -> Vec<String> {
+    BUNDLED.iter().map(|(name, _)| name.to_string()).collect()
+}
+
+pub fn bundled(name: &str) -> Option<Lut> {
+    BUNDLED.iter().find(|(bundled, _)| *bundled == name).map(|(_, curves)| Lut::Curves(Box::new(*curves)))
+}
+
+impl Lut {
+    /// Reads a `.png` or `.cube` file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => {
+                let image = image::open(path).map_err(|e| SnapMagError::ImageDecodeError(e.to_string()))?;
+                Ok(Self::from_png(&image)?)
+            }
+            "cube" => Ok(Self::parse_cube(&fs::read_to_string(path)?)?),
+            _ => Err(SnapMagError::InvalidArgument(format!("LUT must be a .png or .cube file: {}", path.display())).into()),
+        }
+    }
+
+    fn from_png(image: &DynamicImage) -> Result<Self, SnapMagError> {
+        let size = PNG_CUBE_SIZE;
+        let (width, height) = image.dimensions();
+        let cube = |x_of: &dyn Fn(u32, u32) -> u32, y_of: &dyn Fn(u32, u32) -> u32| {
+            let mut entries = Vec::with_capacity((size * size * size) as usize);
+            for b in 0..size {
+                for g in 0..size {
+                    for r in 0..size {
+                        let pixel = image.get_pixel(x_of(r, b), y_of(g, b));
+                        entries.push([0, 1, 2].map(|i| pixel[i] as f32 / 255.0));
+                    }
+                }
+            }
+            Self::Cube { size: size as usize, entries }
+        };
+        match (width, height) {
+            (256, 1) => {
+                let mut curves = [[0u8; 256]; 3];
+                for x in 0..256 {
+                    let pixel = image.get_pixel(x, 0);
+                    for (channel, table) in curves.iter_mut().enumerate() {
+                        table[x as usize] = pixel[channel];
+                    }
+                }
+                Ok(Self::Curves(Box::new(curves)))
+            }
+            // One 16×16 red/green slice per blue value, side by side.
+            (256, 16) => Ok(cube(&|r, b| b * size + r, &|g, _| g)),
+            // The same slices in a 4×4 grid.
+            (64, 64) => Ok(cube(&|r, b| (b % 4) * size + r, &|g, b| (b / 4) * size + g)),
+            _ => Err(SnapMagError::InvalidArgument(format!(
+                "LUT image must be 256x1, 256x16 or 64x64, not {}x{}",
+                width, height
+            ))),
+        }
+    }
+
+    /// Parses the Adobe/Resolve `.cube` text format. Only the default 0–1
+    /// domain is supported.
+    fn parse_cube(text: &str) -> Result<Self, SnapMagError> {
+        let invalid = |message: String| SnapMagError::InvalidArgument(format!("invalid .cube file: {}", message));
+        let mut curve_size = None;
+        let mut cube_size = None;
+        let mut entries: Vec<[f32; 3]> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("TITLE") {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let keyword = fields.next().unwrap_or("");
+            let size = fields.clone().next().and_then(|size| size.parse::<usize>().ok());
+            let bad_size = || invalid(format!("bad {} on line {}", keyword, number + 1));
+            match keyword {
+                "LUT_1D_SIZE" => curve_size = Some(size.filter(|size| (2..=MAX_CURVE_SIZE).contains(size)).ok_or_else(bad_size)?),
+                "LUT_3D_SIZE" => cube_size = Some(size.filter(|size| (2..=MAX_CUBE_SIZE).contains(size)).ok_or_else(bad_size)?),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if !fields.all(|value| value.parse::<f32>() == Ok(expected)) {
+                        return Err(invalid("only the 0-1 domain is supported".to_string()));
+                    }
+                }
+                _ => {
+                    let values: Vec<f32> = line.split_whitespace().filter_map(|value| value.parse().ok()).collect();
+                    let [r, g, b] = values[..] else {
+                        return Err(invalid(format!("unexpected line {}: {}", number + 1, line)));
+                    };
+                    entries.push([r, g, b]);
+                }
+            }
+        }
+
+        match (curve_size, cube_size) {
+            (Some(size), None) if entries.len() == size => {
+                let mut curves = [[0u8; 256]; 3];
+                for value in 0..256 {
+                    let position = value as f32 / 255.0 * (size - 1) as f32;
+                    let low = position.floor() as usize;
+                    let high = (low + 1).min(size - 1);
+                    let t = position - low as f32;
+                    for (channel, table) in curves.iter_mut().enumerate() {
+                        let mapped = entries[low][channel] * (1.0 - t) + entries[high][channel] * t;
+                        table[value] = to_byte(mapped);
+                    }
+                }
+                Ok(Self::Curves(Box::new(curves)))
+            }
+            (None, Some(size)) if entries.len() == size * size * size => Ok(Self::Cube { size, entries }),
+            (None, None) => Err(invalid("no LUT_1D_SIZE or LUT_3D_SIZE".to_string())),
+            (Some(_), Some(_)) => Err(invalid("both LUT_1D_SIZE and LUT_3D_SIZE are set".to_string())),
+            _ => Err(invalid(format!("{} entries don't match the declared size", entries.len()))),
+        }
+    }
+
+    /// Maps every pixel of `image` through the table. Alpha is kept as is.
+    pub fn apply(&self, image: &DynamicImage) -> RgbaImage {
+        let mut mapped = image.to_rgba8();
+        match self {
+            Self::Curves(curves) => {
+                for pixel in mapped.pixels_mut() {
+                    for (channel, table) in curves.iter().enumerate() {
+                        pixel[channel] = table[pixel[channel] as usize];
+                    }
+                }
+            }
+            Self::Cube { size, entries } => {
+                for pixel in mapped.pixels_mut() {
+                    let rgb = lookup_trilinear(*size, entries, [pixel[0], pixel[1], pixel[2]]);
+                    for (channel, value) in pixel.0.iter_mut().zip(rgb) {
+                        *channel = to_byte(value);
+                    }
+                }
+            }
+        }
+        mapped
+    }
+}
+
+/// Blends the eight cube entries around `rgb`.
+fn lookup_trilinear(size: usize, entries: &[[f32; 3]], rgb: [u8; 3]) -> [f32; 3] {
+    let scale = (size - 1) as f32 / 255.0;
+    let position = rgb.map(|value| value as f32 * scale);
+    let low = position.map(|p| (p.floor() as usize).min(size - 1));
+    let high = low.map(|l| (l + 1).min(size - 1));
+    let t = [0, 1, 2].map(|i| position[i] - low[i] as f32);
+
+    let entry = |r: usize, g: usize, b: usize| entries[r + g * size + b * size * size];
+    let mut result = [0.0; 3];
+    for (r, weight_r) in [(low[0], 1.0 - t[0]), (high[0], t[0])] {
+        for (g, weight_g) in [(low[1], 1.0 - t[1]), (high[1], t[1])] {
+            for (b, weight_b) in [(low[2], 1.0 - t[2]), (high[2], t[2])] {
+                let weight = weight_r * weight_g * weight_b;
+                for (sum, value) in result.iter_mut().zip(entry(r, g, b)) {
+                    *sum += value * weight;
+                }
+            }
+        }
+    }
+    result
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
@@ -1,19 +1,21 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use clap::Parser;
+
+#[cfg(target_os = "windows")]
+const MUTEX_NAME: &str = "SnapMag_SingleInstance_Mutex";
+
 #[cfg(target_os = "windows")]
 fn check_single_instance() -> bool {
-    use winapi::um::winuser::{FindWindowW, SetForegroundWindow, ShowWindow, SetFocus, SW_RESTORE};
+    use winapi::um::winuser::{AllowSetForegroundWindow, FindWindowExW, GetWindowThreadProcessId, PostMessageW, HWND_MESSAGE};
     use winapi::um::errhandlingapi::GetLastError;
     use winapi::shared::winerror::ERROR_ALREADY_EXISTS;
     use std::ffi::OsStr;
     use std::os::windows::ffi::OsStrExt;
 
-    const MUTEX_NAME: &str = "SnapMag_SingleInstance_Mutex";
-    const WINDOW_TITLE: &str = "SnapMag";
-
     let mutex_name: Vec<u16> = OsStr::new(MUTEX_NAME).encode_wide().chain(std::iter::once(0)).collect();
-    let window_title: Vec<u16> = OsStr::new(WINDOW_TITLE).encode_wide().chain(std::iter::once(0)).collect();
+    let ipc_class: Vec<u16> = OsStr::new(app_lib::platform::IPC_WINDOW_CLASS).encode_wide().chain(std::iter::once(0)).collect();
 
     unsafe {
         let mutex = winapi::um::synchapi::CreateMutexW(
@@ -32,13 +34,15 @@ fn check_single_instance() -> bool {
         if last_error == ERROR_ALREADY_EXISTS {
             println!("Another instance detected, activating existing window...");
 
-            let hwnd = FindWindowW(std::ptr::null(), window_title.as_ptr());
+            // Found by class rather than title, which the frontend may change.
+            let hwnd = FindWindowExW(HWND_MESSAGE, std::ptr::null_mut(), ipc_class.as_ptr(), std::ptr::null());
             if !hwnd.is_null() {
                 println!("Found window, activating...");
-                // 使用 SW_RESTORE 确保窗口从最小化恢复
-                ShowWindow(hwnd, SW_RESTORE);
-                SetForegroundWindow(hwnd);
-                SetFocus(hwnd);
+                // Only the foreground process may hand the foreground over.
+                let mut pid = 0;
+                GetWindowThreadProcessId(hwnd, &mut pid);
+                AllowSetForegroundWindow(pid);
+                PostMessageW(hwnd, app_lib::platform::WM_SHOWMAIN, 0, 0);
             } else {
                 println!("Window not found");
             }
@@ -53,12 +57,63 @@ fn check_single_instance() -> bool {
     true
 }
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(unix)]
+fn check_single_instance() -> bool {
+    app_lib::platform::claim_single_instance()
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
 fn check_single_instance() -> bool {
     true
 }
 
+/// Whether the GUI is running. The CLI takes the single-instance mutex for
+/// its own lifetime, so a GUI launched meanwhile defers to it.
+#[cfg(target_os = "windows")]
+fn gui_is_running() -> bool {
+    use winapi::um::errhandlingapi::GetLastError;
+    use winapi::shared::winerror::ERROR_ALREADY_EXISTS;
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+
+    let mutex_name: Vec<u16> = OsStr::new(MUTEX_NAME).encode_wide().chain(std::iter::once(0)).collect();
+    unsafe {
+        let mutex = winapi::um::synchapi::CreateMutexW(std::ptr::null_mut(), 0, mutex_name.as_ptr());
+        !mutex.is_null() && GetLastError() == ERROR_ALREADY_EXISTS
+    }
+}
+
+#[cfg(unix)]
+fn gui_is_running() -> bool {
+    app_lib::platform::instance_is_running()
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
+fn gui_is_running() -> bool {
+    false
+}
+
+/// Release builds use the GUI subsystem and start without a console, so
+/// borrow the one of the shell that launched us for CLI output.
+#[cfg(target_os = "windows")]
+fn attach_parent_console() {
+    use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_parent_console() {}
+
 fn main() {
+    if std::env::args_os().len() > 1 {
+        attach_parent_console();
+    }
+    if let Some(command) = app_lib::cli::Cli::parse().command {
+        std::process::exit(app_lib::cli::run(command, gui_is_running()));
+    }
+
     let is_first_instance = check_single_instance();
 
     if !is_first_instance {
@@ -0,0 +1,242 @@
+//! PDF export for `export_images_to_pdf`, built on `printpdf`.
+//!
+//! Images are embedded as JPEG (`DCTDecode`), which PDF readers decode
+//! natively, and text uses the standard Helvetica font, so nothing has to be
+//! embedded beyond the images themselves. Pages keep only their compressed
+//! JPEG, and the document is serialized straight into a temp file that
+//! replaces the destination once it is complete.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use image::DynamicImage;
+use printpdf::{BuiltinFont, DictItem, ExternalStream, ExternalXObject, Mm, Op, PdfFontHandle, PdfPage, PdfSaveOptions, Point, Pt, TextItem, XObjectTransform};
+use crate::error::SnapMagError;
+use crate::image_ops;
+
+/// Space kept free around each image, in points.
+const MARGIN: f32 = 36.0;
+const JPEG_QUALITY: u8 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageSize {
+    A4,
+    Letter,
+    A3,
+}
+
+impl PageSize {
+    pub fn parse(name: &str) -> Result<Self, SnapMagError> {
+        match name {
+            "A4" => Ok(Self::A4),
+            "Letter" => Ok(Self::Letter),
+            "A3" => Ok(Self::A3),
+            _ => Err(SnapMagError::InvalidArgument(format!("page size must be A4, Letter or A3, not {:?}", name))),
+        }
+    }
+
+    /// Width and height in points, portrait.
+    fn dimensions(self) -> (f32, f32) {
+        match self {
+            Self::A4 => (595.28, 841.89),
+            Self::Letter => (612.0, 792.0),
+            Self::A3 => (841.89, 1190.55),
+        }
+    }
+}
+
+/// An export being built page by page; `write_to` serializes it.
+pub struct PdfDocument {
+    page_size: PageSize,
+    document: printpdf::PdfDocument,
+    pages: Vec<PdfPage>,
+}
+
+impl PdfDocument {
+    pub fn new(page_size: PageSize) -> Self {
+        Self { page_size, document: printpdf::PdfDocument::new("SnapMag export"), pages: Vec::new() }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Adds a page of centered lines of text, given as `(font size, text)`
+    /// from the top.
+    pub fn add_text_page(&mut self, lines: &[(f32, String)]) {
+        let (width, height) = self.page_size.dimensions();
+        let mut ops = vec![Op::StartTextSection];
+        let mut y = height * 0.6;
+        for (size, text) in lines {
+            // Helvetica averages about half an em per character.
+            let x = (width - text.chars().count() as f32 * size * 0.5) / 2.0;
+            ops.push(Op::SetFont { font: PdfFontHandle::Builtin(BuiltinFont::Helvetica), size: Pt(*size) });
+            ops.push(Op::SetTextCursor { pos: Point { x: Pt(x.max(MARGIN)), y: Pt(y) } });
+            ops.push(Op::ShowText { items: vec![TextItem::Text(ascii_text(text))] });
+            y -= size * 1.8;
+        }
+        ops.push(Op::EndTextSection);
+        self.add_page(ops);
+    }
+
+    /// Adds a page with `image` scaled to fit inside the margins, centered.
+    /// Transparent areas are flattened onto white.
+    pub fn add_image_page(&mut self, image: &DynamicImage) -> anyhow::Result<()> {
+        let flattened = image_ops::composite_on_background(image, [255, 255, 255]);
+        let (pixel_width, pixel_height) = flattened.dimensions();
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY).encode_image(&flattened)?;
+
+        let name = |name: &str| DictItem::Name(name.as_bytes().to_vec());
+        let dict = BTreeMap::from([
+            ("Type".to_string(), name("XObject")),
+            ("Subtype".to_string(), name("Image")),
+            ("Width".to_string(), DictItem::Int(pixel_width as i64)),
+            ("Height".to_string(), DictItem::Int(pixel_height as i64)),
+            ("ColorSpace".to_string(), name("DeviceRGB")),
+            ("BitsPerComponent".to_string(), DictItem::Int(8)),
+            ("Filter".to_string(), name("DCTDecode")),
+        ]);
+        // JPEG is already compressed; `compress: false` keeps it as is.
+        let stream = ExternalStream { dict, content: jpeg, compress: false };
+        let id = self.document.add_xobject(&ExternalXObject { stream, width: None, height: None, dpi: None });
+
+        let (width, height) = self.page_size.dimensions();
+        let scale = ((width - 2.0 * MARGIN) / pixel_width as f32).min((height - 2.0 * MARGIN) / pixel_height as f32);
+        let (drawn_width, drawn_height) = (pixel_width as f32 * scale, pixel_height as f32 * scale);
+        let transform = XObjectTransform {
+            translate_x: Some(Pt((width - drawn_width) / 2.0)),
+            translate_y: Some(Pt((height - drawn_height) / 2.0)),
+            scale_x: Some(drawn_width),
+            scale_y: Some(drawn_height),
+            no_auto_scale: true,
+            ..XObjectTransform::default()
+        };
+        self.add_page(vec![Op::UseXobject { id, transform }]);
+        Ok(())
+    }
+
+    /// Writes the document to `dest` through a sibling temp file, so a
+    /// failed export never leaves a truncated PDF under the final name.
+    pub fn write_to(mut self, dest: &Path) -> anyhow::Result<()> {
+        let mut tmp_name = dest.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = std::path::PathBuf::from(tmp_name);
+
+        let result = (|| {
+            let mut writer = CheckedWriter { inner: BufWriter::new(fs::File::create(&tmp_path)?), error: None };
+            let mut warnings = Vec::new();
+            let pages = std::mem::take(&mut self.pages);
+            self.document.with_pages(pages).save_writer(&mut writer, &PdfSaveOptions::default(), &mut warnings);
+            if let Some(e) = writer.error.take() {
+                return Err(e.into());
+            }
+            for warning in &warnings {
+                log::debug!("PDF export: {:?}", warning);
+            }
+            let file = writer.inner.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            drop(file);
+            fs::rename(&tmp_path, dest)?;
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn add_page(&mut self, ops: Vec<Op>) {
+        let (width, height) = self.page_size.dimensions();
+        self.pages.push(PdfPage::new(Mm::from(Pt(width)), Mm::from(Pt(height)), ops));
+    }
+}
+
+/// Keeps the first write error, which `printpdf` would otherwise drop.
+struct CheckedWriter<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Write for CheckedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        if let Err(e) = &result {
+            self.error.get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        if let Err(e) = &result {
+            self.error.get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+}
+
+/// Characters outside ASCII are replaced, as the standard font's encoding
+/// can't show most of them.
+fn ascii_text(text: &str) -> String {
+    text.chars().map(|c| if (' '..='~').contains(&c) { c } else { '?' }).collect()
+}
+
+/// Writes a PDF with a title page followed by one page per image in `paths`
+/// to `dest`, calling `progress(current, total)` after each image. Returns
+/// the number of pages, title page included.
+pub fn export_images(paths: &[std::path::PathBuf], dest: &Path, page_size: PageSize, mut progress: impl FnMut(usize, usize)) -> anyhow::Result<usize> {
+    let mut document = PdfDocument::new(page_size);
+    let exported = chrono::Local::now().format("%Y-%m-%d %H:%M");
+    document.add_text_page(&[
+        (28.0, "SnapMag export".to_string()),
+        (14.0, format!("Exported {}", exported)),
+        (14.0, format!("{} image{}", paths.len(), if paths.len() == 1 { "" } else { "s" })),
+    ]);
+
+    for (index, path) in paths.iter().enumerate() {
+        let image = image::open(path).map_err(|e| SnapMagError::ImageDecodeError(format!("{}: {}", path.display(), e)))?;
+        document.add_image_page(&image)?;
+        progress(index + 1, paths.len());
+    }
+
+    let pages = document.page_count();
+    document.write_to(dest)?;
+    log::info!("Exported {} image(s) to {}", paths.len(), dest.display());
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn export_writes_a_pdf_and_no_temp_file() {
+        let dir = TempDir::new().unwrap();
+        let image_path = dir.path().join("shot.png");
+        RgbaImage::from_pixel(30, 20, Rgba([10, 200, 30, 128])).save(&image_path).unwrap();
+        let dest = dir.path().join("export.pdf");
+
+        let mut progress = Vec::new();
+        let pages = export_images(&[image_path.clone(), image_path], &dest, PageSize::A4, |current, total| progress.push((current, total))).unwrap();
+        assert_eq!(pages, 3);
+        assert_eq!(progress, [(1, 2), (2, 2)]);
+
+        let pdf = fs::read(&dest).unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert!(pdf.windows(9).any(|window| window == b"DCTDecode"));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn failed_export_leaves_nothing_behind() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("export.pdf");
+        assert!(export_images(&[dir.path().join("missing.png")], &dest, PageSize::Letter, |_, _| {}).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,212 @@
+//! Sending images straight to another SnapMag instance over TCP.
+//!
+//! A transfer is one connection of length-prefixed frames (a 4-byte
+//! big-endian length, then the bytes): the shared token, checked before
+//! anything else is read, then the JSON `PeerHeader`, then the image file
+//! bytes until the sender closes. Both sides must have the same token set.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+use crate::error::SnapMagError;
+use crate::image_manager::ImageManager;
+use crate::lock::constant_time_eq;
+use crate::types::{CaptureSource, ImageMetadata, ImagesAddedEvent};
+
+const PEER_FILE: &str = "peer.json";
+const MAX_TOKEN_BYTES: u32 = 1024;
+const MAX_HEADER_BYTES: u32 = 64 * 1024;
+const MAX_PAYLOAD_BYTES: u64 = 200 * 1024 * 1024;
+/// How long a new connection has to send the token.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// Transfers received at once; further connections are dropped.
+const MAX_CONNECTIONS: usize = 8;
+/// How often the accept loop checks whether it was stopped.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerHeader {
+    metadata: ImageMetadata,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PeerFile {
+    token: Option<String>,
+}
+
+/// Shared token, kept in `peer/peer.json`, and the running import server,
+/// if any.
+pub struct PeerTransfer {
+    dir: PathBuf,
+    token: Option<String>,
+    server: Option<ImportServer>,
+}
+
+struct ImportServer {
+    running: Arc<AtomicBool>,
+}
+
+impl PeerTransfer {
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let file: PeerFile = fs::read(dir.join(PEER_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Ok(Self { dir, token: file.token, server: None })
+    }
+
+    /// Sets the token both peers must share; `None` or blank disables
+    /// transfers until set again.
+    pub fn set_token(&mut self, token: Option<String>) -> anyhow::Result<()> {
+        self.token = token.filter(|token| !token.trim().is_empty());
+        let file = PeerFile { token: self.token.clone() };
+        fs::write(self.dir.join(PEER_FILE), serde_json::to_vec(&file)?)?;
+        Ok(())
+    }
+
+    fn token(&self) -> Result<String, SnapMagError> {
+        self.token.clone().ok_or_else(|| SnapMagError::InvalidArgument("peer token is not set".to_string()))
+    }
+
+    /// Sends `metadata`'s file to the import server at `peer_addr`.
+    pub fn export_image(&self, metadata: &ImageMetadata, peer_addr: &str) -> anyhow::Result<()> {
+        let token = self.token()?;
+        let header = serde_json::to_vec(&PeerHeader { metadata: metadata.clone() })?;
+        let data = fs::read(Path::new(&metadata.path))?;
+
+        let mut stream = TcpStream::connect(peer_addr)?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        write_frame(&mut stream, token.as_bytes())?;
+        write_frame(&mut stream, &header)?;
+        stream.write_all(&data)?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        log::info!("Sent image {} to {}", metadata.id, peer_addr);
+        Ok(())
+    }
+
+    /// Accepts transfers on `port` of `bind_address` (loopback when `None`)
+    /// on a background thread, receiving each on its own thread. Received
+    /// images are saved as imports and announced through `images-added`.
+    pub fn start_server(&mut self, bind_address: Option<IpAddr>, port: u16, app: AppHandle, image_manager: Arc<Mutex<ImageManager>>) -> anyhow::Result<()> {
+        if self.server.is_some() {
+            return Err(SnapMagError::InvalidArgument("import server is already running".to_string()).into());
+        }
+        let token = Arc::new(self.token()?);
+        let bind_address = bind_address.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let listener = TcpListener::bind((bind_address, port))?;
+        listener.set_nonblocking(true)?;
+        log::info!("Peer import server listening on {}:{}", bind_address, port);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let connections = Arc::new(AtomicUsize::new(0));
+        {
+            let running = running.clone();
+            thread::spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    match listener.accept() {
+                        Ok((stream, addr)) => {
+                            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                                connections.fetch_sub(1, Ordering::SeqCst);
+                                log::warn!("Dropping peer connection from {}: too many transfers in progress", addr);
+                                continue;
+                            }
+                            let (token, app, image_manager, connections) = (token.clone(), app.clone(), image_manager.clone(), connections.clone());
+                            thread::spawn(move || {
+                                if let Err(e) = Self::receive(stream, &token, &app, &image_manager) {
+                                    log::error!("Failed to receive image from {}: {}", addr, e);
+                                }
+                                connections.fetch_sub(1, Ordering::SeqCst);
+                            });
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                        Err(e) => {
+                            log::error!("Peer import server accept failed: {}", e);
+                            thread::sleep(ACCEPT_POLL_INTERVAL);
+                        }
+                    }
+                }
+                log::info!("Peer import server stopped");
+            });
+        }
+        self.server = Some(ImportServer { running });
+        Ok(())
+    }
+
+    /// Stops accepting transfers. The accept loop exits within
+    /// `ACCEPT_POLL_INTERVAL`; transfers in progress finish on their own.
+    pub fn stop_server(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.running.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn receive(mut stream: TcpStream, token: &str, app: &AppHandle, image_manager: &Mutex<ImageManager>) -> anyhow::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(AUTH_TIMEOUT))?;
+        let sent_token = read_frame(&mut stream, MAX_TOKEN_BYTES, "token")?;
+        if !constant_time_eq(&sent_token, token.as_bytes()) {
+            return Err(SnapMagError::InvalidArgument("peer token does not match".to_string()).into());
+        }
+
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        let header: PeerHeader = serde_json::from_slice(&read_frame(&mut stream, MAX_HEADER_BYTES, "header")?)?;
+
+        let mut data = Vec::new();
+        stream.take(MAX_PAYLOAD_BYTES + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_PAYLOAD_BYTES {
+            return Err(SnapMagError::InvalidArgument("peer image is too large".to_string()).into());
+        }
+
+        crate::image_manager::validate_image(&data)?;
+        let hash = crate::image_manager::content_hash(&data);
+        let metadata = {
+            let mut manager = image_manager.lock().unwrap();
+            let saved = manager.save_image_with_hash(&data, hash, CaptureSource::Import)?;
+            let mut metadata = saved.metadata;
+            if saved.is_duplicate {
+                log::info!("Received image {} is already in the library", metadata.id);
+                return Ok(());
+            }
+            for tag in &header.metadata.tags {
+                metadata = manager.add_tag(&metadata.id, tag)?;
+            }
+            if header.metadata.notes.is_some() {
+                manager.set_image_notes(&metadata.id, header.metadata.notes.clone())?;
+            }
+            metadata
+        };
+        log::info!("Received image {} from peer", metadata.id);
+        if let Err(e) = app.emit("images-added", ImagesAddedEvent { items: vec![crate::to_list_item(metadata)] }) {
+            log::error!("Failed to emit images-added event: {}", e);
+        }
+        Ok(())
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(data)
+}
+
+/// Reads a frame of at most `max_bytes`, refusing larger ones before
+/// reading them; `what` names the frame in the error.
+fn read_frame(stream: &mut TcpStream, max_bytes: u32, what: &str) -> anyhow::Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length);
+    if length > max_bytes {
+        return Err(SnapMagError::InvalidArgument(format!("peer {} of {} bytes is too large", what, length)).into());
+    }
+    let mut data = vec![0u8; length as usize];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,390 @@
+use std::sync::Mutex;
+use tauri::{AppHandle, LogicalSize, Manager, WebviewWindow};
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// Logical size of the main window when it was last hidden. Re-applied on
+/// restore so a window hidden on one monitor comes back at the same logical
+/// size on a monitor with a different scale factor.
+static SAVED_SIZE: Mutex<Option<LogicalSize<f64>>> = Mutex::new(None);
+
+/// Brings `window` back from hidden or minimized and gives it focus.
+pub fn restore_and_focus(window: &WebviewWindow) {
+    #[cfg(target_os = "windows")]
+    let restored = match window.hwnd() {
+        Ok(hwnd) => {
+            restore_hwnd(windows::Win32::Foundation::HWND(hwnd.0));
+            true
+        }
+        Err(_) => false,
+    };
+    #[cfg(not(target_os = "windows"))]
+    let restored = false;
+
+    // An accessory app has to be activated before its window can take focus.
+    #[cfg(target_os = "macos")]
+    if let Err(e) = window.app_handle().show() {
+        log::warn!("Failed to activate the app: {}", e);
+    }
+
+    if !restored {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+
+    if let Some(size) = *SAVED_SIZE.lock().unwrap() {
+        if let Err(e) = window.set_size(size) {
+            log::warn!("Failed to restore window size: {}", e);
+        }
+    }
+}
+
+/// Hides `window` instead of closing it, remembering its logical size.
+pub fn hide(window: &tauri::Window) {
+    let logical_size = window.scale_factor().and_then(|scale| {
+        window.inner_size().map(|size| size.to_logical::<f64>(scale))
+    });
+    if let Ok(size) = logical_size {
+        *SAVED_SIZE.lock().unwrap() = Some(size);
+    }
+
+    #[cfg(target_os = "windows")]
+    if let Ok(hwnd) = window.hwnd() {
+        use windows::Win32::UI::WindowsAndMessaging::{ShowWindow, SW_HIDE};
+        unsafe {
+            let _ = ShowWindow(windows::Win32::Foundation::HWND(hwnd.0), SW_HIDE);
+        }
+        return;
+    }
+    let _ = window.hide();
+}
+
+/// Restores and focuses the main window from any thread.
+fn show_main_window(app: &AppHandle) {
+    // The window must be touched on the main thread.
+    let handle = app.clone();
+    let _ = app.run_on_main_thread(move || {
+        if let Some(window) = handle.get_webview_window("main") {
+            restore_and_focus(&window);
+        }
+    });
+}
+
+/// Class of the hidden message-only window the first instance creates. A
+/// second instance finds it with `FindWindowExW(HWND_MESSAGE, ..)` and posts
+/// `WM_SHOWMAIN`, so activation doesn't depend on the window title.
+#[cfg(target_os = "windows")]
+pub const IPC_WINDOW_CLASS: &str = "SnapMag_IPC_Window";
+/// Posted to the IPC window to bring the main window back.
+#[cfg(target_os = "windows")]
+pub const WM_SHOWMAIN: u32 = windows::Win32::UI::WindowsAndMessaging::WM_APP + 1;
+
+#[cfg(target_os = "windows")]
+static IPC_APP: Mutex<Option<AppHandle>> = Mutex::new(None);
+
+/// Creates the IPC window on its own thread, which runs its message loop.
+#[cfg(target_os = "windows")]
+pub fn spawn_ipc_window(app: AppHandle) {
+    *IPC_APP.lock().unwrap() = Some(app);
+    std::thread::spawn(|| unsafe {
+        use windows::core::PCWSTR;
+        use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+        use windows::Win32::UI::WindowsAndMessaging::{
+            CreateWindowExW, DispatchMessageW, GetMessageW, RegisterClassW, HWND_MESSAGE, MSG, WINDOW_EX_STYLE, WINDOW_STYLE, WNDCLASSW,
+        };
+        
+        let class_name: Vec<u16> = IPC_WINDOW_CLASS.encode_utf16().chain(std::iter::once(0)).collect();
+        let instance = GetModuleHandleW(None).ok().map(Into::into);
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(ipc_window_proc),
+            hInstance: instance.unwrap_or_default(),
+            lpszClassName: PCWSTR(class_name.as_ptr()),
+            ..Default::default()
+        };
+        if RegisterClassW(&class) == 0 {
+            log::error!("Failed to register the IPC window class");
+            return;
+        }
+        let created = CreateWindowExW(
+            WINDOW_EX_STYLE(0),
+            PCWSTR(class_name.as_ptr()),
+            PCWSTR::null(),
+            WINDOW_STYLE(0),
+            0,
+            0,
+            0,
+            0,
+            Some(HWND_MESSAGE),
+            None,
+            instance,
+            None,
+        );
+        if let Err(e) = created {
+            log::error!("Failed to create the IPC window: {}", e);
+            return;
+        }
+        
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, None, 0, 0).as_bool() {
+            DispatchMessageW(&msg);
+        }
+    });
+}
+
+#[cfg(target_os = "windows")]
+unsafe extern "system" fn ipc_window_proc(
+    hwnd: windows::Win32::Foundation::HWND,
+    msg: u32,
+    wparam: windows::Win32::Foundation::WPARAM,
+    lparam: windows::Win32::Foundation::LPARAM,
+) -> windows::Win32::Foundation::LRESULT {
+    if msg == WM_SHOWMAIN {
+        if let Some(app) = IPC_APP.lock().unwrap().as_ref() {
+            show_main_window(app);
+        }
+        return windows::Win32::Foundation::LRESULT(0);
+    }
+    windows::Win32::UI::WindowsAndMessaging::DefWindowProcW(hwnd, msg, wparam, lparam)
+}
+
+/// Restores and focuses a top-level window by handle.
+#[cfg(target_os = "windows")]
+fn restore_hwnd(hwnd: windows::Win32::Foundation::HWND) {
+    use windows::Win32::UI::Input::KeyboardAndMouse::SetFocus;
+    use windows::Win32::UI::WindowsAndMessaging::{SetForegroundWindow, ShowWindow, SW_RESTORE};
+
+    // 使用 SW_RESTORE 确保窗口从最小化恢复
+    unsafe {
+        let _ = ShowWindow(hwnd, SW_RESTORE);
+        let _ = SetForegroundWindow(hwnd);
+        let _ = SetFocus(Some(hwnd));
+    }
+}
+
+/// Whether a process with `pid` is still running.
+#[cfg(target_os = "windows")]
+pub fn process_alive(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+    use windows::Win32::System::Threading::{GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+
+    unsafe {
+        let Ok(process) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid) else {
+            return false;
+        };
+        let mut exit_code = 0u32;
+        let result = GetExitCodeProcess(process, &mut exit_code);
+        let _ = CloseHandle(process);
+        result.is_ok() && exit_code == STILL_ACTIVE.0 as u32
+    }
+}
+
+/// Whether a process with `pid` is still running.
+#[cfg(unix)]
+pub fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the target; EPERM means it exists but isn't ours.
+    unsafe { libc::kill(pid, 0) == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM) }
+}
+
+/// When process `pid` started, in Unix seconds, so a reused PID can be told
+/// apart from the process that had it before.
+#[cfg(target_os = "windows")]
+pub fn process_started_at(pid: u32) -> Option<i64> {
+    use windows::Win32::Foundation::{CloseHandle, FILETIME};
+    use windows::Win32::System::Threading::{GetProcessTimes, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION};
+    /// Seconds from 1601, where `FILETIME` counts from, to 1970.
+    const FILETIME_UNIX_OFFSET_SECS: i64 = 11_644_473_600;
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let (mut created, mut exited, mut kernel, mut user) = (FILETIME::default(), FILETIME::default(), FILETIME::default(), FILETIME::default());
+        let result = GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user);
+        let _ = CloseHandle(process);
+        result.ok()?;
+        let intervals = (created.dwHighDateTime as u64) << 32 | created.dwLowDateTime as u64;
+        Some((intervals / 10_000_000) as i64 - FILETIME_UNIX_OFFSET_SECS)
+    }
+}
+
+/// When process `pid` started, in Unix seconds, from its `/proc` entry.
+#[cfg(target_os = "linux")]
+pub fn process_started_at(pid: u32) -> Option<i64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name before it may contain spaces, so count fields from
+    // its closing parenthesis: `starttime`, in clock ticks after boot, is
+    // the 20th after it.
+    let start_ticks: i64 = stat.rsplit_once(')')?.1.split_whitespace().nth(19)?.parse().ok()?;
+    let boot_time: i64 = std::fs::read_to_string("/proc/stat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    (ticks_per_sec > 0).then(|| boot_time + start_ticks / ticks_per_sec as i64)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+pub fn process_started_at(_pid: u32) -> Option<i64> {
+    None
+}
+
+/// Whether `path` is on a network drive: a mapped or UNC share.
+#[cfg(target_os = "windows")]
+pub fn is_network_drive(path: &std::path::Path) -> bool {
+    use std::path::{Component, Prefix};
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::GetDriveTypeW;
+    /// `GetDriveTypeW` result for a remote drive.
+    const DRIVE_REMOTE: u32 = 4;
+
+    let root = match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::UNC(..) | Prefix::VerbatimUNC(..) => return true,
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => format!("{}:\\", letter as char),
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let root: Vec<u16> = root.encode_utf16().chain(std::iter::once(0)).collect();
+    unsafe { GetDriveTypeW(PCWSTR(root.as_ptr())) == DRIVE_REMOTE }
+}
+
+/// Whether `path` is on a network file system (NFS, SMB or CIFS).
+#[cfg(target_os = "linux")]
+pub fn is_network_drive(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    const NFS_SUPER_MAGIC: u32 = 0x6969;
+    const SMB_SUPER_MAGIC: u32 = 0x517b;
+    const CIFS_MAGIC_NUMBER: u32 = 0xff53_4d42;
+    const SMB2_MAGIC_NUMBER: u32 = 0xfe53_4d42;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    matches!(stat.f_type as u32, NFS_SUPER_MAGIC | SMB_SUPER_MAGIC | CIFS_MAGIC_NUMBER | SMB2_MAGIC_NUMBER)
+}
+
+/// Whether `path` is on a file system that isn't mounted locally.
+#[cfg(target_os = "macos")]
+pub fn is_network_drive(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    stat.f_flags & libc::MNT_LOCAL as u32 == 0
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+pub fn is_network_drive(_path: &std::path::Path) -> bool {
+    false
+}
+
+/// Whether the taskbar and tray use the light theme, from the
+/// `SystemUsesLightTheme` registry value. `None` where it can't be read.
+#[cfg(target_os = "windows")]
+pub fn taskbar_uses_light_theme() -> Option<bool> {
+    use windows::core::w;
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+    let mut value = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            w!(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize"),
+            w!("SystemUsesLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut _),
+            Some(&mut size),
+        )
+    };
+    result.is_ok().then_some(value != 0)
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn taskbar_uses_light_theme() -> Option<bool> {
+    None
+}
+
+/// Socket claimed by the first instance, waiting for `serve_instance_requests`.
+#[cfg(unix)]
+static INSTANCE_LISTENER: Mutex<Option<UnixListener>> = Mutex::new(None);
+
+/// Sent by a second instance over the socket to have the window shown. A
+/// connection without it, like `instance_is_running`'s, only probes.
+#[cfg(unix)]
+const SHOW_REQUEST: &[u8] = b"show";
+
+#[cfg(unix)]
+fn instance_socket_path() -> std::path::PathBuf {
+    let user = std::env::var("USER").unwrap_or_default();
+    std::env::temp_dir().join(format!("snapmag-{}.sock", user))
+}
+
+/// The non-Windows counterpart of the single-instance mutex: binds a per-user
+/// Unix socket. Returns `false` when another instance owns it, after asking
+/// that instance to show its window.
+#[cfg(unix)]
+pub fn claim_single_instance() -> bool {
+    let path = instance_socket_path();
+    if let Ok(mut stream) = UnixStream::connect(&path) {
+        println!("Another instance detected, activating existing window...");
+        let _ = stream.write_all(SHOW_REQUEST);
+        return false;
+    }
+    // Nobody is listening, so the socket is left over from a crash.
+    let _ = std::fs::remove_file(&path);
+    match UnixListener::bind(&path) {
+        Ok(listener) => *INSTANCE_LISTENER.lock().unwrap() = Some(listener),
+        Err(e) => println!("Failed to bind single-instance socket, error: {}", e),
+    }
+    true
+}
+
+/// Whether another instance holds the socket from `claim_single_instance`,
+/// without asking it to show its window.
+#[cfg(unix)]
+pub fn instance_is_running() -> bool {
+    UnixStream::connect(instance_socket_path()).is_ok()
+}
+
+/// Shows the main window whenever a second instance sends `SHOW_REQUEST`
+/// over the socket from `claim_single_instance`.
+#[cfg(unix)]
+pub fn serve_instance_requests(app: AppHandle) {
+    let Some(listener) = INSTANCE_LISTENER.lock().unwrap().take() else {
+        return;
+    };
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(1)));
+            let mut request = [0u8; SHOW_REQUEST.len()];
+            if stream.read_exact(&mut request).is_ok() && request == SHOW_REQUEST {
+                show_main_window(&app);
+            }
+        }
+    });
+}
+
+/// Removes the socket on exit so the next launch doesn't have to probe it.
+#[cfg(unix)]
+pub fn release_single_instance() {
+    let _ = std::fs::remove_file(instance_socket_path());
+}
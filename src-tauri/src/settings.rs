@@ -0,0 +1,328 @@
+//! Carrying the listener and library configuration between machines.
+//!
+//! A settings file is read field by field: unknown fields are ignored, a
+//! field that doesn't parse keeps its default, and out-of-range values are
+//! clamped. Each such fix is returned as a warning instead of failing the
+//! whole import, and nothing is applied before the user has seen the
+//! resulting `diff`. The peer transfer token and import server are not
+//! settings, so an import can never open a listening port.
+
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::clipboard::{self, ClipboardListenerConfig};
+use crate::error::SnapMagError;
+use crate::image_manager::ImageManagerConfig;
+use crate::types::{RetentionPreset, SettingChange, TrayIconStyle};
+
+/// Bumped when a field changes meaning; files with a newer version are refused.
+pub const SETTINGS_SCHEMA_VERSION: u32 = 1;
+/// Largest `max_dropped_folder_files` an import accepts.
+const MAX_DROPPED_FOLDER_FILES: usize = 10_000;
+/// The tray icon style, kept in the storage directory between runs.
+const TRAY_ICON_STYLE_FILE: &str = "tray_icon_style.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub schema_version: u32,
+    pub clipboard: ClipboardListenerConfig,
+    pub library: ImageManagerConfig,
+    pub interface: InterfaceSettings,
+}
+
+/// Settings kept outside the listener and the library.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterfaceSettings {
+    /// Count new captures on the tray icon.
+    pub show_badge: bool,
+    pub tray_icon_style: TrayIconStyle,
+    /// Hours text snippets are kept; `None` keeps them.
+    pub snippet_retention_hours: Option<i64>,
+}
+
+impl Default for InterfaceSettings {
+    fn default() -> Self {
+        Self { show_badge: true, tray_icon_style: TrayIconStyle::default(), snippet_retention_hours: None }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new(ClipboardListenerConfig::default(), ImageManagerConfig::default(), InterfaceSettings::default())
+    }
+}
+
+impl Settings {
+    pub fn new(clipboard: ClipboardListenerConfig, library: ImageManagerConfig, interface: InterfaceSettings) -> Self {
+        Self { schema_version: SETTINGS_SCHEMA_VERSION, clipboard, library, interface }
+    }
+
+    pub fn export(&self, dest: &Path) -> anyhow::Result<()> {
+        fs::write(dest, serde_json::to_vec_pretty(self)?)?;
+        log::info!("Exported settings to {}", dest.display());
+        Ok(())
+    }
+
+    /// Reads a file written by `export`, returning the settings and a
+    /// warning for every field that was ignored, reset or clamped.
+    pub fn import(src: &Path) -> anyhow::Result<(Self, Vec<String>)> {
+        let value: Value = serde_json::from_slice(&fs::read(src)?)?;
+        let Value::Object(file) = value else {
+            return Err(SnapMagError::InvalidArgument("settings file is not a JSON object".to_string()).into());
+        };
+        let version = file.get("schema_version").and_then(Value::as_u64).ok_or_else(|| {
+            SnapMagError::InvalidArgument("settings file has no schema_version".to_string())
+        })?;
+        if version > SETTINGS_SCHEMA_VERSION as u64 {
+            return Err(SnapMagError::InvalidArgument(format!(
+                "settings schema {} is newer than the supported {}",
+                version, SETTINGS_SCHEMA_VERSION
+            ))
+            .into());
+        }
+
+        let mut warnings = Vec::new();
+        let clipboard = read_section(&file, "clipboard", &mut warnings)?;
+        let library = read_section(&file, "library", &mut warnings)?;
+        let interface = read_section(&file, "interface", &mut warnings)?;
+        let mut settings = Self::new(clipboard, library, interface);
+        settings.clamp(&mut warnings);
+        for warning in &warnings {
+            log::warn!("Settings import: {}", warning);
+        }
+        Ok((settings, warnings))
+    }
+
+    /// What applying `imported` over these settings changes, one entry per
+    /// differing field, for the user to confirm before it is applied.
+    pub fn diff(&self, imported: &Settings) -> anyhow::Result<Vec<SettingChange>> {
+        let mut changes = Vec::new();
+        diff_values("", &serde_json::to_value(self)?, &serde_json::to_value(imported)?, &mut changes);
+        Ok(changes)
+    }
+
+    /// Keeps `current`'s confirmation threshold when the file has none, so
+    /// an import can't switch off the confirmation of bulk deletes.
+    pub fn keep_confirm_threshold(&mut self, current: Option<usize>, warnings: &mut Vec<String>) {
+        if self.library.cleanup_confirm_threshold.is_none() && current.is_some() {
+            warnings.push("library.cleanup_confirm_threshold can't be cleared by an import, kept the current one".to_string());
+            self.library.cleanup_confirm_threshold = current;
+        }
+    }
+
+    /// Brings values the setters would reject back into range.
+    fn clamp(&mut self, warnings: &mut Vec<String>) {
+        let clipboard = &mut self.clipboard;
+        let library = &mut self.library;
+        let defaults = ClipboardListenerConfig::default();
+
+        if clipboard::validate_extractor_chain(&clipboard.extractor_chain).is_err() {
+            warnings.push("clipboard.extractor_chain is invalid, reset to the default".to_string());
+            clipboard.extractor_chain = defaults.extractor_chain;
+        }
+        let max_files = clipboard.max_dropped_folder_files.clamp(1, MAX_DROPPED_FOLDER_FILES);
+        if max_files != clipboard.max_dropped_folder_files {
+            warnings.push(format!("clipboard.max_dropped_folder_files clamped to {}", max_files));
+            clipboard.max_dropped_folder_files = max_files;
+        }
+        if clipboard.max_decode_pixels == 0 || clipboard.max_clipboard_bytes == 0 {
+            warnings.push("clipboard capture size limits of 0 reset to the defaults".to_string());
+            if clipboard.max_decode_pixels == 0 {
+                clipboard.max_decode_pixels = defaults.max_decode_pixels;
+            }
+            if clipboard.max_clipboard_bytes == 0 {
+                clipboard.max_clipboard_bytes = defaults.max_clipboard_bytes;
+            }
+        }
+        if clipboard.min_snippet_chars == 0 {
+            warnings.push("clipboard.min_snippet_chars clamped to 1".to_string());
+            clipboard.min_snippet_chars = 1;
+        }
+
+        if library.max_ipc_read_bytes == 0 {
+            warnings.push("library.max_ipc_read_bytes reset to the default".to_string());
+            library.max_ipc_read_bytes = ImageManagerConfig::default().max_ipc_read_bytes;
+        }
+        if library.png_compression_level > 9 {
+            warnings.push("library.png_compression_level clamped to 9".to_string());
+            library.png_compression_level = 9;
+        }
+        if library.ephemeral_minutes == Some(0) {
+            warnings.push("library.ephemeral_minutes clamped to 1".to_string());
+            library.ephemeral_minutes = Some(1);
+        }
+        if library.max_library_bytes == Some(0) || library.max_library_count == Some(0) {
+            warnings.push("library limits of 0 mean no limit".to_string());
+            library.max_library_bytes = library.max_library_bytes.filter(|&bytes| bytes > 0);
+            library.max_library_count = library.max_library_count.filter(|&count| count > 0);
+        }
+        if let RetentionPreset::Custom(hours) = library.retention_preset {
+            if hours <= 0 {
+                warnings.push("library.retention_preset hours clamped to 1".to_string());
+                library.retention_preset = RetentionPreset::Custom(1);
+            }
+        }
+        library.tag_retention_policies.retain(|tag, hours| {
+            let valid = !tag.trim().is_empty() && *hours >= 0;
+            if !valid {
+                warnings.push(format!("library.tag_retention_policies entry for {:?} ignored", tag));
+            }
+            valid
+        });
+
+        let interface = &mut self.interface;
+        if interface.snippet_retention_hours.is_some_and(|hours| hours <= 0) {
+            warnings.push("interface.snippet_retention_hours must be positive, snippets are kept".to_string());
+            interface.snippet_retention_hours = None;
+        }
+    }
+}
+
+/// The style saved by `persist_tray_icon_style` in `dir`; `Auto` when there
+/// is none or it doesn't parse.
+pub fn load_tray_icon_style(dir: &Path) -> TrayIconStyle {
+    fs::read(dir.join(TRAY_ICON_STYLE_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+pub fn persist_tray_icon_style(dir: &Path, style: TrayIconStyle) -> anyhow::Result<()> {
+    fs::write(dir.join(TRAY_ICON_STYLE_FILE), serde_json::to_vec(&style)?)?;
+    Ok(())
+}
+
+/// Appends a change for every leaf under `path` that differs between
+/// `current` and `imported`.
+fn diff_values(path: &str, current: &Value, imported: &Value, changes: &mut Vec<SettingChange>) {
+    if let (Value::Object(current), Value::Object(imported)) = (current, imported) {
+        let mut keys: Vec<&String> = current.keys().chain(imported.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let field = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+            diff_values(&field, current.get(key).unwrap_or(&Value::Null), imported.get(key).unwrap_or(&Value::Null), changes);
+        }
+    } else if current != imported {
+        changes.push(SettingChange { field: path.to_string(), current: current.clone(), imported: imported.clone() });
+    }
+}
+
+/// Deserializes `file[name]` one field at a time on top of the defaults, so
+/// a bad field only loses that field.
+fn read_section<T>(file: &Map<String, Value>, name: &str, warnings: &mut Vec<String>) -> anyhow::Result<T>
+where
+    T: Default + Serialize + for<'de> Deserialize<'de>,
+{
+    let Value::Object(mut merged) = serde_json::to_value(T::default())? else {
+        unreachable!("settings sections serialize as objects");
+    };
+    let Some(section) = file.get(name) else {
+        warnings.push(format!("{} settings missing, using defaults", name));
+        return Ok(serde_json::from_value(Value::Object(merged))?);
+    };
+    let Value::Object(section) = section else {
+        warnings.push(format!("{} settings are not an object, using defaults", name));
+        return Ok(serde_json::from_value(Value::Object(merged))?);
+    };
+
+    for (key, value) in section {
+        let Some(default) = merged.get(key).cloned() else {
+            continue;
+        };
+        merged.insert(key.clone(), value.clone());
+        if serde_json::from_value::<T>(Value::Object(merged.clone())).is_err() {
+            warnings.push(format!("{}.{} has an invalid value, kept the default", name, key));
+            merged.insert(key.clone(), default);
+        }
+    }
+    Ok(serde_json::from_value(Value::Object(merged))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn export_then_import_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("settings.json");
+        let mut settings = Settings::default();
+        settings.clipboard.min_snippet_chars = 12;
+        settings.library.png_compression_level = 1;
+        settings.library.retention_preset = RetentionPreset::Custom(36);
+        settings.library.tag_retention_policies.insert("keep".to_string(), 24 * 365);
+        settings.clipboard.notify_on_non_image = true;
+        settings.interface.show_badge = false;
+        settings.interface.tray_icon_style = TrayIconStyle::Mono;
+        settings.interface.snippet_retention_hours = Some(72);
+
+        settings.export(&path).unwrap();
+        let (imported, warnings) = Settings::import(&path).unwrap();
+        assert!(warnings.is_empty(), "unexpected warnings: {:?}", warnings);
+        assert_eq!(serde_json::to_value(&imported).unwrap(), serde_json::to_value(&settings).unwrap());
+    }
+
+    #[test]
+    fn bad_fields_keep_defaults_and_warn() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("settings.json");
+        fs::write(&path, r#"{
+            "schema_version": 1,
+            "clipboard": { "min_snippet_chars": "many", "unknown": true },
+            "library": { "png_compression_level": 42 },
+            "interface": { "snippet_retention_hours": -1 }
+        }"#).unwrap();
+
+        let (imported, warnings) = Settings::import(&path).unwrap();
+        assert_eq!(imported.clipboard.min_snippet_chars, ClipboardListenerConfig::default().min_snippet_chars);
+        assert_eq!(imported.library.png_compression_level, 9);
+        assert_eq!(imported.interface.snippet_retention_hours, None);
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn diff_lists_changed_fields() {
+        let current = Settings::default();
+        let mut imported = Settings::default();
+        imported.library.png_compression_level = 3;
+        imported.interface.tray_icon_style = TrayIconStyle::Dark;
+
+        let changes = current.diff(&imported).unwrap();
+        let fields: Vec<&str> = changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(fields, ["interface.tray_icon_style", "library.png_compression_level"]);
+        assert_eq!(changes[1].imported, serde_json::json!(3));
+    }
+
+    #[test]
+    fn import_keeps_confirm_threshold() {
+        let mut imported = Settings::default();
+        imported.library.cleanup_confirm_threshold = None;
+        let mut warnings = Vec::new();
+        imported.keep_confirm_threshold(Some(20), &mut warnings);
+        assert_eq!(imported.library.cleanup_confirm_threshold, Some(20));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("settings.json");
+        fs::write(&path, format!(r#"{{ "schema_version": {} }}"#, SETTINGS_SCHEMA_VERSION + 1)).unwrap();
+        assert!(Settings::import(&path).is_err());
+    }
+
+    #[test]
+    fn tray_icon_style_survives_a_restart() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(load_tray_icon_style(dir.path()), TrayIconStyle::Auto);
+
+        persist_tray_icon_style(dir.path(), TrayIconStyle::Mono).unwrap();
+        assert_eq!(load_tray_icon_style(dir.path()), TrayIconStyle::Mono);
+
+        fs::write(dir.path().join(TRAY_ICON_STYLE_FILE), "\"sparkly\"").unwrap();
+        assert_eq!(load_tray_icon_style(dir.path()), TrayIconStyle::Auto);
+    }
+}
@@ -0,0 +1,121 @@
+//! Slideshows timed by the backend, so they keep their pace while the
+//! webview is busy. Each one is a task emitting `slideshow-next` until it is
+//! stopped or, without looping, has shown every image.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use crate::error::SnapMagError;
+use crate::types::SlideshowNextEvent;
+
+/// Shortest interval `start` accepts.
+pub const MIN_INTERVAL_MS: u64 = 100;
+
+/// Running slideshows by id. Each holds the sender of its paused flag;
+/// dropping it stops the task.
+#[derive(Default)]
+pub struct Slideshows {
+    next_id: AtomicU64,
+    running: Arc<Mutex<HashMap<String, watch::Sender<bool>>>>,
+}
+
+impl Slideshows {
+    /// Starts showing `ids` one every `interval_ms` and returns the
+    /// slideshow id. The first image is shown right away.
+    pub fn start(&self, app: AppHandle, ids: Vec<String>, interval_ms: u64, loop_: bool) -> Result<String, SnapMagError> {
+        if ids.is_empty() {
+            return Err(SnapMagError::InvalidArgument("a slideshow needs at least one image".to_string()));
+        }
+        if interval_ms < MIN_INTERVAL_MS {
+            return Err(SnapMagError::InvalidArgument(format!("interval must be at least {} ms, got {}", MIN_INTERVAL_MS, interval_ms)));
+        }
+
+        let slideshow_id = format!("slideshow-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let (sender, paused) = watch::channel(false);
+        self.running.lock().unwrap().insert(slideshow_id.clone(), sender);
+
+        log::info!("Starting {} of {} image(s) every {} ms", slideshow_id, ids.len(), interval_ms);
+        let running = self.running.clone();
+        let id = slideshow_id.clone();
+        tauri::async_runtime::spawn(async move {
+            run(&app, &id, &ids, Duration::from_millis(interval_ms), loop_, paused).await;
+            running.lock().unwrap().remove(&id);
+            log::debug!("Slideshow {} ended", id);
+        });
+        Ok(slideshow_id)
+    }
+
+    pub fn pause(&self, id: &str) -> Result<(), SnapMagError> {
+        self.set_paused(id, true)
+    }
+
+    /// Continues a paused slideshow with the next image, shown right away.
+    pub fn resume(&self, id: &str) -> Result<(), SnapMagError> {
+        self.set_paused(id, false)
+    }
+
+    pub fn stop(&self, id: &str) -> Result<(), SnapMagError> {
+        self.running
+            .lock()
+            .unwrap()
+            .remove(id)
+            .map(drop)
+            .ok_or_else(|| SnapMagError::NotFound(format!("slideshow {}", id)))
+    }
+
+    fn set_paused(&self, id: &str, paused: bool) -> Result<(), SnapMagError> {
+        let running = self.running.lock().unwrap();
+        let sender = running.get(id).ok_or_else(|| SnapMagError::NotFound(format!("slideshow {}", id)))?;
+        sender.send_if_modified(|current| std::mem::replace(current, paused) != paused);
+        Ok(())
+    }
+}
+
+/// Emits `ids` in order until the sender of `paused` is dropped, waiting
+/// while it is set.
+async fn run(app: &AppHandle, slideshow_id: &str, ids: &[String], interval: Duration, loop_: bool, mut paused: watch::Receiver<bool>) {
+    let mut index = 0;
+    loop {
+        while *paused.borrow_and_update() {
+            if paused.changed().await.is_err() {
+                return;
+            }
+        }
+        let event = SlideshowNextEvent {
+            slideshow_id: slideshow_id.to_string(),
+            image_id: ids[index].clone(),
+            index,
+            total: ids.len(),
+        };
+        if let Err(e) = app.emit("slideshow-next", event) {
+            log::error!("Failed to emit slideshow-next event: {}", e);
+        }
+        index += 1;
+        if index == ids.len() {
+            if !loop_ {
+                return;
+            }
+            index = 0;
+        }
+
+        // A pause cuts the wait short; the next image follows the resume.
+        let deadline = Instant::now() + interval;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => break,
+                changed = paused.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    if *paused.borrow_and_update() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
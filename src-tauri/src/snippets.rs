@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::error::SnapMagError;
+use crate::types::TextSnippet;
+
+const SNIPPETS_FILE: &str = "snippets.json";
+/// Most snippets kept; the oldest are dropped beyond this.
+const MAX_SNIPPETS: usize = 1000;
+/// Longer text is not a snippet worth keeping (a copied document or log).
+#[cfg(target_os = "windows")]
+const MAX_SNIPPET_CHARS: usize = 20_000;
+
+/// Contents of `snippets.json`. Files written before the retention was kept
+/// hold only the snippets array.
+#[derive(Serialize, Deserialize)]
+struct SnippetsFile {
+    #[serde(default)]
+    retention_hours: Option<i64>,
+    snippets: Vec<TextSnippet>,
+}
+
+/// Text blocks captured from the clipboard while `capture_text` is on, kept
+/// in `snippets/snippets.json` apart from the image library.
+pub struct SnippetStore {
+    dir: PathBuf,
+    /// Oldest first.
+    snippets: Vec<TextSnippet>,
+    /// Snippets older than this many hours are dropped; `None` keeps them.
+    retention_hours: Option<i64>,
+}
+
+impl SnippetStore {
+    /// Opens the store in `dir`, reloading snippets and their retention from
+    /// a previous session.
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let file = fs::read(dir.join(SNIPPETS_FILE)).ok().and_then(|data| {
+            serde_json::from_slice::<SnippetsFile>(&data)
+                .or_else(|_| serde_json::from_slice(&data).map(|snippets| SnippetsFile { retention_hours: None, snippets }))
+                .ok()
+        });
+        let (snippets, retention_hours) = file.map_or((Vec::new(), None), |file| (file.snippets, file.retention_hours));
+        Ok(Self { dir, snippets, retention_hours })
+    }
+
+    /// Stores `text` unless the same text is already kept or it is shorter
+    /// than `min_chars` or longer than `MAX_SNIPPET_CHARS`. Returns the new
+    /// snippet.
+    #[cfg(target_os = "windows")]
+    pub fn add(&mut self, text: &str, source_app: Option<String>, min_chars: usize, now: i64) -> anyhow::Result<Option<TextSnippet>> {
+        let text = text.trim();
+        let len = text.chars().count();
+        if len < min_chars || len > MAX_SNIPPET_CHARS {
+            return Ok(None);
+        }
+        let id = crate::image_manager::content_hash(text.as_bytes());
+        if self.snippets.iter().any(|snippet| snippet.id == id) {
+            return Ok(None);
+        }
+
+        let snippet = TextSnippet { id, text: text.to_string(), created_at: now, source_app };
+        self.snippets.push(snippet.clone());
+        self.prune(now);
+        self.persist()?;
+        Ok(Some(snippet))
+    }
+
+    /// Snippets newest first.
+    pub fn list(&self) -> Vec<TextSnippet> {
+        self.snippets.iter().rev().cloned().collect()
+    }
+
+    /// Case-insensitive substring search, newest first.
+    pub fn search(&self, query: &str) -> Vec<TextSnippet> {
+        let query = query.to_lowercase();
+        self.snippets
+            .iter()
+            .rev()
+            .filter(|snippet| snippet.text.to_lowercase().contains(&query))
+            .cloned()
+            .collect()
+    }
+
+    pub fn delete(&mut self, id: &str) -> anyhow::Result<()> {
+        let index = self.snippets
+            .iter()
+            .position(|snippet| snippet.id == id)
+            .ok_or_else(|| SnapMagError::NotFound(id.to_string()))?;
+        self.snippets.remove(index);
+        self.persist()
+    }
+
+    pub fn retention_hours(&self) -> Option<i64> {
+        self.retention_hours
+    }
+
+    /// Sets how long snippets are kept and drops the ones already past it.
+    pub fn set_retention_hours(&mut self, hours: Option<i64>, now: i64) -> anyhow::Result<()> {
+        if hours.is_some_and(|hours| hours <= 0) {
+            return Err(SnapMagError::InvalidArgument("retention hours must be positive".to_string()).into());
+        }
+        self.retention_hours = hours;
+        self.prune(now);
+        self.persist()
+    }
+
+    /// Drops snippets past the retention, for the periodic sweep. Returns
+    /// how many were dropped.
+    pub fn prune_expired(&mut self, now: i64) -> anyhow::Result<usize> {
+        let before = self.snippets.len();
+        self.prune(now);
+        let dropped = before - self.snippets.len();
+        if dropped > 0 {
+            self.persist()?;
+        }
+        Ok(dropped)
+    }
+
+    fn prune(&mut self, now: i64) {
+        if let Some(hours) = self.retention_hours {
+            let cutoff = now - hours.saturating_mul(3600);
+            self.snippets.retain(|snippet| snippet.created_at >= cutoff);
+        }
+        let excess = self.snippets.len().saturating_sub(MAX_SNIPPETS);
+        self.snippets.drain(..excess);
+    }
+
+    fn persist(&self) -> anyhow::Result<()> {
+        let file = SnippetsFile { retention_hours: self.retention_hours, snippets: self.snippets.clone() };
+        fs::write(self.dir.join(SNIPPETS_FILE), serde_json::to_vec(&file)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn snippet(id: &str, created_at: i64) -> TextSnippet {
+        TextSnippet { id: id.to_string(), text: format!("text {}", id), created_at, source_app: None }
+    }
+
+    #[test]
+    fn retention_is_kept_across_sessions() {
+        let dir = TempDir::new().unwrap();
+        let mut store = SnippetStore::open(dir.path().to_path_buf()).unwrap();
+        store.set_retention_hours(Some(24), 0).unwrap();
+
+        let reopened = SnippetStore::open(dir.path().to_path_buf()).unwrap();
+        assert_eq!(reopened.retention_hours(), Some(24));
+    }
+
+    #[test]
+    fn snippets_saved_as_a_bare_array_load() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(SNIPPETS_FILE), serde_json::to_vec(&vec![snippet("a", 10)]).unwrap()).unwrap();
+
+        let store = SnippetStore::open(dir.path().to_path_buf()).unwrap();
+        assert_eq!(store.list().len(), 1);
+        assert_eq!(store.retention_hours(), None);
+    }
+
+    #[test]
+    fn sweep_drops_snippets_past_retention() {
+        let dir = TempDir::new().unwrap();
+        let file = SnippetsFile { retention_hours: Some(1), snippets: vec![snippet("old", 0), snippet("new", 7000)] };
+        fs::write(dir.path().join(SNIPPETS_FILE), serde_json::to_vec(&file).unwrap()).unwrap();
+
+        let mut store = SnippetStore::open(dir.path().to_path_buf()).unwrap();
+        assert_eq!(store.prune_expired(7200).unwrap(), 1);
+        assert_eq!(store.list()[0].id, "new");
+        assert_eq!(SnippetStore::open(dir.path().to_path_buf()).unwrap().list().len(), 1);
+    }
+}
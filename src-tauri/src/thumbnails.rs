@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use image::codecs::jpeg::JpegEncoder;
+
+/// JPEG qualities tried in order until a thumbnail fits the requested byte budget.
+const JPEG_QUALITIES: [u8; 3] = [80, 60, 40];
+
+/// On-disk cache of small JPEG previews stored as `thumbs/{id}_{px}.jpg`.
+#[derive(Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+}
+
+impl ThumbnailCache {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the cached thumbnail of `source` bounded to `px` on its longer
+    /// side, generating it on a miss. Generation steps down the JPEG quality
+    /// until the result is at most `max_bytes`; the smallest attempt is kept
+    /// even if it is still larger.
+    pub fn get_or_create(&self, id: &str, source: &Path, px: u32, max_bytes: usize) -> anyhow::Result<Vec<u8>> {
+        let cache_path = self.dir.join(format!("{}_{}.jpg", id, px));
+        if let Ok(data) = fs::read(&cache_path) {
+            return Ok(data);
+        }
+
+        let image = image::open(source)?;
+        let thumbnail = image.thumbnail(px, px).to_rgb8();
+
+        let mut data = Vec::new();
+        for quality in JPEG_QUALITIES {
+            data.clear();
+            JpegEncoder::new_with_quality(&mut data, quality).encode_image(&thumbnail)?;
+            if data.len() <= max_bytes {
+                break;
+            }
+        }
+
+        if let Err(e) = fs::write(&cache_path, &data) {
+            log::warn!("Failed to cache thumbnail {}: {}", cache_path.display(), e);
+        }
+        Ok(data)
+    }
+
+    /// Drops every cached size of one image.
+    pub fn remove(&self, id: &str) {
+        let prefix = format!("{}_", id);
+        for path in self.cached_files() {
+            let matches = path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|name| name.starts_with(&prefix));
+            if matches {
+                let _ = fs::remove_file(&path);
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        for path in self.cached_files() {
+            let _ = fs::remove_file(&path);
+        }
+    }
+
+    fn cached_files(&self) -> Vec<PathBuf> {
+        fs::read_dir(&self.dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).filter(|path| path.is_file()).collect())
+            .unwrap_or_default()
+    }
+}
@@ -1,14 +1,611 @@
+use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureSource {
+    /// Bitmap data placed on the clipboard by a screenshot tool.
+    #[default]
+    Screenshot,
+    /// An image file copied in a file manager (CF_HDROP).
+    File,
+    /// An image synced from a phone (Phone Link, KDE Connect), copied as a
+    /// temporary file the sync app deletes shortly after.
+    Phone,
+    /// Image bytes handed over by the frontend.
+    Import,
+    /// Produced by one of the image processing commands.
+    Derived,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageMetadata {
+    /// Stable ULID; unlike `content_hash` it survives edits to the image.
     pub id: String,
+    /// `content_hash` of the image, refreshed when its file is replaced.
+    #[serde(default)]
+    pub content_hash: String,
+    /// Hash id the entry had before ids became ULIDs. Commands taking an id
+    /// still accept it.
+    #[serde(default)]
+    pub legacy_id: Option<String>,
     pub path: String,
     pub created_at: i64,
+    /// `created_at` as UTC ISO 8601 (`YYYY-MM-DDTHH:MM:SSZ`).
+    #[serde(default)]
+    pub created_at_iso: String,
     pub ocr_result: Option<String>,
+    pub original_format: Option<String>,
+    /// Format of the stored file as sniffed from its bytes, e.g. `jpeg` for
+    /// a `.jfif` source.
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Whether the stored image has an alpha channel. Clipboard bitmaps only
+    /// get one when their alpha carries real transparency.
+    #[serde(default)]
+    pub has_alpha: bool,
+    /// Bits per channel of the stored image, 0 when unknown.
+    #[serde(default)]
+    pub bit_depth: u8,
+    #[serde(default)]
+    pub size_bytes: u64,
+    #[serde(default)]
+    pub source: CaptureSource,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// `data:image/jpeg;base64,...` preview, only filled in by `get_images_page`.
+    #[serde(default)]
+    pub inline_thumb: Option<String>,
+    /// Unix seconds after which an ephemeral capture is deleted.
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// BlurHash placeholder, filled in by `get_blurhash` or on capture.
+    #[serde(default)]
+    pub blurhash: Option<String>,
+    /// Clipboard DIB the image was converted from, kept with
+    /// `keep_raw_capture`.
+    #[serde(default)]
+    pub raw_path: Option<String>,
+    /// Title of the foreground window when the image was captured, kept with
+    /// `capture_window_title`.
+    #[serde(default)]
+    pub context_title: Option<String>,
+    /// Burst of captures the image belongs to, see `get_sessions`. `None` for
+    /// derived images and entries that predate sessions.
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+/// Summary of one capture session: captures taken at most
+/// `session_gap_minutes` after the previous one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSession {
+    pub session_id: String,
+    /// Unix seconds of the first and last capture.
+    pub start: i64,
+    pub end: i64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+/// Layout of `export_ocr_corpus`. TSV is one `id`, `created_at_iso`,
+/// `ocr_result` line per image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OcrCorpusFormat {
+    #[default]
+    Tsv,
+    Json,
+    Csv,
+}
+
+/// Which tray icon to show. `Light` is the light icon, for dark taskbars,
+/// and `Dark` the dark one; `Auto` follows the system theme.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrayIconStyle {
+    #[default]
+    Auto,
+    Light,
+    Dark,
+    Mono,
+}
+
+/// A setting an import would change, `field` being its dotted path in the
+/// settings file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingChange {
+    pub field: String,
+    pub current: serde_json::Value,
+    pub imported: serde_json::Value,
+}
+
+/// An imported settings file waiting for `confirm_settings_import`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsImportPreview {
+    pub token: String,
+    pub changes: Vec<SettingChange>,
+    /// Fields that were ignored, reset or clamped while reading the file.
+    pub warnings: Vec<String>,
+}
+
+/// One image in an OCR corpus export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OcrCorpusEntry {
+    pub id: String,
+    pub created_at_iso: String,
+    pub ocr_result: String,
+}
+
+/// How long images are kept before the background sweep deletes them.
+/// Serialized as `{ "kind": "custom", "hours": 48 }`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "hours")]
+pub enum RetentionPreset {
+    #[default]
+    #[serde(rename = "keep_forever")]
+    KeepForever,
+    #[serde(rename = "keep_7_days")]
+    Keep7Days,
+    #[serde(rename = "keep_24_hours")]
+    Keep24Hours,
+    #[serde(rename = "custom")]
+    Custom(i64),
+}
+
+impl RetentionPreset {
+    /// Age in hours past which images are removed, `None` to keep them.
+    pub fn hours(self) -> Option<i64> {
+        match self {
+            Self::KeepForever => None,
+            Self::Keep7Days => Some(7 * 24),
+            Self::Keep24Hours => Some(24),
+            Self::Custom(hours) => Some(hours),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClipboardEvent {
     pub image_path: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardStats {
+    /// Polling iterations of the listener loop.
+    pub iterations: u64,
+    pub images_captured: u64,
+    /// Clipboard images that were already in the library.
+    pub duplicates_skipped: u64,
+    /// Captures that failed to save.
+    pub errors: u64,
+    /// Library files read by the duplicate scans of new captures.
+    pub files_scanned: u64,
+    pub last_capture_at: Option<i64>,
+    pub uptime_seconds: u64,
+    /// A full-screen app is in the foreground and `quiet_when_fullscreen`
+    /// is on.
+    pub quiet: bool,
+}
+
+/// Payload of `quiet-mode-changed`.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuietModeEvent {
+    pub quiet: bool,
+    /// Whether captures are paused too, not just notifications.
+    pub capture_paused: bool,
+}
+
+/// Payload of `clipboard-non-image`: the clipboard changed but holds no
+/// image, so nothing was captured.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardNonImageEvent {
+    /// Clipboard format ids on offer, standard and registered.
+    pub formats: Vec<u32>,
+    pub has_text: bool,
+    pub has_files: bool,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardCaptureSkippedEvent {
+    /// Process name of the clipboard owner, or `unknown`.
+    pub app: String,
+}
+
+/// A clipboard format that was offered but couldn't be read, also the
+/// `clipboard-extraction-error` payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipboardError {
+    pub timestamp: i64,
+    /// Clipboard format id, e.g. 17 for CF_DIBV5.
+    pub format: u32,
+    pub error_message: String,
+}
+
+/// Copied text kept by the listener with `capture_text` on, also the
+/// `snippet-captured` payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextSnippet {
+    /// Content hash of the text.
+    pub id: String,
+    pub text: String,
+    pub created_at: i64,
+    /// Process name of the clipboard owner, when known.
+    pub source_app: Option<String>,
+}
+
+/// Payload of `background-error`, also returned by `get_recent_errors`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundErrorEvent {
+    /// Failing part of the app, e.g. `clipboard` or `scheduler`.
+    pub component: String,
+    pub message: String,
+    /// Stable identifier of the failure, e.g. `save_failed`.
+    pub code: String,
+    pub timestamp: i64,
+}
+
+/// A clipboard image that was not saved but can still be recovered.
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSkippedEvent {
+    /// Why it was skipped: `cooldown`, or `too-large` when the data is over
+    /// `max_clipboard_bytes` or `max_decode_pixels`.
+    pub reason: String,
+    /// Clipboard format name, e.g. `CF_DIBV5`; set for `too-large`.
+    pub format: Option<String>,
+    pub bytes: Option<u64>,
+    pub pixels: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRateComparison {
+    pub window_a_count: usize,
+    pub window_b_count: usize,
+    pub window_a_rate_per_hour: f64,
+    pub window_b_rate_per_hour: f64,
+    pub percent_change: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyCaptureStats {
+    /// Local calendar date, `YYYY-MM-DD`.
+    pub date: String,
+    pub count: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SourceCaptureStats {
+    pub count: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureStats {
+    /// One entry per day, oldest first, including days without captures.
+    pub days: Vec<DailyCaptureStats>,
+    pub by_source: HashMap<CaptureSource, SourceCaptureStats>,
+    pub total_count: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoEntryInfo {
+    pub operation: String,
+    pub created_at: i64,
+    pub image_ids: Vec<String>,
+    pub total_bytes: u64,
+}
+
+/// Captures saved within one coalescing window, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagesAddedEvent {
+    pub items: Vec<ImageMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagesRestoredEvent {
+    pub ids: Vec<String>,
+}
+
+/// Criteria for `request_delete_images_where`; an image must match all that
+/// are set.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DeleteFilter {
+    /// Unix seconds; only images captured before it match.
+    pub older_than: Option<i64>,
+    pub source: Option<CaptureSource>,
+    /// Only images without tags match.
+    #[serde(default)]
+    pub untagged_only: bool,
+    pub min_size_bytes: Option<u64>,
+}
+
+/// Result of `confirm_delete_images_where`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeletionSummary {
+    pub ids: Vec<String>,
+    /// Sum of the removed images' sizes.
+    pub freed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagesRemovedEvent {
+    pub ids: Vec<String>,
+}
+
+/// Files that moved, as `(old, new)` asset URLs. Cached URLs of the old
+/// paths no longer load.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathsInvalidatedEvent {
+    pub mapping: Vec<(String, String)>,
+}
+
+#[cfg(target_os = "windows")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FolderImportTruncatedEvent {
+    pub folders: Vec<String>,
+    pub max_files: usize,
+}
+
+/// Result of `clear_index` / `confirm_clear_all`, also the `images-cleared` payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearSummary {
+    /// Images dropped from the index.
+    pub index_entries: usize,
+    /// Files removed from the storage directory, including unindexed strays.
+    pub files_removed: usize,
+}
+
+/// File rename planned or done by `humanize_filenames`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenameOperation {
+    pub old_name: String,
+    pub new_name: String,
+    pub id: String,
+}
+
+/// Image file renamed by `fix_extensions` because its extension didn't
+/// match its content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtensionFix {
+    pub id: String,
+    pub old_path: String,
+    pub new_path: String,
+    /// Sniffed format, as in `ImageMetadata::format`.
+    pub format: String,
+}
+
+/// Image file in the storage folder with no index entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanedFile {
+    pub path: String,
+    pub size_bytes: u64,
+    /// Unix seconds of the last modification.
+    pub mtime: i64,
+}
+
+/// Images whose perceptual hashes are within the requested distance of the
+/// oldest of them, `canonical_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NearDuplicateGroup {
+    pub canonical_id: String,
+    /// Oldest first.
+    pub duplicate_ids: Vec<String>,
+    /// Similarity to the canonical image of the least similar duplicate,
+    /// from 0.0 to 1.0.
+    pub similarity: f64,
+}
+
+/// Encoding of a `copy_resized_to_clipboard` variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum VariantFormat {
+    Png,
+    /// `quality` from 1 to 100. Transparent areas become white.
+    Jpeg { quality: u8 },
+}
+
+/// One consecutive pair of `estimate_temporal_order`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemporalOrderResult {
+    pub id_from: String,
+    pub id_to: String,
+    /// Perceptual similarity of the two images, from 0.0 to 1.0.
+    pub similarity: f64,
+    /// How likely the pair is back-to-back frames of one workflow, from 0.0
+    /// to 1.0. Pairs captured from different windows count half.
+    pub confidence: f64,
+    /// Time between the two captures, only set when the pair looks like a
+    /// tight sequence.
+    pub estimated_transition_ms: Option<u32>,
+}
+
+/// Rectangle in stored-image pixels, from the top-left corner.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Monitor an image was most likely captured from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorIdentification {
+    /// Position in the system's monitor list at the time of the call.
+    pub monitor_index: u32,
+    /// 1.0 when the image is exactly the monitor's size, lower for a crop
+    /// with the monitor's aspect ratio.
+    pub confidence: f64,
+    /// Physical pixels.
+    pub monitor_resolution: (u32, u32),
+}
+
+/// A sampled color; `hex` is `#rrggbb` and leaves out alpha.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PixelColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+    pub hex: String,
+}
+
+/// Rectangular zone of a screenshot, in image pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UiRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub dominant_color: [u8; 3],
+}
+
+/// First half of a two-phase bulk delete: what would be removed and the
+/// token that confirms it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DestructiveConfirmation {
+    pub token: String,
+    pub image_count: usize,
+    pub total_bytes: u64,
+    pub expires_in_secs: u64,
+}
+
+/// One callout for `annotate_image`. Coordinates are image pixels and
+/// colors `#rrggbb`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AnnotationOp {
+    /// Outline of `stroke` pixels drawn inside the rectangle.
+    Rect { x: i32, y: i32, w: u32, h: u32, color: String, stroke: u32 },
+    Arrow { from: [i32; 2], to: [i32; 2], color: String },
+    /// `size` is the line height in pixels; (`x`, `y`) is the top left.
+    Text { x: i32, y: i32, content: String, size: f32, color: String },
+}
+
+/// An annotation that was not drawn.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnotationError {
+    /// Position of the op in the request.
+    pub index: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnnotationResult {
+    pub image: ImageMetadata,
+    pub errors: Vec<AnnotationError>,
+}
+
+/// Averages over the most recent captures, see `get_average_capture_size`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AverageSizeStats {
+    pub sample_count: usize,
+    pub avg_file_size_bytes: f64,
+    pub avg_width: f64,
+    pub avg_height: f64,
+    pub avg_pixel_count: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageStats {
+    pub image_count: usize,
+    pub total_bytes: u64,
+    pub max_library_bytes: Option<u64>,
+    pub max_library_count: Option<usize>,
+    pub janitor: JanitorStats,
+}
+
+/// Result of `get_storage_performance`, timed with a 1 KB probe file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoragePerformance {
+    pub is_network_drive: bool,
+    pub write_latency_ms: f64,
+    /// Reading the probe straight back, which the OS answers from its cache:
+    /// the per-read overhead of the folder, not how fast the drive reads.
+    pub cached_read_latency_ms: f64,
+}
+
+/// Temporary-file cleanup since the app started, see `run_janitor_now`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JanitorStats {
+    pub reclaimed_bytes: u64,
+    /// Unix seconds of the last sweep.
+    pub last_run: Option<i64>,
+}
+
+/// Result of `get_images`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageList {
+    pub images: Vec<ImageMetadata>,
+    /// The library is still being read at startup, so `images` may be
+    /// incomplete; `library-ready` follows when it is done.
+    pub loading: bool,
+}
+
+/// Payload of `pdf-progress`, sent after each image is added to the PDF.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdfProgressEvent {
+    pub current: usize,
+    pub total: usize,
+}
+
+/// Payload of `slideshow-next`, sent for each image a slideshow shows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlideshowNextEvent {
+    pub slideshow_id: String,
+    pub image_id: String,
+    /// Position of `image_id` in the slideshow's list.
+    pub index: usize,
+    pub total: usize,
+}
+
+/// Payload of `library-ready`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryReadyEvent {
+    /// Images indexed once loading finished.
+    pub count: usize,
+}
+
+/// Result of `create_webp_sprite_strip`: the thumbnails in one row, for a
+/// gallery that loads a single image.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpriteStripResult {
+    /// `data:image/webp;base64,...`
+    pub data_url: String,
+    pub offsets: Vec<SpriteOffset>,
+    pub strip_width: u32,
+    pub strip_height: u32,
+}
+
+/// Top-left corner of an image's cell in a sprite strip, for CSS
+/// `background-position: -{x}px -{y}px`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpriteOffset {
+    pub id: String,
+    pub x: u32,
+    pub y: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImagePage {
+    pub images: Vec<ImageMetadata>,
+    /// Zero-based page index.
+    pub page: usize,
+    pub page_size: usize,
+    /// Number of matching images across all pages.
+    pub total: usize,
+}
@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::types::{ImageMetadata, UndoEntryInfo};
+
+const JOURNAL_FILE: &str = "journal.json";
+const MAX_UNDO_ENTRIES: usize = 10;
+const MAX_UNDO_BYTES: u64 = 200 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoItem {
+    pub metadata: ImageMetadata,
+    stash_path: Option<PathBuf>,
+    /// Where `metadata.raw_path` is parked.
+    #[serde(default)]
+    raw_stash_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoEntry {
+    seq: u64,
+    pub operation: String,
+    pub created_at: i64,
+    pub items: Vec<UndoItem>,
+}
+
+/// Bounded stack of destructive operations whose files are parked in the
+/// `undo/` directory instead of being deleted.
+pub struct UndoJournal {
+    dir: PathBuf,
+    entries: Vec<UndoEntry>,
+    next_seq: u64,
+}
+
+impl UndoItem {
+    /// Moves the stashed file back to its original path. If a file with the
+    /// same content was captured again in the meantime the stash is dropped.
+    /// Restoring again after a partial failure picks up where it stopped.
+    pub fn restore(&self) -> anyhow::Result<()> {
+        if let (Some(raw_stash_path), Some(raw_path)) = (&self.raw_stash_path, &self.metadata.raw_path) {
+            unstash(raw_stash_path, Path::new(raw_path))?;
+        }
+        let Some(stash_path) = &self.stash_path else {
+            return Ok(());
+        };
+        unstash(stash_path, Path::new(&self.metadata.path))
+    }
+}
+
+fn unstash(stash_path: &Path, target: &Path) -> anyhow::Result<()> {
+    if !target.exists() {
+        fs::rename(stash_path, target)?;
+    } else if stash_path.exists() {
+        fs::remove_file(stash_path)?;
+    }
+    Ok(())
+}
+
+impl UndoEntry {
+    fn total_bytes(&self) -> u64 {
+        self.items.iter().map(|item| item.metadata.size_bytes).sum()
+    }
+
+    pub fn info(&self) -> UndoEntryInfo {
+        UndoEntryInfo {
+            operation: self.operation.clone(),
+            created_at: self.created_at,
+            image_ids: self.items.iter().map(|item| item.metadata.id.clone()).collect(),
+            total_bytes: self.total_bytes(),
+        }
+    }
+
+    fn discard(&self) {
+        let stash_paths = self.items.iter().flat_map(|item| item.stash_path.iter().chain(&item.raw_stash_path));
+        for stash_path in stash_paths {
+            if let Err(e) = fs::remove_file(stash_path) {
+                log::warn!("Failed to remove undo stash {}: {}", stash_path.display(), e);
+            }
+        }
+    }
+}
+
+impl UndoJournal {
+    /// Opens the journal in `dir`, reloading entries persisted by a previous
+    /// session and removing stash files nothing refers to.
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let journal_path = dir.join(JOURNAL_FILE);
+        let mut entries: Vec<UndoEntry> = fs::read(&journal_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        let _ = fs::remove_file(&journal_path);
+
+        entries.retain(|entry| {
+            entry.items.iter().all(|item| item.stash_path.as_ref().map_or(true, |path| path.exists()))
+        });
+
+        let referenced: Vec<&PathBuf> = entries
+            .iter()
+            .flat_map(|entry| entry.items.iter().flat_map(|item| item.stash_path.iter().chain(&item.raw_stash_path)))
+            .collect();
+        for dir_entry in fs::read_dir(&dir)?.flatten() {
+            let path = dir_entry.path();
+            if path.is_file() && !referenced.contains(&&path) {
+                log::debug!("Removing stale undo stash: {}", path.display());
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        let next_seq = entries.iter().map(|entry| entry.seq + 1).max().unwrap_or(0);
+        if !entries.is_empty() {
+            log::info!("Restored {} undo entries from previous session", entries.len());
+        }
+
+        Ok(Self { dir, entries, next_seq })
+    }
+
+    /// Empty journal that leaves `dir` untouched, for a process sharing the
+    /// storage directory with the one that owns the journal.
+    pub fn detached(dir: PathBuf) -> Self {
+        Self { dir, entries: Vec::new(), next_seq: 0 }
+    }
+
+    /// Stashes the files of `images` and pushes one entry for them. Returns
+    /// `false` without touching anything when the operation is too large to keep.
+    pub fn record(&mut self, operation: &str, images: Vec<ImageMetadata>) -> anyhow::Result<bool> {
+        let total_bytes: u64 = images.iter().map(|metadata| metadata.size_bytes).sum();
+        if total_bytes > MAX_UNDO_BYTES {
+            log::warn!("{} of {} bytes exceeds the undo budget, not journaling", operation, total_bytes);
+            return Ok(false);
+        }
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let mut items: Vec<UndoItem> = Vec::with_capacity(images.len());
+        for metadata in images {
+            let source = Path::new(&metadata.path);
+            let stash_path = if source.exists() {
+                let file_name = source.file_name().and_then(|n| n.to_str()).unwrap_or(&metadata.id);
+                let stash_path = self.dir.join(format!("{}-{}", seq, file_name));
+                if let Err(e) = fs::rename(source, &stash_path) {
+                    log::error!("Failed to stash {} for undo: {}", source.display(), e);
+                    for item in &items {
+                        let _ = item.restore();
+                    }
+                    return Err(anyhow::anyhow!("Failed to stash image file: {}", e));
+                }
+                Some(stash_path)
+            } else {
+                None
+            };
+            // The raw capture is best effort; the image itself is restorable
+            // without it.
+            let raw_stash_path = metadata.raw_path.as_ref()
+                .map(PathBuf::from)
+                .filter(|raw| raw.exists())
+                .and_then(|raw| {
+                    let file_name = raw.file_name()?.to_str()?.to_string();
+                    let raw_stash_path = self.dir.join(format!("{}-{}", seq, file_name));
+                    match fs::rename(&raw, &raw_stash_path) {
+                        Ok(()) => Some(raw_stash_path),
+                        Err(e) => {
+                            log::warn!("Failed to stash {} for undo: {}", raw.display(), e);
+                            None
+                        }
+                    }
+                });
+            items.push(UndoItem { metadata, stash_path, raw_stash_path });
+        }
+
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)?
+            .as_secs() as i64;
+
+        self.entries.push(UndoEntry {
+            seq,
+            operation: operation.to_string(),
+            created_at,
+            items,
+        });
+
+        while self.entries.len() > MAX_UNDO_ENTRIES || self.total_bytes() > MAX_UNDO_BYTES {
+            let oldest = self.entries.remove(0);
+            log::debug!("Dropping oldest undo entry: {}", oldest.operation);
+            oldest.discard();
+        }
+
+        Ok(true)
+    }
+
+    /// The most recent entry, left on the stack until it is restored.
+    pub fn peek(&self) -> Option<&UndoEntry> {
+        self.entries.last()
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop()
+    }
+
+    /// Puts `entry` back on top of the stack, for the items of a popped
+    /// entry that failed to restore.
+    pub fn push_back(&mut self, entry: UndoEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Entries newest first.
+    pub fn entries(&self) -> Vec<UndoEntryInfo> {
+        self.entries.iter().rev().map(UndoEntry::info).collect()
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.drain(..) {
+            entry.discard();
+        }
+    }
+
+    /// Writes the journal so the next session can reload it.
+    pub fn persist(&self) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(&self.entries)?;
+        fs::write(self.dir.join(JOURNAL_FILE), data)?;
+        Ok(())
+    }
+
+    fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(UndoEntry::total_bytes).sum()
+    }
+}
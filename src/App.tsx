@@ -2,7 +2,7 @@ import { useState, useEffect, useCallback, useRef } from 'react';
 import { ImageCard } from './components/ImageCard';
 import { ContextMenu } from './components/ContextMenu';
 import { ImageLightbox } from './components/ImageLightbox';
-import { getImages, deleteImage, clearAllImages, resetClipboardHash, listenClipboardUpdate, copyFileToClipboard } from './services/api';
+import { getImages, deleteImage, clearAllImages, resetClipboardHash, listenClipboardUpdate, listenLibraryReady, copyFileToClipboard } from './services/api';
 import type { ImageMetadata, ContextMenuPosition } from './types';
 
 function App() {
@@ -24,7 +24,7 @@ function App() {
 
   const loadImages = useCallback(async () => {
     try {
-      const data = await getImages();
+      const { images: data } = await getImages();
       setImages(data);
     } catch (error) {
       console.error('Failed to load images:', error);
@@ -111,12 +111,15 @@ function App() {
           console.log('Clipboard update:', event);
           loadImages();
         });
+        // The first load may arrive before the library is fully read.
+        const unlistenLibraryReady = await listenLibraryReady(() => loadImages());
         
         console.log('Clipboard listener set up successfully');
         
         return () => {
           console.log('Cleaning up clipboard listener');
           unlisten();
+          unlistenLibraryReady();
         };
       } catch (error) {
         console.error('Failed to set up clipboard listener:', error);
@@ -184,8 +187,9 @@ function App() {
 
   const handleClearAll = useCallback(async () => {
     try {
-      await clearAllImages();
-      setImages([]);
+      if (await clearAllImages()) {
+        setImages([]);
+      }
     } catch (error) {
       console.error('Failed to clear all images:', error);
     }
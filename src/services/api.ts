@@ -1,7 +1,7 @@
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { isTauri as checkIsTauri } from '@tauri-apps/api/core';
-import type { ImageMetadata, ClipboardEvent } from '../types';
+import type { ImageMetadata, ImageList, ClipboardEvent, DestructiveConfirmation } from '../types';
 
 let isTauriCache: boolean | null = null;
 
@@ -12,11 +12,12 @@ async function isTauriEnvironment(): Promise<boolean> {
   return isTauriCache;
 }
 
-export async function getImages(): Promise<ImageMetadata[]> {
+/** `loading` is true while the library is still being read at startup. */
+export async function getImages(): Promise<ImageList> {
   if (!(await isTauriEnvironment())) {
-    return [];
+    return { images: [], loading: false };
   }
-  return await invoke<ImageMetadata[]>('get_images');
+  return await invoke<ImageList>('get_images');
 }
 
 export async function deleteImage(id: string): Promise<void> {
@@ -26,11 +27,19 @@ export async function deleteImage(id: string): Promise<void> {
   return await invoke<void>('delete_image', { id });
 }
 
-export async function clearAllImages(): Promise<void> {
+/** Resolves to whether the user confirmed and the images were cleared. */
+export async function clearAllImages(): Promise<boolean> {
   if (!(await isTauriEnvironment())) {
-    return;
+    return false;
+  }
+  // The backend deletes only with a token from the request phase.
+  const { token, image_count, total_bytes } = await invoke<DestructiveConfirmation>('request_clear_all');
+  const megabytes = (total_bytes / (1024 * 1024)).toFixed(1);
+  if (!window.confirm(`确定要清空全部 ${image_count} 张图片（${megabytes} MB）吗？`)) {
+    return false;
   }
-  return await invoke<void>('clear_all_images');
+  await invoke('confirm_clear_all', { token });
+  return true;
 }
 
 export async function resetClipboardHash(): Promise<void> {
@@ -54,11 +63,19 @@ export async function saveImageFromClipboard(imageData: Uint8Array): Promise<Ima
   return await invoke<ImageMetadata>('save_image_from_clipboard', { imageData: Array.from(imageData) });
 }
 
-export async function cleanupOldImages(hours: number): Promise<void> {
+/** Resolves to a confirmation when the cleanup is large enough to need one. */
+export async function cleanupOldImages(hours: number): Promise<DestructiveConfirmation | null> {
   if (!(await isTauriEnvironment())) {
-    return;
+    return null;
   }
-  return await invoke<void>('cleanup_old_images', { hours });
+  return await invoke<DestructiveConfirmation | null>('cleanup_old_images', { hours });
+}
+
+export async function confirmCleanupOldImages(token: string): Promise<number> {
+  if (!(await isTauriEnvironment())) {
+    throw new Error('Not in Tauri environment');
+  }
+  return await invoke<number>('confirm_cleanup_old_images', { token });
 }
 
 export async function readImageFile(path: string): Promise<Uint8Array> {
@@ -75,3 +92,19 @@ export function listenClipboardUpdate(callback: (event: ClipboardEvent) => void)
   }
   return listen<ClipboardEvent>('clipboard-update', (event) => callback(event.payload));
 }
+
+/** Fires once per burst of captures with every image saved in it. */
+export function listenImagesAdded(callback: (items: ImageMetadata[]) => void) {
+  if (!(checkIsTauri())) {
+    return Promise.resolve(() => {});
+  }
+  return listen<{ items: ImageMetadata[] }>('images-added', (event) => callback(event.payload.items));
+}
+
+/** Fires once the library has been read at startup. */
+export function listenLibraryReady(callback: (count: number) => void) {
+  if (!(checkIsTauri())) {
+    return Promise.resolve(() => {});
+  }
+  return listen<{ count: number }>('library-ready', (event) => callback(event.payload.count));
+}
@@ -5,6 +5,11 @@ export interface ImageMetadata {
   ocrResult?: string;
 }
 
+export interface ImageList {
+  images: ImageMetadata[];
+  loading: boolean;
+}
+
 export interface ClipboardEvent {
   imagePath: string;
 }
@@ -18,3 +23,10 @@ export interface ContextMenuPosition {
   x: number;
   y: number;
 }
+
+export interface DestructiveConfirmation {
+  token: string;
+  image_count: number;
+  total_bytes: number;
+  expires_in_secs: number;
+}